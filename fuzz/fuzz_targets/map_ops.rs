@@ -0,0 +1,46 @@
+//! Runs a sequence of insert/get/remove ops on `aligned_cuckoo_table` and on a `HashMap`, and
+//! checks that they agree after every op.
+//!
+//! `cargo +nightly fuzz run map_ops` (from the repo root) starts from the seed corpus in
+//! `fuzz/corpus/map_ops`.
+
+#![no_main]
+
+use std::collections::HashMap;
+
+use arbitrary::Arbitrary;
+use cuckoo_hashing_benchmark::aligned_cuckoo_table::HashTable;
+use libfuzzer_sys::fuzz_target;
+
+/// Keys are `u8` so that gets and removes often hit keys already in the table. That still takes
+/// the table from two groups (where inserts soon need the cuckoo BFS) through several doublings.
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Insert(u8, u64),
+    Get(u8),
+    Remove(u8),
+}
+
+fuzz_target!(|ops: Vec<Op>| {
+    let mut table = HashTable::new();
+    let mut reference = HashMap::new();
+    for op in ops {
+        match op {
+            Op::Insert(key, value) => {
+                let key = u64::from(key);
+                // `replace` rather than `insert`, which may duplicate a key in its second group.
+                assert_eq!(table.replace(key, value), reference.insert(key, value));
+            }
+            Op::Get(key) => {
+                let key = u64::from(key);
+                assert_eq!(table.get(&key), reference.get(&key));
+            }
+            Op::Remove(key) => {
+                let key = u64::from(key);
+                let removed = table.find_entry(key).map(|entry| entry.remove());
+                assert_eq!(removed, reference.remove(&key));
+            }
+        }
+        assert_eq!(table.len(), reference.len());
+    }
+});
@@ -0,0 +1,354 @@
+//! Variant of `aligned_cuckoo_table` that stores keys and values in separate arrays rather than
+//! as `(u64, V)` pairs, so small values pack tightly instead of each padding out to 8 bytes: for
+//! `V = u8` a slot takes 10 bytes (key, value and tag) instead of 17. The control bytes and the
+//! probing are as in `aligned_cuckoo_table`. Fixed capacity: no growth, erase or double hashing.
+
+use core::hint::likely;
+use core::{alloc::Layout, ptr::NonNull};
+use core::fmt;
+
+use crate::{TRACK_PROBE_LENGTH, TryReserveError, infallible};
+use crate::control::{Group, Tag, TagSliceExt as _};
+use crate::cuckoo_bfs::{self, CuckooSlots};
+use crate::dropper::{self, Dropper};
+use crate::u64_fold_hash_fast::fold_hash_fast;
+use crate::uunwrap::UUnwrap;
+use crate::DebugEntries;
+
+pub struct HashTable<V: Copy> {
+    aligned_bucket_mask: usize,
+
+    // K0, K1, ..., K_n, [Padding], V0, V1, ..., V_n, [Padding], C0, C1, ..., C_n
+    // ^ keys                       ^ values                     ^ ctrl
+    keys: NonNull<u64>,
+    values: NonNull<V>,
+    ctrl: NonNull<u8>,
+
+    // Number of elements in the table, only really used by len()
+    items: usize,
+
+    // Seed for the hash function
+    seed: u64,
+
+    total_probe_length: usize,
+
+    dropper: Dropper,
+}
+
+// SAFETY: `keys`, `values` and `ctrl` are three disjoint arrays in the one allocation `dropper`
+// owns, and no other table shares it. The BFS moves a slot by writing all three, only ever under
+// `&mut self`.
+unsafe impl<V: Copy + Send> Send for HashTable<V> {}
+unsafe impl<V: Copy + Sync> Sync for HashTable<V> {}
+
+impl<V: Copy> HashTable<V> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_huge_pages(capacity, false)
+    }
+
+    /// As `with_capacity`, but on huge pages where the kernel allows; see `dropper::alloc`.
+    #[cfg(target_os = "linux")]
+    pub fn with_capacity_hugepage(capacity: usize) -> Self {
+        Self::with_capacity_and_huge_pages(capacity, true)
+    }
+
+    fn with_capacity_and_huge_pages(capacity: usize, huge_pages: bool) -> Self {
+        // Calculate sizes
+        let num_buckets = infallible(
+            capacity
+                .checked_mul(8)
+                .and_then(|n| (n / 7).checked_next_power_of_two())
+                .map(|n| n.max(Group::WIDTH))
+                .ok_or(TryReserveError::CapacityOverflow),
+        );
+        let (layout, values_offset, ctrl_offset) = Self::layout(num_buckets);
+        // Allocate
+        let (alloc, layout) = unsafe { dropper::alloc(layout, huge_pages) };
+        if alloc.is_null() {
+            alloc::alloc::handle_alloc_error(layout);
+        }
+        // Write control
+        let ctrl = unsafe { NonNull::new_unchecked(alloc.add(ctrl_offset)) };
+        let ctrl_slice = unsafe { core::slice::from_raw_parts_mut(ctrl.as_ptr() as *mut Tag, num_buckets) };
+        ctrl_slice.fill_empty();
+        let seed = fastrand::Rng::with_seed(123).u64(..);
+
+        Self {
+            aligned_bucket_mask: num_buckets - Group::WIDTH,
+            keys: unsafe { NonNull::new_unchecked(alloc.cast()) },
+            values: unsafe { NonNull::new_unchecked(alloc.add(values_offset).cast()) },
+            ctrl,
+            items: 0,
+            seed,
+            total_probe_length: 0,
+            dropper: Dropper { alloc, layout },
+        }
+    }
+
+    /// Layout of a table with `num_buckets`, and the offsets of the values and of `ctrl` within
+    /// it. The keys come first.
+    fn layout(num_buckets: usize) -> (Layout, usize, usize) {
+        let align = core::mem::align_of::<u64>()
+            .max(core::mem::align_of::<V>())
+            .max(Group::WIDTH);
+        let values_offset = (num_buckets * core::mem::size_of::<u64>()).next_multiple_of(core::mem::align_of::<V>());
+        let ctrl_offset = (values_offset + num_buckets * core::mem::size_of::<V>()).next_multiple_of(Group::WIDTH);
+        let size = ctrl_offset + num_buckets;
+        (Layout::from_size_align(size, align).uunwrap(), values_offset, ctrl_offset)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn print_stats(&self) {
+        println!(
+            "  avg_probe_length: {}",
+            self.total_probe_length as f64 / self.items as f64
+        );
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The hash of `key`. Its low bits pick the first group and its top 7 bits the tag; the
+    /// second group is the first XOR the scrambled tag.
+    #[inline(always)]
+    pub fn hash_of(&self, key: u64) -> u64 {
+        fold_hash_fast(key, self.seed)
+    }
+
+    /// Returns the number of buckets in the table.
+    #[inline(always)]
+    pub fn num_buckets(&self) -> usize {
+        self.aligned_bucket_mask + Group::WIDTH
+    }
+
+    /// Bytes of the table's heap allocation: the key and value arrays, each padded to the next
+    /// one's alignment, plus the control bytes.
+    pub fn heap_bytes(&self) -> usize {
+        self.dropper.layout.size()
+    }
+
+    #[inline(always)]
+    pub fn insert(&mut self, key: u64, value: V) -> (bool, usize, usize) {
+        let hash0 = fold_hash_fast(key, self.seed);
+        let tag = Tag::full(hash0);
+        let pos0 = hash0 as usize & self.aligned_bucket_mask;
+        let pos1 = (hash0 ^ scramble_tag(tag)) as usize & self.aligned_bucket_mask;
+
+        if let Some(index) = self.find_in_group(pos0, tag, key) {
+            unsafe { self.value(index).write(value) };
+            return (false, index, 1);
+        }
+        if let Some(index) = self.find_in_group(pos1, tag, key) {
+            unsafe { self.value(index).write(value) };
+            return (false, index, 2);
+        }
+
+        let (bucket_index, insertion_probe_length) = if let Some(index) = self.first_empty(pos0) {
+            (index, 1)
+        } else if let Some(index) = self.first_empty(pos1) {
+            (index, 2)
+        } else {
+            cuckoo_bfs::make_room(self, pos0, pos1, 3, usize::MAX)
+                .unwrap_or_else(|_| panic!("Failed to insert into cuckoo table; need to rehash"))
+        };
+        unsafe {
+            self.key(bucket_index).write(key);
+            self.value(bucket_index).write(value);
+            self.set_ctrl(bucket_index, tag);
+        }
+        self.items += 1;
+        (true, bucket_index, insertion_probe_length)
+    }
+
+    #[inline(always)]
+    pub fn get(&mut self, key: &u64) -> Option<&V> {
+        let key = *key;
+        let hash0 = fold_hash_fast(key, self.seed);
+        let tag = Tag::full(hash0);
+        let pos0 = hash0 as usize & self.aligned_bucket_mask;
+        if TRACK_PROBE_LENGTH {
+            self.total_probe_length += 1;
+        }
+        if let Some(index) = self.find_in_group(pos0, tag, key) {
+            return Some(unsafe { &*self.value(index) });
+        }
+        let pos1 = (hash0 ^ scramble_tag(tag)) as usize & self.aligned_bucket_mask;
+        if TRACK_PROBE_LENGTH {
+            self.total_probe_length += 1;
+        }
+        self.find_in_group(pos1, tag, key)
+            .map(|index| unsafe { &*self.value(index) })
+    }
+
+    pub fn probe_length(&self, key: u64) -> (usize, bool) {
+        let hash0 = fold_hash_fast(key, self.seed);
+        let tag = Tag::full(hash0);
+        let pos0 = hash0 as usize & self.aligned_bucket_mask;
+        if self.find_in_group(pos0, tag, key).is_some() {
+            return (1, true);
+        }
+        let pos1 = (hash0 ^ scramble_tag(tag)) as usize & self.aligned_bucket_mask;
+        (2, self.find_in_group(pos1, tag, key).is_some())
+    }
+
+    #[inline(always)]
+    fn find_in_group(&self, pos: usize, tag: Tag, key: u64) -> Option<usize> {
+        let group = unsafe { Group::load_aligned(self.ctrl(pos)) };
+        for bit in group.match_tag(tag) {
+            let index = pos + bit;
+            if likely(unsafe { *self.key(index) } == key) {
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    #[inline(always)]
+    fn first_empty(&self, pos: usize) -> Option<usize> {
+        let group = unsafe { Group::load_aligned(self.ctrl(pos)) };
+        group.match_empty().lowest_set_bit().map(|bit| pos + bit)
+    }
+
+    /// Given a full slot at `index` in the group at `pos`, returns the slot's other group.
+    #[inline(always)]
+    fn other_pos(&self, pos: usize, index: usize) -> usize {
+        let tag = unsafe { *self.ctrl(index) };
+        pos ^ (scramble_tag(tag) as usize & self.aligned_bucket_mask)
+    }
+
+    #[inline(always)]
+    unsafe fn ctrl(&self, index: usize) -> *mut Tag {
+        self.ctrl.as_ptr().add(index).cast()
+    }
+
+    #[inline(always)]
+    unsafe fn key(&self, index: usize) -> *mut u64 {
+        self.keys.as_ptr().add(index)
+    }
+
+    #[inline(always)]
+    unsafe fn value(&self, index: usize) -> *mut V {
+        self.values.as_ptr().add(index)
+    }
+
+    #[inline(always)]
+    unsafe fn set_ctrl(&self, index: usize, tag: Tag) {
+        *self.ctrl(index) = tag;
+    }
+}
+
+impl<V: Copy> CuckooSlots for HashTable<V> {
+    const WIDTH: usize = Group::WIDTH;
+
+    #[inline(always)]
+    fn other_pos(&self, pos: usize, index: usize) -> usize {
+        Self::other_pos(self, pos, index)
+    }
+
+    #[inline(always)]
+    fn first_empty(&self, pos: usize) -> Option<usize> {
+        Self::first_empty(self, pos)
+    }
+
+    #[inline(always)]
+    unsafe fn move_slot(&mut self, from: usize, to: usize) {
+        self.key(to).write(self.key(from).read());
+        self.value(to).write(self.value(from).read());
+        self.set_ctrl(to, *self.ctrl(from));
+    }
+}
+
+#[inline(always)]
+fn scramble_tag(tag: Tag) -> u64 {
+    (tag.0 as u64).wrapping_mul(MUL).rotate_left(32)
+}
+
+const MUL: u64 = 0x2d35_8dcc_aa6c_78a5;
+
+impl<V: Copy + fmt::Debug> fmt::Debug for HashTable<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = move || {
+            (0..self.num_buckets())
+                .filter(move |&index| unsafe { *self.ctrl(index) }.is_full())
+                .map(move |index| unsafe { (*self.key(index), &*self.value(index)) })
+        };
+        f.debug_struct("HashTable")
+            .field("len", &self.len())
+            .field("num_buckets", &self.num_buckets())
+            .field("entries", &DebugEntries(entries))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_randomized_vs_std_hashmap() {
+        let mut table = HashTable::with_capacity(1 << 14);
+        let mut std_map = HashMap::new();
+        let mut rng = fastrand::Rng::with_seed(2118);
+        // Fill to the 7/8 maximum load, so plenty of inserts go through the BFS.
+        for _ in 0..(1 << 14) * 7 / 8 {
+            let key = rng.u64(..);
+            let value = rng.u8(..);
+            let (inserted, _, _) = table.insert(key, value);
+            assert_eq!(inserted, std_map.insert(key, value).is_none());
+        }
+        assert_eq!(table.len(), std_map.len());
+        for (key, value) in &std_map {
+            assert_eq!(table.get(key), Some(value));
+        }
+        for _ in 0..10_000 {
+            let key = rng.u64(..);
+            assert_eq!(table.get(&key), std_map.get(&key));
+        }
+    }
+
+    #[test]
+    fn test_update_and_wide_values() {
+        // Values wider and more aligned than the keys.
+        let mut table = HashTable::<u128>::with_capacity(1000);
+        for key in 0..1000u64 {
+            assert!(table.insert(key, key as u128).0);
+        }
+        for key in 0..1000u64 {
+            assert!(!table.insert(key, (key as u128) << 64).0);
+        }
+        assert_eq!(table.len(), 1000);
+        for key in 0..1000u64 {
+            assert_eq!(table.get(&key), Some(&((key as u128) << 64)));
+            assert!(table.probe_length(key).1);
+        }
+        assert_eq!(table.get(&1000), None);
+        assert!(!table.probe_length(1000).1);
+    }
+
+    #[test]
+    fn test_heap_bytes() {
+        let capacity = 1 << 14;
+        let soa = HashTable::<u8>::with_capacity(capacity);
+        let aos = crate::aligned_cuckoo_table::HashTable::<u8>::with_capacity(capacity);
+        assert_eq!(soa.num_buckets(), aos.num_buckets());
+        let num_buckets = soa.num_buckets();
+        // 8 + 1 + 1 bytes per slot, against a 16-byte `(u64, u8)` plus the tag.
+        assert_eq!(soa.heap_bytes(), num_buckets * 10);
+        assert!(aos.heap_bytes() >= num_buckets * 17);
+        assert!(soa.heap_bytes() < aos.heap_bytes());
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn test_capacity_overflow_panics() {
+        HashTable::<u64>::with_capacity(usize::MAX / 4);
+    }
+}
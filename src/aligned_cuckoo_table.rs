@@ -1,15 +1,30 @@
 //! A cuckoo hash table with 2 choices of group, each with 8-16 buckets per group.
 
-use std::hint::{black_box, likely};
-use std::mem::MaybeUninit;
-use std::{alloc::Layout, ptr::NonNull};
-
-use crate::TRACK_PROBE_LENGTH;
-use crate::control::{Group, Tag, TagSliceExt as _};
-use crate::u64_fold_hash_fast::{self, fold_hash_fast};
+use core::hint::likely;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::MaybeUninit;
+use core::{alloc::Layout, ptr::NonNull};
+use core::fmt;
+
+use crate::{FillToFailure, InsertError, TryReserveError, infallible};
+use crate::control::{BitMask, BitMaskIter, Group, Tag, TagSliceExt as _};
+use crate::cuckoo_bfs::{self, CuckooSlots};
+use crate::u64_fold_hash_fast::{BuildHasherHash64, FoldHashFast, Hash64};
 use crate::uunwrap::UUnwrap;
-
-pub struct HashTable<V: Copy> {
+use crate::DebugEntries;
+
+/// `W` is the number of slots per group, at most the SIMD `Group::WIDTH`. Narrower groups load a
+/// whole SIMD group and ignore the slots past `W`, so 4-, 8- and 16-slot buckets can be compared
+/// on the same hardware.
+///
+/// `D` is how many levels of displacements the cuckoo BFS of `insert` searches below the key's
+/// own two groups (see `insert_cold`). Deeper searches find room at higher loads, at the cost of
+/// worst-case insert latency. 16-slot groups reach the 7/8 growth threshold at any depth from 2,
+/// and 4-slot groups from 3. With 2-slot groups and 2^15 slots, the first failed insert came at
+/// 50%, 68%, 74% and 74% load for `D` from 2 to 5: past 4 the limit is the at most 128 second
+/// groups the 7-bit tag can pick for a group, not the depth.
+pub struct HashTable<V: Copy, H: Hash64 = FoldHashFast, const W: usize = { Group::WIDTH }, const D: usize = BFS_DEPTH> {
     aligned_bucket_mask: usize,
 
     // [Padding], T_n, ..., T1, T0, C0, C1, ...
@@ -22,39 +37,307 @@ pub struct HashTable<V: Copy> {
     // Seed for the hash function
     seed: u64,
 
-    marker: std::marker::PhantomData<V>,
+    hasher: H,
+
+    // Second-group derivation; see `second_hash`.
+    double_hashing: bool,
+
+    // Whether lookups stop at a first group with an empty slot; see `with_early_return`.
+    early_return: bool,
+
+    // Whether `get` loads both groups before matching either; see `with_both_groups_loaded`.
+    load_both_groups: bool,
+
+    // Whether `insert` fills the emptier of the key's two groups; see `with_less_loaded_insertion`.
+    less_loaded: bool,
+
+    // Growth target as `(numerator, denominator)` of `len()`; see `with_grow_factor`.
+    grow_factor: (usize, usize),
+
+    // Whether the growth threshold is every slot rather than 7/8 of them; see `with_full_load`.
+    full_load: bool,
+
+    // Whether `EntryHandle::remove` halves a sparse table; see `with_shrink_on_remove`.
+    shrink_on_remove: bool,
+
+    // Whether `get` counts `tag_false_positives`; see `with_tag_false_positive_counting`.
+    count_tag_false_positives: bool,
+    tag_false_positives: usize,
+
+    // Whether `get` checks `last_lookup` first; see `with_lookup_cache`.
+    lookup_cache: bool,
+    // The key and slot of the last `get` hit. Cleared whenever an entry moves or is erased.
+    last_lookup: Option<(u64, usize)>,
+    lookup_cache_hits: usize,
+
+    marker: core::marker::PhantomData<V>,
 
     total_probe_length: usize,
     total_insert_probe_length: usize,
     max_insert_probe_length: usize,
+    // Sum over inserts of the groups probed after the first. Counted only on the paths that
+    // leave the first group, which is cheaper than summing every insert's probe length (that
+    // cost ~10% on in-cache builds at 85% load).
+    insert_probes_past_first_group: usize,
+
+    // Holes left by `erase_index` since the last full rehash; checked by `insert_and_erase`.
+    #[cfg(debug_assertions)]
+    tombstones: usize,
 }
 
+// SAFETY: `ctrl` is the only pointer to the table's allocation, which `Drop` frees; cloning copies
+// it into a fresh one. Writes through it happen under `&mut self`, and `get` updates the lookup
+// cache through `&mut self` too. An unallocated table points at `UNALLOCATED_CTRL`, which is
+// never written. The hasher is held by value, hence the `H` bounds.
+unsafe impl<V: Copy + Send, H: Hash64 + Send, const W: usize, const D: usize> Send for HashTable<V, H, W, D> {}
+unsafe impl<V: Copy + Sync, H: Hash64 + Sync, const W: usize, const D: usize> Sync for HashTable<V, H, W, D> {}
+
+/// Load factor the cuckoo BFS reliably fills a table to before `try_insert` fails, with the
+/// growth threshold moved out of the way by `with_full_load`. Filling random keys into 2^10 to
+/// 2^20 slots reached at least 99.6% on every seed. At the default threshold, `try_insert` stops
+/// with `InsertError::NeedsGrowth` at 7/8 load instead.
+pub const PEAK_LOAD_FACTOR: f64 = 0.99;
+
+/// Default BFS depth `D`: two roots, then up to three levels of displacements.
+pub const BFS_DEPTH: usize = 3;
+
 impl<V: Copy> HashTable<V> {
     /// Create a new hash table with a small initial capacity that will grow as needed.
     pub fn new() -> Self {
-        Self::with_capacity(16)
+        Self::with_capacity(INITIAL_CAPACITY)
     }
 
     pub fn with_capacity(capacity: usize) -> Self {
-        // Calculate sizes
-        // TODO: integer overflow...
-        let num_buckets = ((capacity * 8) / 7).next_power_of_two();
-        let bucket_size = std::mem::size_of::<(u64, V)>();
-        let align = std::mem::align_of::<(u64, V)>().max(Group::WIDTH);
-        let ctrl_offset = (bucket_size * num_buckets).next_multiple_of(align);
-        let size = ctrl_offset + num_buckets;
-        let layout = Layout::from_size_align(size, align).uunwrap();
-        // Allocate
-        let alloc = unsafe { std::alloc::alloc(layout) };
-        // Write control
-        let ctrl = unsafe { NonNull::new_unchecked(alloc.add(ctrl_offset)) };
-        let ctrl_slice =
-            unsafe { std::slice::from_raw_parts_mut(ctrl.as_ptr() as *mut Tag, num_buckets) };
-        ctrl_slice.fill_empty();
-        // dbg!(num_buckets, bucket_size, align, ctrl_offset, size, layout, alloc, ctrl);
+        Self::with_capacity_and_hasher(capacity, FoldHashFast)
+    }
+
+    /// As `with_capacity`, but sized so that `capacity` items fill at most `max_load` of the
+    /// buckets, trading memory for shorter probes. Only the initial size: once full, the table
+    /// still grows at 7/8 load.
+    pub fn with_capacity_and_load(capacity: usize, max_load: f64) -> Self {
+        assert!(max_load > 0.0 && max_load < 1.0, "max_load must be in (0, 1)");
+        let min_buckets = capacity as f64 / max_load;
+        // `f64::ceil` needs `std`.
+        let min_buckets = min_buckets as usize + ((min_buckets as usize as f64) < min_buckets) as usize;
+        Self::with_num_buckets_and_hasher(min_buckets.next_power_of_two().max(MIN_NUM_BUCKETS), FoldHashFast)
+    }
+
+    /// Builds a table sized to fit `pairs`, placing every key that fits in its first group
+    /// before running the cuckoo BFS for the rest. Compared to inserting in arbitrary order, this
+    /// avoids kicks that push keys out to their second group, so more lookups finish after one
+    /// group.
+    ///
+    /// `pairs` is sorted in place (by first group). For duplicate keys the last value wins.
+    pub fn from_pairs(pairs: &mut [(u64, V)]) -> Self {
+        // `with_capacity` needs room for at least one whole group.
+        let mut table = Self::with_capacity(pairs.len().max(Group::WIDTH));
+        table.bulk_place(pairs);
+        table
+    }
+
+    /// Rebuilds the table `dump_layout` described, slot for slot. The layout must come from a
+    /// table with the default hasher, seed and second-group derivation; panics if some entry
+    /// isn't in one of its key's groups under them, or if the layout is over the 7/8 load.
+    pub fn from_layout((ctrl, entries): (Vec<u8>, Vec<(u64, V)>)) -> Self {
+        let num_buckets = ctrl.len();
+        assert!(
+            num_buckets.is_power_of_two() && num_buckets >= Group::WIDTH,
+            "bucket count must be a power of two of at least one group"
+        );
+        let mut table = Self::with_num_buckets_and_hasher(num_buckets, FoldHashFast);
+        assert!(entries.len() <= table.items_until_growth, "layout is over the maximum load");
+        let mut entries = entries.into_iter();
+        for (index, &byte) in ctrl.iter().enumerate() {
+            let tag = Tag(byte);
+            if !tag.is_full() {
+                assert!(tag == Tag::EMPTY, "slot {index} is neither full nor empty");
+                continue;
+            }
+            let (key, value) = entries.next().expect("fewer entries than full slots");
+            let (pos0, pos1) = table.candidate_indices(key);
+            let pos = index & !(Group::WIDTH - 1);
+            assert!(
+                tag == Tag::full(table.hasher.hash64(key, table.seed)) && (pos == pos0 || pos == pos1),
+                "key {key} doesn't belong in slot {index}"
+            );
+            unsafe {
+                table.bucket(index).write((key, value));
+                table.set_ctrl(index, tag);
+            }
+            table.items_until_growth -= 1;
+        }
+        assert!(entries.next().is_none(), "more entries than full slots");
+        table
+    }
+
+    /// Builds a table from an iterator of known length, sized up front from `len()` so that it
+    /// allocates exactly once. For duplicate keys the last value wins.
+    pub fn from_exact_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (u64, V)>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let iter = iter.into_iter();
+        let mut table = Self::with_capacity(iter.len().max(Group::WIDTH));
+        for (key, value) in iter {
+            table.replace(key, value);
+        }
+        table
+    }
+}
+
+impl<V: Copy, S: core::hash::BuildHasher> HashTable<V, BuildHasherHash64<S>> {
+    /// As `with_capacity`, but hashing keys through `hasher`'s `Hasher`s, as a `HashMap` built
+    /// with it would. The table's seed is hashed in ahead of each key, so `reset_with_seed` still
+    /// moves every key.
+    pub fn with_hasher(capacity: usize, hasher: S) -> Self {
+        Self::with_capacity_and_hasher(capacity, BuildHasherHash64(hasher))
+    }
+}
+
+/// Reserves the iterator's `size_hint` lower bound, growing as usual past it. For duplicate keys
+/// the last value wins. See also `extend_prefetched`.
+impl<V: Copy, H: Hash64, const W: usize, const D: usize> Extend<(u64, V)> for HashTable<V, H, W, D> {
+    fn extend<I: IntoIterator<Item = (u64, V)>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for (key, value) in iter {
+            self.replace(key, value);
+        }
+    }
+}
+
+/// Sized from the iterator's `size_hint` lower bound, growing as usual past it. Use
+/// `from_exact_iter` when the length is known. For duplicate keys the last value wins.
+impl<V: Copy> FromIterator<(u64, V)> for HashTable<V> {
+    fn from_iter<I: IntoIterator<Item = (u64, V)>>(iter: I) -> Self {
+        let iter = iter.into_iter();
+        let mut table = Self::with_capacity(iter.size_hint().0.max(INITIAL_CAPACITY));
+        for (key, value) in iter {
+            table.replace(key, value);
+        }
+        table
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<V: Copy + Send + Sync> HashTable<V> {
+    /// Parallel `from_pairs`: sorts `pairs` by first group, then splits the table into shards of
+    /// consecutive groups (by the high bits of the group index) and runs the greedy first-group
+    /// pass for each shard on its own thread. Shards cover disjoint ranges of the one allocation,
+    /// so nothing needs stitching together afterwards. The keys whose first group overflowed are
+    /// then inserted serially.
+    ///
+    /// `pairs` is sorted in place. For duplicate keys the last value wins.
+    pub fn par_build(pairs: &mut [(u64, V)]) -> Self {
+        use rayon::prelude::*;
+
+        /// The table's `ctrl`, shared with the shard threads.
+        #[derive(Clone, Copy)]
+        struct SharedCtrl(NonNull<u8>);
+        // SAFETY: each shard only writes the groups of its own keys; see `place_in_first_groups`.
+        unsafe impl Send for SharedCtrl {}
+        unsafe impl Sync for SharedCtrl {}
+        impl SharedCtrl {
+            fn get(self) -> NonNull<u8> {
+                self.0
+            }
+        }
+
+        let mut table = Self::with_capacity(pairs.len().max(Group::WIDTH));
+        let seed = table.seed;
+        let aligned_bucket_mask = table.aligned_bucket_mask;
+        let pos0 = move |key: u64| FoldHashFast.hash64(key, seed) as usize & aligned_bucket_mask;
+        // Stable sort, so duplicates of a key stay adjacent and in their original order.
+        pairs.par_sort_by_key(|&(key, _)| (pos0(key), key));
+
+        let num_groups = table.num_buckets() / Group::WIDTH;
+        let num_shards = (rayon::current_num_threads() * 4).next_power_of_two().min(num_groups);
+        let shard_buckets = table.num_buckets() / num_shards;
+        let mut shards = Vec::with_capacity(num_shards);
+        let mut rest = &pairs[..];
+        for shard in 1..=num_shards {
+            let end = rest.partition_point(|&(key, _)| pos0(key) < shard * shard_buckets);
+            let (head, tail) = rest.split_at(end);
+            shards.push(head);
+            rest = tail;
+        }
+
+        let ctrl = SharedCtrl(table.ctrl);
+        let results: Vec<(usize, Vec<(u64, V)>)> = shards
+            .into_par_iter()
+            .map(|shard| {
+                let mut overflow = Vec::new();
+                let placed = unsafe {
+                    Self::place_in_first_groups(
+                        ctrl.get(),
+                        aligned_bucket_mask,
+                        seed,
+                        &FoldHashFast,
+                        shard,
+                        &mut overflow,
+                    )
+                };
+                (placed, overflow)
+            })
+            .collect();
+
+        for (placed, overflow) in results {
+            table.items_until_growth -= placed;
+            // Groups that overflowed go through the normal path (second group, then BFS).
+            for (key, value) in overflow {
+                let _ = table.insert(key, value);
+            }
+        }
+        table
+    }
+}
+
+/// An empty table that doesn't allocate until the first `insert`, which allocates what `new`
+/// would.
+impl<V: Copy, H: Hash64 + Default, const W: usize, const D: usize> Default for HashTable<V, H, W, D> {
+    fn default() -> Self {
+        const { assert!(W.is_power_of_two() && W <= Group::WIDTH && D >= 1) };
+        Self {
+            aligned_bucket_mask: 0,
+            ctrl: NonNull::from(&UNALLOCATED_CTRL.tags).cast(),
+            // Zero, so the first insert goes through `grow`.
+            items_until_growth: 0,
+            seed: fastrand::Rng::with_seed(123).u64(..),
+            hasher: H::default(),
+            double_hashing: false,
+            early_return: false,
+            load_both_groups: false,
+            less_loaded: false,
+            grow_factor: (2, 1),
+            full_load: false,
+            shrink_on_remove: false,
+            count_tag_false_positives: false,
+            tag_false_positives: 0,
+            lookup_cache: false,
+            last_lookup: None,
+            lookup_cache_hits: 0,
+            marker: core::marker::PhantomData,
+            total_probe_length: 0,
+            total_insert_probe_length: 0,
+            max_insert_probe_length: 0,
+            insert_probes_past_first_group: 0,
+            #[cfg(debug_assertions)]
+            tombstones: 0,
+        }
+    }
+}
+
+impl<V: Copy, H: Hash64, const W: usize, const D: usize> HashTable<V, H, W, D> {
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: H) -> Self {
+        Self::with_num_buckets_and_hasher(capacity_to_buckets(capacity), hasher)
+    }
+
+    fn with_num_buckets_and_hasher(num_buckets: usize, hasher: H) -> Self {
+        const { assert!(W.is_power_of_two() && W <= Group::WIDTH) };
+        let ctrl = Self::alloc_ctrl(num_buckets);
         let seed = fastrand::Rng::with_seed(123).u64(..);
-        let bucket_mask = num_buckets - 1;
-        let aligned_bucket_mask = num_buckets - Group::WIDTH;
+        let aligned_bucket_mask = num_buckets - W;
         let items_until_growth = (num_buckets * 7) / 8;
         // println!("num_buckets = {}, items_until_growth = {}", num_buckets, items_until_growth);
 
@@ -63,37 +346,546 @@ impl<V: Copy> HashTable<V> {
             ctrl,
             items_until_growth,
             seed,
-            marker: std::marker::PhantomData,
+            hasher,
+            double_hashing: false,
+            early_return: false,
+            load_both_groups: false,
+            less_loaded: false,
+            grow_factor: (2, 1),
+            full_load: false,
+            shrink_on_remove: false,
+            count_tag_false_positives: false,
+            tag_false_positives: 0,
+            lookup_cache: false,
+            last_lookup: None,
+            lookup_cache_hits: 0,
+            marker: core::marker::PhantomData,
             total_probe_length: 0,
             total_insert_probe_length: 0,
             max_insert_probe_length: 0,
+            insert_probes_past_first_group: 0,
+            #[cfg(debug_assertions)]
+            tombstones: 0,
+        }
+    }
+
+    /// Allocates a table of `num_buckets` with every control byte `EMPTY`.
+    fn alloc_ctrl(num_buckets: usize) -> NonNull<u8> {
+        infallible(Self::try_alloc_ctrl(num_buckets))
+    }
+
+    /// As `alloc_ctrl`, but fails instead of panicking.
+    fn try_alloc_ctrl(num_buckets: usize) -> Result<NonNull<u8>, TryReserveError> {
+        let (layout, ctrl_offset) = Self::try_layout(num_buckets)?;
+        // Allocate
+        let alloc = unsafe { alloc::alloc::alloc(layout) };
+        if alloc.is_null() {
+            return Err(TryReserveError::AllocError { layout });
+        }
+        // Write control, including the padding
+        let ctrl = unsafe { NonNull::new_unchecked(alloc.add(ctrl_offset)) };
+        let ctrl_slice = unsafe {
+            core::slice::from_raw_parts_mut(ctrl.as_ptr() as *mut Tag, num_buckets + Self::CTRL_PADDING)
+        };
+        ctrl_slice.fill_empty();
+        Ok(ctrl)
+    }
+
+    /// Control bytes past the last group, so that loading a whole SIMD group at the last `W`-slot
+    /// group stays in bounds.
+    const CTRL_PADDING: usize = Group::WIDTH - W;
+
+    /// Layout of a table with `num_buckets`, and the offset of `ctrl` within it.
+    #[inline(always)]
+    fn layout(num_buckets: usize) -> (Layout, usize) {
+        Self::try_layout(num_buckets).uunwrap()
+    }
+
+    #[inline(always)]
+    fn try_layout(num_buckets: usize) -> Result<(Layout, usize), TryReserveError> {
+        let bucket_size = core::mem::size_of::<(u64, V)>();
+        let align = core::mem::align_of::<(u64, V)>().max(Group::WIDTH);
+        let overflow = TryReserveError::CapacityOverflow;
+        let ctrl_offset = bucket_size
+            .checked_mul(num_buckets)
+            .and_then(|size| size.checked_next_multiple_of(align))
+            .ok_or(overflow)?;
+        let size = ctrl_offset
+            .checked_add(num_buckets + Self::CTRL_PADDING)
+            .ok_or(overflow)?;
+        let layout = Layout::from_size_align(size, align).map_err(|_| overflow)?;
+        Ok((layout, ctrl_offset))
+    }
+
+    /// Whether this is a `Default` table that hasn't allocated yet.
+    #[inline(always)]
+    fn is_unallocated(&self) -> bool {
+        core::ptr::eq(self.ctrl.as_ptr(), UNALLOCATED_CTRL.tags.as_ptr().cast())
+    }
+
+    fn bulk_place(&mut self, pairs: &mut [(u64, V)]) {
+        // Stable sort, so duplicates of a key stay adjacent and in their original order.
+        pairs.sort_by_key(|&(key, _)| {
+            (self.hasher.hash64(key, self.seed) as usize & self.aligned_bucket_mask, key)
+        });
+
+        let mut overflow = Vec::new();
+        let placed = unsafe {
+            Self::place_in_first_groups(
+                self.ctrl,
+                self.aligned_bucket_mask,
+                self.seed,
+                &self.hasher,
+                pairs,
+                &mut overflow,
+            )
+        };
+        self.items_until_growth -= placed;
+
+        // Groups that overflowed go through the normal path (second group, then BFS).
+        for (key, value) in overflow {
+            let _ = self.insert(key, value);
+        }
+    }
+
+    /// Greedy pass of `bulk_place`: walks `pairs` (sorted by first group, then key) filling each
+    /// group with its own keys. Keys whose group is already full are pushed to `overflow`.
+    /// Returns the number of slots filled.
+    ///
+    /// # Safety
+    ///
+    /// `ctrl` and `aligned_bucket_mask` must describe a live table. Only the first groups of keys
+    /// in `pairs` are touched, so calls on pairs with disjoint first groups may run concurrently.
+    unsafe fn place_in_first_groups(
+        ctrl: NonNull<u8>,
+        aligned_bucket_mask: usize,
+        seed: u64,
+        hasher: &H,
+        pairs: &[(u64, V)],
+        overflow: &mut Vec<(u64, V)>,
+    ) -> usize {
+        let mut placed = 0;
+        let mut last_placed: Option<(u64, usize)> = None;
+        for &(key, value) in pairs.iter() {
+            if let Some((last_key, index)) = last_placed
+                && last_key == key
+            {
+                (*Self::bucket_static(ctrl, index)).1 = value;
+                continue;
+            }
+            let hash0 = hasher.hash64(key, seed);
+            let pos0 = hash0 as usize & aligned_bucket_mask;
+            let group0 = GroupW::<W>::load(Self::ctrl_static(ctrl, pos0));
+            match group0.match_empty().lowest_set_bit() {
+                Some(bit) => {
+                    let index = pos0 + bit;
+                    Self::bucket_static(ctrl, index).write((key, value));
+                    *Self::ctrl_static(ctrl, index) = Tag::full(hash0);
+                    placed += 1;
+                    last_placed = Some((key, index));
+                }
+                None => overflow.push((key, value)),
+            }
+        }
+        placed
+    }
+
+    /// Derives the second group from the high half of the hash, `(hash0 >> 32) * C`, instead of
+    /// XOR-ing in a scrambled tag. The two choices are then independent of each other rather than
+    /// differing by one of 128 fixed offsets, at the cost of rehashing keys during the cuckoo BFS.
+    ///
+    /// Must be set before anything is inserted.
+    pub fn with_double_hashing(mut self, double_hashing: bool) -> Self {
+        assert!(self.is_empty(), "second-group derivation can't change once keys are placed");
+        self.double_hashing = double_hashing;
+        self
+    }
+
+    /// Makes `get` and `probe_length` give up after the first group if it has an empty slot,
+    /// instead of always checking both groups.
+    ///
+    /// Off by default:
+    /// * early return has ~no impact on find_hit, since we will have found the key anyway.
+    /// * early return *slows down* in-cache find_miss, perhaps simply from time spent checking
+    ///   for empty slots.
+    /// * early return prevents deletions from working: a key only goes to its second group when
+    ///   its first group is full, and erasing from (or rebucketing) the first group breaks that.
+    ///
+    /// So only enable it for tables that are built at their final size and never erased from.
+    /// With it disabled, we can instead do "less-loaded" cuckoo insertions; see
+    /// `with_less_loaded_insertion`.
+    pub fn with_early_return(mut self, early_return: bool) -> Self {
+        assert!(!(early_return && self.less_loaded), "early return needs keys in their first group when it has room");
+        assert!(!(early_return && self.load_both_groups), "early return only loads the second group when needed");
+        self.early_return = early_return;
+        self
+    }
+
+    /// Makes `get` load both of the key's groups before matching tags in either, so that out of
+    /// cache both cache-line misses are in flight at once, rather than resolving the first group
+    /// before touching the second. Can't be combined with `with_early_return`, which exists to
+    /// skip the second group.
+    ///
+    /// Off by default: with 2^20 buckets it made find_miss 1.2-2.3x faster from 25% to 87.5%
+    /// load, but find_hit anywhere from a little faster to 30% slower, since most hits end in
+    /// the first group and now also wait on the second. At 2^25 buckets both were noisy either
+    /// way.
+    pub fn with_both_groups_loaded(mut self, load_both_groups: bool) -> Self {
+        assert!(!(load_both_groups && self.early_return), "early return only loads the second group when needed");
+        self.load_both_groups = load_both_groups;
+        self
+    }
+
+    /// Makes `insert` put a new key in whichever of its two groups has more empty slots (the
+    /// first on a tie), rather than in the first group whenever it has room. This keeps group
+    /// loads even, so fewer inserts at high load find both groups full and fall back to the
+    /// cuckoo BFS. The cost is more keys living in their second group: at 7/8 load with 16-slot
+    /// groups, about a third of present-key lookups need both groups, against ~6% without it.
+    ///
+    /// Off by default. Insert always loads both groups when it is on, and it can't be combined
+    /// with `with_early_return`, since a key may sit in its second group while its first has room.
+    pub fn with_less_loaded_insertion(mut self, less_loaded: bool) -> Self {
+        assert!(!(less_loaded && self.early_return), "early return needs keys in their first group when it has room");
+        self.less_loaded = less_loaded;
+        self
+    }
+
+    /// Makes `get` count the slots whose tag matched but whose key didn't, readable with
+    /// `tag_false_positives`. This is the key comparisons the 7-bit tag fails to filter out, and
+    /// what `aligned_cuckoo_tag16_table`'s 16-bit tags cut. Off by default; the check only runs
+    /// after a key comparison fails.
+    pub fn with_tag_false_positive_counting(mut self, enabled: bool) -> Self {
+        self.count_tag_false_positives = enabled;
+        self
+    }
+
+    /// Slots `get` compared because their tag matched, but whose key differed, since the table
+    /// was built. Always 0 unless enabled with `with_tag_false_positive_counting`.
+    pub fn tag_false_positives(&self) -> usize {
+        self.tag_false_positives
+    }
+
+    /// Makes `get` remember the slot of its last hit and answer a repeated lookup of that key
+    /// from it, without hashing or loading any control bytes; for workloads that look the same
+    /// key up several times in a row, such as get-then-update. Hits are readable with
+    /// `lookup_cache_hits`. Off by default; a disabled cache costs `get` one predictable branch.
+    pub fn with_lookup_cache(mut self, enabled: bool) -> Self {
+        self.lookup_cache = enabled;
+        self.last_lookup = None;
+        self
+    }
+
+    /// Lookups `get` answered from the cached slot since the table was built. Always 0 unless
+    /// enabled with `with_lookup_cache`.
+    pub fn lookup_cache_hits(&self) -> usize {
+        self.lookup_cache_hits
+    }
+
+    /// Sets how far the table grows once it is full: to `len() * numerator / denominator`
+    /// items, at the 7/8 maximum load. The default is `(2, 1)`.
+    ///
+    /// Bucket counts stay powers of two and growth splits groups one doubling at a time, so any
+    /// factor up to 2 is a single `rebucket` and the table never ends up with more than twice the
+    /// buckets it needs; larger factors grow further in one go.
+    pub fn with_grow_factor(mut self, numerator: usize, denominator: usize) -> Self {
+        assert!(denominator > 0, "grow factor denominator must be nonzero");
+        assert!(numerator > denominator, "grow factor must be greater than 1");
+        assert!(try_grown_num_buckets(1, 1, (numerator, denominator)).is_ok(), "grow factor overflows");
+        self.grow_factor = (numerator, denominator);
+        self
+    }
+
+    /// Moves the growth threshold from 7/8 load to every slot, so that `try_insert` only fails
+    /// once the cuckoo BFS finds no room, and `insert` only grows then. This is how
+    /// `PEAK_LOAD_FACTOR` is measured; at the default threshold 16-slot groups never get that
+    /// far.
+    ///
+    /// Must be set before anything is inserted. Off by default.
+    pub fn with_full_load(mut self, full_load: bool) -> Self {
+        assert!(self.is_empty(), "the growth threshold can't change once keys are placed");
+        self.full_load = full_load;
+        if !self.is_unallocated() {
+            self.items_until_growth = self.item_capacity();
+        }
+        self
+    }
+
+    /// Makes `EntryHandle::remove` halve the table, rebuilding it, whenever a removal leaves
+    /// `len()` below a quarter of the capacity, so that a long-lived table doesn't hold on to its
+    /// peak size. Halving then leaves it under half full, well clear of growing again. A shrink
+    /// moves every entry, so indices from earlier inserts go stale.
+    ///
+    /// Off by default.
+    pub fn with_shrink_on_remove(mut self, enabled: bool) -> Self {
+        self.shrink_on_remove = enabled;
+        self
+    }
+
+    /// Halves the table if it is below a quarter full; see `with_shrink_on_remove`.
+    #[inline(always)]
+    fn shrink_if_sparse(&mut self) {
+        let capacity = self.item_capacity();
+        if core::hint::unlikely(self.len() < capacity / 4 && self.num_buckets() > MIN_NUM_BUCKETS) {
+            self.shrink_half();
+        }
+    }
+
+    #[inline(never)]
+    #[cold]
+    fn shrink_half(&mut self) {
+        self.rebuild(self.num_buckets() / 2, self.seed);
+    }
+
+    /// Rebuckets until the table reaches the size chosen by `grow_factor`.
+    #[inline(never)]
+    #[cold]
+    fn grow(&mut self) {
+        if self.is_unallocated() {
+            infallible(self.try_allocate(INITIAL_CAPACITY));
+            return;
+        }
+        let target_num_buckets = grown_num_buckets(self.len(), self.num_buckets(), self.grow_factor);
+        self.rebucket_to(target_num_buckets);
+    }
+
+    /// Doubles the number of buckets without reinserting anything: doubling adds one bit to the
+    /// group index, so each old group splits into the two groups at the same position in each
+    /// half, and every entry moves to whichever its hash's new bit picks, with no BFS. This is
+    /// how the table grows on its own; call it to grow ahead of time, off the hot path.
+    ///
+    /// On full tables from 2^10 to 2^25 buckets it measured 1.3-2.8x faster than reinserting
+    /// every entry into a table twice the size, the gap widening out of cache.
+    pub fn grow_double(&mut self) {
+        if self.is_unallocated() {
+            self.grow();
+            return;
+        }
+        self.rebucket_to(self.num_buckets() * 2);
+    }
+
+    /// Makes sure `additional` more keys can be inserted without growing.
+    pub fn reserve(&mut self, additional: usize) {
+        infallible(self.try_reserve(additional));
+    }
+
+    /// As `reserve`, but returns an error instead of panicking when the new size overflows or
+    /// the allocator fails.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        if additional > self.items_until_growth {
+            self.try_reserve_rehash(additional)
+        } else {
+            Ok(())
+        }
+    }
+
+    #[inline(never)]
+    #[cold]
+    fn try_reserve_rehash(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let capacity = self.len().checked_add(additional).ok_or(TryReserveError::CapacityOverflow)?;
+        if self.is_unallocated() {
+            return self.try_allocate(capacity.max(INITIAL_CAPACITY));
+        }
+        // One allocation of the final size rather than a `rebucket` per doubling, so that
+        // failure leaves the table as it was.
+        self.try_rebuild(try_capacity_to_buckets(capacity)?, self.seed)
+    }
+
+    /// Shrinks the table to the fewest buckets that hold both `len()` and `min_capacity` items
+    /// at the 7/8 maximum load, rebuilding it. Does nothing if that isn't fewer buckets than now.
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        let num_buckets = capacity_to_buckets(min_capacity.max(self.len()));
+        if num_buckets < self.num_buckets() {
+            self.rebuild(num_buckets, self.seed);
+        }
+    }
+
+    /// Shrinks the table as far as `len()` allows; see `shrink_to`.
+    pub fn shrink_to_fit(&mut self) {
+        self.shrink_to(0);
+    }
+
+    /// Allocates the first buckets of a `Default` table, for `capacity` items.
+    fn try_allocate(&mut self, capacity: usize) -> Result<(), TryReserveError> {
+        let num_buckets = try_capacity_to_buckets(capacity)?;
+        self.ctrl = Self::try_alloc_ctrl(num_buckets)?;
+        self.aligned_bucket_mask = num_buckets - W;
+        self.items_until_growth = self.item_capacity();
+        Ok(())
+    }
+
+    /// Doubles the table until it has at least `target_num_buckets` buckets.
+    fn rebucket_to(&mut self, target_num_buckets: usize) {
+        let len = self.len();
+        self.last_lookup = None;
+        while self.num_buckets() < target_num_buckets {
+            self.ctrl = Self::rebucket(self.aligned_bucket_mask, self.ctrl, self.seed, &self.hasher, self.double_hashing);
+            self.aligned_bucket_mask = self.aligned_bucket_mask | (self.aligned_bucket_mask << 1);
+        }
+        self.items_until_growth = self.item_capacity() - len;
+        self.debug_assert_len();
+    }
+
+    /// Rebuilds the table into a fresh allocation of the same size, hashing with `seed`. Use it
+    /// to recover from an insert that fails with `InsertError::NeedsRehash`, or from keys chosen
+    /// to collide under the current seed.
+    ///
+    /// If some key finds no slot under the new seed either, the table grows rather than failing.
+    pub fn reset_with_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        if self.is_unallocated() {
+            return;
+        }
+        self.rebuild(self.num_buckets(), seed);
+    }
+
+    /// Rebuilds the table with a fresh random seed, as `reset_with_seed`, if
+    /// `avg_insert_probe_length` is above `threshold`. Keys chosen to collide under the current seed push inserts into the BFS, so
+    /// checking this periodically defends against them without waiting for an insert to fail.
+    #[cfg(feature = "std")]
+    pub fn maybe_reseed(&mut self, threshold: f64) {
+        if self.avg_insert_probe_length() > threshold {
+            self.reset_with_seed(fastrand::u64(..));
+        }
+    }
+
+    /// Reinserts every entry into a fresh allocation of `new_num_buckets`, hashing with
+    /// `new_seed`. This is the one place entries are reinserted, for `reserve`, `shrink_to` and
+    /// `reset_with_seed`; growing splits groups in place instead (see `grow_double`), and `clone`
+    /// copies the allocation as is.
+    fn rebuild(&mut self, new_num_buckets: usize, new_seed: u64) {
+        infallible(self.try_rebuild(new_num_buckets, new_seed));
+    }
+
+    /// As `rebuild`, but fails, leaving the table unchanged, if the allocation does.
+    ///
+    /// If some key finds no slot, the rebuild starts over at twice the size from the old
+    /// allocation, which stays untouched until every entry has been placed.
+    fn try_rebuild(&mut self, mut num_buckets: usize, seed: u64) -> Result<(), TryReserveError> {
+        let old_num_buckets = self.num_buckets();
+        let old_ctrl = self.ctrl;
+        let restore = (self.aligned_bucket_mask, self.items_until_growth, self.seed, self.last_lookup, self.insert_probes_past_first_group);
+        let result = loop {
+            let new_ctrl = match Self::try_alloc_ctrl(num_buckets) {
+                Ok(new_ctrl) => new_ctrl,
+                Err(err) => break Err(err),
+            };
+            self.ctrl = new_ctrl;
+            self.seed = seed;
+            self.aligned_bucket_mask = num_buckets - W;
+            self.items_until_growth = self.item_capacity();
+            self.last_lookup = None;
+            // Recounted by the reinsertions below, so it describes the new layout.
+            self.insert_probes_past_first_group = 0;
+            if self.reinsert_from(old_ctrl, old_num_buckets) {
+                break Ok(());
+            }
+            unsafe { Self::dealloc(new_ctrl, num_buckets) };
+            match num_buckets.checked_mul(2) {
+                Some(doubled) => num_buckets = doubled,
+                None => break Err(TryReserveError::CapacityOverflow),
+            }
+        };
+        if let Err(err) = result {
+            self.ctrl = old_ctrl;
+            (self.aligned_bucket_mask, self.items_until_growth, self.seed, self.last_lookup, self.insert_probes_past_first_group) = restore;
+            return Err(err);
+        }
+        #[cfg(debug_assertions)]
+        {
+            self.tombstones = 0;
+        }
+        unsafe { Self::dealloc(old_ctrl, old_num_buckets) };
+        self.debug_assert_len();
+        Ok(())
+    }
+
+    /// Inserts every entry of the allocation `old_ctrl` of `old_num_buckets` into this table.
+    /// Returns false as soon as one finds no slot or the table reaches its growth threshold;
+    /// `old_ctrl` is only read.
+    fn reinsert_from(&mut self, old_ctrl: NonNull<u8>, old_num_buckets: usize) -> bool {
+        for pos in (0..old_num_buckets).step_by(W) {
+            let full = unsafe { GroupW::<W>::load(Self::ctrl_static(old_ctrl, pos)) }.match_full();
+            for bit in full {
+                let (key, value) = unsafe { *Self::bucket_static(old_ctrl, pos + bit) };
+                if self.items_until_growth == 0 || self.insert_unique_no_grow(key, value).is_err() {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Copies the whole allocation of a table with `num_buckets`, buckets and control bytes,
+    /// from `src` to `dst`.
+    unsafe fn copy_allocation(src: NonNull<u8>, dst: NonNull<u8>, num_buckets: usize) {
+        let (layout, ctrl_offset) = Self::layout(num_buckets);
+        core::ptr::copy_nonoverlapping(src.as_ptr().sub(ctrl_offset), dst.as_ptr().sub(ctrl_offset), layout.size());
+    }
+
+    /// A copy of `self`'s fields, pointing at `ctrl` instead of `self`'s allocation.
+    fn clone_with_ctrl(&self, ctrl: NonNull<u8>) -> Self
+    where
+        H: Clone,
+    {
+        Self {
+            aligned_bucket_mask: self.aligned_bucket_mask,
+            ctrl,
+            items_until_growth: self.items_until_growth,
+            seed: self.seed,
+            hasher: self.hasher.clone(),
+            double_hashing: self.double_hashing,
+            early_return: self.early_return,
+            load_both_groups: self.load_both_groups,
+            less_loaded: self.less_loaded,
+            grow_factor: self.grow_factor,
+            full_load: self.full_load,
+            shrink_on_remove: self.shrink_on_remove,
+            count_tag_false_positives: self.count_tag_false_positives,
+            tag_false_positives: self.tag_false_positives,
+            lookup_cache: self.lookup_cache,
+            // The clone has the same layout, so the cached slot holds the key there too.
+            last_lookup: self.last_lookup,
+            lookup_cache_hits: self.lookup_cache_hits,
+            marker: core::marker::PhantomData,
+            total_probe_length: self.total_probe_length,
+            total_insert_probe_length: self.total_insert_probe_length,
+            max_insert_probe_length: self.max_insert_probe_length,
+            insert_probes_past_first_group: self.insert_probes_past_first_group,
+            #[cfg(debug_assertions)]
+            tombstones: self.tombstones,
         }
     }
 
     #[inline(always)]
     unsafe fn dealloc(ctrl: NonNull<u8>, num_buckets: usize) {
-        let align = std::mem::align_of::<(u64, V)>().max(Group::WIDTH);
-        let bucket_size = std::mem::size_of::<(u64, V)>();
-        let ctrl_offset = (bucket_size * num_buckets).next_multiple_of(align);
-        let size = ctrl_offset + num_buckets;
-        let layout = Layout::from_size_align(size, align).uunwrap();
-        unsafe { std::alloc::dealloc(ctrl.as_ptr().sub(ctrl_offset), layout) };
+        let (layout, ctrl_offset) = Self::layout(num_buckets);
+        unsafe { alloc::alloc::dealloc(ctrl.as_ptr().sub(ctrl_offset), layout) };
     }
 
+    /// Items the table holds at its growth threshold.
     #[inline(always)]
-    fn item_capacity(aligned_bucket_mask: usize) -> usize {
-        ((aligned_bucket_mask + Group::WIDTH) * 7) / 8
+    fn item_capacity(&self) -> usize {
+        let num_buckets = self.aligned_bucket_mask + W;
+        if self.full_load { num_buckets } else { (num_buckets * 7) / 8 }
     }
 
     // Item capacity when doubling:
-    //    (((new_aligned_bucket_mask + Group::WIDTH) * 7) / 8) - (((old_aligned_bucket_mask + Group::WIDTH) * 7) / 8)
+    //    (((new_aligned_bucket_mask + W) * 7) / 8) - (((old_aligned_bucket_mask + W) * 7) / 8)
     // =  (new_aligned_bucket_mask - old_aligned_bucket_mask) * 7 / 8
 
-    /// Safety: caller promises that there have been no tombstones in the table.
+    /// Inserts `key` and, if it was new, immediately erases it again. This is the unit of work of
+    /// the insert+erase churn benchmark.
+    ///
+    /// # Safety
+    ///
+    /// Caller promises that there have been no tombstones in the table. A tombstone is the hole
+    /// `erase_index` leaves: `insert` fills a first group with a free slot without checking the
+    /// second group, so it can duplicate a key sitting there. Debug builds check this.
     #[inline(always)]
     pub unsafe fn insert_and_erase(&mut self, key: u64, value: V) {
-        let (inserted, index, _) = self.insert(key, value);
-        if inserted {
+        #[cfg(debug_assertions)]
+        assert!(self.tombstones == 0, "insert_and_erase called on a table with tombstones");
+        if let Inserted::New { index } = self.insert(key, value) {
             unsafe {
                 self.set_ctrl(index, Tag::EMPTY);
             }
@@ -106,6 +898,13 @@ impl<V: Copy> HashTable<V> {
         self.total_probe_length as f64 / self.len() as f64
     }
 
+    /// Average groups probed per insert since the last full rehash, counting the cuckoo BFS
+    /// levels; see `maybe_reseed`. NaN for an empty table.
+    #[inline(always)]
+    pub fn avg_insert_probe_length(&self) -> f64 {
+        1.0 + self.insert_probes_past_first_group as f64 / self.len() as f64
+    }
+    #[cfg(feature = "std")]
     #[inline(always)]
     pub fn print_stats(&self) {
         let items = self.len() as f64;
@@ -121,17 +920,55 @@ impl<V: Copy> HashTable<V> {
             "  max_insert_probe_length: {}",
             self.max_insert_probe_length
         );
+        if self.count_tag_false_positives {
+            println!("  tag_false_positives: {}", self.tag_false_positives);
+        }
     }
 
     #[inline(always)]
     pub fn len(&self) -> usize {
-        Self::item_capacity(self.aligned_bucket_mask) - self.items_until_growth
+        if self.is_unallocated() {
+            return 0;
+        }
+        self.item_capacity() - self.items_until_growth
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The `H` hash of `key`. Its low bits pick the first group and its top 7 bits the tag; the
+    /// second group comes from both, as in `second_hash`.
+    #[inline(always)]
+    pub fn hash_of(&self, key: u64) -> u64 {
+        self.hasher.hash64(key, self.seed)
     }
 
     /// Returns the number of buckets in the table.
     #[inline(always)]
     pub fn num_buckets(&self) -> usize {
-        self.aligned_bucket_mask + Group::WIDTH
+        if self.is_unallocated() {
+            return 0;
+        }
+        self.aligned_bucket_mask + W
+    }
+
+    /// Fraction of slots that are full; 0 for an unallocated table.
+    pub fn load_factor(&self) -> f64 {
+        if self.is_unallocated() {
+            return 0.0;
+        }
+        self.len() as f64 / self.num_buckets() as f64
+    }
+
+    /// Bytes of the table's heap allocation: the `(u64, V)` buckets, padded to their alignment,
+    /// plus the control bytes. 0 for a `Default` table that hasn't allocated yet.
+    pub fn heap_bytes(&self) -> usize {
+        if self.is_unallocated() {
+            return 0;
+        }
+        Self::layout(self.num_buckets()).0.size()
     }
 
     /// Doubles the table size and redistributes all elements using the efficient
@@ -139,26 +976,18 @@ impl<V: Copy> HashTable<V> {
     #[inline(never)]
     #[cold]
     // extern "rust-cold"
-    fn rebucket(old_aligned_bucket_mask: usize, old_ctrl: NonNull<u8>, seed: u64) -> NonNull<u8> {
-        let old_num_buckets = old_aligned_bucket_mask + Group::WIDTH;
+    fn rebucket(
+        old_aligned_bucket_mask: usize,
+        old_ctrl: NonNull<u8>,
+        seed: u64,
+        hasher: &H,
+        double_hashing: bool,
+    ) -> NonNull<u8> {
+        let old_num_buckets = old_aligned_bucket_mask + W;
         let new_num_buckets = old_num_buckets * 2;
         // println!("rebucket {}->{}", old_num_buckets, new_num_buckets);
-        let old_items = 1 + ((old_num_buckets * 7) / 8);
-        let items_until_growth = (1 + ((new_num_buckets * 7) / 8)) - old_items;
-
-        // Calculate new layout
-        let bucket_size = std::mem::size_of::<(u64, V)>();
-        let align = std::mem::align_of::<(u64, V)>().max(Group::WIDTH);
-        let new_ctrl_offset = (bucket_size * new_num_buckets).next_multiple_of(align);
-        let new_size = new_ctrl_offset + new_num_buckets;
-        let new_layout = Layout::from_size_align(new_size, align).uunwrap();
-
         // Allocate new table
-        let new_alloc = unsafe { std::alloc::alloc(new_layout) };
-        let new_ctrl = unsafe { NonNull::new_unchecked(new_alloc.add(new_ctrl_offset)) };
-        let new_ctrl_slice =
-            unsafe { std::slice::from_raw_parts_mut(new_ctrl.as_ptr() as *mut Tag, new_num_buckets) };
-        new_ctrl_slice.fill_empty();  // TODO: optimize
+        let new_ctrl = Self::alloc_ctrl(new_num_buckets); // TODO: optimize the EMPTY fill
 
         // Helper to access buckets in new table
         let new_bucket = |index: usize| -> *mut (u64, V) {
@@ -172,19 +1001,15 @@ impl<V: Copy> HashTable<V> {
 
         // Efficient group-based rebucketing for aligned cuckoo tables.
         // Process each group in the old table and split elements into two child groups.
-        let new_aligned_bucket_mask = new_num_buckets - Group::WIDTH;
-        let old_num_groups = old_num_buckets / Group::WIDTH;
+        let old_num_groups = old_num_buckets / W;
 
-        let new_bucket_mask = new_num_buckets - 1;
-        let new_aligned_bucket_mask = new_num_buckets - Group::WIDTH;
+        let new_aligned_bucket_mask = new_num_buckets - W;
         let new_bit = new_aligned_bucket_mask ^ old_aligned_bucket_mask;
         assert!(new_bit.count_ones() == 1);
 
         // println!("aligned_bucket_mask = 0b{:b}, new_aligned_bucket_mask = 0b{:b}, new_bit = 0b{:b}", self.aligned_bucket_mask, new_aligned_bucket_mask, new_bit);
-        let empties = unsafe { Group::load(Group::static_empty().as_ptr()) };
-
         for old_group_idx in 0..old_num_groups {
-            let old_group_base = old_group_idx * Group::WIDTH;
+            let old_group_base = old_group_idx * W;
 
             // The two child groups for this parent group:
             // Child A: same position in new table
@@ -192,29 +1017,24 @@ impl<V: Copy> HashTable<V> {
             let mut child_a_pos = old_group_base;
             let mut child_b_pos = old_group_base + old_num_buckets;
 
-            unsafe {
-                Group::store_aligned(empties, Self::ctrl_static(new_ctrl, child_a_pos));
-                Group::store_aligned(empties, Self::ctrl_static(new_ctrl, child_b_pos));
-            }
-
-
-            let num_full = unsafe { Group::load(Self::ctrl_static(old_ctrl, old_group_base)) }.match_empty().lowest_set_bit().unwrap_or(Group::WIDTH);
+            // Not just the slots before the first empty one: erasing leaves holes.
+            let full = unsafe { GroupW::<W>::load(Self::ctrl_static(old_ctrl, old_group_base)) }.match_full();
 
             // Process each slot in the parent group
-            for offset in 0..num_full {
+            for offset in full {
                 let old_idx = old_group_base + offset;
                 let tag = unsafe { *Self::ctrl_static(old_ctrl, old_idx) };
 
                 let (key, value) = unsafe { *Self::bucket_static(old_ctrl, old_idx) };
 
                 // Determine which child group based on the new hash bit
-                let hash0 = fold_hash_fast(key, seed);
-                let hash1 = hash0 ^ scramble_tag(tag);
+                let hash0 = hasher.hash64(key, seed);
+                let hash1 = second_hash(double_hashing, hash0, tag);
                 let hash0_was_used = (hash0 as usize & old_aligned_bucket_mask) == old_group_base;
-                let hash = std::hint::select_unpredictable(hash0_was_used, hash0, hash1);
+                let hash = core::hint::select_unpredictable(hash0_was_used, hash0, hash1);
                 let goes_to_child_b = (hash as usize & new_bit) != 0;
 
-                let target_idx = std::hint::select_unpredictable(goes_to_child_b, child_b_pos, child_a_pos);
+                let target_idx = core::hint::select_unpredictable(goes_to_child_b, child_b_pos, child_a_pos);
                 unsafe {
                     new_bucket(target_idx).write((key, value));
                     *new_ctrl_ptr(target_idx) = tag;
@@ -224,35 +1044,142 @@ impl<V: Copy> HashTable<V> {
                 child_b_pos += goes_to_child_b as usize;
             }
 
-            // Both children should have at most Group::WIDTH elements
-            debug_assert!(child_a_pos <= old_group_base + Group::WIDTH);
-            debug_assert!(child_b_pos <= old_group_base + old_num_buckets + Group::WIDTH);
+            // Both children should have at most W elements
+            debug_assert!(child_a_pos <= old_group_base + W);
+            debug_assert!(child_b_pos <= old_group_base + old_num_buckets + W);
         }
         unsafe { Self::dealloc(old_ctrl, old_num_buckets) };
         new_ctrl
     }
 
+    /// Inserts `key`, or updates its value if it's in its first group or (if that group is
+    /// full) its second. Use `replace` to update a key wherever it is.
     #[inline(always)]
-    pub fn insert(&mut self, key: u64, value: V) -> (bool, usize, usize) {
+    pub fn insert(&mut self, key: u64, value: V) -> Inserted {
         const RUN_RESIZE_CHECK: bool = true;
-        if RUN_RESIZE_CHECK && std::hint::unlikely(self.items_until_growth == 0) {
-            self.ctrl = unsafe { Self::rebucket(self.aligned_bucket_mask, self.ctrl, self.seed) };
-            let old_aligned_bucket_mask = self.aligned_bucket_mask;
-            let new_aligned_bucket_mask = old_aligned_bucket_mask | (old_aligned_bucket_mask << 1);
-            self.items_until_growth = (new_aligned_bucket_mask - old_aligned_bucket_mask) * 7 / 8;
-            self.aligned_bucket_mask = new_aligned_bucket_mask;
-        }
-        let hash0 = fold_hash_fast(key, self.seed);
+        if RUN_RESIZE_CHECK && core::hint::unlikely(self.items_until_growth == 0) {
+            self.grow();
+        }
+        match self.insert_no_grow(key, value) {
+            Ok((true, index, _)) => Inserted::New { index },
+            Ok((false, index, _)) => Inserted::Updated { index },
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// As `insert`, for a key known not to be in the table; returns its slot index. When the
+    /// key's first group has an empty slot it goes there straight away, skipping the match scan
+    /// of both groups, which is the common case when bulk-loading distinct keys. Inserting a key
+    /// that is already present stores it twice.
+    ///
+    /// It hasn't measurably paid off: `insert` already fills a first group with room before
+    /// loading the second, so this only saves the first group's tag match, and building pre-sized
+    /// tables of random keys (2^10 to 2^25 buckets, 25% to 87.5% load) was within run-to-run
+    /// noise of `insert`.
+    #[inline(always)]
+    pub fn insert_unique(&mut self, key: u64, value: V) -> usize {
+        if core::hint::unlikely(self.items_until_growth == 0) {
+            self.grow();
+        }
+        match self.insert_unique_no_grow(key, value) {
+            Ok(index) => index,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Body of `insert_unique` after the growth check. Requires `items_until_growth > 0`.
+    #[inline(always)]
+    fn insert_unique_no_grow(&mut self, key: u64, value: V) -> Result<usize, InsertError> {
+        // Less-loaded insertion may prefer the second group even when the first has room.
+        if !self.less_loaded {
+            let hash0 = self.hasher.hash64(key, self.seed);
+            let pos0 = hash0 as usize & self.aligned_bucket_mask;
+            let group0 = unsafe { GroupW::<W>::load_aligned(self.ctrl(pos0)) };
+            if let Some(bit) = group0.match_empty().lowest_set_bit() {
+                let index = pos0 + bit;
+                self.items_until_growth -= 1;
+                unsafe {
+                    self.bucket(index).write((key, value));
+                    self.set_ctrl(index, Tag::full(hash0));
+                }
+                return Ok(index);
+            }
+        }
+        self.insert_no_grow(key, value).map(|(_, index, _)| index)
+    }
+
+    /// As `insert`, but never grows or panics: fails with `InsertError::NeedsGrowth` at the growth
+    /// threshold (unless `key` is already present) and with `InsertError::NeedsRehash` when the
+    /// BFS finds no free slot. The table is unchanged on failure.
+    #[inline(always)]
+    pub fn try_insert(&mut self, key: u64, value: V) -> Result<(bool, usize, usize), InsertError> {
+        self.insert_with_budget(key, value, usize::MAX)
+    }
+
+    /// As `try_insert`, but also fails, with `InsertError::BudgetExceeded`, once the cuckoo BFS
+    /// has visited `max_nodes` groups beyond the key's own two. This bounds the worst-case
+    /// insert latency; the caller can then grow or rehash off the hot path and retry.
+    #[inline(always)]
+    pub fn insert_with_budget(
+        &mut self,
+        key: u64,
+        value: V,
+        max_nodes: usize,
+    ) -> Result<(bool, usize, usize), InsertError> {
+        if core::hint::unlikely(self.items_until_growth == 0) {
+            return match self.find_index(key) {
+                Some(index) => {
+                    unsafe { (*self.bucket(index)).1 = value };
+                    Ok((false, index, self.probe_length(key).0))
+                }
+                None => Err(InsertError::NeedsGrowth),
+            };
+        }
+        self.insert_no_grow_tracked(key, value, max_nodes)
+            .map(|(inserted, index, probe_length, _)| (inserted, index, probe_length))
+    }
+
+    /// As `insert`, but also reports whether indices from earlier inserts went stale.
+    #[inline(always)]
+    pub fn insert_tracked(&mut self, key: u64, value: V) -> InsertResult {
+        let resized = core::hint::unlikely(self.items_until_growth == 0);
+        if resized {
+            self.grow();
+        }
+        match self.insert_no_grow_tracked(key, value, usize::MAX) {
+            Ok((inserted, index, _, relocated)) => InsertResult { inserted, index, relocated, resized },
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Body of `insert` after the growth check. Requires `items_until_growth > 0`.
+    #[inline(always)]
+    fn insert_no_grow(&mut self, key: u64, value: V) -> Result<(bool, usize, usize), InsertError> {
+        self.insert_no_grow_tracked(key, value, usize::MAX)
+            .map(|(inserted, index, probe_length, _)| (inserted, index, probe_length))
+    }
+
+    /// As `insert_no_grow`, plus whether the BFS moved existing entries. The BFS visits at most
+    /// `max_nodes` groups; see `insert_with_budget`.
+    #[inline(always)]
+    fn insert_no_grow_tracked(
+        &mut self,
+        key: u64,
+        value: V,
+        max_nodes: usize,
+    ) -> Result<(bool, usize, usize, bool), InsertError> {
+        let hash0 = self.hasher.hash64(key, self.seed);
         let tag_hash = Tag::full(hash0);
-        let hash1 = hash0 ^ scramble_tag(tag_hash);
+        let hash1 = second_hash(self.double_hashing, hash0, tag_hash);
         let mut insertion_probe_length = 1; // Start with 1 probe
+        let mut relocated = false;
 
         const EARLY_RETURN: bool = true;
 
         let (bucket, index) = 'hit: loop {
             let bucket_index = 'search_empty: loop {
                 let pos0 = hash0 as usize & self.aligned_bucket_mask;
-                let group0 = unsafe { Group::load(self.ctrl(pos0)) };
+                let group0 = unsafe { GroupW::<W>::load_aligned(self.ctrl(pos0)) };
 
                 // Probe first group for a match.
                 for bit in group0.match_tag(tag_hash) {
@@ -271,7 +1198,7 @@ impl<V: Copy> HashTable<V> {
                 // h1 fetch. Build/distinct-key only: skipping the h1 match-scan can duplicate a key
                 // already present in h1, so it is gated behind EARLY_RETURN (incorrect for
                 // insert-or-update semantics).
-                if EARLY_RETURN {
+                if EARLY_RETURN && !self.less_loaded {
                     if let Some(insert_slot) = group0.match_empty().lowest_set_bit() {
                         insertion_probe_length = 1;
                         break 'search_empty pos0 + insert_slot;
@@ -281,7 +1208,7 @@ impl<V: Copy> HashTable<V> {
                 // Probe second group for a match.
                 insertion_probe_length = 2; // If we reach here, we've probed 2 groups
                 let pos1 = hash1 as usize & self.aligned_bucket_mask;
-                let group1 = unsafe { Group::load(self.ctrl(pos1)) };
+                let group1 = unsafe { GroupW::<W>::load_aligned(self.ctrl(pos1)) };
 
                 for bit in group1.match_tag(tag_hash) {
                     let index = pos1 + bit;
@@ -294,6 +1221,15 @@ impl<V: Copy> HashTable<V> {
                 }
 
                 // Now search for (a path to) an empty slot.
+                if self.less_loaded {
+                    let (empty0, empty1) = (group0.match_empty(), group1.match_empty());
+                    if empty1.count_ones() > empty0.count_ones() {
+                        let insert_slot = pos1 + empty1.lowest_set_bit().uunwrap();
+                        insertion_probe_length = 2;
+                        self.insert_probes_past_first_group += 1;
+                        break 'search_empty insert_slot;
+                    }
+                }
                 if let Some(insert_slot) = group0.match_empty().lowest_set_bit() {
                     let insert_slot = pos0 + insert_slot;
                     insertion_probe_length = 1; // Found in first group
@@ -302,161 +1238,527 @@ impl<V: Copy> HashTable<V> {
                 if let Some(insert_slot) = group1.match_empty().lowest_set_bit() {
                     let insert_slot = pos1 + insert_slot;
                     insertion_probe_length = 2; // Found in second group
+                    self.insert_probes_past_first_group += 1;
                     break 'search_empty insert_slot;
                 }
 
-                // Cuckoo loop. BFS queue maintains group indexes to visit.
-                //
-                // We search two complete N-ary trees, where N=Group::WIDTH. We search up to depth D=3, i.e.
-                // 2 groups at the first level, 2*N, 2*N^2, 2*N^3.
-                //
-                // The parent of node at index `i` is at index `(i-2)/N`. Inversely, the first child of
-                // node `j` is at index `j*N+2`.
-                const N: usize = Group::WIDTH;
-                const BFS_MAX_LEN: usize = 2 * (1 + N + N * N + N * N * N);
-
-                let mut bfs_queue = [MaybeUninit::<usize>::uninit(); BFS_MAX_LEN];
-                bfs_queue[0].write(pos0);
-                bfs_queue[1].write(pos1);
-                let mut bfs_read_pos = 0;
-                let (mut path_index, mut bucket_index) = 'bfs: loop {
-                    let pos0 = unsafe { bfs_queue[bfs_read_pos + 0].assume_init() };
-
-                    let bfs_write_pos = bfs_read_pos * N + 2;
-                    if bfs_write_pos >= BFS_MAX_LEN {
-                        panic!();
-                        // panic!("Failed to insert into cuckoo table; need to rehash, items_until_growth = {}, items = {}, num_buckets = {}", self.items_until_growth, self.len(), self.num_buckets());
-                    }
-
-                    for i in 0..N {
-                        let other_pos0 = pos0
-                            ^ (scramble_tag(unsafe { *self.ctrl(pos0 + i) }) as usize
-                                & self.aligned_bucket_mask);
-                        let other_group0 = unsafe { Group::load(self.ctrl(other_pos0)) };
-                        let bfs_write_pos_i = bfs_write_pos + i;
-                        if let Some(empty_pos) = other_group0.match_empty().lowest_set_bit() {
-                            // Calculate insertion probe length based on BFS level
-                            insertion_probe_length = 2 + (bfs_write_pos_i - 2) / N;
-                            break 'bfs (bfs_write_pos_i, other_pos0 + empty_pos);
-                        }
-
-                        unsafe {
-                            *bfs_queue
-                                .get_unchecked_mut(bfs_write_pos_i)
-                                .write(other_pos0);
-                        }
-                    }
-
-                    bfs_read_pos += 1;
-                }; // 'bfs
-                while path_index >= 2 {
-                    let parent_path_index = (path_index - 2) / N;
-                    let parent_bucket_offset = (path_index - 2) % N;
-                    let parent_bucket_index =
-                        unsafe { bfs_queue.get_unchecked(parent_path_index).assume_init() }
-                            + parent_bucket_offset;
-
-                    // Move from parent to child.
-                    unsafe {
-                        let parent_kv = self.bucket(parent_bucket_index).read();
-                        self.bucket(bucket_index).write(parent_kv);
-                        self.set_ctrl(bucket_index, unsafe { *self.ctrl(parent_bucket_index) });
-                    }
-                    bucket_index = parent_bucket_index;
-                    path_index = parent_path_index;
-                }
+                // Both groups are full: search for a path to an empty slot.
+                let (bucket_index, probe_length) = self.insert_cold(pos0, pos1, max_nodes)?;
+                insertion_probe_length = probe_length;
+                self.insert_probes_past_first_group += probe_length - 1;
+                relocated = true;
+                self.last_lookup = None;
                 break 'search_empty bucket_index;
             }; // 'search_empty
 
+            // Counted only once a slot is found: every failure above returns before this, so a
+            // failed `try_insert` leaves `len()` as it was.
             self.items_until_growth -= 1;
             unsafe {
                 self.bucket(bucket_index).write((key, value));
                 self.set_ctrl(bucket_index, tag_hash);
             }
+            if relocated {
+                self.debug_assert_len();
+            }
             // println!("inserted key {} at bucket {} of {}. hash0 = {}, hash1 = {}", key, bucket_index, self.num_buckets(), hash0 as usize & self.aligned_bucket_mask, hash1 as usize & self.aligned_bucket_mask);
-            return (true, bucket_index, insertion_probe_length);
+            return Ok((true, bucket_index, insertion_probe_length, relocated));
         }; // 'hit
         unsafe { (*bucket).1 = value };
-        return (false, index, insertion_probe_length);
+        Ok((false, index, insertion_probe_length, relocated))
+    }
+
+    /// The cuckoo BFS of `insert`, for when both of the key's groups (at `pos0` and `pos1`) are
+    /// full. Moves entries along the shortest path to an empty slot and returns the slot it
+    /// freed in `pos0` or `pos1`, plus the insertion probe length. Gives up after visiting
+    /// `max_nodes` groups, leaving the table unchanged.
+    ///
+    /// Outlined so that the common case, a free slot in one of the two groups, stays small. On
+    /// `insert_and_erase` (2^12 to 2^22 buckets, 50-85% load) this measured within noise of the
+    /// inlined version.
+    #[inline(never)]
+    #[cold]
+    fn insert_cold(&mut self, pos0: usize, pos1: usize, max_nodes: usize) -> Result<(usize, usize), InsertError> {
+        cuckoo_bfs::make_room(self, pos0, pos1, D, max_nodes)
     }
 
     #[inline(always)]
     pub fn get(&mut self, key: &u64) -> Option<&V> {
-        let key = *key;
-        let mut hash64 = fold_hash_fast(key, self.seed);
+        // The optional lookup behaviour is all off by default. Testing it once here, rather than
+        // at each use, keeps the default lookup loop free of the checks.
+        if self.lookup_cache || self.early_return || self.load_both_groups || self.count_tag_false_positives {
+            self.get_impl::<true>(*key)
+        } else {
+            self.get_impl::<false>(*key)
+        }
+    }
+
+    /// Body of `get`. With `OPTIONS` false, the lookup cache, early return, loading both groups
+    /// and counting tag false positives are all off.
+    #[inline(always)]
+    fn get_impl<const OPTIONS: bool>(&mut self, key: u64) -> Option<&V> {
+        let cached = if OPTIONS && self.lookup_cache { self.last_lookup } else { None };
+        if let Some((_, index)) = cached.filter(|&(cached_key, _)| cached_key == key) {
+            self.lookup_cache_hits += 1;
+            return Some(unsafe { &(*self.bucket(index)).1 });
+        }
+        let mut hash64 = self.hasher.hash64(key, self.seed);
         let tag_hash = Tag::full(hash64);
         let mut is_second_group = false;
 
+        if OPTIONS && self.load_both_groups {
+            let pos0 = hash64 as usize & self.aligned_bucket_mask;
+            let pos1 = second_hash(self.double_hashing, hash64, tag_hash) as usize & self.aligned_bucket_mask;
+            let group0 = unsafe { GroupW::<W>::load_aligned(self.ctrl(pos0)) };
+            let group1 = unsafe { GroupW::<W>::load_aligned(self.ctrl(pos1)) };
+            for (pos, group) in [(pos0, group0), (pos1, group1)] {
+                for bit in group.match_tag(tag_hash) {
+                    let index = pos + bit;
+                    let bucket = unsafe { self.bucket(index) };
+                    if likely(unsafe { (*bucket).0 } == key) {
+                        if OPTIONS && self.lookup_cache {
+                            self.last_lookup = Some((key, index));
+                        }
+                        return Some(unsafe { &(*bucket).1 });
+                    }
+                    if OPTIONS && self.count_tag_false_positives {
+                        self.tag_false_positives += 1;
+                    }
+                }
+            }
+            return None;
+        }
+
         // First group
         loop {
             let pos = hash64 as usize & self.aligned_bucket_mask;
             // println!("searching for key at bucket {}", pos);
-            let group = unsafe { Group::load(self.ctrl(pos)) };
-            for bit in group.match_tag(tag_hash) {
+            let group = unsafe { GroupW::<W>::load_aligned(self.ctrl(pos)) };
+            let matches = group.match_tag(tag_hash);
+            // Start pulling in the data line as soon as we know which bucket to compare against,
+            // rather than stalling on it at the key comparison.
+            if let Some(bit) = matches.lowest_set_bit() {
+                prefetch_bucket(unsafe { self.bucket(pos + bit) });
+            }
+            for bit in matches {
                 let index = pos + bit;
 
                 let bucket = unsafe { self.bucket(index) };
 
                 if likely(unsafe { (*bucket).0 } == key) {
+                    if OPTIONS && self.lookup_cache {
+                        self.last_lookup = Some((key, index));
+                    }
                     return Some(unsafe { &(*bucket).1 });
                 }
+                if OPTIONS && self.count_tag_false_positives {
+                    self.tag_false_positives += 1;
+                }
             }
-            // We skip early return on empty slots.
-            // * early return has ~no impact on find_hit, since we will have found the key anyway.
-            // * early return *slows down* in-cache find_miss, perhaps simply from time spent checking
-            //   for empty slots.
-            // * early return prevents deletions from working.
-            //
-            // Additionally, given early return is disabled, we can improve probe lengths even further,
-            // by doing "less-loaded" cuckoo insertions. We don't do that in this table but instead in
-            // a later one.
-            const ALLOW_EARLY_RETURN: bool = false;
-            if (ALLOW_EARLY_RETURN && likely(group.match_empty().any_bit_set())) || is_second_group
+            // See `with_early_return` for why this is off by default.
+            if (OPTIONS && self.early_return && likely(group.match_empty().any_bit_set())) || is_second_group
             {
                 return None;
             }
-            let tag64 = scramble_tag(tag_hash);
-            hash64 = hash64 ^ tag64;
+            hash64 = second_hash(self.double_hashing, hash64, tag_hash);
             is_second_group = true;
         }
     }
 
-    pub fn probe_length(&self, key: u64) -> (usize, bool) {
-        let mut hash64 = fold_hash_fast(key, self.seed);
+    /// Returns `(groups, key_comparisons, found)`: the groups a lookup of `key` scans, and the
+    /// slots among them whose tag matched and so needed a full key comparison. Only tag false
+    /// positives make the comparisons exceed the one for the key itself.
+    ///
+    /// Like `get`, a miss only stops after the first group with `with_early_return`. Before
+    /// early return was optional, this always stopped at a first group with an empty slot (while
+    /// `get` never did), so miss probe lengths recorded then match a table with early return on
+    /// today, and are lower than the default table reports.
+    pub fn probe_length(&self, key: u64) -> (usize, usize, bool) {
+        let mut hash64 = self.hasher.hash64(key, self.seed);
         let tag_hash = Tag::full(hash64);
         let mut probe_count = 0;
+        let mut key_comparisons = 0;
 
         // First group
         loop {
             probe_count += 1;
             let pos = hash64 as usize & self.aligned_bucket_mask;
-            let group = unsafe { Group::load(self.ctrl(pos)) };
+            let group = unsafe { GroupW::<W>::load(self.ctrl(pos)) };
 
             for bit in group.match_tag(tag_hash) {
                 let index = pos + bit;
                 let bucket = unsafe { self.bucket(index) };
+                key_comparisons += 1;
                 if unsafe { (*bucket).0 } == key {
-                    return (probe_count, true); // Key found
+                    return (probe_count, key_comparisons, true); // Key found
                 }
             }
 
-            if group.match_empty().any_bit_set() {
-                return (probe_count, false); // Empty slot found, key absent
+            if self.early_return && group.match_empty().any_bit_set() {
+                return (probe_count, key_comparisons, false); // Empty slot found, key absent
             }
 
             if probe_count >= 2 {
-                return (probe_count, false); // After checking both groups, key absent
+                return (probe_count, key_comparisons, false); // After checking both groups, key absent
+            }
+
+            hash64 = second_hash(self.double_hashing, hash64, tag_hash);
+        }
+    }
+
+    /// Looks up `key` and returns a handle to its slot, so that the caller can read, update or
+    /// remove the entry without probing again.
+    ///
+    /// The handle mutably borrows the table, so no `insert` (which may relocate entries via the
+    /// cuckoo loop or rebucketing) can run while it is alive, and the cached slot index stays valid.
+    #[inline(always)]
+    pub fn find_entry(&mut self, key: u64) -> Option<EntryHandle<'_, V, H, W, D>> {
+        let index = self.find_index(key)?;
+        Some(EntryHandle { table: self, index })
+    }
+
+    /// Returns mutable references to the values of all `keys` at once, like
+    /// `HashMap::get_disjoint_mut`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any two keys are equal, since they would alias the same slot.
+    pub fn get_disjoint_mut<const N: usize>(&mut self, keys: [u64; N]) -> [Option<&mut V>; N] {
+        for (i, key) in keys.iter().enumerate() {
+            assert!(!keys[..i].contains(key), "duplicate keys passed to get_disjoint_mut");
+        }
+        // Distinct keys live in distinct slots, so the references don't overlap.
+        keys.map(|key| self.find_index(key).map(|index| unsafe { &mut (*self.bucket(index)).1 }))
+    }
+
+    /// Like `get_disjoint_mut` for a runtime number of keys: sets `out[i]` to the value of
+    /// `keys[i]`. Instead of panicking, a key whose slot was already handed out (an earlier
+    /// duplicate of it) gets `None`, as does an absent key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `keys` and `out` have different lengths.
+    pub fn get_many_mut<'a>(&'a mut self, keys: &[u64], out: &mut [Option<&'a mut V>]) {
+        assert_eq!(keys.len(), out.len(), "get_many_mut needs one output per key");
+        // Sorting by slot brings aliasing keys together, earliest first.
+        let mut slots = keys
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &key)| self.find_index(key).map(|index| (index, i)))
+            .collect::<Vec<_>>();
+        slots.sort_unstable();
+        for value in out.iter_mut() {
+            *value = None;
+        }
+        for aliasing in slots.chunk_by(|a, b| a.0 == b.0) {
+            let (index, i) = aliasing[0];
+            out[i] = Some(unsafe { &mut (*self.bucket(index)).1 });
+        }
+    }
+
+    /// Returns the first slots of the two groups `key` may live in, first group first. Each group
+    /// spans `W` slots from there. The two are equal when both hashes pick the same group.
+    #[inline(always)]
+    pub fn candidate_indices(&self, key: u64) -> (usize, usize) {
+        let hash0 = self.hash_of(key);
+        let hash1 = second_hash(self.double_hashing, hash0, Tag::full(hash0));
+        (hash0 as usize & self.aligned_bucket_mask, hash1 as usize & self.aligned_bucket_mask)
+    }
+
+    /// Returns the slot index holding `key`, if present. Probes both groups, like `get`.
+    #[inline(always)]
+    fn find_index(&self, key: u64) -> Option<usize> {
+        let hash0 = self.hasher.hash64(key, self.seed);
+        let tag_hash = Tag::full(hash0);
+        let hash1 = second_hash(self.double_hashing, hash0, tag_hash);
+        for hash in [hash0, hash1] {
+            let pos = hash as usize & self.aligned_bucket_mask;
+            let group = unsafe { GroupW::<W>::load(self.ctrl(pos)) };
+            for bit in group.match_tag(tag_hash) {
+                let index = pos + bit;
+                if likely(unsafe { (*self.bucket(index)).0 } == key) {
+                    return Some(index);
+                }
+            }
+        }
+        None
+    }
+
+    /// Inserts or updates `key` like `insert`, returning a reference to the stored value. The
+    /// slot is the one `insert` already found, so there's no second lookup.
+    #[inline(always)]
+    pub fn insert_and_get(&mut self, key: u64, value: V) -> &mut V {
+        let index = self.insert(key, value).index();
+        unsafe { &mut (*self.bucket(index)).1 }
+    }
+
+    #[inline(always)]
+    pub fn contains_key(&self, key: &u64) -> bool {
+        self.find_index(*key).is_some()
+    }
+
+    /// Whether the group at `pos` holds `key`, whose tag is `tag`.
+    #[inline(always)]
+    fn group_contains(&self, pos: usize, tag: Tag, key: u64) -> bool {
+        let group = unsafe { GroupW::<W>::load(self.ctrl(pos)) };
+        group
+            .match_tag(tag)
+            .into_iter()
+            .any(|bit| unsafe { (*self.bucket(pos + bit)).0 } == key)
+    }
+
+    /// Iterates over all keys, in slot order.
+    pub fn keys(&self) -> Keys<'_, V, H, W, D> {
+        Keys { inner: self.iter() }
+    }
+
+    /// Consumes the table, yielding its keys in slot order. As with `into_iter`, the allocation
+    /// is freed when the iterator is dropped.
+    pub fn into_keys(self) -> IntoKeys<V, H, W, D> {
+        IntoKeys { inner: self.into_iter() }
+    }
+
+    /// Consumes the table, yielding its values in slot order; see `into_keys`.
+    pub fn into_values(self) -> IntoValues<V, H, W, D> {
+        IntoValues { inner: self.into_iter() }
+    }
+
+    /// Estimates the fraction of slots that are full from the empty slots of every `stride`-th
+    /// group, reading `1 / stride` of the control bytes. For load decisions that can live with
+    /// a rough answer; `len()` is exact.
+    pub fn count_empty_groups_sampled(&self, stride: usize) -> f64 {
+        assert!(stride > 0, "stride must be positive");
+        let (mut empty, mut sampled) = (0, 0);
+        for pos in (0..self.num_buckets()).step_by(W * stride) {
+            empty += unsafe { GroupW::<W>::load(self.ctrl(pos)) }.match_empty().count_ones();
+            sampled += W;
+        }
+        if sampled == 0 {
+            return 0.0;
+        }
+        1.0 - empty as f64 / sampled as f64
+    }
+
+    /// Counts the full slots by scanning the control bytes. Always equal to `len()`, which is
+    /// kept incrementally; this recomputes it from scratch to check that.
+    pub fn recount(&self) -> usize {
+        (0..self.num_buckets())
+            .step_by(W)
+            .map(|pos| unsafe { GroupW::<W>::load(self.ctrl(pos)) }.match_full().count_ones())
+            .sum()
+    }
+
+    /// In debug builds, checks `len()` against `recount()` after the code that moves entries
+    /// around. Skipped for large tables, where the scan would make debug-build tests quadratic.
+    #[inline(always)]
+    fn debug_assert_len(&self) {
+        if cfg!(debug_assertions) && self.num_buckets() <= 1024 {
+            assert_eq!(self.recount(), self.len(), "len out of sync with the control bytes");
+        }
+    }
+
+    /// Returns, per slot, whether it is full. For plotting where entries land; not fast.
+    pub fn occupancy_bitmap(&self) -> Vec<bool> {
+        let mut bitmap = vec![false; self.num_buckets()];
+        for pos in (0..self.num_buckets()).step_by(W) {
+            for bit in unsafe { GroupW::<W>::load(self.ctrl(pos)) }.match_full() {
+                bitmap[pos + bit] = true;
+            }
+        }
+        bitmap
+    }
+
+    /// Returns the control byte of every slot, and the entries of the full slots in slot order.
+    /// `from_layout` rebuilds exactly this layout, so a benchmark can save one and reload it
+    /// instead of depending on insertion order.
+    pub fn dump_layout(&self) -> (Vec<u8>, Vec<(u64, V)>) {
+        let ctrl = (0..self.num_buckets()).map(|index| unsafe { (*self.ctrl(index)).0 }).collect();
+        let entries = self.iter().map(|(key, &value)| (key, value)).collect();
+        (ctrl, entries)
+    }
+
+    /// Renders the control bytes for debugging, one line per group: `E` for an empty slot, `D`
+    /// for a deleted one, or the tag of a full one as two hex digits.
+    #[cfg(debug_assertions)]
+    pub fn ctrl_string(&self) -> alloc::string::String {
+        use core::fmt::Write as _;
+        let mut out = alloc::string::String::new();
+        for index in 0..self.num_buckets() {
+            let tag = unsafe { *self.ctrl(index) };
+            let sep = if index % W == W - 1 { '\n' } else { ' ' };
+            match tag {
+                Tag::EMPTY => out.push('E'),
+                Tag::DELETED => out.push('D'),
+                _ => write!(out, "{:02x}", tag.0).uunwrap(),
+            }
+            out.push(sep);
+        }
+        out
+    }
+
+    /// Returns a random entry: the first full slot at or after a uniformly random slot, wrapping
+    /// around. At typical loads that is within a group or two. Not uniform over entries: an entry
+    /// after a run of empty slots is picked more often.
+    pub fn sample_one(&self, rng: &mut fastrand::Rng) -> Option<(u64, &V)> {
+        if self.is_empty() {
+            return None;
+        }
+        let start = rng.usize(..self.num_buckets());
+        let (mut group_pos, mut offset) = (start - start % W, start % W);
+        loop {
+            let full = unsafe { GroupW::<W>::load(self.ctrl(group_pos)) }.match_full();
+            if let Some(bit) = full.into_iter().find(|&bit| bit >= offset) {
+                let bucket = unsafe { &*self.bucket(group_pos + bit) };
+                return Some((bucket.0, &bucket.1));
+            }
+            offset = 0;
+            group_pos = (group_pos + W) & self.aligned_bucket_mask;
+        }
+    }
+
+    /// Iterates over all entries, in slot order.
+    pub fn iter(&self) -> Iter<'_, V, H, W, D> {
+        Iter {
+            table: self,
+            group_pos: 0,
+            bits: unsafe { GroupW::<W>::load(self.ctrl(0)) }.match_full().into_iter(),
+        }
+    }
+
+    /// Counts the keys present in both `self` and `other`, by looking up each entry of the
+    /// smaller table in the larger.
+    ///
+    /// When both tables place keys identically (same seed, size and second-group derivation, and
+    /// a stateless hasher), every key has the same two candidate groups in both, so the lookup
+    /// reuses the entry's group and tag instead of rehashing its key.
+    pub fn intersection_count(&self, other: &Self) -> usize {
+        let (small, large) = if self.len() <= other.len() { (self, other) } else { (other, self) };
+        let same_layout = small.seed == large.seed
+            && small.aligned_bucket_mask == large.aligned_bucket_mask
+            && small.double_hashing == large.double_hashing
+            && core::mem::size_of::<H>() == 0;
+        if !same_layout {
+            return small.iter().filter(|(key, _)| large.contains_key(key)).count();
+        }
+        let mut count = 0;
+        for group_pos in (0..small.num_buckets()).step_by(W) {
+            let group = unsafe { GroupW::<W>::load(small.ctrl(group_pos)) };
+            for bit in group.match_full() {
+                let index = group_pos + bit;
+                let tag = unsafe { *small.ctrl(index) };
+                let key = unsafe { (*small.bucket(index)).0 };
+                count += (large.group_contains(group_pos, tag, key)
+                    || large.group_contains(small.other_pos(group_pos, index), tag, key))
+                    as usize;
+            }
+        }
+        count
+    }
+
+    /// Inserts `key`, returning the previous value if the key was already present (like
+    /// `std::collections::HashMap::insert`).
+    #[inline(always)]
+    pub fn replace(&mut self, key: u64, value: V) -> Option<V> {
+        if let Some(index) = self.find_index(key) {
+            let slot = unsafe { &mut (*self.bucket(index)).1 };
+            return Some(core::mem::replace(slot, value));
+        }
+        let _ = self.insert(key, value);
+        None
+    }
+
+    /// Moves every entry of `other` into `self`. For keys in both tables, `other`'s value wins.
+    pub fn merge(&mut self, other: Self) {
+        self.merge_with(other, |value, other_value| *value = other_value);
+    }
+
+    /// As `merge`, but resolves keys in both tables with `f(&mut value, other_value)`.
+    pub fn merge_with(&mut self, other: Self, mut f: impl FnMut(&mut V, V)) {
+        self.reserve(other.len());
+        for (key, &other_value) in other.iter() {
+            match self.find_index(key) {
+                Some(index) => f(unsafe { &mut (*self.bucket(index)).1 }, other_value),
+                None => {
+                    let _ = self.insert(key, other_value);
+                }
+            }
+        }
+    }
+
+    /// Copies every entry of `other` into `self`, leaving `other` as it was. For keys in both
+    /// tables, `other`'s value wins, as in `merge`.
+    pub fn extend_from(&mut self, other: &Self) {
+        self.reserve(other.len());
+        for (key, &value) in other.iter() {
+            self.replace(key, value);
+        }
+    }
+
+    /// As `Extend`, but prefetches each key's two control groups `EXTEND_PREFETCH_DISTANCE` keys
+    /// before inserting it, so that for tables out of cache the misses of consecutive keys
+    /// overlap instead of each insert stalling on its own. Keys are inserted in order, so for
+    /// duplicate keys the last value wins.
+    ///
+    /// It hasn't paid off: building 3/4-full tables of random keys, it measured 20-30% slower
+    /// than `Extend` in cache (2^12 to 2^20 buckets) and within noise out of cache (2^23), at
+    /// distances from 4 to 32. The inserts are independent, so out-of-order execution already
+    /// overlaps their misses; also prefetching the data lines made it slower still.
+    pub fn extend_prefetched(&mut self, pairs: impl IntoIterator<Item = (u64, V)>) {
+        let pairs = pairs.into_iter();
+        self.reserve(pairs.size_hint().0);
+        // Keys whose groups have been prefetched but that aren't inserted yet; key `i` is at
+        // `i % EXTEND_PREFETCH_DISTANCE`.
+        let mut in_flight = [MaybeUninit::<(u64, V)>::uninit(); EXTEND_PREFETCH_DISTANCE];
+        let mut count = 0;
+        for pair in pairs {
+            let slot = &mut in_flight[count % EXTEND_PREFETCH_DISTANCE];
+            if count >= EXTEND_PREFETCH_DISTANCE {
+                let (key, value) = unsafe { slot.assume_init() };
+                self.replace(key, value);
+            }
+            let (pos0, pos1) = self.candidate_indices(pair.0);
+            unsafe {
+                prefetch(self.ctrl(pos0));
+                prefetch(self.ctrl(pos1));
             }
+            slot.write(pair);
+            count += 1;
+        }
+        for i in count.saturating_sub(EXTEND_PREFETCH_DISTANCE)..count {
+            let (key, value) = unsafe { in_flight[i % EXTEND_PREFETCH_DISTANCE].assume_init() };
+            self.replace(key, value);
+        }
+    }
 
-            let tag64 = scramble_tag(tag_hash);
-            hash64 = hash64 ^ tag64;
+    /// Returns the other candidate group of the entry at `index`, which lives in group `pos`.
+    #[inline(always)]
+    fn other_pos(&self, pos: usize, index: usize) -> usize {
+        let tag = unsafe { *self.ctrl(index) };
+        if !self.double_hashing {
+            // `hash1 = hash0 ^ scramble_tag(tag)`, so each group is the other XOR the same offset.
+            return pos ^ (scramble_tag(tag) as usize & self.aligned_bucket_mask);
         }
+        let key = unsafe { (*self.bucket(index)).0 };
+        let hash0 = self.hasher.hash64(key, self.seed);
+        let pos0 = hash0 as usize & self.aligned_bucket_mask;
+        let pos1 = second_hash(true, hash0, tag) as usize & self.aligned_bucket_mask;
+        if pos0 == pos { pos1 } else { pos0 }
     }
 
+    /// Marks the full slot at `index` as empty. The value is not dropped.
+    ///
+    /// # Safety
+    ///
+    /// `index` must be a full slot of this table, e.g. as returned by `insert`.
     #[inline(always)]
     pub unsafe fn erase_index(&mut self, index: usize) {
         self.set_ctrl(index, Tag::EMPTY);
+        self.last_lookup = None;
         self.items_until_growth += 1;
+        #[cfg(debug_assertions)]
+        {
+            self.tombstones += 1;
+        }
     }
 
     #[inline(always)]
@@ -486,142 +1788,603 @@ impl<V: Copy> HashTable<V> {
     }
 }
 
-impl<V: Copy> Drop for HashTable<V> {
-    fn drop(&mut self) {
-        unsafe { Self::dealloc(self.ctrl, self.num_buckets()) };
+/// Set operations, for using the table as a `HashSet<u64>`.
+impl<H: Hash64, const W: usize, const D: usize> HashTable<(), H, W, D> {
+    /// Adds `key`, returning whether it was new.
+    #[inline(always)]
+    pub fn insert_key(&mut self, key: u64) -> bool {
+        // Not `insert`: its early return can duplicate a key that sits in its second group.
+        self.replace(key, ()).is_none()
     }
-}
 
-fn scramble_tag(tag: Tag) -> u64 {
-    (tag.0 as u64).wrapping_mul(MUL).rotate_left(32)
+    #[inline(always)]
+    pub fn contains(&self, key: u64) -> bool {
+        self.find_index(key).is_some()
+    }
 }
 
-const MUL: u64 = 0x2d35_8dcc_aa6c_78a5;
+impl<V: Copy, H: Hash64, const W: usize, const D: usize> CuckooSlots for HashTable<V, H, W, D> {
+    const WIDTH: usize = W;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::collections::HashMap;
+    #[inline(always)]
+    fn other_pos(&self, pos: usize, index: usize) -> usize {
+        Self::other_pos(self, pos, index)
+    }
 
-    #[test]
-    fn test_basic_insert_and_get() {
-        let mut table = HashTable::with_capacity(16);
+    #[inline(always)]
+    fn first_empty(&self, pos: usize) -> Option<usize> {
+        let group = unsafe { GroupW::<W>::load(self.ctrl(pos)) };
+        group.match_empty().lowest_set_bit().map(|bit| pos + bit)
+    }
 
-        // Test basic insertion
-        let (inserted, _, _) = table.insert(42, 100);
-        assert!(inserted);
-        assert_eq!(table.len(), 1);
+    #[inline(always)]
+    unsafe fn move_slot(&mut self, from: usize, to: usize) {
+        self.bucket(to).write(self.bucket(from).read());
+        self.set_ctrl(to, *self.ctrl(from));
+    }
+}
 
-        // Test retrieval
-        assert_eq!(table.get(&42), Some(&100));
-        assert_eq!(table.get(&999), None);
+impl<V: Copy, H: Hash64, const W: usize, const D: usize> Drop for HashTable<V, H, W, D> {
+    fn drop(&mut self) {
+        if self.is_unallocated() {
+            return;
+        }
+        unsafe { Self::dealloc(self.ctrl, self.num_buckets()) };
     }
+}
 
-    #[test]
-    fn test_update_existing() {
-        let mut table = HashTable::with_capacity(16);
+/// Copies the allocation byte for byte, so the clone has the same layout, seed and statistics.
+impl<V: Copy, H: Hash64 + Clone, const W: usize, const D: usize> Clone for HashTable<V, H, W, D> {
+    fn clone(&self) -> Self {
+        if self.is_unallocated() {
+            return self.clone_with_ctrl(self.ctrl);
+        }
+        let ctrl = Self::alloc_ctrl(self.num_buckets());
+        unsafe { Self::copy_allocation(self.ctrl, ctrl, self.num_buckets()) };
+        self.clone_with_ctrl(ctrl)
+    }
 
-        // Insert initial value
-        let (inserted, _, _) = table.insert(123, 456);
-        assert!(inserted);
-        assert_eq!(table.len(), 1);
+    /// Overwrites `self`'s allocation in place when it has as many buckets as `source`'s, so a
+    /// scratch table rebuilt from the same source each iteration allocates only once.
+    fn clone_from(&mut self, source: &Self) {
+        if self.is_unallocated() || source.is_unallocated() || self.num_buckets() != source.num_buckets() {
+            *self = source.clone();
+            return;
+        }
+        unsafe { Self::copy_allocation(source.ctrl, self.ctrl, self.num_buckets()) };
+        let mut old = core::mem::replace(self, source.clone_with_ctrl(self.ctrl));
+        // `self` owns the allocation now; drop the rest of `old` without freeing it.
+        old.ctrl = NonNull::from(&UNALLOCATED_CTRL.tags).cast();
+    }
+}
 
-        // Update with new value
-        let (inserted, _, _) = table.insert(123, 789);
-        assert!(!inserted); // Should be false since key already existed
-        assert_eq!(table.len(), 1); // Length should remain the same
+/// What [`HashTable::insert`] did. `index` is the key's slot until the table grows or a later
+/// insert's BFS moves it.
+#[must_use]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Inserted {
+    /// The key was new.
+    New { index: usize },
+    /// The key was already present, and its value was overwritten.
+    Updated { index: usize },
+}
 
-        // Verify updated value
-        assert_eq!(table.get(&123), Some(&789));
+impl Inserted {
+    #[inline(always)]
+    pub fn is_new(self) -> bool {
+        matches!(self, Self::New { .. })
     }
 
-    #[test]
-    fn test_multiple_insertions() {
-        let mut table = HashTable::with_capacity(64);
-
-        // Insert multiple values
-        for i in 1..=20 {
-            let (inserted, _, _) = table.insert(i, i * 10);
-            assert!(inserted);
+    #[inline(always)]
+    pub fn index(self) -> usize {
+        match self {
+            Self::New { index } | Self::Updated { index } => index,
         }
+    }
+}
 
-        assert_eq!(table.len(), 20);
+/// Result of [`HashTable::insert_tracked`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InsertResult {
+    /// Whether the key was new, rather than updated.
+    pub inserted: bool,
+    /// Slot of the key.
+    pub index: usize,
+    /// Whether the cuckoo BFS moved other entries to make room, changing some of their indices.
+    pub relocated: bool,
+    /// Whether the table grew first, changing the indices of all other entries.
+    pub resized: bool,
+}
 
-        // Verify all values
-        for i in 1..=20 {
-            assert_eq!(table.get(&i), Some(&(i * 10)));
+/// Iterator over the entries of a [`HashTable`], returned by [`HashTable::iter`].
+pub struct Iter<'a, V: Copy, H: Hash64 = FoldHashFast, const W: usize = { Group::WIDTH }, const D: usize = BFS_DEPTH> {
+    table: &'a HashTable<V, H, W, D>,
+    group_pos: usize,
+    bits: BitMaskIter,
+}
+
+impl<'a, V: Copy, H: Hash64, const W: usize, const D: usize> Iter<'a, V, H, W, D> {
+    /// The next full slot.
+    #[inline]
+    fn next_bucket(&mut self) -> Option<&'a (u64, V)> {
+        loop {
+            if let Some(bit) = self.bits.next() {
+                return Some(unsafe { &*self.table.bucket(self.group_pos + bit) });
+            }
+            self.group_pos += W;
+            if self.group_pos >= self.table.num_buckets() {
+                return None;
+            }
+            self.bits = unsafe { GroupW::<W>::load(self.table.ctrl(self.group_pos)) }
+                .match_full()
+                .into_iter();
         }
     }
+}
 
-    #[test]
-    fn test_cross_check_with_std_hashmap_small() {
-        let mut cuckoo_table = HashTable::with_capacity(32);
-        let mut std_map = HashMap::new();
-
-        let keys = [1, 5, 10, 15, 20, 25, 30, 35];
+impl<'a, V: Copy, H: Hash64, const W: usize, const D: usize> Iterator for Iter<'a, V, H, W, D> {
+    type Item = (u64, &'a V);
 
-        // Insert same data into both
-        for &key in &keys {
-            let value = key * 2;
-            cuckoo_table.insert(key, value);
-            std_map.insert(key, value);
-        }
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_bucket().map(|bucket| (bucket.0, &bucket.1))
+    }
+}
 
-        // Verify both have same length
-        assert_eq!(cuckoo_table.len(), std_map.len());
+/// Iterator over the keys of a [`HashTable`], returned by [`HashTable::keys`].
+pub struct Keys<'a, V: Copy, H: Hash64 = FoldHashFast, const W: usize = { Group::WIDTH }, const D: usize = BFS_DEPTH> {
+    inner: Iter<'a, V, H, W, D>,
+}
 
-        // Verify all lookups match
-        for &key in &keys {
-            assert_eq!(cuckoo_table.get(&key).copied(), std_map.get(&key).copied());
-        }
+impl<'a, V: Copy, H: Hash64, const W: usize, const D: usize> Iterator for Keys<'a, V, H, W, D> {
+    type Item = &'a u64;
 
-        // Test non-existent keys
-        for &key in &[2, 7, 12, 99] {
-            assert_eq!(cuckoo_table.get(&key), None);
-            assert_eq!(std_map.get(&key), None);
-        }
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next_bucket().map(|bucket| &bucket.0)
     }
+}
 
-    #[test]
-    fn test_randomized_small() {
-        let mut rng = fastrand::Rng::with_seed(12345);
-        let mut cuckoo_table = HashTable::with_capacity(128);
-        let mut std_map = HashMap::new();
-
-        // Random insertions
-        for _ in 0..50 {
-            let key = rng.u64(1..1000); // Avoid key 0 for simplicity
-            let value = rng.u64(..);
+/// Owning iterator over the entries of a [`HashTable`], returned by `into_iter`. The table's
+/// allocation is freed when the iterator is dropped; values are `Copy`, so there is nothing to
+/// drop in the slots it didn't reach.
+pub struct IntoIter<V: Copy, H: Hash64 = FoldHashFast, const W: usize = { Group::WIDTH }, const D: usize = BFS_DEPTH> {
+    table: HashTable<V, H, W, D>,
+    group_pos: usize,
+    bits: BitMaskIter,
+}
 
-            let (cuckoo_inserted, _, _) = cuckoo_table.insert(key, value);
-            let std_existed = std_map.insert(key, value).is_some();
+impl<V: Copy, H: Hash64, const W: usize, const D: usize> IntoIterator for HashTable<V, H, W, D> {
+    type Item = (u64, V);
+    type IntoIter = IntoIter<V, H, W, D>;
 
-            // Check insertion result consistency
-            assert_eq!(cuckoo_inserted, !std_existed);
+    fn into_iter(self) -> IntoIter<V, H, W, D> {
+        let bits = unsafe { GroupW::<W>::load(self.ctrl(0)) }.match_full().into_iter();
+        IntoIter {
+            table: self,
+            group_pos: 0,
+            bits,
         }
+    }
+}
 
-        // Verify lengths match
-        assert_eq!(cuckoo_table.len(), std_map.len());
+impl<V: Copy, H: Hash64, const W: usize, const D: usize> Iterator for IntoIter<V, H, W, D> {
+    type Item = (u64, V);
 
-        // Verify all lookups match
-        for &key in std_map.keys() {
-            assert_eq!(cuckoo_table.get(&key).copied(), std_map.get(&key).copied());
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(bit) = self.bits.next() {
+                return Some(unsafe { self.table.bucket(self.group_pos + bit).read() });
+            }
+            self.group_pos += W;
+            if self.group_pos >= self.table.num_buckets() {
+                return None;
+            }
+            self.bits = unsafe { GroupW::<W>::load(self.table.ctrl(self.group_pos)) }
+                .match_full()
+                .into_iter();
         }
     }
+}
 
-    #[test]
-    fn test_randomized_medium() {
-        let mut rng = fastrand::Rng::with_seed(67890);
-        let mut cuckoo_table = HashTable::with_capacity(512);
-        let mut std_map = HashMap::new();
+/// Owning iterator over the keys of a [`HashTable`], returned by [`HashTable::into_keys`].
+pub struct IntoKeys<V: Copy, H: Hash64 = FoldHashFast, const W: usize = { Group::WIDTH }, const D: usize = BFS_DEPTH> {
+    inner: IntoIter<V, H, W, D>,
+}
 
-        // Random insertions and updates
-        for _ in 0..200 {
-            let key = rng.u64(1..500);
-            let value = rng.u64(..);
+impl<V: Copy, H: Hash64, const W: usize, const D: usize> Iterator for IntoKeys<V, H, W, D> {
+    type Item = u64;
 
-            let (cuckoo_inserted, _, _) = cuckoo_table.insert(key, value);
-            let std_existed = std_map.insert(key, value).is_some();
+    #[inline]
+    fn next(&mut self) -> Option<u64> {
+        self.inner.next().map(|(key, _)| key)
+    }
+}
+
+/// Owning iterator over the values of a [`HashTable`], returned by [`HashTable::into_values`].
+pub struct IntoValues<V: Copy, H: Hash64 = FoldHashFast, const W: usize = { Group::WIDTH }, const D: usize = BFS_DEPTH> {
+    inner: IntoIter<V, H, W, D>,
+}
+
+impl<V: Copy, H: Hash64, const W: usize, const D: usize> Iterator for IntoValues<V, H, W, D> {
+    type Item = V;
+
+    #[inline]
+    fn next(&mut self) -> Option<V> {
+        self.inner.next().map(|(_, value)| value)
+    }
+}
+
+/// A handle to an occupied slot of a [`HashTable`], returned by [`HashTable::find_entry`].
+///
+/// Caches the slot index found by the lookup; the mutable borrow of the table guarantees the
+/// entry can't be relocated while the handle exists.
+pub struct EntryHandle<'a, V: Copy, H: Hash64 = FoldHashFast, const W: usize = { Group::WIDTH }, const D: usize = BFS_DEPTH> {
+    table: &'a mut HashTable<V, H, W, D>,
+    index: usize,
+}
+
+impl<'a, V: Copy, H: Hash64, const W: usize, const D: usize> EntryHandle<'a, V, H, W, D> {
+    /// Returns the slot index of this entry.
+    #[inline(always)]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    #[inline(always)]
+    pub fn key(&self) -> u64 {
+        unsafe { (*self.table.bucket(self.index)).0 }
+    }
+
+    #[inline(always)]
+    pub fn get(&self) -> &V {
+        unsafe { &(*self.table.bucket(self.index)).1 }
+    }
+
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut V {
+        unsafe { &mut (*self.table.bucket(self.index)).1 }
+    }
+
+    /// Converts the handle into a mutable reference that lives as long as the table borrow.
+    #[inline(always)]
+    pub fn into_mut(self) -> &'a mut V {
+        unsafe { &mut (*self.table.bucket(self.index)).1 }
+    }
+
+    /// Removes the entry from the table, returning its value. Shrinks the table if that leaves it
+    /// sparse and `with_shrink_on_remove` is set.
+    #[inline(always)]
+    pub fn remove(self) -> V {
+        let value = unsafe { (*self.table.bucket(self.index)).1 };
+        unsafe { self.table.erase_index(self.index) };
+        if self.table.shrink_on_remove {
+            self.table.shrink_if_sparse();
+        }
+        value
+    }
+}
+
+/// Prefetches the cache line holding `bucket` into L1. A no-op unless the `prefetch` feature is
+/// enabled on x86_64.
+///
+/// Off by default: on out-of-cache find_hit (2^25 buckets, 75% load) it measured no faster, and
+/// often slower, than letting the key load itself miss; the load issues right after anyway.
+#[inline(always)]
+fn prefetch_bucket<T>(bucket: *const T) {
+    #[cfg(feature = "prefetch")]
+    prefetch(bucket);
+    #[cfg(not(feature = "prefetch"))]
+    let _ = bucket;
+}
+
+/// How many keys ahead of inserting them `extend_prefetched` prefetches their groups.
+const EXTEND_PREFETCH_DISTANCE: usize = 8;
+
+/// Prefetches the cache line holding `ptr` into L1. A no-op off x86_64.
+#[inline(always)]
+fn prefetch<T>(ptr: *const T) {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        use core::arch::x86_64::{_MM_HINT_T0, _mm_prefetch};
+        _mm_prefetch::<_MM_HINT_T0>(ptr.cast());
+    }
+}
+
+/// A `Group` load that only looks at its first `W` slots.
+#[derive(Copy, Clone)]
+struct GroupW<const W: usize>(Group);
+
+impl<const W: usize> GroupW<W> {
+    #[inline(always)]
+    unsafe fn load(ptr: *const Tag) -> Self {
+        Self(Group::load(ptr))
+    }
+
+    /// As `load`, for `ptr` at a multiple of `W` slots, as any position masked with
+    /// `aligned_bucket_mask` is. That only lines up with whole groups when `W == Group::WIDTH`;
+    /// narrower windows keep the unaligned load.
+    ///
+    /// Used for the two group loads in `get` and `insert`. On find_hit (2^12 to 2^22 buckets, 50%
+    /// and 85% load) it measured within noise of the unaligned load on x86-64.
+    #[inline(always)]
+    unsafe fn load_aligned(ptr: *const Tag) -> Self {
+        if W == Group::WIDTH {
+            Self(Group::load_aligned(ptr))
+        } else {
+            Self(Group::load(ptr))
+        }
+    }
+
+    #[inline(always)]
+    fn match_tag(self, tag: Tag) -> BitMask {
+        self.0.match_tag(tag).truncate(W)
+    }
+
+    #[inline(always)]
+    fn match_empty(self) -> BitMask {
+        self.0.match_empty().truncate(W)
+    }
+
+    #[inline(always)]
+    fn match_full(self) -> BitMask {
+        self.0.match_full().truncate(W)
+    }
+}
+
+/// Derives the hash of the second candidate group from the first.
+#[inline(always)]
+fn second_hash(double_hashing: bool, hash0: u64, tag: Tag) -> u64 {
+    if double_hashing {
+        (hash0 >> 32).wrapping_mul(DOUBLE_HASH_MUL)
+    } else {
+        hash0 ^ scramble_tag(tag)
+    }
+}
+
+const DOUBLE_HASH_MUL: u64 = 0x9e37_79b9_7f4a_7c15;
+
+fn scramble_tag(tag: Tag) -> u64 {
+    (tag.0 as u64).wrapping_mul(MUL).rotate_left(32)
+}
+
+const MUL: u64 = 0x2d35_8dcc_aa6c_78a5;
+
+/// Capacity of `new()`, and of a `Default` table once it first allocates.
+const INITIAL_CAPACITY: usize = 16;
+
+/// Control bytes of a `Default` table before its first insert: a single group of `EMPTY`, so
+/// lookups miss without a separate check for the missing allocation. A `static` rather than
+/// `Group::static_empty()` so that its address reliably identifies the unallocated state. Aligned
+/// like a `Group`, for `GroupW::load_aligned`.
+static UNALLOCATED_CTRL: AlignedTags = AlignedTags {
+    _align: [],
+    tags: [Tag::EMPTY; Group::WIDTH],
+};
+
+#[repr(C)]
+struct AlignedTags {
+    _align: [Group; 0],
+    tags: [Tag; Group::WIDTH],
+}
+
+#[inline(always)]
+fn capacity_to_buckets(capacity: usize) -> usize {
+    infallible(try_capacity_to_buckets(capacity))
+}
+
+/// Fewest buckets a table is built with. Smaller tables would have fewer than two groups, so a
+/// key's two candidate groups would coincide and `with_capacity(1)` would fail on the first kick.
+const MIN_NUM_BUCKETS: usize = 2 * Group::WIDTH;
+
+fn try_capacity_to_buckets(capacity: usize) -> Result<usize, TryReserveError> {
+    capacity
+        .checked_mul(8)
+        .and_then(|n| (n / 7).checked_next_power_of_two())
+        .map(|n| n.max(MIN_NUM_BUCKETS))
+        .ok_or(TryReserveError::CapacityOverflow)
+}
+
+/// Bucket count to grow to from a full table of `num_buckets` holding `len` items: enough for
+/// `len * grow_factor` items at 7/8 load, and at least double.
+fn grown_num_buckets(len: usize, num_buckets: usize, grow_factor: (usize, usize)) -> usize {
+    infallible(try_grown_num_buckets(len, num_buckets, grow_factor))
+}
+
+fn try_grown_num_buckets(
+    len: usize,
+    num_buckets: usize,
+    (numerator, denominator): (usize, usize),
+) -> Result<usize, TryReserveError> {
+    len.checked_mul(numerator)
+        .and_then(|n| (n / denominator).checked_mul(8))
+        .and_then(|n| (n / 7).checked_next_power_of_two())
+        .map(|n| n.max(num_buckets * 2))
+        .ok_or(TryReserveError::CapacityOverflow)
+}
+
+impl<V: Copy + fmt::Debug, H: Hash64, const W: usize, const D: usize> fmt::Debug for HashTable<V, H, W, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = move || self.iter();
+        f.debug_struct("HashTable")
+            .field("len", &self.len())
+            .field("num_buckets", &self.num_buckets())
+            .field("entries", &DebugEntries(entries))
+            .finish()
+    }
+}
+
+/// Equal when both hold the same keys with equal values, whatever their seeds, sizes or layouts.
+impl<V: Copy + PartialEq, H: Hash64, const W: usize, const D: usize> PartialEq for HashTable<V, H, W, D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self.iter().all(|(key, value)| {
+                other.find_index(key).is_some_and(|index| unsafe { (*other.bucket(index)).1 } == *value)
+            })
+    }
+}
+
+impl<V: Copy + Eq, H: Hash64, const W: usize, const D: usize> Eq for HashTable<V, H, W, D> {}
+
+/// Fills with `with_full_load`, so that only the cuckoo BFS limits the load.
+impl<V: Copy + Default> FillToFailure for HashTable<V> {
+    fn with_capacity(capacity: usize) -> Self {
+        HashTable::with_capacity(capacity).with_full_load(true)
+    }
+
+    fn try_insert_key(&mut self, key: u64) -> bool {
+        self.try_insert(key, V::default()).is_ok()
+    }
+
+    fn load_factor(&self) -> f64 {
+        HashTable::load_factor(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SipHash13;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_peak_load_factor() {
+        crate::assert_reaches_peak_load_factor::<HashTable<u64>>(PEAK_LOAD_FACTOR);
+    }
+
+    #[test]
+    fn test_with_capacity_one() {
+        // Rounded up to the minimum size, so a handful of keys fit.
+        let mut table = HashTable::<u64>::with_capacity(1);
+        for key in 1..=8 {
+            let _ = table.insert(key, key * 10);
+        }
+        assert_eq!(table.len(), 8);
+        for key in 1..=8 {
+            assert_eq!(table.get(&key), Some(&(key * 10)));
+        }
+    }
+
+    #[test]
+    fn test_basic_insert_and_get() {
+        let mut table = HashTable::with_capacity(16);
+
+        // Test basic insertion
+        let inserted = table.insert(42, 100).is_new();
+        assert!(inserted);
+        assert_eq!(table.len(), 1);
+
+        // Test retrieval
+        assert_eq!(table.get(&42), Some(&100));
+        assert_eq!(table.get(&999), None);
+    }
+
+    #[test]
+    fn test_update_existing() {
+        let mut table = HashTable::with_capacity(16);
+
+        // Insert initial value
+        let inserted = table.insert(123, 456).is_new();
+        assert!(inserted);
+        assert_eq!(table.len(), 1);
+
+        // Update with new value
+        let inserted = table.insert(123, 789).is_new();
+        assert!(!inserted); // Should be false since key already existed
+        assert_eq!(table.len(), 1); // Length should remain the same
+
+        // Verify updated value
+        assert_eq!(table.get(&123), Some(&789));
+    }
+
+    #[test]
+    fn test_multiple_insertions() {
+        let mut table = HashTable::with_capacity(64);
+
+        // Insert multiple values
+        for i in 1..=20 {
+            let inserted = table.insert(i, i * 10).is_new();
+            assert!(inserted);
+        }
+
+        assert_eq!(table.len(), 20);
+
+        // Verify all values
+        for i in 1..=20 {
+            assert_eq!(table.get(&i), Some(&(i * 10)));
+        }
+    }
+
+    #[test]
+    fn test_cross_check_with_std_hashmap_small() {
+        let mut cuckoo_table = HashTable::with_capacity(32);
+        let mut std_map = HashMap::new();
+
+        let keys = [1, 5, 10, 15, 20, 25, 30, 35];
+
+        // Insert same data into both
+        for &key in &keys {
+            let value = key * 2;
+            let _ = cuckoo_table.insert(key, value);
+            std_map.insert(key, value);
+        }
+
+        // Verify both have same length
+        assert_eq!(cuckoo_table.len(), std_map.len());
+
+        // Verify all lookups match
+        for &key in &keys {
+            assert_eq!(cuckoo_table.get(&key).copied(), std_map.get(&key).copied());
+        }
+
+        // Test non-existent keys
+        for &key in &[2, 7, 12, 99] {
+            assert_eq!(cuckoo_table.get(&key), None);
+            assert_eq!(std_map.get(&key), None);
+        }
+    }
+
+    #[test]
+    fn test_randomized_small() {
+        let mut rng = fastrand::Rng::with_seed(12345);
+        let mut cuckoo_table = HashTable::with_capacity(128);
+        let mut std_map = HashMap::new();
+
+        // Random insertions
+        for _ in 0..50 {
+            let key = rng.u64(1..1000); // Avoid key 0 for simplicity
+            let value = rng.u64(..);
+
+            let cuckoo_inserted = cuckoo_table.insert(key, value).is_new();
+            let std_existed = std_map.insert(key, value).is_some();
+
+            // Check insertion result consistency
+            assert_eq!(cuckoo_inserted, !std_existed);
+        }
+
+        // Verify lengths match
+        assert_eq!(cuckoo_table.len(), std_map.len());
+
+        // Verify all lookups match
+        for &key in std_map.keys() {
+            assert_eq!(cuckoo_table.get(&key).copied(), std_map.get(&key).copied());
+        }
+    }
+
+    #[test]
+    fn test_randomized_medium() {
+        let mut rng = fastrand::Rng::with_seed(67890);
+        let mut cuckoo_table = HashTable::with_capacity(512);
+        let mut std_map = HashMap::new();
+
+        // Random insertions and updates
+        for _ in 0..200 {
+            let key = rng.u64(1..500);
+            let value = rng.u64(..);
+
+            let cuckoo_inserted = cuckoo_table.insert(key, value).is_new();
+            let std_existed = std_map.insert(key, value).is_some();
 
             assert_eq!(cuckoo_inserted, !std_existed);
         }
@@ -649,7 +2412,7 @@ mod tests {
         ];
 
         for &key in &test_keys {
-            let (inserted, _, _) = table.insert(key, key);
+            let inserted = table.insert(key, key).is_new();
             assert!(inserted);
         }
 
@@ -677,7 +2440,7 @@ mod tests {
             };
             let value = rng.u64(..);
 
-            cuckoo_table.insert(key, value);
+            let _ = cuckoo_table.insert(key, value);
             std_map.insert(key, value);
         }
 
@@ -697,7 +2460,7 @@ mod tests {
 
         // Insert initial values
         for i in 1..=10 {
-            cuckoo_table.insert(i, i);
+            let _ = cuckoo_table.insert(i, i);
             std_map.insert(i, i);
         }
 
@@ -705,7 +2468,7 @@ mod tests {
         for round in 1..=3 {
             for i in 1..=10 {
                 let new_value = i * 100 * round;
-                let (cuckoo_inserted, _, _) = cuckoo_table.insert(i, new_value);
+                let cuckoo_inserted = cuckoo_table.insert(i, new_value).is_new();
                 let std_existed = std_map.insert(i, new_value).is_some();
 
                 assert!(!cuckoo_inserted); // Should be update, not insert
@@ -737,7 +2500,7 @@ mod tests {
                     let key = rng.u64(1..200);
                     let value = rng.u64(..);
 
-                    let (cuckoo_inserted, _, _) = cuckoo_table.insert(key, value);
+                    let cuckoo_inserted = cuckoo_table.insert(key, value).is_new();
                     let std_existed = std_map.insert(key, value).is_some();
                     assert_eq!(cuckoo_inserted, !std_existed);
                 }
@@ -784,7 +2547,7 @@ mod tests {
             let value = rng.u64(..);
 
             cuckoo_table_entries.push((key, value));
-            let (cuckoo_inserted, _, _) = cuckoo_table.insert(key, value);
+            let cuckoo_inserted = cuckoo_table.insert(key, value).is_new();
             let std_inserted = std_map.insert(key, value).is_none();
 
             assert_eq!(cuckoo_inserted, std_inserted);
@@ -820,14 +2583,14 @@ mod tests {
 
         // Insert initial batch
         for i in 1..=50 {
-            cuckoo_table.insert(i, i * 10);
+            let _ = cuckoo_table.insert(i, i * 10);
             std_map.insert(i, i * 10);
         }
 
         // Update some values
         for i in 1..=25 {
             let new_value = rng.u64(..);
-            let (cuckoo_inserted, _, _) = cuckoo_table.insert(i, new_value);
+            let cuckoo_inserted = cuckoo_table.insert(i, new_value).is_new();
             let std_existed = std_map.insert(i, new_value).is_some();
             assert_eq!(cuckoo_inserted, !std_existed);
         }
@@ -835,7 +2598,7 @@ mod tests {
         // Insert more elements to trigger rebucketing
         for i in 51..=150 {
             let value = i * 100;
-            cuckoo_table.insert(i, value);
+            let _ = cuckoo_table.insert(i, value);
             std_map.insert(i, value);
         }
 
@@ -865,7 +2628,7 @@ mod tests {
             };
             let value = rng.u64(..);
 
-            cuckoo_table.insert(key, value);
+            let _ = cuckoo_table.insert(key, value);
             std_map.insert(key, value);
 
             // Periodically verify correctness during growth
@@ -893,7 +2656,7 @@ mod tests {
 
         // Insert sequential keys
         for &key in &keys {
-            cuckoo_table.insert(key, key * 2);
+            let _ = cuckoo_table.insert(key, key * 2);
         }
 
         // Verify all keys are present
@@ -903,4 +2666,1586 @@ mod tests {
             assert_eq!(cuckoo_table.get(&key), Some(&(key * 2)));
         }
     }
+
+    #[test]
+    fn test_get_large_table() {
+        // Big enough that lookups miss cache, so the bucket prefetch (with `--features prefetch`)
+        // is actually in flight when the key is compared.
+        let mut rng = fastrand::Rng::with_seed(2054);
+        let mut table = HashTable::with_capacity(1 << 16);
+        let mut std_map = HashMap::new();
+        for _ in 0..50_000 {
+            let key = rng.u64(..);
+            let value = rng.u64(..);
+            let _ = table.insert(key, value);
+            std_map.insert(key, value);
+        }
+        for (&key, &value) in &std_map {
+            assert_eq!(table.get(&key), Some(&value));
+        }
+        for _ in 0..10_000 {
+            let key = rng.u64(..);
+            assert_eq!(table.get(&key).copied(), std_map.get(&key).copied());
+        }
+    }
+
+    #[test]
+    fn test_double_hashing_round_trip() {
+        // Start small so the keys go through the BFS and several rebuckets.
+        let mut table = HashTable::new().with_double_hashing(true);
+        let mut std_map = HashMap::new();
+        let mut rng = fastrand::Rng::with_seed(2055);
+        for _ in 0..2000 {
+            let key = rng.u64(..);
+            let value = rng.u64(..);
+            let inserted = table.insert(key, value).is_new();
+            assert_eq!(inserted, std_map.insert(key, value).is_none());
+        }
+        assert_eq!(table.len(), std_map.len());
+        for (&key, &value) in &std_map {
+            assert_eq!(table.get(&key), Some(&value));
+            assert_eq!(table.find_entry(key).map(|e| *e.get()), Some(value));
+        }
+        for _ in 0..1000 {
+            let key = rng.u64(..);
+            assert_eq!(table.get(&key).copied(), std_map.get(&key).copied());
+        }
+    }
+
+    #[test]
+    fn test_less_loaded_insertion_round_trip() {
+        // Start small so the keys go through the BFS and several rebuckets.
+        let mut table = HashTable::new().with_less_loaded_insertion(true);
+        let mut std_map = HashMap::new();
+        let mut rng = fastrand::Rng::with_seed(2117);
+        for _ in 0..4000 {
+            // Draw from a small range so that many inserts update a key, which may now sit in
+            // its second group while the first has room.
+            let key = rng.u64(..3000);
+            let value = rng.u64(..);
+            let inserted = table.insert(key, value).is_new();
+            assert_eq!(inserted, std_map.insert(key, value).is_none());
+        }
+        assert_eq!(table.len(), std_map.len());
+        assert_eq!(table.recount(), std_map.len());
+        for (&key, &value) in &std_map {
+            assert_eq!(table.get(&key), Some(&value));
+        }
+    }
+
+    #[test]
+    fn test_less_loaded_insertion_probe_lengths() {
+        // Fills 4-slot groups to 7/8 load, where plenty of inserts need the BFS, and returns how
+        // many did and how many present keys are in their second group.
+        fn fill(less_loaded: bool) -> (usize, usize) {
+            let mut table = HashTable::<u64, FoldHashFast, 4>::with_capacity_and_hasher(1 << 14, FoldHashFast)
+                .with_less_loaded_insertion(less_loaded);
+            let mut rng = fastrand::Rng::with_seed(2117);
+            let keys: Vec<u64> = (0..table.num_buckets() * 7 / 8).map(|_| rng.u64(..)).collect();
+            let mut bfs_inserts = 0;
+            for &key in &keys {
+                let (_, _, probe_length) = table.try_insert(key, key).unwrap();
+                bfs_inserts += (probe_length > 2) as usize;
+            }
+            let second_group = keys.iter().filter(|&&key| matches!(table.probe_length(key), (2, _, true))).count();
+            (bfs_inserts, second_group)
+        }
+        let (bfs_first, second_group_first) = fill(false);
+        let (bfs_balanced, second_group_balanced) = fill(true);
+        // Balancing leaves fewer inserts finding both groups full...
+        assert!(bfs_balanced * 2 < bfs_first, "{bfs_balanced} vs {bfs_first}");
+        // ...but more keys in their second group.
+        assert!(second_group_balanced > second_group_first, "{second_group_balanced} vs {second_group_first}");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_less_loaded_insertion_with_early_return_panics() {
+        let _ = HashTable::<u64>::new().with_early_return(true).with_less_loaded_insertion(true);
+    }
+
+    #[test]
+    fn test_clone() {
+        let mut table = HashTable::new();
+        for key in 0..1000u64 {
+            let _ = table.insert(key, !key);
+        }
+        let mut clone = table.clone();
+        assert_eq!(clone.len(), 1000);
+        assert_eq!(clone.num_buckets(), table.num_buckets());
+        // The clone is independent of the original.
+        let _ = clone.insert(1000, 0);
+        clone.replace(0, 0);
+        assert_eq!(table.get(&1000), None);
+        assert_eq!(table.get(&0), Some(&!0));
+        for key in 1..1000u64 {
+            assert_eq!(clone.get(&key), Some(&!key));
+        }
+
+        // Into a smaller table, an equal-size one and an unallocated one.
+        let mut small = HashTable::with_capacity(16);
+        let _ = small.insert(5000, 1);
+        let mut same = HashTable::with_capacity(1000).with_grow_factor(3, 1);
+        let _ = same.insert(5000, 1);
+        let mut empty = HashTable::default();
+        for target in [&mut small, &mut same, &mut empty] {
+            target.clone_from(&table);
+            assert_eq!(target.len(), 1000);
+            assert_eq!(target.recount(), 1000);
+            assert_eq!(target.get(&5000), None);
+            for key in 0..1000u64 {
+                assert_eq!(target.get(&key), Some(&!key));
+            }
+        }
+        let mut unallocated = table.clone();
+        unallocated.clone_from(&HashTable::default());
+        assert_eq!(unallocated.num_buckets(), 0);
+        assert_eq!(unallocated.get(&0), None);
+    }
+
+    #[test]
+    fn test_with_hasher() {
+        /// FNV-1a over the bytes written, so the layout is the same on every run.
+        #[derive(Default)]
+        struct Fnv1a(u64);
+        impl core::hash::Hasher for Fnv1a {
+            fn write(&mut self, bytes: &[u8]) {
+                for &byte in bytes {
+                    self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100_0000_01b3);
+                }
+            }
+            fn finish(&self) -> u64 {
+                self.0 ^ 0xcbf2_9ce4_8422_2325
+            }
+        }
+        type FixedState = core::hash::BuildHasherDefault<Fnv1a>;
+
+        fn round_trip<S: core::hash::BuildHasher>(hasher: S) -> HashTable<u64, BuildHasherHash64<S>> {
+            // Start small so the keys go through the BFS and several rebuckets.
+            let mut table = HashTable::with_hasher(16, hasher);
+            let mut std_map = HashMap::new();
+            let mut rng = fastrand::Rng::with_seed(2123);
+            for _ in 0..2000 {
+                let key = rng.u64(..);
+                let value = rng.u64(..);
+                assert_eq!(table.replace(key, value), std_map.insert(key, value));
+            }
+            assert_eq!(table.len(), std_map.len());
+            for (&key, &value) in &std_map {
+                assert_eq!(table.get(&key), Some(&value));
+            }
+            assert_eq!(table.get(&rng.u64(..)), None);
+            table
+        }
+        round_trip(std::hash::RandomState::new());
+        let a = round_trip(FixedState::default());
+        let b = round_trip(FixedState::default());
+        // A fixed hasher gives the same layout every time.
+        assert!(a.iter().map(|(key, _)| key).eq(b.iter().map(|(key, _)| key)));
+    }
+
+    #[test]
+    fn test_layout_round_trip() {
+        // Fill to 7/8 load, so some keys were moved by the BFS and sit in their second group.
+        let mut table = HashTable::with_capacity(1 << 10);
+        let mut rng = fastrand::Rng::with_seed(2124);
+        let mut keys = Vec::new();
+        while table.len() < table.num_buckets() * 7 / 8 {
+            let key = rng.u64(..);
+            let _ = table.insert(key, !key);
+            keys.push(key);
+        }
+        let layout = table.dump_layout();
+        assert_eq!(layout.0.len(), table.num_buckets());
+        assert_eq!(layout.1.len(), table.len());
+
+        let mut restored = HashTable::from_layout(layout.clone());
+        assert_eq!(restored.dump_layout(), layout);
+        assert_eq!(restored.len(), table.len());
+        assert_eq!(restored.num_buckets(), table.num_buckets());
+        for &key in &keys {
+            assert_eq!(restored.get(&key), Some(&!key));
+            assert_eq!(restored.probe_length(key), table.probe_length(key));
+        }
+        for _ in 0..1000 {
+            let key = rng.u64(..);
+            assert_eq!(restored.get(&key), table.get(&key));
+        }
+        // Full, so the next insert grows as it would have in the original.
+        let _ = restored.insert(1, 1);
+        assert_eq!(restored.num_buckets(), table.num_buckets() * 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't belong")]
+    fn test_from_layout_misplaced_key_panics() {
+        let mut table = HashTable::with_capacity(64);
+        let _ = table.insert(1, 1);
+        let (ctrl, mut entries) = table.dump_layout();
+        entries[0].0 = 2;
+        let _ = HashTable::from_layout((ctrl, entries));
+    }
+
+    #[test]
+    fn test_from_pairs_vs_incremental() {
+        let mut rng = fastrand::Rng::with_seed(2056);
+        let n = 3000;
+        let mut keys = Vec::new();
+        let mut std_map = HashMap::new();
+        while keys.len() < n {
+            let key = rng.u64(..);
+            if std_map.insert(key, key ^ 1).is_none() {
+                keys.push(key);
+            }
+        }
+
+        let mut incremental = HashTable::with_capacity(n);
+        for &key in &keys {
+            let _ = incremental.insert(key, key ^ 1);
+        }
+        let mut pairs: Vec<(u64, u64)> = keys.iter().map(|&k| (k, k ^ 1)).collect();
+        let mut bulk = HashTable::from_pairs(&mut pairs);
+
+        // Same capacity and contents...
+        assert_eq!(bulk.num_buckets(), incremental.num_buckets());
+        assert_eq!(bulk.len(), n);
+        for _ in 0..1000 {
+            let key = rng.u64(..);
+            assert_eq!(bulk.get(&key), incremental.get(&key));
+        }
+        for &key in &keys {
+            assert_eq!(bulk.get(&key), Some(&(key ^ 1)));
+        }
+
+        // ...but at least as many keys found in their first group.
+        let total_probes = |table: &HashTable<u64>| -> usize {
+            keys.iter().map(|&k| table.probe_length(k).0).sum()
+        };
+        assert!(total_probes(&bulk) <= total_probes(&incremental));
+    }
+
+    #[test]
+    fn test_from_pairs_duplicates() {
+        let mut pairs = vec![(1, 10), (2, 20), (1, 11), (3, 30), (2, 21), (1, 12)];
+        let mut table = HashTable::from_pairs(&mut pairs);
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.get(&1), Some(&12));
+        assert_eq!(table.get(&2), Some(&21));
+        assert_eq!(table.get(&3), Some(&30));
+    }
+
+    #[test]
+    #[should_panic(expected = "denominator")]
+    fn test_grow_factor_zero_denominator_panics() {
+        let _ = HashTable::<u64>::with_capacity(16).with_grow_factor(2, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "overflows")]
+    fn test_grow_factor_overflow_panics() {
+        let _ = HashTable::<u64>::with_capacity(16).with_grow_factor(usize::MAX, 1);
+    }
+
+    #[test]
+    fn test_grow_factor_tight() {
+        const N: u64 = 2000;
+        let mut tight = HashTable::<u64>::with_capacity(16).with_grow_factor(4, 3);
+        let mut loose = HashTable::<u64>::with_capacity(16).with_grow_factor(4, 1);
+        for key in 0..N {
+            let _ = tight.insert(key, key * 3);
+            let _ = loose.insert(key, key * 3);
+        }
+        assert_eq!(tight.len(), N as usize);
+        assert_eq!(loose.len(), N as usize);
+        for key in 0..N {
+            assert_eq!(tight.get(&key), Some(&(key * 3)));
+            assert_eq!(loose.get(&key), Some(&(key * 3)));
+        }
+        // The smallest power of two that holds N at 7/8 load, not twice that.
+        let needed = ((N as usize * 8) / 7).next_power_of_two();
+        assert_eq!(tight.num_buckets(), needed);
+        assert!(loose.num_buckets() > needed);
+    }
+
+    #[test]
+    fn test_default_allocates_lazily() {
+        let mut table = HashTable::<u64>::default();
+        assert_eq!(table.num_buckets(), 0);
+        assert!(table.is_empty());
+        assert_eq!(table.get(&42), None);
+        assert_eq!(table.replace(42, 1), None);
+
+        // The first insert (via `replace`) allocated what `new` would...
+        let num_buckets = HashTable::<u64>::new().num_buckets();
+        assert_eq!(table.num_buckets(), num_buckets);
+        assert_eq!(table.len(), 1);
+        // ...and filling that up doesn't allocate again.
+        let ctrl = table.ctrl;
+        for key in 1..(num_buckets * 7 / 8) as u64 {
+            let _ = table.insert(key + 100, key);
+        }
+        assert_eq!(table.ctrl, ctrl);
+        assert_eq!(table.get(&42), Some(&1));
+        for key in 1..(num_buckets * 7 / 8) as u64 {
+            assert_eq!(table.get(&(key + 100)), Some(&key));
+        }
+
+        // Never-allocated tables drop without freeing the sentinel.
+        drop(HashTable::<u64>::default());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_build_vs_serial() {
+        let n = 1_000_000;
+        let mut rng = fastrand::Rng::with_seed(11);
+        let pairs: Vec<(u64, u64)> = (0..n).map(|_| (rng.u64(..), rng.u64(..))).collect();
+        let mut serial = HashTable::with_capacity(n);
+        for &(key, value) in &pairs {
+            let _ = serial.insert(key, value);
+        }
+        let mut parallel = HashTable::par_build(&mut pairs.clone());
+
+        assert_eq!(parallel.len(), serial.len());
+        assert_eq!(parallel.num_buckets(), serial.num_buckets());
+        for &(key, _) in &pairs {
+            assert_eq!(parallel.get(&key), serial.get(&key));
+        }
+        for _ in 0..10_000 {
+            let key = rng.u64(..);
+            assert_eq!(parallel.get(&key), serial.get(&key));
+        }
+    }
+
+    #[test]
+    fn test_shrink_on_remove() {
+        let mut rng = fastrand::Rng::with_seed(123);
+        let keys: Vec<u64> = (0..10_000).map(|_| rng.u64(..)).collect();
+        for shrink in [false, true] {
+            let mut table = HashTable::with_capacity(keys.len()).with_shrink_on_remove(shrink);
+            table.extend(keys.iter().map(|&key| (key, !key)));
+            let peak_buckets = table.num_buckets();
+            for key in &keys[100..] {
+                assert_eq!(table.find_entry(*key).unwrap().remove(), !key);
+            }
+            assert_eq!(table.len(), 100);
+            if shrink {
+                assert!(table.num_buckets() <= 4 * capacity_to_buckets(100), "{} buckets", table.num_buckets());
+            } else {
+                assert_eq!(table.num_buckets(), peak_buckets);
+            }
+            for key in &keys[..100] {
+                assert_eq!(table.get(key), Some(&!key));
+            }
+            for key in &keys[100..] {
+                assert_eq!(table.get(key), None);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_ctrl_string() {
+        let mut table = HashTable::with_capacity(200);
+        assert!(table.ctrl_string().split_whitespace().all(|entry| entry == "E"));
+        let mut rng = fastrand::Rng::with_seed(123);
+        for _ in 0..150 {
+            let _ = table.insert(rng.u64(..), 0);
+        }
+        let ctrl = table.ctrl_string();
+        assert_eq!(ctrl.lines().count(), table.num_buckets() / Group::WIDTH);
+        let entries: Vec<&str> = ctrl.split_whitespace().collect();
+        assert_eq!(entries.len(), table.num_buckets());
+        let full = entries.iter().filter(|&&entry| entry != "E" && entry != "D").count();
+        assert_eq!(full, table.len());
+        let (tags, _) = table.dump_layout();
+        for (entry, tag) in entries.iter().zip(tags) {
+            if Tag(tag).is_full() {
+                assert_eq!(u8::from_str_radix(entry, 16), Ok(tag));
+            }
+        }
+    }
+
+    #[test]
+    fn test_eq() {
+        let mut rng = fastrand::Rng::with_seed(123);
+        let mut pairs: Vec<(u64, u64)> = (0..3000).map(|_| (rng.u64(..), rng.u64(..))).collect();
+        let a: HashTable<u64> = pairs.iter().copied().collect();
+        pairs.reverse();
+        // Sized differently too, so the layouts can't line up.
+        let mut b = HashTable::with_capacity(10_000);
+        b.extend(pairs.iter().copied());
+        assert_ne!(a.dump_layout(), b.dump_layout());
+        assert_eq!(a, b);
+        assert_eq!(b, a);
+
+        b.replace(pairs[0].0, !pairs[0].1);
+        assert_ne!(a, b);
+        b.replace(pairs[0].0, pairs[0].1);
+        b.find_entry(pairs[1].0).unwrap().remove();
+        assert_ne!(a, b);
+        let _ = b.insert(rng.u64(..), 0);
+        assert_ne!(a, b);
+        assert_eq!(HashTable::<u64>::new(), HashTable::default());
+    }
+
+    #[test]
+    fn test_intersection_count() {
+        use std::collections::HashSet;
+
+        let mut rng = fastrand::Rng::with_seed(5);
+        let a_keys: HashSet<u64> = (0..3000).map(|_| rng.u64(..10_000)).collect();
+        let b_keys: HashSet<u64> = (0..3000).map(|_| rng.u64(..10_000)).collect();
+        let expected = a_keys.intersection(&b_keys).count();
+
+        let mut a = HashTable::with_capacity(4096);
+        let mut b = HashTable::with_capacity(4096);
+        let mut b_small = HashTable::with_capacity(16);
+        for &key in &a_keys {
+            let _ = a.insert(key, ());
+        }
+        for &key in &b_keys {
+            let _ = b.insert(key, ());
+            let _ = b_small.insert(key, ());
+        }
+        assert_eq!(a.iter().count(), a_keys.len());
+        assert!(a.iter().all(|(key, _)| a_keys.contains(&key)));
+
+        // Same layout: group-by-group path.
+        assert_eq!(a.num_buckets(), b.num_buckets());
+        assert_eq!(a.intersection_count(&b), expected);
+        assert_eq!(b.intersection_count(&a), expected);
+        // Different sizes: lookup path.
+        assert_ne!(a.num_buckets(), b_small.num_buckets());
+        assert_eq!(a.intersection_count(&b_small), expected);
+        assert_eq!(a.intersection_count(&HashTable::default()), 0);
+    }
+
+    fn check_group_width<const W: usize>() {
+        let mut table = HashTable::<u64, FoldHashFast, W>::with_capacity_and_hasher(64, FoldHashFast);
+        let mut rng = fastrand::Rng::with_seed(W as u64);
+        // Distinct keys (`insert` assumes them), enough to rebucket several times.
+        let keys: Vec<u64> = (0..5000).map(|_| rng.u64(..)).collect();
+        for &key in &keys {
+            assert!(table.insert(key, key ^ 1).is_new());
+        }
+        assert_eq!(table.len(), keys.len());
+        assert_eq!(table.iter().count(), keys.len());
+        for &key in &keys[..100] {
+            assert_eq!(table.replace(key, 7), Some(key ^ 1));
+        }
+        for (i, &key) in keys.iter().enumerate() {
+            let expected = if i < 100 { 7 } else { key ^ 1 };
+            assert_eq!(table.get(&key), Some(&expected), "W = {W}");
+        }
+        for _ in 0..1000 {
+            assert_eq!(table.get(&rng.u64(..)), None);
+        }
+    }
+
+    /// Fills a table of `W`-slot groups with BFS depth `D` until `try_insert` fails, checks every
+    /// key placed along the way, and returns the load factor reached.
+    fn fill_with_depth<const W: usize, const D: usize>(seed: u64) -> f64 {
+        let mut table = HashTable::<u64, FoldHashFast, W, D>::with_capacity_and_hasher(1024, FoldHashFast);
+        let mut rng = fastrand::Rng::with_seed(seed);
+        let mut keys = Vec::new();
+        loop {
+            let key = rng.u64(..);
+            match table.try_insert(key, !key) {
+                Ok((inserted, _, _)) => {
+                    assert!(inserted);
+                    keys.push(key);
+                }
+                Err(_) => break,
+            }
+        }
+        assert_eq!(table.len(), keys.len());
+        for key in &keys {
+            assert_eq!(table.get(key), Some(&!key), "W = {W}, D = {D}");
+        }
+        table.load_factor()
+    }
+
+    #[test]
+    fn test_bfs_depth() {
+        // 16-slot groups reach the 7/8 growth threshold at any of these depths.
+        for seed in 0..4 {
+            assert_eq!(fill_with_depth::<{ Group::WIDTH }, 2>(seed), 0.875);
+            assert_eq!(fill_with_depth::<{ Group::WIDTH }, 3>(seed), 0.875);
+            assert_eq!(fill_with_depth::<{ Group::WIDTH }, 4>(seed), 0.875);
+        }
+        // 2-slot groups run out of BFS first, so the depth shows in the load reached.
+        let mean = |fill: fn(u64) -> f64| (0..20).map(fill).sum::<f64>() / 20.0;
+        let (d2, d3, d4) = (mean(fill_with_depth::<2, 2>), mean(fill_with_depth::<2, 3>), mean(fill_with_depth::<2, 4>));
+        assert!(d2 < d3 && d3 < d4, "{d2} {d3} {d4}");
+    }
+
+    fn check_load_aligned<const W: usize>() {
+        let mut table = HashTable::<u64, FoldHashFast, W>::with_capacity_and_hasher(1024, FoldHashFast);
+        for key in 0..800 {
+            let _ = table.insert(key, key);
+        }
+        for pos in (0..table.num_buckets()).step_by(W) {
+            let (unaligned, aligned) =
+                unsafe { (GroupW::<W>::load(table.ctrl(pos)), GroupW::<W>::load_aligned(table.ctrl(pos))) };
+            for tag in [Tag::EMPTY, Tag::full(0), Tag::full(u64::MAX)] {
+                assert_eq!(unaligned.match_tag(tag).0, aligned.match_tag(tag).0);
+            }
+            assert_eq!(unaligned.match_full().0, aligned.match_full().0);
+        }
+    }
+
+    #[test]
+    fn test_load_aligned() {
+        check_load_aligned::<4>();
+        check_load_aligned::<{ Group::WIDTH }>();
+        // The unallocated table's control bytes are read with the aligned load too.
+        assert_eq!(HashTable::<u64>::default().get(&1), None);
+    }
+
+    #[test]
+    fn test_group_widths() {
+        check_group_width::<4>();
+        check_group_width::<8>();
+        #[cfg(target_feature = "sse2")]
+        check_group_width::<16>();
+    }
+
+    #[test]
+    fn test_insert_and_get() {
+        let mut table = HashTable::with_capacity(64);
+        for i in 0..40 {
+            *table.insert_and_get(i, i) += 100;
+        }
+        // Updating an existing key returns its slot too.
+        *table.insert_and_get(5, 0) += 1;
+        assert_eq!(table.len(), 40);
+        assert_eq!(table.get(&5), Some(&1));
+        for i in (0..40).filter(|&i| i != 5) {
+            assert_eq!(table.get(&i), Some(&(i + 100)));
+        }
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut table = HashTable::with_capacity(64);
+        for i in 1..=20 {
+            assert_eq!(table.replace(i, i * 10), None);
+        }
+        assert_eq!(table.len(), 20);
+
+        // Updating returns the old value and doesn't change the length.
+        assert_eq!(table.replace(7, 700), Some(70));
+        assert_eq!(table.replace(7, 7000), Some(700));
+        assert_eq!(table.len(), 20);
+        assert_eq!(table.get(&7), Some(&7000));
+        assert_eq!(table.get(&8), Some(&80));
+    }
+
+    #[test]
+    fn test_entry_handle_get_mut() {
+        let mut table = HashTable::with_capacity(64);
+        for i in 1..=20 {
+            let _ = table.insert(i, i * 10);
+        }
+
+        assert!(table.find_entry(999).is_none());
+
+        let mut entry = table.find_entry(7).unwrap();
+        assert_eq!(entry.key(), 7);
+        assert_eq!(*entry.get(), 70);
+        *entry.get_mut() += 1;
+        assert_eq!(*entry.get(), 71);
+
+        // The update is visible through a fresh lookup, and nothing else changed.
+        assert_eq!(table.get(&7), Some(&71));
+        assert_eq!(table.len(), 20);
+        for i in (1..=20).filter(|&i| i != 7) {
+            assert_eq!(table.get(&i), Some(&(i * 10)));
+        }
+    }
+
+    #[test]
+    fn test_entry_handle_remove() {
+        let mut table = HashTable::with_capacity(64);
+        for i in 1..=20 {
+            let _ = table.insert(i, i * 10);
+        }
+
+        let entry = table.find_entry(5).unwrap();
+        let index = entry.index();
+        assert_eq!(entry.remove(), 50);
+
+        // The slot is empty again and the key is gone.
+        assert!(unsafe { *table.ctrl(index) } == Tag::EMPTY);
+        assert_eq!(table.get(&5), None);
+        assert!(table.find_entry(5).is_none());
+        assert_eq!(table.len(), 19);
+        for i in (1..=20).filter(|&i| i != 5) {
+            assert_eq!(table.get(&i), Some(&(i * 10)));
+        }
+    }
+
+    #[test]
+    fn test_get_disjoint_mut_swap() {
+        let mut table = HashTable::with_capacity(64);
+        for key in 0..20 {
+            let _ = table.insert(key, key * 10);
+        }
+        let [Some(a), Some(b), missing] = table.get_disjoint_mut([3, 17, 100]) else {
+            panic!("present keys not found");
+        };
+        assert!(missing.is_none());
+        core::mem::swap(a, b);
+        assert_eq!(table.get(&3), Some(&170));
+        assert_eq!(table.get(&17), Some(&30));
+        assert_eq!(table.get(&4), Some(&40));
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate keys")]
+    fn test_get_disjoint_mut_duplicate_keys() {
+        let mut table = HashTable::with_capacity(64);
+        let _ = table.insert(5, 50);
+        let _ = table.get_disjoint_mut([5, 6, 5]);
+    }
+
+    #[test]
+    fn test_insert_unique_matches_insert() {
+        // Distinct keys land in the same slots as with `insert`, which also fills the first
+        // group first, including once the first groups fill up and the BFS takes over.
+        let mut rng = fastrand::Rng::with_seed(2130);
+        let keys = (0..1792).map(|_| rng.u64(..)).collect::<Vec<_>>();
+        let mut inserted = HashTable::<u64>::with_capacity(1792);
+        let mut unique = HashTable::<u64>::with_capacity(1792);
+        for &key in &keys {
+            let index = inserted.insert(key, key).index();
+            assert_eq!(unique.insert_unique(key, key), index, "key {key}");
+        }
+        assert_eq!(inserted.dump_layout(), unique.dump_layout());
+        // Past the growth threshold, too.
+        for key in 0..1000 {
+            unique.insert_unique(key, key);
+        }
+        assert_eq!(unique.len(), 2792);
+        for &key in keys.iter().chain(&(0..1000).collect::<Vec<_>>()) {
+            assert_eq!(unique.get(&key), Some(&key));
+        }
+    }
+
+    #[test]
+    fn test_get_many_mut() {
+        let mut table = HashTable::with_capacity(1024);
+        for key in 0..1000 {
+            let _ = table.insert(key, key);
+        }
+        let keys = (0..1000).step_by(3).chain([5000]).collect::<Vec<_>>();
+        let mut out = keys.iter().map(|_| None).collect::<Vec<_>>();
+        table.get_many_mut(&keys, &mut out);
+        assert!(out.last().unwrap().is_none());
+        for value in out.into_iter().flatten() {
+            *value += 1_000_000;
+        }
+        for key in 0..1000 {
+            let expected = if key % 3 == 0 { key + 1_000_000 } else { key };
+            assert_eq!(table.get(&key), Some(&expected), "key {key}");
+        }
+    }
+
+    #[test]
+    fn test_get_many_mut_duplicate_keys() {
+        let mut table = HashTable::with_capacity(64);
+        let _ = table.insert(5, 50);
+        let _ = table.insert(6, 60);
+        let mut out = [None, None, None, None, None];
+        table.get_many_mut(&[5, 6, 5, 7, 6], &mut out);
+        let [Some(a), Some(b), None, None, None] = out else {
+            panic!("expected only the first occurrence of each key to be resolved");
+        };
+        *a += 1;
+        *b += 1;
+        assert_eq!(table.get(&5), Some(&51));
+        assert_eq!(table.get(&6), Some(&61));
+    }
+
+    #[test]
+    fn test_grow_after_remove() {
+        // Removing leaves holes in the middle of groups; growing must still move the entries
+        // after them.
+        let mut table = HashTable::new();
+        let mut reference = HashMap::new();
+        for key in 0..1000u64 {
+            let _ = table.insert(key, key);
+            reference.insert(key, key);
+        }
+        for key in (0..1000u64).step_by(3) {
+            assert_eq!(table.find_entry(key).map(|entry| entry.remove()), reference.remove(&key));
+        }
+        for key in 1000..4000u64 {
+            let _ = table.insert(key, key);
+            reference.insert(key, key);
+        }
+        assert_eq!(table.len(), reference.len());
+        for (key, value) in &reference {
+            assert_eq!(table.get(key), Some(value));
+        }
+    }
+
+    #[test]
+    fn test_insert_cold_path() {
+        // Fill to the growth threshold, so that some inserts find both groups full and go
+        // through the BFS.
+        let mut table = HashTable::with_capacity(1024);
+        let num_buckets = table.num_buckets();
+        let n = table.items_until_growth as u64;
+        let mut placed = Vec::new();
+        for key in 0..n {
+            let Inserted::New { index } = table.insert(key, key * 3) else { panic!("{key} not new") };
+            assert_eq!(unsafe { *table.bucket(index) }, (key, key * 3));
+            placed.push(index);
+        }
+        assert_eq!(table.num_buckets(), num_buckets);
+        // Only the BFS moves entries once placed.
+        let moved = (0..n).filter(|&key| table.find_index(key) != Some(placed[key as usize])).count();
+        assert!(moved > 0, "no insert needed the BFS");
+        assert_eq!(table.len(), n as usize);
+        for key in 0..n {
+            assert_eq!(table.get(&key), Some(&(key * 3)));
+        }
+    }
+
+    #[test]
+    fn test_reset_with_seed() {
+        let mut table = HashTable::with_capacity(1024);
+        for key in 0..800 {
+            let _ = table.insert(key, !key);
+        }
+        let num_buckets = table.num_buckets();
+        let indices: Vec<_> = (0..800).map(|key| table.find_index(key).unwrap()).collect();
+
+        table.reset_with_seed(table.seed ^ 0x5eed);
+        assert_eq!(table.num_buckets(), num_buckets);
+        assert_eq!(table.len(), 800);
+        for key in 0..800 {
+            assert_eq!(table.get(&key), Some(&!key));
+        }
+        let moved = (0..800).filter(|&key| table.find_index(key) != Some(indices[key as usize])).count();
+        assert!(moved > 0);
+
+        // An unallocated table just takes the seed.
+        let mut table = HashTable::<u64>::default();
+        table.reset_with_seed(7);
+        let _ = table.insert(1, 2);
+        assert_eq!(table.seed, 7);
+        assert_eq!(table.get(&1), Some(&2));
+    }
+
+    #[test]
+    fn test_insert_tracked() {
+        let mut table = HashTable::with_capacity(64);
+        let mut indices = HashMap::new();
+        let (mut resizes, mut relocations) = (0, 0);
+        for key in 0..2000u64 {
+            let num_buckets = table.num_buckets();
+            let result = table.insert_tracked(key, !key);
+            assert!(result.inserted);
+            assert_eq!(unsafe { *table.bucket(result.index) }, (key, !key));
+            assert_eq!(result.resized, table.num_buckets() != num_buckets, "key {key}");
+            resizes += result.resized as usize;
+            relocations += result.relocated as usize;
+            if result.resized || result.relocated {
+                indices.clear();
+                indices.extend((0..key).map(|key| (key, table.find_index(key).unwrap())));
+            } else {
+                // Nothing else moved.
+                for (&key, &index) in &indices {
+                    assert_eq!(table.find_index(key), Some(index));
+                }
+            }
+            indices.insert(key, result.index);
+        }
+        assert!(resizes > 0);
+        assert!(relocations > 0);
+
+        // An update moves nothing.
+        let mut table = HashTable::with_capacity(64);
+        let index = table.insert_tracked(5, 5).index;
+        let result = table.insert_tracked(5, 50);
+        assert_eq!(result, InsertResult { inserted: false, index, relocated: false, resized: false });
+        assert_eq!(table.get(&5), Some(&50));
+    }
+
+    #[test]
+    fn test_try_insert_needs_growth() {
+        let mut table = HashTable::with_capacity(1024);
+        let num_buckets = table.num_buckets();
+        let item_capacity = table.items_until_growth;
+        for key in 0..item_capacity as u64 {
+            assert!(table.try_insert(key, key).unwrap().0);
+        }
+
+        // At the threshold a new key fails without growing, but an update still succeeds.
+        assert_eq!(table.try_insert(u64::MAX, 0), Err(InsertError::NeedsGrowth));
+        assert_eq!(table.try_insert(3, 30).map(|(inserted, _, _)| inserted), Ok(false));
+        assert_eq!(table.get(&3), Some(&30));
+        assert_eq!(table.len(), item_capacity);
+        assert_eq!(table.num_buckets(), num_buckets);
+    }
+
+    #[test]
+    fn test_try_insert_needs_rehash() {
+        // Keys sharing both their first group and their tag also share their second group, so
+        // once those two groups are full the BFS has nowhere to go.
+        let mut table = HashTable::with_capacity(256);
+        let hash = |key: u64| table.hasher.hash64(key, table.seed);
+        let signature = |hash: u64| (hash as usize & table.aligned_bucket_mask, Tag::full(hash));
+        let target = signature(hash(0));
+        let keys: Vec<u64> = (0..)
+            .filter(|&key| signature(hash(key)) == target)
+            .take(2 * Group::WIDTH + 1)
+            .collect();
+
+        let mut inserted = 0;
+        let err = keys.iter().find_map(|&key| match table.try_insert(key, !key) {
+            Ok(_) => {
+                inserted += 1;
+                None
+            }
+            Err(err) => Some(err),
+        });
+        assert_eq!(err, Some(InsertError::NeedsRehash));
+        assert_eq!(table.len(), inserted);
+        // Retrying fails the same way, still without counting the key.
+        let failed = keys[inserted];
+        assert_eq!(table.try_insert(failed, 0), Err(InsertError::NeedsRehash));
+        assert_eq!(table.len(), inserted);
+        assert_eq!(table.get(&failed), None);
+        for key in &keys[..inserted] {
+            assert_eq!(table.get(key), Some(&!key));
+        }
+    }
+
+    #[test]
+    fn test_insert_with_budget() {
+        let mut table = HashTable::with_capacity(4096);
+        let n = table.items_until_growth as u64 - 64;
+        for key in 0..n {
+            let _ = table.insert(key, key);
+        }
+        // A key whose two groups are full and whose first BFS node is full too.
+        let (key, err) = (n..)
+            .map(|key| (key, table.insert_with_budget(key, !key, 1)))
+            .find_map(|(key, result)| result.err().map(|err| (key, err)))
+            .unwrap();
+        assert_eq!(err, InsertError::BudgetExceeded);
+        assert_eq!(table.get(&key), None);
+        let len = table.len();
+
+        // Same for a zero budget, which gives up as soon as the BFS would start.
+        assert_eq!(table.insert_with_budget(key, !key, 0), Err(InsertError::BudgetExceeded));
+        assert_eq!(table.len(), len);
+
+        // A full budget searches the whole tree and places it.
+        assert!(table.insert_with_budget(key, !key, usize::MAX).unwrap().0);
+        assert_eq!(table.get(&key), Some(&!key));
+        for key in 0..n {
+            assert_eq!(table.get(&key), Some(&key));
+        }
+    }
+
+    #[test]
+    fn test_early_return() {
+        for early_return in [false, true] {
+            // Low load, so every key sits in its first group and early return is sound.
+            let mut table = HashTable::with_capacity(1024).with_early_return(early_return);
+            for i in 1..=20 {
+                let _ = table.insert(i, i * 10);
+            }
+            assert_eq!(table.find_entry(5).unwrap().remove(), 50);
+
+            assert_eq!(table.get(&5), None);
+            assert_eq!(table.probe_length(5), (if early_return { 1 } else { 2 }, 0, false));
+            for i in (1..=20).filter(|&i| i != 5) {
+                assert_eq!(table.get(&i), Some(&(i * 10)));
+                assert_eq!(table.probe_length(i), (1, 1, true));
+            }
+        }
+    }
+
+    #[test]
+    fn test_both_groups_loaded() {
+        for double_hashing in [false, true] {
+            let build = |load_both_groups: bool| {
+                HashTable::<u64>::with_capacity(4096)
+                    .with_double_hashing(double_hashing)
+                    .with_both_groups_loaded(load_both_groups)
+                    .with_tag_false_positive_counting(true)
+            };
+            let (mut sequential, mut parallel) = (build(false), build(true));
+            let mut rng = fastrand::Rng::with_seed(2135);
+            // At the growth limit, so plenty of keys live in their second group.
+            for _ in 0..sequential.items_until_growth {
+                let key = rng.u64(..);
+                let _ = sequential.insert(key, key);
+                let _ = parallel.insert(key, key);
+            }
+            // Replaying the seed gives present keys, then absent ones.
+            let mut rng = fastrand::Rng::with_seed(2135);
+            let keys = (0..2 * sequential.len()).map(|_| rng.u64(..)).collect::<Vec<_>>();
+            for key in keys {
+                assert_eq!(parallel.get(&key), sequential.get(&key), "key {key}");
+            }
+            assert_eq!(parallel.tag_false_positives(), sequential.tag_false_positives());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "early return")]
+    fn test_both_groups_loaded_with_early_return_panics() {
+        let _ = HashTable::<u64>::with_capacity(64).with_both_groups_loaded(true).with_early_return(true);
+    }
+
+    #[test]
+    fn test_adversarial_keys_vs_keyed_hash() {
+        // Knowing the seed, an attacker can search for keys with the same first group and tag
+        // under `fold_hash_fast`; they then share their second group too, so at most two groups'
+        // worth of them fit.
+        let mut fast = HashTable::<u64>::with_capacity(1024);
+        let signature = |key: u64| {
+            let hash = fast.hasher.hash64(key, fast.seed);
+            (hash as usize & fast.aligned_bucket_mask, Tag::full(hash))
+        };
+        let target = signature(0);
+        let keys: Vec<u64> = (0..)
+            .filter(|&key| signature(key) == target)
+            .take(4 * Group::WIDTH)
+            .collect();
+        let failures = keys.iter().filter(|&&key| fast.try_insert(key, key).is_err()).count();
+        assert!(failures >= keys.len() - 2 * Group::WIDTH);
+
+        // The same keys are unremarkable under a hash the attacker doesn't know the key to.
+        let mut rng = fastrand::Rng::with_seed(42);
+        let hasher = SipHash13::new(rng.u64(..), rng.u64(..));
+        let mut keyed = HashTable::<u64, _>::with_capacity_and_hasher(1024, hasher);
+        for &key in &keys {
+            assert!(keyed.try_insert(key, key).is_ok());
+        }
+        assert_eq!(keyed.len(), keys.len());
+    }
+
+    #[test]
+    fn test_merge() {
+        let filled = |keys: core::ops::Range<u64>, value: u64| {
+            let mut table = HashTable::with_capacity(16);
+            for key in keys {
+                let _ = table.insert(key, value);
+            }
+            table
+        };
+
+        // Last write wins.
+        let mut merged = HashTable::with_capacity(16);
+        merged.merge(filled(0..1000, 1));
+        merged.merge(filled(500..3000, 2));
+        assert_eq!(merged.len(), 3000);
+        for key in 0..3000 {
+            assert_eq!(merged.get(&key), Some(&if key < 500 { 1 } else { 2 }));
+        }
+
+        let mut left = filled(0..1000, 1);
+        left.merge_with(filled(500..3000, 2), |value, other_value| *value += other_value);
+        assert_eq!(left.len(), 3000);
+        for key in 0..3000 {
+            let expected = match key {
+                0..500 => 1,
+                500..1000 => 3,
+                _ => 2,
+            };
+            assert_eq!(left.get(&key), Some(&expected));
+        }
+    }
+
+    #[test]
+    fn test_extend_from() {
+        let mut a = HashTable::with_capacity(16);
+        let mut b = HashTable::with_capacity(16);
+        for key in 0..600u64 {
+            let _ = a.insert(key, key);
+        }
+        for key in 400..1000u64 {
+            let _ = b.insert(key, key + 1);
+        }
+        let b_entries: HashMap<u64, u64> = b.iter().map(|(key, &value)| (key, value)).collect();
+
+        a.extend_from(&b);
+        assert_eq!(a.len(), 1000);
+        for key in 0..1000u64 {
+            let expected = if key < 400 { key } else { key + 1 };
+            assert_eq!(a.get(&key), Some(&expected));
+        }
+        assert_eq!(b.len(), 600);
+        assert_eq!(b.iter().map(|(key, &value)| (key, value)).collect::<HashMap<_, _>>(), b_entries);
+    }
+
+    #[test]
+    fn test_from_iter() {
+        // No useful size hint, so this grows.
+        let mut table: HashTable<u64> = (0..1000u64).filter(|k| k % 3 != 0).map(|k| (k, k * 2)).collect();
+        assert_eq!(table.len(), 666);
+        for key in 0..1000 {
+            assert_eq!(table.get(&key).copied(), (key % 3 != 0).then_some(key * 2));
+        }
+
+        let mut table = HashTable::from_exact_iter((0..1000u32).map(|k| (k as u64 % 500, k as u64)));
+        assert_eq!(table.len(), 500);
+        for key in 0..500 {
+            assert_eq!(table.get(&key), Some(&(key + 500)));
+        }
+        assert!(HashTable::<u64>::from_exact_iter([]).is_empty());
+    }
+
+    #[test]
+    fn test_grow_double() {
+        let mut table = HashTable::<u64>::default();
+        table.grow_double();
+        assert_eq!(table.num_buckets(), capacity_to_buckets(INITIAL_CAPACITY));
+
+        for double_hashing in [false, true] {
+            let mut table = HashTable::with_capacity(1024).with_double_hashing(double_hashing);
+            let n = table.items_until_growth as u64;
+            for key in 0..n {
+                let _ = table.insert(key, !key);
+            }
+            // Holes too.
+            for key in (0..n).step_by(5) {
+                table.find_entry(key).unwrap().remove();
+            }
+            let num_buckets = table.num_buckets();
+            let len = table.len();
+            table.grow_double();
+            assert_eq!(table.num_buckets(), num_buckets * 2);
+            assert_eq!(table.len(), len);
+            assert_eq!(table.occupancy_bitmap().iter().filter(|&&full| full).count(), len);
+            for key in 0..n {
+                assert_eq!(table.get(&key).copied(), (key % 5 != 0).then_some(!key));
+            }
+            // Room for another table's worth without growing again.
+            for key in n..2 * n {
+                let _ = table.insert(key, !key);
+            }
+            assert_eq!(table.num_buckets(), num_buckets * 2);
+        }
+    }
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(HashTable::<u64>::default().keys().count(), 0);
+        let mut rng = fastrand::Rng::with_seed(11);
+        let mut inserted: Vec<u64> = (0..3000).map(|_| rng.u64(..)).collect();
+        let mut table = HashTable::new();
+        for &key in &inserted {
+            let _ = table.insert(key, 0);
+        }
+        let mut keys: Vec<u64> = table.keys().copied().collect();
+        keys.sort_unstable();
+        inserted.sort_unstable();
+        assert_eq!(keys, inserted);
+    }
+
+    #[test]
+    fn test_count_empty_groups_sampled() {
+        assert_eq!(HashTable::<u64>::default().count_empty_groups_sampled(4), 0.0);
+        let mut table = HashTable::with_capacity(1 << 16);
+        let mut rng = fastrand::Rng::with_seed(3);
+        for _ in 0..table.items_until_growth * 3 / 4 {
+            let _ = table.insert(rng.u64(..), 0);
+        }
+        let load = table.len() as f64 / table.num_buckets() as f64;
+        // Every group: exact, up to rounding.
+        assert!((table.count_empty_groups_sampled(1) - load).abs() < 1e-9);
+        for stride in [2, 4, 16] {
+            let estimate = table.count_empty_groups_sampled(stride);
+            assert!((estimate - load).abs() < 0.03, "stride {stride}: {estimate} vs {load}");
+        }
+    }
+
+    #[test]
+    fn test_occupancy_bitmap() {
+        assert!(HashTable::<u64>::default().occupancy_bitmap().is_empty());
+        let mut table = HashTable::with_capacity(1024);
+        for key in 0..700 {
+            let _ = table.insert(key, key);
+        }
+        table.find_entry(3).unwrap().remove();
+        let bitmap = table.occupancy_bitmap();
+        assert_eq!(bitmap.len(), table.num_buckets());
+        assert_eq!(bitmap.iter().filter(|&&full| full).count(), table.len());
+        for key in 4..700 {
+            assert!(bitmap[table.find_index(key).unwrap()]);
+        }
+    }
+
+    #[test]
+    fn test_recount() {
+        assert_eq!(HashTable::<u64>::default().recount(), 0);
+        let mut rng = fastrand::Rng::with_seed(2115);
+        let mut table = HashTable::with_capacity(1000);
+        let mut keys = Vec::new();
+        // Mostly inserts, filling the table past its starting size so it relocates and grows,
+        // with some removals of present keys.
+        for _ in 0..20_000 {
+            if !keys.is_empty() && rng.u8(..4) == 0 {
+                let key = keys.swap_remove(rng.usize(..keys.len()));
+                table.find_entry(key).unwrap().remove();
+            } else {
+                let key = rng.u64(..);
+                table.replace(key, key);
+                keys.push(key);
+            }
+            assert_eq!(table.recount(), table.len());
+        }
+        assert_eq!(table.len(), keys.len());
+    }
+
+    #[test]
+    fn test_extend_prefetched() {
+        let mut rng = fastrand::Rng::with_seed(2114);
+        // Fewer, as many as, and more keys than the prefetch distance, with repeats.
+        for n in [0, 3, EXTEND_PREFETCH_DISTANCE, 5000] {
+            let pairs = (0..n).map(|i| (rng.u64(..n as u64 / 2 + 1), i as u64)).collect::<Vec<_>>();
+            let mut expected = HashTable::new();
+            expected.extend(pairs.iter().copied());
+            let mut table = HashTable::new();
+            table.extend_prefetched(pairs.iter().copied());
+            assert_eq!(table.len(), expected.len());
+            for &(key, _) in &pairs {
+                assert_eq!(table.get(&key), expected.get(&key).copied().as_ref());
+            }
+        }
+        // The last value wins.
+        let mut table = HashTable::new();
+        table.extend_prefetched([(1, 10), (2, 20), (1, 11)]);
+        assert_eq!((table.len(), table.get(&1)), (2, Some(&11)));
+    }
+
+    #[test]
+    fn test_maybe_reseed() {
+        let mut table = HashTable::with_capacity(1024);
+        let mut rng = fastrand::Rng::with_seed(2111);
+        let mut keys = (0..200).map(|_| rng.u64(..)).collect::<Vec<_>>();
+        for &key in &keys {
+            let _ = table.insert(key, key);
+        }
+        let baseline = table.avg_insert_probe_length();
+        table.maybe_reseed(1.2);
+        assert_eq!(table.avg_insert_probe_length(), baseline);
+
+        // Adversarial keys sharing one first group, so all but the first few go to their second
+        // group or through the BFS.
+        let target = table.candidate_indices(keys[0]).0;
+        keys.extend((0..).filter(|&key| table.candidate_indices(key).0 == target).take(8 * Group::WIDTH));
+        for &key in &keys[200..] {
+            let _ = table.insert(key, key);
+        }
+        let attacked = table.avg_insert_probe_length();
+        assert!(attacked > 1.2, "{attacked}");
+        table.maybe_reseed(1.2);
+        assert!(table.avg_insert_probe_length() < attacked);
+        assert_eq!(table.len(), keys.len());
+        for &key in &keys {
+            assert_eq!(table.get(&key), Some(&key));
+        }
+    }
+
+    #[test]
+    fn test_probe_length_key_comparisons() {
+        let mut table = HashTable::with_capacity(64);
+        // Keys sharing key 1's tag and first group, so every lookup among them compares each
+        // one placed before it.
+        let (hasher, seed) = (table.hasher, table.seed);
+        let tag = |key| Tag::full(hasher.hash64(key, seed));
+        let colliders: Vec<u64> = (1..)
+            .filter(|&key| tag(key) == tag(1) && table.candidate_indices(key).0 == table.candidate_indices(1).0)
+            .take(4)
+            .collect();
+        for &key in &colliders {
+            let _ = table.insert(key, key);
+        }
+        for (i, &key) in colliders.iter().enumerate() {
+            let (groups, key_comparisons, found) = table.probe_length(key);
+            assert!(found);
+            assert_eq!(groups, 1);
+            assert_eq!(key_comparisons, i + 1);
+        }
+        let (groups, key_comparisons, found) = table.probe_length(colliders[3]);
+        assert!(key_comparisons > groups, "{key_comparisons} vs {groups}");
+        assert!(found);
+        // A miss on an unrelated key compares nothing, however many groups it scans.
+        let absent = (1 << 40..).find(|&key| tag(key) != tag(1)).unwrap();
+        assert_eq!(table.probe_length(absent), (2, 0, false));
+    }
+
+    #[test]
+    fn test_lookup_cache() {
+        let mut table = HashTable::with_capacity(64).with_lookup_cache(true);
+        for key in 0..40 {
+            let _ = table.insert(key, key * 10);
+        }
+        assert_eq!(table.get(&7), Some(&70));
+        assert_eq!(table.lookup_cache_hits(), 0);
+        for _ in 0..3 {
+            assert_eq!(table.get(&7), Some(&70));
+        }
+        assert_eq!(table.lookup_cache_hits(), 3);
+        // An update in place keeps the slot, and the cache sees the new value.
+        let _ = table.insert(7, 71);
+        assert_eq!(table.get(&7), Some(&71));
+        assert_eq!(table.lookup_cache_hits(), 4);
+        // A miss, or a different key, goes through the table.
+        assert_eq!(table.get(&1000), None);
+        assert_eq!(table.get(&8), Some(&80));
+        assert_eq!(table.lookup_cache_hits(), 4);
+
+        // Growing moves every entry.
+        for key in 40..1000 {
+            let _ = table.insert(key, key * 10);
+        }
+        let hits = table.lookup_cache_hits();
+        assert_eq!(table.get(&8), Some(&80));
+        assert_eq!(table.lookup_cache_hits(), hits);
+
+        // Erasing clears the cache.
+        assert_eq!(table.get(&8), Some(&80));
+        assert_eq!(table.lookup_cache_hits(), hits + 1);
+        assert_eq!(table.find_entry(8).unwrap().remove(), 80);
+        assert_eq!(table.get(&8), None);
+        assert_eq!(table.lookup_cache_hits(), hits + 1);
+    }
+
+    #[test]
+    fn test_lookup_cache_invalidated_by_relocation() {
+        // Fill 4-slot groups to the growth threshold, so that later inserts go through the BFS
+        // and move entries.
+        let mut table = HashTable::<u64, FoldHashFast, 4>::with_capacity_and_hasher(1 << 10, FoldHashFast)
+            .with_lookup_cache(true);
+        let mut rng = fastrand::Rng::with_seed(2121);
+        let mut keys = Vec::new();
+        while table.len() < table.num_buckets() * 7 / 8 - 1 {
+            let key = rng.u64(..);
+            let _ = table.insert(key, key);
+            keys.push(key);
+        }
+        let mut relocations = 0;
+        while relocations < 100 {
+            // Cache a key in the new key's first group, where the BFS frees a slot by moving an
+            // entry out.
+            let new_key = rng.u64(..);
+            let (pos0, _) = table.candidate_indices(new_key);
+            let Some(&cached) = keys.iter().find(|&&key| table.find_entry(key).unwrap().index() / 4 == pos0 / 4)
+            else {
+                continue;
+            };
+            assert_eq!(table.get(&cached), Some(&cached));
+            let result = table.insert_tracked(new_key, new_key);
+            relocations += result.relocated as usize;
+            // The cached slot must still hold `cached`, wherever the BFS moved it.
+            assert_eq!(table.get(&cached), Some(&cached));
+            unsafe { table.erase_index(result.index) };
+        }
+    }
+
+    #[test]
+    fn test_tag_false_positives() {
+        for enabled in [false, true] {
+            let mut table = HashTable::with_capacity(64).with_tag_false_positive_counting(enabled);
+            let _ = table.insert(1, 10);
+            // A key with the same tag and first group as key 1, so `get` compares it against 1.
+            let tag = |key| Tag::full(table.hasher.hash64(key, table.seed));
+            let collider = (2..)
+                .find(|&key| {
+                    tag(key) == tag(1) && table.candidate_indices(key).0 == table.candidate_indices(1).0
+                })
+                .unwrap();
+            assert_eq!(table.get(&1), Some(&10));
+            assert_eq!(table.tag_false_positives(), 0);
+            assert_eq!(table.get(&collider), None);
+            if enabled {
+                assert!(table.tag_false_positives() >= 1);
+            } else {
+                assert_eq!(table.tag_false_positives(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sample_one() {
+        let mut rng = fastrand::Rng::with_seed(2107);
+        assert_eq!(HashTable::<u64>::default().sample_one(&mut rng), None);
+        let mut table = HashTable::with_capacity(1024);
+        for key in 0..300 {
+            let _ = table.insert(key, key * 3);
+        }
+        let mut seen = vec![0; 300];
+        for _ in 0..100_000 {
+            let (key, &value) = table.sample_one(&mut rng).unwrap();
+            assert_eq!(value, key * 3);
+            seen[key as usize] += 1;
+        }
+        assert!(seen.iter().all(|&count| count > 0));
+
+        // A single entry is found from any start, including by wrapping around.
+        let mut table = HashTable::with_capacity(1024);
+        let _ = table.insert(5, 50);
+        for _ in 0..100 {
+            assert_eq!(table.sample_one(&mut rng), Some((5, &50)));
+        }
+    }
+
+    #[test]
+    fn test_set() {
+        use std::collections::HashSet;
+
+        let mut rng = fastrand::Rng::with_seed(7);
+        let mut table = HashTable::<()>::new();
+        let mut std_set = HashSet::new();
+        for _ in 0..5000 {
+            // Small keys, so many inserts are repeats.
+            let key = rng.u64(..2000);
+            assert_eq!(table.insert_key(key), std_set.insert(key));
+        }
+        assert_eq!(table.len(), std_set.len());
+        for key in 0..3000 {
+            assert_eq!(table.contains(key), std_set.contains(&key));
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "insert_and_erase called on a table with tombstones")]
+    fn test_insert_and_erase_after_erase_panics() {
+        let mut table = HashTable::with_capacity(1024);
+        for key in 0..100 {
+            let _ = table.insert(key, key);
+        }
+        unsafe { table.insert_and_erase(1000, 0) };
+        table.find_entry(5).unwrap().remove();
+        unsafe { table.insert_and_erase(1001, 0) };
+    }
+
+    #[test]
+    fn test_candidate_indices() {
+        for double_hashing in [false, true] {
+            let mut table = HashTable::with_capacity(1024).with_double_hashing(double_hashing);
+            let n = table.items_until_growth as u64;
+            for key in 0..n {
+                let _ = table.insert(key, key);
+            }
+            // Including keys the BFS moved to their other group.
+            for key in 0..n {
+                let (pos0, pos1) = table.candidate_indices(key);
+                assert!(pos0 % Group::WIDTH == 0 && pos1 % Group::WIDTH == 0);
+                let index = table.find_index(key).unwrap();
+                assert!((pos0..pos0 + Group::WIDTH).contains(&index) || (pos1..pos1 + Group::WIDTH).contains(&index));
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_of() {
+        for double_hashing in [false, true] {
+            let mut table = HashTable::<u64>::with_capacity(1024).with_double_hashing(double_hashing);
+            let hashes = (0..100).map(|key| table.hash_of(key)).collect::<Vec<_>>();
+            for key in 0..100 {
+                let _ = table.insert(key, key);
+            }
+            for key in 0..100 {
+                let hash = table.hash_of(key);
+                assert_eq!(hash, hashes[key as usize], "stable across inserts");
+                assert_eq!(hash, crate::u64_fold_hash_fast::fold_hash_fast(key, table.seed));
+                let hash1 = second_hash(double_hashing, hash, Tag::full(hash));
+                assert_eq!(
+                    table.candidate_indices(key),
+                    (hash as usize & table.aligned_bucket_mask, hash1 as usize & table.aligned_bucket_mask)
+                );
+            }
+        }
+        // The first key into an empty table lands at the start of the group its hash picks.
+        let mut table = HashTable::<u64>::with_capacity(1024);
+        let index = table.insert(7, 7).index();
+        assert_eq!(index, table.hash_of(7) as usize & table.aligned_bucket_mask);
+    }
+
+    #[test]
+    fn test_reserve() {
+        let mut table = HashTable::<u64>::default();
+        table.reserve(1000);
+        let num_buckets = table.num_buckets();
+        for key in 0..1000 {
+            let _ = table.insert(key, key);
+        }
+        assert_eq!(table.num_buckets(), num_buckets);
+
+        table.reserve(5000);
+        let num_buckets = table.num_buckets();
+        for key in 1000..6000 {
+            let _ = table.insert(key, key);
+        }
+        assert_eq!(table.num_buckets(), num_buckets);
+        for key in 0..6000 {
+            assert_eq!(table.get(&key), Some(&key));
+        }
+    }
+
+    #[test]
+    fn test_grow_shrink_reseed() {
+        let mut table = HashTable::<u64>::with_capacity(64);
+        for key in 0..10_000 {
+            let _ = table.insert(key, key * 3);
+        }
+        let grown = table.num_buckets();
+        for key in 1000..10_000 {
+            table.find_entry(key).unwrap().remove();
+        }
+
+        table.shrink_to(4000);
+        assert!(table.num_buckets() < grown);
+        assert!(table.items_until_growth >= 3000);
+        table.shrink_to_fit();
+        assert_eq!(table.num_buckets(), capacity_to_buckets(1000));
+        let seed = table.seed;
+        table.reset_with_seed(seed.wrapping_add(1));
+        assert_eq!(table.num_buckets(), capacity_to_buckets(1000));
+
+        assert_eq!(table.len(), 1000);
+        for key in 0..10_000 {
+            let expected = (key < 1000).then_some(key * 3);
+            assert_eq!(table.get(&key).copied(), expected, "key {key}");
+        }
+        // Still a working table afterwards.
+        for key in 1000..2000 {
+            let _ = table.insert(key, key * 3);
+        }
+        for key in 0..2000 {
+            assert_eq!(table.get(&key), Some(&(key * 3)));
+        }
+    }
+
+    #[test]
+    fn test_try_rebuild_retries_larger() {
+        let mut table = HashTable::with_capacity(1000);
+        for key in 0..1000 {
+            let _ = table.insert(key, key * 2);
+        }
+        let num_buckets = table.num_buckets();
+        // Far too small for 1000 entries: the rebuild doubles until they fit, from the old
+        // allocation each time.
+        assert_eq!(table.try_rebuild(num_buckets / 4, table.seed), Ok(()));
+        assert_eq!(table.num_buckets(), num_buckets);
+        assert_eq!(table.len(), 1000);
+        for key in 0..1000 {
+            assert_eq!(table.get(&key), Some(&(key * 2)));
+        }
+
+        // A failed allocation leaves everything as it was.
+        let seed = table.seed;
+        assert!(matches!(table.try_rebuild(1 << 50, seed ^ 1), Err(TryReserveError::AllocError { .. })));
+        assert_eq!((table.num_buckets(), table.seed, table.len()), (num_buckets, seed, 1000));
+        for key in 0..1000 {
+            assert_eq!(table.get(&key), Some(&(key * 2)));
+        }
+    }
+
+    #[test]
+    fn test_try_reserve() {
+        let mut table = HashTable::with_capacity(64);
+        for key in 0..50 {
+            let _ = table.insert(key, key);
+        }
+        let num_buckets = table.num_buckets();
+        assert_eq!(table.try_reserve(usize::MAX), Err(TryReserveError::CapacityOverflow));
+        // A valid layout far bigger than the address space, so the allocator returns null.
+        assert!(matches!(table.try_reserve(1 << 50), Err(TryReserveError::AllocError { .. })));
+
+        // The table is untouched and still usable.
+        assert_eq!(table.num_buckets(), num_buckets);
+        assert_eq!(table.try_reserve(1000), Ok(()));
+        assert!(table.num_buckets() >= 1050);
+        for key in 0..50 {
+            assert_eq!(table.get(&key), Some(&key));
+        }
+
+        let mut table = HashTable::<u64>::default();
+        assert!(matches!(table.try_reserve(1 << 50), Err(TryReserveError::AllocError { .. })));
+        assert_eq!(table.num_buckets(), 0);
+        let _ = table.insert(1, 1);
+        assert_eq!(table.get(&1), Some(&1));
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let mut table = HashTable::with_capacity(16);
+        let mut rng = fastrand::Rng::with_seed(123);
+        let mut expected: Vec<(u64, u64)> = (0..5000).map(|_| (rng.u64(..), rng.u64(..))).collect();
+        for &(key, value) in &expected {
+            let _ = table.insert(key, value);
+        }
+
+        let mut pairs: Vec<(u64, u64)> = table.into_iter().collect();
+        pairs.sort_unstable();
+        expected.sort_unstable();
+        assert_eq!(pairs, expected);
+
+        // Dropping a partly consumed iterator, or one over a never-allocated table.
+        let mut table = HashTable::with_capacity(64);
+        for key in 0..50 {
+            let _ = table.insert(key, key);
+        }
+        assert_eq!(table.into_iter().take(10).count(), 10);
+        assert_eq!(HashTable::<u64>::default().into_iter().next(), None);
+    }
+
+    // Small enough to run under Miri, which checks that dropping the iterators frees the table.
+    #[test]
+    fn test_into_keys_and_values() {
+        let build = || {
+            let mut table = HashTable::with_capacity(64);
+            for key in 0..50 {
+                let _ = table.insert(key, key * 10);
+            }
+            table
+        };
+        let mut keys: Vec<u64> = build().into_keys().collect();
+        keys.sort_unstable();
+        assert_eq!(keys, (0..50).collect::<Vec<_>>());
+        let mut values: Vec<u64> = build().into_values().collect();
+        values.sort_unstable();
+        assert_eq!(values, (0..50).map(|key| key * 10).collect::<Vec<_>>());
+
+        // Partly consumed, then dropped; and never allocated.
+        assert_eq!(build().into_keys().take(10).count(), 10);
+        assert_eq!(build().into_values().take(10).count(), 10);
+        assert_eq!(HashTable::<u64>::default().into_keys().next(), None);
+        assert_eq!(HashTable::<u64>::default().into_values().next(), None);
+    }
+
+    #[test]
+    fn test_with_capacity_and_load() {
+        for capacity in [100, 600, 5000] {
+            let default = HashTable::<u64>::with_capacity(capacity);
+            let half = HashTable::<u64>::with_capacity_and_load(capacity, 0.5);
+            assert_eq!(half.num_buckets(), 2 * default.num_buckets());
+            assert!(half.num_buckets() >= 2 * capacity);
+        }
+        // Exact multiples don't round up a power of two.
+        assert_eq!(HashTable::<u64>::with_capacity_and_load(512, 0.5).num_buckets(), 1024);
+
+        let mut table = HashTable::with_capacity_and_load(1000, 0.75);
+        for key in 0..1000 {
+            let _ = table.insert(key, key);
+        }
+        assert!(table.len() as f64 <= 0.75 * table.num_buckets() as f64);
+        for key in 0..1000 {
+            assert_eq!(table.get(&key), Some(&key));
+        }
+    }
 }
@@ -0,0 +1,317 @@
+//! Variant of `aligned_cuckoo_table` with a parallel array of 16-bit control words (see
+//! `control16`) in place of 7-bit tags, to measure what tag false positives cost in `find_miss`.
+//! Fixed capacity: no growth, erase or double hashing.
+
+use core::hint::likely;
+use core::{alloc::Layout, ptr::NonNull};
+use core::fmt;
+
+use crate::{TRACK_PROBE_LENGTH, TryReserveError, infallible};
+use crate::control16::{self, EMPTY, GROUP_WIDTH, tag16};
+use crate::cuckoo_bfs::{self, CuckooSlots};
+use crate::dropper::{self, Dropper};
+use crate::u64_fold_hash_fast::fold_hash_fast;
+use crate::uunwrap::UUnwrap;
+use crate::DebugEntries;
+
+pub struct HashTable<V: Copy> {
+    aligned_bucket_mask: usize,
+
+    // [Padding], T_n, ..., T1, T0, C0, C1, ...
+    //                              ^ points here
+    ctrl: NonNull<u16>,
+
+    // Number of elements in the table, only really used by len()
+    items: usize,
+
+    // Seed for the hash function
+    seed: u64,
+
+    total_probe_length: usize,
+
+    marker: core::marker::PhantomData<V>,
+    dropper: Dropper,
+}
+
+// SAFETY: `ctrl` points into the allocation `dropper` owns, with the `(u64, V)` buckets below it
+// and the `u16` control words above, and no other table shares it. `set_ctrl` and the BFS's moves
+// write through it, but only under `&mut self`.
+unsafe impl<V: Copy + Send> Send for HashTable<V> {}
+unsafe impl<V: Copy + Sync> Sync for HashTable<V> {}
+
+impl<V: Copy> HashTable<V> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_huge_pages(capacity, false)
+    }
+
+    /// As `with_capacity`, but on huge pages where the kernel allows; see `dropper::alloc`.
+    #[cfg(target_os = "linux")]
+    pub fn with_capacity_hugepage(capacity: usize) -> Self {
+        Self::with_capacity_and_huge_pages(capacity, true)
+    }
+
+    fn with_capacity_and_huge_pages(capacity: usize, huge_pages: bool) -> Self {
+        // Calculate sizes
+        let num_buckets = infallible(
+            capacity
+                .checked_mul(8)
+                .and_then(|n| (n / 7).checked_next_power_of_two())
+                .map(|n| n.max(GROUP_WIDTH))
+                .ok_or(TryReserveError::CapacityOverflow),
+        );
+        let bucket_size = core::mem::size_of::<(u64, V)>();
+        // A whole group of control words is one 32-byte AVX2 load.
+        let align = core::mem::align_of::<(u64, V)>().max(32);
+        let ctrl_offset = (bucket_size * num_buckets).next_multiple_of(align);
+        let size = ctrl_offset + num_buckets * core::mem::size_of::<u16>();
+        let layout = Layout::from_size_align(size, align).uunwrap();
+        // Allocate
+        let (alloc, layout) = unsafe { dropper::alloc(layout, huge_pages) };
+        if alloc.is_null() {
+            alloc::alloc::handle_alloc_error(layout);
+        }
+        // Write control
+        let ctrl = unsafe { NonNull::new_unchecked(alloc.add(ctrl_offset).cast::<u16>()) };
+        let ctrl_slice = unsafe { core::slice::from_raw_parts_mut(ctrl.as_ptr(), num_buckets) };
+        ctrl_slice.fill(EMPTY);
+        let seed = fastrand::Rng::with_seed(123).u64(..);
+
+        Self {
+            aligned_bucket_mask: num_buckets - GROUP_WIDTH,
+            ctrl,
+            items: 0,
+            seed,
+            total_probe_length: 0,
+            marker: core::marker::PhantomData,
+            dropper: Dropper { alloc, layout },
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn print_stats(&self) {
+        println!(
+            "  avg_probe_length: {}",
+            self.total_probe_length as f64 / self.items as f64
+        );
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The hash of `key`. Its low bits pick the first group and `hash >> 49` is the 16-bit tag;
+    /// the second group is the first XOR the scrambled tag.
+    #[inline(always)]
+    pub fn hash_of(&self, key: u64) -> u64 {
+        fold_hash_fast(key, self.seed)
+    }
+
+    /// Returns the number of buckets in the table.
+    #[inline(always)]
+    pub fn num_buckets(&self) -> usize {
+        self.aligned_bucket_mask + GROUP_WIDTH
+    }
+
+    #[inline(always)]
+    pub fn insert(&mut self, key: u64, value: V) -> (bool, usize, usize) {
+        let hash0 = fold_hash_fast(key, self.seed);
+        let tag = tag16(hash0);
+        let pos0 = hash0 as usize & self.aligned_bucket_mask;
+        let pos1 = (hash0 ^ scramble_tag16(tag)) as usize & self.aligned_bucket_mask;
+
+        if let Some(index) = self.find_in_group(pos0, tag, key) {
+            unsafe { (*self.bucket(index)).1 = value };
+            return (false, index, 1);
+        }
+        if let Some(index) = self.find_in_group(pos1, tag, key) {
+            unsafe { (*self.bucket(index)).1 = value };
+            return (false, index, 2);
+        }
+
+        let (bucket_index, insertion_probe_length) = if let Some(index) = self.first_empty(pos0) {
+            (index, 1)
+        } else if let Some(index) = self.first_empty(pos1) {
+            (index, 2)
+        } else {
+            cuckoo_bfs::make_room(self, pos0, pos1, 3, usize::MAX)
+                .unwrap_or_else(|_| panic!("Failed to insert into cuckoo table; need to rehash"))
+        };
+        unsafe {
+            self.bucket(bucket_index).write((key, value));
+            self.set_ctrl(bucket_index, tag);
+        }
+        self.items += 1;
+        (true, bucket_index, insertion_probe_length)
+    }
+
+    #[inline(always)]
+    pub fn get(&mut self, key: &u64) -> Option<&V> {
+        let key = *key;
+        let hash0 = fold_hash_fast(key, self.seed);
+        let tag = tag16(hash0);
+        let pos0 = hash0 as usize & self.aligned_bucket_mask;
+        if TRACK_PROBE_LENGTH {
+            self.total_probe_length += 1;
+        }
+        if let Some(index) = self.find_in_group(pos0, tag, key) {
+            return Some(unsafe { &(*self.bucket(index)).1 });
+        }
+        let pos1 = (hash0 ^ scramble_tag16(tag)) as usize & self.aligned_bucket_mask;
+        if TRACK_PROBE_LENGTH {
+            self.total_probe_length += 1;
+        }
+        self.find_in_group(pos1, tag, key)
+            .map(|index| unsafe { &(*self.bucket(index)).1 })
+    }
+
+    #[inline(always)]
+    fn find_in_group(&self, pos: usize, tag: u16, key: u64) -> Option<usize> {
+        let (mut mask, stride) = unsafe { control16::match_tag16(tag, self.ctrl(pos)) };
+        while mask != 0 {
+            let index = pos + mask.trailing_zeros() as usize / stride;
+            if likely(unsafe { (*self.bucket(index)).0 } == key) {
+                return Some(index);
+            }
+            mask &= mask - 1;
+        }
+        None
+    }
+
+    #[inline(always)]
+    fn first_empty(&self, pos: usize) -> Option<usize> {
+        let (mask, stride) = unsafe { control16::match_empty16(self.ctrl(pos)) };
+        (mask != 0).then(|| pos + mask.trailing_zeros() as usize / stride)
+    }
+
+    /// Given a full slot at `index` in the group at `pos`, returns the slot's other group.
+    #[inline(always)]
+    fn other_pos(&self, pos: usize, index: usize) -> usize {
+        let tag = unsafe { *self.ctrl(index) };
+        pos ^ (scramble_tag16(tag) as usize & self.aligned_bucket_mask)
+    }
+
+    #[inline(always)]
+    unsafe fn ctrl(&self, index: usize) -> *mut u16 {
+        self.ctrl.as_ptr().add(index)
+    }
+
+    #[inline(always)]
+    unsafe fn bucket(&self, index: usize) -> *mut (u64, V) {
+        let data_end: *mut (u64, V) = self.ctrl.as_ptr().cast();
+        data_end.sub(index + 1)
+    }
+
+    #[inline(always)]
+    unsafe fn set_ctrl(&self, index: usize, tag: u16) {
+        *self.ctrl(index) = tag;
+    }
+}
+
+impl<V: Copy> CuckooSlots for HashTable<V> {
+    const WIDTH: usize = GROUP_WIDTH;
+
+    #[inline(always)]
+    fn other_pos(&self, pos: usize, index: usize) -> usize {
+        Self::other_pos(self, pos, index)
+    }
+
+    #[inline(always)]
+    fn first_empty(&self, pos: usize) -> Option<usize> {
+        Self::first_empty(self, pos)
+    }
+
+    #[inline(always)]
+    unsafe fn move_slot(&mut self, from: usize, to: usize) {
+        self.bucket(to).write(self.bucket(from).read());
+        self.set_ctrl(to, *self.ctrl(from));
+    }
+}
+
+#[inline(always)]
+fn scramble_tag16(tag: u16) -> u64 {
+    (tag as u64).wrapping_mul(MUL).rotate_left(32)
+}
+
+const MUL: u64 = 0x2d35_8dcc_aa6c_78a5;
+
+impl<V: Copy + fmt::Debug> fmt::Debug for HashTable<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = move || {
+            (0..=self.aligned_bucket_mask + GROUP_WIDTH - 1)
+                .filter(move |&index| unsafe { *self.ctrl(index) } != EMPTY)
+                .map(move |index| {
+                    let bucket = unsafe { &*self.bucket(index) };
+                    (bucket.0, &bucket.1)
+                })
+        };
+        f.debug_struct("HashTable")
+            .field("len", &self.len())
+            .field("num_buckets", &self.num_buckets())
+            .field("entries", &DebugEntries(entries))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_randomized_vs_std_hashmap() {
+        let mut table = HashTable::with_capacity(1 << 14);
+        let mut std_map = HashMap::new();
+        let mut rng = fastrand::Rng::with_seed(42);
+        // Fill to the 7/8 maximum load, so most keys share groups with many others.
+        for _ in 0..(1 << 14) * 7 / 8 {
+            let key = rng.u64(..);
+            let value = rng.u64(..);
+            let (inserted, _, _) = table.insert(key, value);
+            assert_eq!(inserted, std_map.insert(key, value).is_none());
+        }
+        assert_eq!(table.len(), std_map.len());
+        for (key, value) in &std_map {
+            assert_eq!(table.get(key), Some(value));
+        }
+        for _ in 0..10_000 {
+            let key = rng.u64(..);
+            assert_eq!(table.get(&key), std_map.get(&key));
+        }
+    }
+
+    #[test]
+    fn test_no_false_negative_on_tag_collision() {
+        // Keys with identical 16-bit tags, so each match mask has several candidates to check.
+        let seed = fastrand::Rng::with_seed(123).u64(..);
+        let tag = tag16(fold_hash_fast(0, seed));
+        let keys: Vec<u64> = (0..)
+            .filter(|&key| tag16(fold_hash_fast(key, seed)) == tag)
+            .take(24)
+            .collect();
+
+        let mut table = HashTable::with_capacity(64);
+        for &key in &keys {
+            assert!(table.insert(key, !key).0);
+        }
+        for &key in &keys {
+            assert_eq!(table.get(&key), Some(&!key));
+        }
+        // A colliding key that was never inserted is still a miss.
+        let absent = (keys[keys.len() - 1] + 1..)
+            .find(|&key| tag16(fold_hash_fast(key, seed)) == tag)
+            .unwrap();
+        assert_eq!(table.get(&absent), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn test_capacity_overflow_panics() {
+        HashTable::<u64>::with_capacity(usize::MAX / 4);
+    }
+}
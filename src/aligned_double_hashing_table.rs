@@ -1,13 +1,15 @@
 //! A quadratic probing hash table for u64 keys. SwissTable design following `hashbrown` crate,
 //! with a lot of features removed but the same optimizations valid.
 
-use std::{alloc::Layout, ptr::NonNull};
+use core::{alloc::Layout, ptr::NonNull};
+use core::fmt;
 
-use crate::control::{Group, Tag, TagSliceExt as _};
-use crate::dropper::Dropper;
+use crate::control::{BitMaskIter, Group, Tag, TagSliceExt as _};
+use crate::dropper::{self, Dropper};
 use crate::u64_fold_hash_fast::fold_hash_fast;
 use crate::uunwrap::UUnwrap;
-use crate::TRACK_PROBE_LENGTH;
+use crate::{InsertError, TRACK_PROBE_LENGTH, TryReserveError, infallible};
+use crate::DebugEntries;
 
 pub struct HashTable<V> {
     // Mask to get an index from a hash value. The value is one less than the
@@ -23,24 +25,38 @@ pub struct HashTable<V> {
     // Number of elements in the table, only really used by len()
     items: usize,
 
+    // Number of `EMPTY` slots that may still be filled before the table must grow. Taking a
+    // `DELETED` slot doesn't consume growth, so probe sequences always end at an `EMPTY` slot.
+    growth_left: usize,
+
+    // Number of `DELETED` slots. Once this passes `num_buckets() / 8` the next `insert` compacts
+    // the table, before tombstones make misses walk most of the probe sequence.
+    tombstones: usize,
+
+    // Growth target as `(numerator, denominator)` of `len()`; see `with_grow_factor`.
+    grow_factor: (usize, usize),
+
+    // Whether the number of groups is prime rather than a power of two; see `with_prime_buckets`.
+    prime_buckets: bool,
+
     // Seed for the hash function
     seed: u64,
 
     total_probe_length: usize,
 
-    marker: std::marker::PhantomData<V>,
+    marker: core::marker::PhantomData<V>,
     dropper: Dropper,
 }
 
-/// Probe sequence based on triangular numbers, which is guaranteed (since our
-/// table size is a power of two) to visit every group of elements exactly once.
-///
-/// A triangular probe has us jump by 1 more group every time. So first we
-/// jump by 1 group (meaning we just continue our linear scan), then 2 groups
-/// (skipping over 1 group), then 3 groups (skipping over 2 groups), and so on.
-///
-/// Proof that the probe will visit every group in the table:
-/// <https://fgiesen.wordpress.com/2015/02/22/triangular-numbers-mod-2n/>
+// SAFETY: `ctrl` points into the allocation `dropper` owns. A rehash builds a new table and moves
+// it into `self`, so no allocation is ever shared between tables. Every write through `ctrl`,
+// including `set_ctrl` despite its `&self`, happens under `&mut self`.
+unsafe impl<V: Send> Send for HashTable<V> {}
+unsafe impl<V: Sync> Sync for HashTable<V> {}
+
+/// Double-hashing probe sequence: a fixed stride of whole groups, chosen per key (see
+/// `probe_seq`). The stride in groups is coprime with the number of groups (odd for a power of
+/// two, anything nonzero for a prime), so the probe visits every group exactly once.
 #[derive(Clone)]
 struct ProbeSeq {
     pos: usize,
@@ -49,52 +65,196 @@ struct ProbeSeq {
 
 impl ProbeSeq {
     #[inline]
-    fn move_next(&mut self, bucket_mask: usize) {
-        // We should have found an empty bucket by now and ended the probe.
-        debug_assert!(
-            self.stride <= bucket_mask,
-            "Went past end of probe sequence"
-        );
+    fn move_next(&mut self, num_buckets: usize) {
+        // Only a single-group table has a stride of the whole table, stepping back to itself.
+        debug_assert!(self.stride <= num_buckets, "stride past the end of the table");
 
+        // Both are at most `num_buckets`, so one subtraction reduces the sum whether or not
+        // `num_buckets` is a power of two, and it can't overflow.
         self.pos += self.stride;
-        self.pos &= bucket_mask;
+        if self.pos >= num_buckets {
+            self.pos -= num_buckets;
+        }
     }
 }
 
 impl<V> HashTable<V> {
     pub fn with_capacity(capacity: usize) -> Self {
-        // Calculate sizes
         // TODO: integer overflow...
-        let num_buckets = ((capacity * 8) / 7).next_power_of_two();
-        let bucket_size = std::mem::size_of::<(u64, V)>();
-        let align = std::mem::align_of::<(u64, V)>().max(Group::WIDTH);
-        let ctrl_offset = (bucket_size * num_buckets).next_multiple_of(align);
-        let size = ctrl_offset + num_buckets;
-        let layout = Layout::from_size_align(size, align).uunwrap();
+        Self::with_num_buckets(min_num_buckets(capacity), false)
+    }
+
+    /// As `with_capacity`, but on huge pages, kept across rehashes; see `dropper::alloc`.
+    #[cfg(target_os = "linux")]
+    pub fn with_capacity_hugepage(capacity: usize) -> Self {
+        Self::with_num_buckets(min_num_buckets(capacity), true)
+    }
+
+    fn with_num_buckets(num_buckets: usize, huge_pages: bool) -> Self {
+        infallible(Self::try_with_num_buckets(num_buckets, huge_pages))
+    }
+
+    /// As `with_num_buckets`, but fails instead of panicking.
+    fn try_with_num_buckets(num_buckets: usize, huge_pages: bool) -> Result<Self, TryReserveError> {
+        // Calculate sizes
+        let bucket_size = core::mem::size_of::<(u64, V)>();
+        let align = core::mem::align_of::<(u64, V)>().max(Group::WIDTH);
+        let overflow = TryReserveError::CapacityOverflow;
+        let ctrl_offset = bucket_size
+            .checked_mul(num_buckets)
+            .and_then(|size| size.checked_next_multiple_of(align))
+            .ok_or(overflow)?;
+        let size = ctrl_offset.checked_add(num_buckets).ok_or(overflow)?;
+        let layout = Layout::from_size_align(size, align).map_err(|_| overflow)?;
         // Allocate
-        let alloc = unsafe { std::alloc::alloc(layout) };
+        let (alloc, layout) = unsafe { dropper::alloc(layout, huge_pages) };
+        if alloc.is_null() {
+            return Err(TryReserveError::AllocError { layout });
+        }
         // Write control
         let ctrl = unsafe { NonNull::new_unchecked(alloc.add(ctrl_offset)) };
         let ctrl_slice =
-            unsafe { std::slice::from_raw_parts_mut(ctrl.as_ptr() as *mut Tag, num_buckets) };
+            unsafe { core::slice::from_raw_parts_mut(ctrl.as_ptr() as *mut Tag, num_buckets) };
         ctrl_slice.fill_empty();
         // dbg!(num_buckets, bucket_size, align, ctrl_offset, size, layout, alloc, ctrl);
         let seed = fastrand::Rng::with_seed(123).u64(..);
         let bucket_mask = num_buckets - 1;
         let aligned_bucket_mask = num_buckets - Group::WIDTH;
 
-        Self {
+        Ok(Self {
             bucket_mask,
             aligned_bucket_mask,
             ctrl,
             items: 0,
+            growth_left: (num_buckets * 7) / 8,
+            tombstones: 0,
+            grow_factor: (2, 1),
+            prime_buckets: false,
             seed,
-            marker: std::marker::PhantomData,
+            marker: core::marker::PhantomData,
             total_probe_length: 0,
             dropper: Dropper { alloc, layout },
+        })
+    }
+
+    /// Sets how far the table grows once it is full: to `len() * numerator / denominator`
+    /// items, at the 7/8 maximum load. The default is `(2, 1)`.
+    ///
+    /// Bucket counts stay powers of two, so any factor up to 2 grows by exactly one doubling and
+    /// the table never ends up with more than twice the buckets it needs; larger factors skip
+    /// intermediate rehashes when the final size is known to be much bigger.
+    pub fn with_grow_factor(mut self, numerator: usize, denominator: usize) -> Self {
+        assert!(denominator > 0, "grow factor denominator must be nonzero");
+        assert!(numerator > denominator, "grow factor must be greater than 1");
+        assert!(try_grown_num_buckets(1, 1, (numerator, denominator)).is_ok(), "grow factor overflows");
+        self.grow_factor = (numerator, denominator);
+        self
+    }
+
+    /// Sizes the table to a prime number of groups, the smallest at least the power of two it
+    /// would otherwise have, and keeps it prime as it grows or shrinks. Probes then reduce the
+    /// hash with `%` instead of a mask, to test whether prime tables cluster less. Rehashes, so
+    /// call it right after `with_capacity`.
+    pub fn with_prime_buckets(mut self, prime_buckets: bool) -> Self {
+        let num_buckets = self.num_buckets().next_power_of_two();
+        self.prime_buckets = prime_buckets;
+        self.rehash(self.sized_num_buckets(num_buckets));
+        self
+    }
+
+    /// The bucket count to use for a power-of-two `num_buckets`: itself, or with
+    /// `prime_buckets`, the next prime number of groups.
+    fn sized_num_buckets(&self, num_buckets: usize) -> usize {
+        if !self.prime_buckets {
+            return num_buckets;
         }
+        next_prime(num_buckets / Group::WIDTH) * Group::WIDTH
+    }
+
+    /// Rehashes every item into a new allocation sized by `grow_factor`.
+    #[cold]
+    #[inline(never)]
+    fn grow(&mut self) {
+        let num_buckets = grown_num_buckets(self.items, self.num_buckets().next_power_of_two(), self.grow_factor);
+        self.rehash(self.sized_num_buckets(num_buckets));
+    }
+
+    /// Rehashes every item into a new allocation of the same size, clearing all tombstones.
+    #[cold]
+    #[inline(never)]
+    pub fn compact(&mut self) {
+        self.rehash(self.num_buckets());
     }
 
+    /// Shrinks the table to the fewest buckets that hold both `len()` and `min_capacity` items
+    /// at the 7/8 maximum load, rehashing every item. Does nothing if that isn't fewer buckets
+    /// than now.
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        let num_buckets = self.sized_num_buckets(min_num_buckets(min_capacity.max(self.items)));
+        if num_buckets < self.num_buckets() {
+            self.rehash(num_buckets);
+        }
+    }
+
+    /// Shrinks the table as far as `len()` allows; see `shrink_to`.
+    pub fn shrink_to_fit(&mut self) {
+        self.shrink_to(0);
+    }
+
+    /// `compact` and, if under a quarter of the slots are full, `shrink_to_fit`, in a single
+    /// rehash. For long-lived tables that see many erases. Does nothing if there is neither a
+    /// tombstone to drop nor space to give back.
+    pub fn gc(&mut self) {
+        let num_buckets = if self.items < self.num_buckets() / 4 {
+            self.sized_num_buckets(min_num_buckets(self.items))
+        } else {
+            self.num_buckets()
+        };
+        if self.tombstones > 0 || num_buckets < self.num_buckets() {
+            self.rehash(num_buckets);
+        }
+    }
+
+    /// Makes sure `additional` more keys can be inserted without growing, returning an error
+    /// instead of panicking when the new size overflows or the allocator fails. The table is
+    /// unchanged on failure.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        if additional <= self.growth_left {
+            return Ok(());
+        }
+        let capacity = self.items.checked_add(additional).ok_or(TryReserveError::CapacityOverflow)?;
+        let num_buckets = capacity
+            .checked_mul(8)
+            .and_then(|n| (n / 7).checked_next_power_of_two())
+            .map(|n| self.sized_num_buckets(n))
+            .ok_or(TryReserveError::CapacityOverflow)?;
+        // Rehashing at the same size is enough if tombstones were taking up the growth.
+        self.try_rehash(num_buckets.max(self.num_buckets()))
+    }
+
+    fn rehash(&mut self, new_num_buckets: usize) {
+        infallible(self.try_rehash(new_num_buckets));
+    }
+
+    /// As `rehash`. The new table is allocated before anything moves, so failure leaves `self`
+    /// unchanged.
+    fn try_rehash(&mut self, new_num_buckets: usize) -> Result<(), TryReserveError> {
+        let num_buckets = self.num_buckets();
+        let mut new_table = Self::try_with_num_buckets(new_num_buckets, self.dropper.huge_pages())?;
+        new_table.grow_factor = self.grow_factor;
+        new_table.prime_buckets = self.prime_buckets;
+        for index in 0..num_buckets {
+            if unsafe { *self.ctrl(index) }.is_full() {
+                let (key, value) = unsafe { self.bucket(index).read() };
+                new_table.insert(key, value);
+            }
+        }
+        new_table.total_probe_length = self.total_probe_length;
+        // The old allocation is freed by its `Dropper`; values were moved out above.
+        *self = new_table;
+        Ok(())
+    }
+    #[cfg(feature = "std")]
     pub fn print_stats(&self) {
         println!(
             "  avg_probe_length: {}",
@@ -107,8 +267,97 @@ impl<V> HashTable<V> {
         self.items
     }
 
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The hash of `key`: the low half picks the first group, the high half the probe stride.
+    #[inline(always)]
+    pub fn hash_of(&self, key: u64) -> u64 {
+        fold_hash_fast(key, self.seed)
+    }
+
+    /// Returns the number of buckets in the table.
+    #[inline(always)]
+    pub fn num_buckets(&self) -> usize {
+        self.bucket_mask + 1
+    }
+
+    /// Returns the number of `DELETED` slots left behind by `erase_index`.
+    #[inline(always)]
+    pub fn tombstone_count(&self) -> usize {
+        self.tombstones
+    }
+
+    /// Returns `(ctrl, bucket_mask, items)`, describing the table's memory in SwissTable's
+    /// layout:
+    /// * `ctrl` points at `bucket_mask + 1` control bytes, one per bucket: `0xFF` for empty,
+    ///   `0x80` for deleted, or the top 7 bits of the key's hash (the tag) for full.
+    /// * Bucket `i` is the `(u64, V)` at `ctrl.cast::<(u64, V)>().sub(i + 1)`: buckets sit right
+    ///   below the control bytes in reverse order, with stride `size_of::<(u64, V)>()`. The
+    ///   allocation begins that many bytes times `bucket_mask + 1`, rounded up to a multiple of
+    ///   `max(align_of::<(u64, V)>(), Group::WIDTH)`, below `ctrl`.
+    ///
+    /// Unlike hashbrown, there are no trailing control bytes replicating the first group, since
+    /// probes only load whole aligned groups. The probe sequence also differs (see `probe_seq`),
+    /// so the buckets can be shared with another SwissTable, but its lookups only find keys after
+    /// a rehash over this memory.
+    #[inline(always)]
+    pub fn as_raw_parts(&self) -> (NonNull<u8>, usize, usize) {
+        (self.ctrl, self.bucket_mask, self.items)
+    }
+
     #[inline(always)]
     pub fn insert(&mut self, key: u64, value: V) -> (bool, usize) {
+        if core::hint::unlikely(self.growth_left == 0) {
+            self.grow();
+        } else if core::hint::unlikely(self.tombstones > self.num_buckets() / 8) {
+            self.compact();
+        }
+        self.insert_no_grow(key, value)
+    }
+
+    /// As `insert`, but never rehashes: fails with `InsertError::NeedsGrowth` at the growth
+    /// threshold (unless `key` is already present), and past the tombstone threshold inserts
+    /// anyway. The table is unchanged on failure. Pair it with `maintain` to keep rehashes off
+    /// the insert path.
+    #[inline(always)]
+    pub fn try_insert(&mut self, key: u64, value: V) -> Result<(bool, usize), InsertError> {
+        if core::hint::unlikely(self.growth_left == 0) {
+            return match self.find_index(key) {
+                Some(index) => {
+                    unsafe { (*self.bucket(index)).1 = value };
+                    Ok((false, index))
+                }
+                None => Err(InsertError::NeedsGrowth),
+            };
+        }
+        Ok(self.insert_no_grow(key, value))
+    }
+
+    /// Grows the table if under `num_buckets() / 16` more keys fit before the growth threshold,
+    /// or else compacts it if there are over `num_buckets() / 16` tombstones, returning whether
+    /// it rehashed. Either way, the next `num_buckets() / 16` inserts and erases afterwards can't
+    /// reach the thresholds at which `insert` grows or compacts, or `try_insert` fails. Call it
+    /// between operations to choose when rehashes happen.
+    pub fn maintain(&mut self) -> bool {
+        let headroom = self.num_buckets() / 16;
+        if self.growth_left <= headroom {
+            // At least doubles, which leaves over `7/8 * num_buckets()` free.
+            self.grow();
+        } else if self.tombstones > headroom {
+            // Tombstones don't count against `growth_left`, so it stays over `headroom`.
+            self.compact();
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Body of `insert` after the growth and compaction checks. Requires `growth_left > 0`.
+    #[inline(always)]
+    fn insert_no_grow(&mut self, key: u64, value: V) -> (bool, usize) {
         let mut insert_slot = None;
         let hash64 = fold_hash_fast(key, self.seed);
         let tag_hash = Tag::full(hash64);
@@ -122,7 +371,7 @@ impl<V> HashTable<V> {
             }
 
             for bit in group.match_tag(tag_hash) {
-                let index = (probe_seq.pos + bit) & self.bucket_mask;
+                let index = probe_seq.pos + bit;
 
                 let bucket = unsafe { self.bucket(index) };
 
@@ -141,10 +390,10 @@ impl<V> HashTable<V> {
 
             if let Some(insert_slot) = insert_slot {
                 if group.match_empty().any_bit_set() {
-                    let insert_slot = insert_slot & self.bucket_mask;
                     unsafe {
-                        // The first Group::WIDTH control slots are replicated as the last Group::WIDTH control slots. We
-                        // write to both.
+                        let was_empty = (*self.ctrl(insert_slot)).special_is_empty();
+                        self.growth_left -= was_empty as usize;
+                        self.tombstones -= !was_empty as usize;
                         self.set_ctrl(insert_slot, tag_hash);
                         self.bucket(insert_slot).write((key, value));
                         self.items += 1;
@@ -153,7 +402,7 @@ impl<V> HashTable<V> {
                 }
             }
 
-            probe_seq.move_next(self.bucket_mask);
+            probe_seq.move_next(self.num_buckets());
         }
     }
 
@@ -168,7 +417,7 @@ impl<V> HashTable<V> {
             let group = unsafe { Group::load(self.ctrl(probe_seq.pos)) };
 
             for bit in group.match_tag(tag_hash) {
-                let index = (probe_seq.pos + bit) & self.bucket_mask;
+                let index = probe_seq.pos + bit;
 
                 let bucket = unsafe { self.bucket(index) };
 
@@ -181,10 +430,64 @@ impl<V> HashTable<V> {
                 return None;
             }
 
-            probe_seq.move_next(self.bucket_mask);
+            probe_seq.move_next(self.num_buckets());
+        }
+    }
+
+    /// Returns the number of groups `get(key)` loads, and whether it finds `key`. Like `get`,
+    /// the probe walks past `DELETED` tombstones and only stops at a group with an `EMPTY` slot.
+    pub fn probe_length(&self, key: u64) -> (usize, bool) {
+        let hash64 = fold_hash_fast(key, self.seed);
+        let tag_hash = Tag::full(hash64);
+        let mut probe_seq = self.probe_seq(hash64);
+        let mut probe_count = 0;
+
+        loop {
+            probe_count += 1;
+            let group = unsafe { Group::load(self.ctrl(probe_seq.pos)) };
+
+            for bit in group.match_tag(tag_hash) {
+                let index = probe_seq.pos + bit;
+                let bucket = unsafe { self.bucket(index) };
+                if unsafe { (*bucket).0 } == key {
+                    return (probe_count, true); // Key found
+                }
+            }
+
+            if group.match_empty().any_bit_set() {
+                return (probe_count, false); // Empty slot found, key absent
+            }
+
+            probe_seq.move_next(self.num_buckets());
         }
     }
 
+    fn find_index(&self, key: u64) -> Option<usize> {
+        let hash64 = fold_hash_fast(key, self.seed);
+        let tag_hash = Tag::full(hash64);
+        let mut probe_seq = self.probe_seq(hash64);
+        loop {
+            let group = unsafe { Group::load(self.ctrl(probe_seq.pos)) };
+            for bit in group.match_tag(tag_hash) {
+                let index = probe_seq.pos + bit;
+                if unsafe { (*self.bucket(index)).0 } == key {
+                    return Some(index);
+                }
+            }
+            if group.match_empty().any_bit_set() {
+                return None;
+            }
+            probe_seq.move_next(self.num_buckets());
+        }
+    }
+
+    /// Inserts `key` and, if it was new, immediately erases it again by resetting its slot to
+    /// `EMPTY`. This is the unit of work of the insert+erase churn benchmark.
+    ///
+    /// # Safety
+    ///
+    /// The table must contain no `DELETED` tombstones: the slot is reset straight to `EMPTY`, which
+    /// is only correct if `insert` took an `EMPTY` slot.
     #[inline(always)]
     pub unsafe fn insert_and_erase(&mut self, key: u64, value: V) {
         let (inserted, index) = self.insert(key, value);
@@ -192,21 +495,83 @@ impl<V> HashTable<V> {
             unsafe {
                 self.set_ctrl(index, Tag::EMPTY);
             }
+            self.items -= 1;
+            self.growth_left += 1;
         }
     }
 
+    /// Folds `f` over the values of all full slots, a control group at a time, without building
+    /// an iterator.
+    pub fn fold_values<B>(&self, init: B, mut f: impl FnMut(B, &V) -> B) -> B {
+        let mut acc = init;
+        for pos in (0..self.num_buckets()).step_by(Group::WIDTH) {
+            for bit in unsafe { Group::load_aligned(self.ctrl(pos)) }.match_full() {
+                acc = f(acc, unsafe { &(*self.bucket(pos + bit)).1 });
+            }
+        }
+        acc
+    }
+
+    /// Iterates over all keys, in slot order.
+    pub fn keys(&self) -> Keys<'_, V> {
+        Keys {
+            table: self,
+            group_pos: 0,
+            bits: unsafe { Group::load_aligned(self.ctrl(0)) }.match_full().into_iter(),
+        }
+    }
+
+    /// Iterates over all entries, in slot order, removing and yielding those for which `f`
+    /// returns true. Each entry is removed (see `erase_index`) before it is yielded, so dropping
+    /// the iterator early leaves the rest in the table.
+    pub fn extract_if<F: FnMut(&u64, &mut V) -> bool>(&mut self, f: F) -> ExtractIf<'_, V, F> {
+        let bits = unsafe { Group::load_aligned(self.ctrl(0)) }.match_full().into_iter();
+        ExtractIf { table: self, group_pos: 0, bits, f }
+    }
+
+    /// Sum of all values; zero (`V::default()`) for an empty table.
+    pub fn values_sum(&self) -> V
+    where
+        V: Copy + Default + core::ops::Add<Output = V>,
+    {
+        self.fold_values(V::default(), |sum, &value| sum + value)
+    }
+
+    pub fn values_min(&self) -> Option<V>
+    where
+        V: Copy + Ord,
+    {
+        self.fold_values(None, |min: Option<V>, &value| Some(min.map_or(value, |min| min.min(value))))
+    }
+
+    pub fn values_max(&self) -> Option<V>
+    where
+        V: Copy + Ord,
+    {
+        self.fold_values(None, |max: Option<V>, &value| Some(max.map_or(value, |max| max.max(value))))
+    }
+
+    /// Marks the full slot at `index` as erased, leaving an `EMPTY` or `DELETED` tag depending on
+    /// whether a probe sequence can pass through it. The value is not dropped.
+    ///
+    /// # Safety
+    ///
+    /// `index` must be a full slot of this table, e.g. as returned by `insert`.
     #[inline(always)]
     pub unsafe fn erase_index(&mut self, index: usize) {
-        let index_before = index.wrapping_sub(Group::WIDTH) & self.bucket_mask;
-        let empty_before = Group::load(self.ctrl(index_before)).match_empty();
-        let empty_after = Group::load(self.ctrl(index)).match_empty();
-        let ctrl = if empty_before.leading_zeros() + empty_after.trailing_zeros() >= Group::WIDTH {
-            Tag::DELETED
-        } else {
+        // Probe sequences visit whole aligned groups and stop at the first one with an `EMPTY`, so
+        // only the slot's own group decides whether a probe can pass through it. That group is
+        // also the only one in bounds: there are no mirror ctrl bytes past the last group.
+        let group = Group::load_aligned(self.ctrl(index & !(Group::WIDTH - 1)));
+        let ctrl = if group.match_empty().any_bit_set() {
             Tag::EMPTY
+        } else {
+            Tag::DELETED
         };
         self.set_ctrl(index, ctrl);
         self.items -= 1;
+        self.growth_left += (ctrl == Tag::EMPTY) as usize;
+        self.tombstones += (ctrl == Tag::DELETED) as usize;
     }
 
     #[inline(always)]
@@ -226,9 +591,611 @@ impl<V> HashTable<V> {
     }
 
     fn probe_seq(&self, hash64: u64) -> ProbeSeq {
+        if self.prime_buckets {
+            // At least two groups, since 2 is the smallest prime.
+            let num_groups = self.num_buckets() / Group::WIDTH;
+            return ProbeSeq {
+                pos: (hash64 as usize % num_groups) * Group::WIDTH,
+                stride: (1 + hash64.rotate_left(32) as usize % (num_groups - 1)) * Group::WIDTH,
+            };
+        }
         ProbeSeq {
             pos: (hash64 as usize) & self.aligned_bucket_mask,
             stride: (hash64.rotate_left(32) as usize & self.aligned_bucket_mask) | Group::WIDTH,
         }
     }
 }
+
+/// Iterator over the keys of a [`HashTable`], returned by [`HashTable::keys`].
+pub struct Keys<'a, V> {
+    table: &'a HashTable<V>,
+    group_pos: usize,
+    bits: BitMaskIter,
+}
+
+impl<'a, V> Iterator for Keys<'a, V> {
+    type Item = &'a u64;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(bit) = self.bits.next() {
+                return Some(unsafe { &(*self.table.bucket(self.group_pos + bit)).0 });
+            }
+            self.group_pos += Group::WIDTH;
+            if self.group_pos >= self.table.num_buckets() {
+                return None;
+            }
+            self.bits = unsafe { Group::load_aligned(self.table.ctrl(self.group_pos)) }
+                .match_full()
+                .into_iter();
+        }
+    }
+}
+
+/// Iterator returned by [`HashTable::extract_if`].
+pub struct ExtractIf<'a, V, F> {
+    table: &'a mut HashTable<V>,
+    group_pos: usize,
+    // Full slots of the group at `group_pos`, as of loading it. Erasing one doesn't change the
+    // others.
+    bits: BitMaskIter,
+    f: F,
+}
+
+impl<V, F: FnMut(&u64, &mut V) -> bool> Iterator for ExtractIf<'_, V, F> {
+    type Item = (u64, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Some(bit) = self.bits.next() else {
+                self.group_pos += Group::WIDTH;
+                if self.group_pos >= self.table.num_buckets() {
+                    return None;
+                }
+                self.bits = unsafe { Group::load_aligned(self.table.ctrl(self.group_pos)) }
+                    .match_full()
+                    .into_iter();
+                continue;
+            };
+            let index = self.group_pos + bit;
+            let bucket = unsafe { &mut *self.table.bucket(index) };
+            if (self.f)(&bucket.0, &mut bucket.1) {
+                unsafe {
+                    self.table.erase_index(index);
+                    return Some(self.table.bucket(index).read());
+                }
+            }
+        }
+    }
+}
+
+/// Bucket count to grow to from a full table of `num_buckets` holding `len` items: enough for
+/// `len * grow_factor` items at 7/8 load, and at least double.
+fn grown_num_buckets(len: usize, num_buckets: usize, grow_factor: (usize, usize)) -> usize {
+    infallible(try_grown_num_buckets(len, num_buckets, grow_factor))
+}
+
+fn try_grown_num_buckets(
+    len: usize,
+    num_buckets: usize,
+    (numerator, denominator): (usize, usize),
+) -> Result<usize, TryReserveError> {
+    len.checked_mul(numerator)
+        .and_then(|n| (n / denominator).checked_mul(8))
+        .and_then(|n| (n / 7).checked_next_power_of_two())
+        .map(|n| n.max(num_buckets * 2))
+        .ok_or(TryReserveError::CapacityOverflow)
+}
+
+/// The smallest prime at least `n`.
+fn next_prime(n: usize) -> usize {
+    let is_prime = |n: usize| n >= 2 && (2..).take_while(|d| d * d <= n).all(|d| !n.is_multiple_of(d));
+    (n..).find(|&n| is_prime(n)).uunwrap()
+}
+
+/// The fewest buckets that hold `capacity` items at the 7/8 maximum load: at least one group,
+/// which every probe loads whole.
+fn min_num_buckets(capacity: usize) -> usize {
+    ((capacity * 8) / 7).next_power_of_two().max(Group::WIDTH)
+}
+
+impl<V: fmt::Debug> fmt::Debug for HashTable<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = move || {
+            (0..=self.bucket_mask)
+                .filter(move |&index| unsafe { *self.ctrl(index) }.is_full())
+                .map(move |index| {
+                    let bucket = unsafe { &*self.bucket(index) };
+                    (bucket.0, &bucket.1)
+                })
+        };
+        f.debug_struct("HashTable")
+            .field("len", &self.len())
+            .field("num_buckets", &self.num_buckets())
+            .field("entries", &DebugEntries(entries))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_with_capacity_hugepage() {
+        let mut table = HashTable::with_capacity_hugepage(1000);
+        let num_buckets = table.num_buckets();
+        for key in 0..100_000u64 {
+            table.insert(key, !key);
+        }
+        // Still huge-page aligned after growing.
+        assert!(table.num_buckets() > num_buckets);
+        assert_eq!(table.dropper.alloc as usize % dropper::HUGE_PAGE_SIZE, 0);
+        for key in 0..100_000u64 {
+            assert_eq!(table.get(&key), Some(&!key));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "denominator")]
+    fn test_grow_factor_zero_denominator_panics() {
+        let _ = HashTable::<u64>::with_capacity(16).with_grow_factor(2, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "overflows")]
+    fn test_grow_factor_overflow_panics() {
+        let _ = HashTable::<u64>::with_capacity(16).with_grow_factor(usize::MAX, 1);
+    }
+
+    #[test]
+    fn test_grow_factor_tight() {
+        const N: u64 = 2000;
+        let mut tight = HashTable::<u64>::with_capacity(16).with_grow_factor(4, 3);
+        let mut loose = HashTable::<u64>::with_capacity(16).with_grow_factor(4, 1);
+        for key in 0..N {
+            tight.insert(key, key * 3);
+            loose.insert(key, key * 3);
+        }
+        assert_eq!(tight.len(), N as usize);
+        for key in 0..N {
+            assert_eq!(tight.get(&key), Some(&(key * 3)));
+            assert_eq!(loose.get(&key), Some(&(key * 3)));
+        }
+        // The smallest power of two that holds N at 7/8 load, not twice that.
+        let needed = ((N as usize * 8) / 7).next_power_of_two();
+        assert_eq!(tight.num_buckets(), needed);
+        assert!(loose.num_buckets() > needed);
+    }
+
+    #[test]
+    fn test_delete_churn_keeps_probe_length_bounded() {
+        let mut table = HashTable::<u64>::with_capacity(4096);
+        let num_buckets = table.num_buckets();
+        let mut rng = fastrand::Rng::with_seed(7);
+        let mut live: Vec<u64> = (0..3000).map(|_| rng.u64(..)).collect();
+        for &key in &live {
+            table.insert(key, key);
+        }
+        for round in 0..50 {
+            for _ in 0..4000 {
+                let key = live.swap_remove(rng.usize(..live.len()));
+                let index = table.find_index(key).unwrap();
+                unsafe { table.erase_index(index) };
+                let key = rng.u64(..);
+                table.insert(key, key);
+                live.push(key);
+            }
+            assert!(table.tombstone_count() <= num_buckets / 8 + 1, "round {round}");
+            let misses: usize = (0..1000).map(|_| table.probe_length(rng.u64(..)).0).sum();
+            assert!(misses < 1000 * 4, "round {round}: avg miss probe length {}", misses as f64 / 1000.0);
+        }
+        assert_eq!(table.num_buckets(), num_buckets);
+        assert_eq!(table.len(), live.len());
+        for &key in &live {
+            assert_eq!(table.get(&key), Some(&key));
+        }
+    }
+
+    #[test]
+    fn test_fold_values() {
+        let mut table = HashTable::<u64>::with_capacity(16);
+        assert_eq!(table.values_sum(), 0);
+        assert_eq!(table.values_min(), None);
+
+        // Counters, with some keys erased so that the walk skips tombstones.
+        let mut rng = fastrand::Rng::with_seed(2074);
+        let mut counters = HashMap::new();
+        let mut total = 0;
+        for _ in 0..10_000 {
+            let key = rng.u64(..500);
+            let value = rng.u64(1..1000);
+            match counters.get(&key) {
+                Some(&old) if rng.bool() => {
+                    let (_, index) = table.insert(key, old);
+                    unsafe { table.erase_index(index) };
+                    counters.remove(&key);
+                    total -= old;
+                }
+                _ => {
+                    table.insert(key, value);
+                    total = total - counters.insert(key, value).unwrap_or(0) + value;
+                }
+            }
+        }
+        assert!(table.tombstone_count() > 0);
+        assert_eq!(table.values_sum(), total);
+        assert_eq!(table.fold_values(0, |count, _| count + 1), counters.len());
+        assert_eq!(table.values_min(), counters.values().copied().min());
+        assert_eq!(table.values_max(), counters.values().copied().max());
+    }
+
+    #[test]
+    fn test_try_reserve() {
+        let mut table = HashTable::with_capacity(64);
+        for key in 0..50 {
+            table.insert(key, key);
+        }
+        let num_buckets = table.num_buckets();
+        assert_eq!(table.try_reserve(usize::MAX), Err(TryReserveError::CapacityOverflow));
+        // A valid layout far bigger than the address space, so the allocator returns null.
+        assert!(matches!(table.try_reserve(1 << 50), Err(TryReserveError::AllocError { .. })));
+
+        assert_eq!(table.num_buckets(), num_buckets);
+        assert_eq!(table.try_reserve(1000), Ok(()));
+        let num_buckets = table.num_buckets();
+        for key in 50..1050 {
+            table.insert(key, key);
+        }
+        assert_eq!(table.num_buckets(), num_buckets);
+        for key in 0..1050 {
+            assert_eq!(table.get(&key), Some(&key));
+        }
+    }
+
+    #[test]
+    fn test_as_raw_parts() {
+        let mut table = HashTable::with_capacity(1000);
+        for key in 0..800u64 {
+            table.insert(key, key * 2);
+        }
+        for key in (0..800u64).step_by(5) {
+            let (_, index) = table.insert(key, key * 2);
+            unsafe { table.erase_index(index) };
+        }
+
+        // Read the table back through the documented layout only.
+        let (ctrl, bucket_mask, items) = table.as_raw_parts();
+        let ctrl_byte = |i: usize| unsafe { *ctrl.as_ptr().add(i) };
+        let bucket = |i: usize| unsafe { *ctrl.cast::<(u64, u64)>().as_ptr().sub(i + 1) };
+        let mut scanned: Vec<_> = (0..=bucket_mask)
+            .filter(|&i| ctrl_byte(i) & 0x80 == 0)
+            .map(bucket)
+            .collect();
+        scanned.sort();
+        assert_eq!(scanned.len(), items);
+        assert_eq!(scanned, (0..800u64).filter(|key| key % 5 != 0).map(|key| (key, key * 2)).collect::<Vec<_>>());
+
+        // Probing by tag over the raw bytes finds what `get` does.
+        let aligned_bucket_mask = bucket_mask + 1 - Group::WIDTH;
+        for key in 0..1000u64 {
+            let hash = fold_hash_fast(key, table.seed);
+            let tag = (hash >> 57) as u8;
+            let mut pos = hash as usize & aligned_bucket_mask;
+            let stride = (hash.rotate_left(32) as usize & aligned_bucket_mask) | Group::WIDTH;
+            let found = loop {
+                let group = pos..pos + Group::WIDTH;
+                if let Some(i) = group.clone().find(|&i| ctrl_byte(i) == tag && bucket(i).0 == key) {
+                    break Some(bucket(i).1);
+                }
+                if group.into_iter().any(|i| ctrl_byte(i) == 0xFF) {
+                    break None;
+                }
+                pos = (pos + stride) & bucket_mask;
+            };
+            assert_eq!(found.as_ref(), table.get(&key), "key {key}");
+        }
+    }
+
+    #[test]
+    fn test_probe_seq_visits_every_group_once() {
+        let mut rng = fastrand::Rng::with_seed(2148);
+        // From a single group (`with_capacity(1)`) up, power-of-two and prime group counts.
+        for capacity in [1, 16, 100, 1000, 5000] {
+            for prime_buckets in [false, true] {
+                let table = HashTable::<u64>::with_capacity(capacity).with_prime_buckets(prime_buckets);
+                let num_buckets = table.num_buckets();
+                let num_groups = num_buckets / Group::WIDTH;
+                for _ in 0..1000 {
+                    let hash64 = rng.u64(..);
+                    let mut probe_seq = table.probe_seq(hash64);
+                    let start = probe_seq.pos;
+                    let mut seen = vec![false; num_groups];
+                    for _ in 0..num_groups {
+                        let pos = probe_seq.pos;
+                        assert!(pos.is_multiple_of(Group::WIDTH) && pos < num_buckets);
+                        assert!(!seen[pos / Group::WIDTH], "{num_groups} groups, hash {hash64:#x}: revisited {pos}");
+                        seen[pos / Group::WIDTH] = true;
+                        probe_seq.move_next(num_buckets);
+                    }
+                    // Every group once, then back to the start.
+                    assert_eq!(probe_seq.pos, start);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_prime_buckets() {
+        let mut table = HashTable::with_capacity(1000).with_prime_buckets(true);
+        let is_prime = |n: usize| (2..n).take_while(|d| d * d <= n).all(|d| !n.is_multiple_of(d));
+        assert!(is_prime(table.num_buckets() / Group::WIDTH), "{}", table.num_buckets());
+        // Enough keys to grow a few times, which keeps the group count prime.
+        let mut rng = fastrand::Rng::with_seed(2133);
+        let keys = (0..20_000).map(|_| rng.u64(..)).collect::<Vec<_>>();
+        for &key in &keys {
+            table.insert(key, key);
+        }
+        assert!(table.num_buckets().is_multiple_of(Group::WIDTH) && !table.num_buckets().is_power_of_two());
+        assert!(is_prime(table.num_buckets() / Group::WIDTH), "{}", table.num_buckets());
+        for &key in &keys {
+            assert_eq!(table.get(&key), Some(&key));
+        }
+        // Misses end at an empty group rather than cycling.
+        let num_groups = table.num_buckets() / Group::WIDTH;
+        for _ in 0..10_000 {
+            let (groups, found) = table.probe_length(rng.u64(..));
+            assert!(!found && groups <= num_groups);
+        }
+        // And every group can be reached: filling to the growth limit still terminates.
+        let mut table = HashTable::with_capacity(100).with_prime_buckets(true);
+        let num_buckets = table.num_buckets();
+        for key in 0..table.growth_left as u64 {
+            table.insert(key, key);
+        }
+        assert_eq!(table.num_buckets(), num_buckets);
+        table.shrink_to_fit();
+        assert!(is_prime(table.num_buckets() / Group::WIDTH));
+        for key in 0..table.len() as u64 {
+            assert_eq!(table.get(&key), Some(&key));
+        }
+    }
+
+    #[test]
+    fn test_probe_length_past_tombstones() {
+        // Filled to the growth limit, so that erases leave tombstones.
+        let mut table = HashTable::with_capacity(1000);
+        let n = table.growth_left as u64;
+        for key in 0..n {
+            table.insert(key, key);
+        }
+        for key in (0..n).step_by(2) {
+            let index = table.find_index(key).unwrap();
+            unsafe { table.erase_index(index) };
+        }
+        assert!(table.tombstone_count() > 0);
+
+        // Count the groups `get` loads from the raw control bytes: it stops at the first group
+        // holding an `EMPTY`, and a `DELETED` doesn't count.
+        let ctrl = |i: usize| unsafe { *table.ctrl(i) };
+        let mut passed_tombstone = false;
+        for key in (0..n).step_by(2).chain(n..n + 1000) {
+            let mut probe_seq = table.probe_seq(fold_hash_fast(key, table.seed));
+            let mut groups = 1;
+            loop {
+                let group = probe_seq.pos..probe_seq.pos + Group::WIDTH;
+                if group.clone().any(|i| ctrl(i) == Tag::EMPTY) {
+                    break;
+                }
+                passed_tombstone |= group.into_iter().any(|i| ctrl(i) == Tag::DELETED);
+                probe_seq.move_next(table.num_buckets());
+                groups += 1;
+            }
+            assert_eq!(table.probe_length(key), (groups, false), "key {key}");
+        }
+        assert!(passed_tombstone);
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut table = HashTable::with_capacity(16);
+        assert_eq!(table.keys().count(), 0);
+        let mut rng = fastrand::Rng::with_seed(11);
+        let mut inserted: Vec<u64> = (0..3000).map(|_| rng.u64(..)).collect();
+        for &key in &inserted {
+            table.insert(key, 0);
+        }
+        // Erased slots, including tombstones, are skipped.
+        for key in inserted.drain(..1000) {
+            let index = table.find_index(key).unwrap();
+            unsafe { table.erase_index(index) };
+        }
+        let mut keys: Vec<u64> = table.keys().copied().collect();
+        keys.sort_unstable();
+        inserted.sort_unstable();
+        assert_eq!(keys, inserted);
+    }
+
+    #[test]
+    fn test_extract_if() {
+        let mut table = HashTable::with_capacity(1000);
+        for key in 0..1000 {
+            table.insert(key, key * 10);
+        }
+        let mut odd: Vec<(u64, u64)> = table.extract_if(|&key, _| key % 2 == 1).collect();
+        odd.sort_unstable();
+        assert_eq!(odd, (1..1000).step_by(2).map(|key| (key, key * 10)).collect::<Vec<_>>());
+        assert_eq!(table.len(), 500);
+        for key in 0..1000 {
+            let expected = (key % 2 == 0).then_some(key * 10);
+            assert_eq!(table.get(&key).copied(), expected, "key {key}");
+        }
+
+        // The predicate may update the entries it keeps.
+        let kept = table.extract_if(|_, value| {
+            *value += 1;
+            false
+        });
+        assert_eq!(kept.count(), 0);
+        for key in (0..1000).step_by(2) {
+            assert_eq!(table.get(&key), Some(&(key * 10 + 1)));
+        }
+
+        // Dropping the iterator early leaves the unvisited entries alone.
+        let mut extract = table.extract_if(|_, _| true);
+        let first = extract.next().unwrap();
+        drop(extract);
+        assert_eq!(table.len(), 499);
+        assert_eq!(table.get(&first.0), None);
+        let mut keys: Vec<u64> = table.keys().copied().collect();
+        keys.sort_unstable();
+        assert_eq!(keys, (0..1000).step_by(2).filter(|&key| key != first.0).collect::<Vec<_>>());
+        // New inserts still find their way past whatever tombstones were left.
+        table.insert(first.0, 0);
+        assert_eq!(table.get(&first.0), Some(&0));
+    }
+
+    #[test]
+    fn test_extract_if_last_group() {
+        let mut table = HashTable::with_capacity(1000);
+        let last_group = table.num_buckets() - Group::WIDTH;
+        let mut in_last_group = Vec::new();
+        for key in 0..1000 {
+            let (_, index) = table.insert(key, key);
+            if index >= last_group {
+                in_last_group.push(key);
+            }
+        }
+        assert!(!in_last_group.is_empty());
+        let mut extracted: Vec<u64> = table.extract_if(|&key, _| in_last_group.contains(&key)).map(|(key, _)| key).collect();
+        extracted.sort_unstable();
+        assert_eq!(extracted, in_last_group);
+        assert_eq!(table.len(), 1000 - in_last_group.len());
+        for key in 0..1000 {
+            assert_eq!(table.get(&key).is_some(), !in_last_group.contains(&key), "key {key}");
+        }
+        for &key in &in_last_group {
+            table.insert(key, key);
+        }
+        for key in 0..1000 {
+            assert_eq!(table.get(&key), Some(&key));
+        }
+    }
+
+    #[test]
+    fn test_maintain_keeps_rehashes_off_try_insert() {
+        let mut table = HashTable::with_capacity(16);
+        let mut rng = fastrand::Rng::with_seed(123);
+        let mut keys = Vec::new();
+        let (mut rehashes, mut idle) = (0, 0);
+        for _ in 0..200 {
+            if table.maintain() {
+                rehashes += 1;
+            } else {
+                idle += 1;
+            }
+            let (ctrl, bucket_mask, _) = table.as_raw_parts();
+            for _ in 0..table.num_buckets() / 16 {
+                // Mostly inserts, so the table keeps growing, with erases leaving tombstones.
+                if rng.u8(..4) == 0 && !keys.is_empty() {
+                    let key: u64 = keys.swap_remove(rng.usize(..keys.len()));
+                    let index = table.find_index(key).unwrap();
+                    unsafe { table.erase_index(index) };
+                } else {
+                    let key = rng.u64(..);
+                    assert_eq!(table.try_insert(key, !key).map(|(inserted, _)| inserted), Ok(true));
+                    keys.push(key);
+                }
+            }
+            assert_eq!(table.as_raw_parts(), (ctrl, bucket_mask, keys.len()));
+        }
+        assert!(rehashes > 0 && idle > 0, "{rehashes} rehashes, {idle} idle");
+        for key in &keys {
+            assert_eq!(table.get(key), Some(&!key));
+        }
+    }
+
+    #[test]
+    fn test_gc() {
+        // Filled to the growth limit, so that erases leave tombstones.
+        let mut table = HashTable::with_capacity(1000);
+        let n = table.growth_left as u64;
+        for key in 0..n {
+            table.insert(key, key);
+        }
+        let num_buckets = table.num_buckets();
+        for key in 50..n {
+            let index = table.find_index(key).unwrap();
+            unsafe { table.erase_index(index) };
+        }
+        assert!(table.tombstone_count() > 0);
+        let misses_before: usize = (n..n + 1000).map(|key| table.probe_length(key).0).sum();
+
+        table.gc();
+        assert!(table.num_buckets() < num_buckets);
+        assert_eq!(table.num_buckets(), 64);
+        assert_eq!(table.tombstone_count(), 0);
+        let misses_after: usize = (n..n + 1000).map(|key| table.probe_length(key).0).sum();
+        assert!(misses_after < misses_before, "{misses_after} vs {misses_before}");
+        for key in 0..n {
+            assert_eq!(table.get(&key), (key < 50).then_some(&key));
+        }
+
+        // Over a quarter full: only drops the tombstones.
+        for key in 0..10u64 {
+            let index = table.find_index(key).unwrap();
+            unsafe { table.erase_index(index) };
+        }
+        table.gc();
+        assert_eq!(table.num_buckets(), 64);
+        assert_eq!(table.tombstone_count(), 0);
+        assert_eq!(table.len(), 40);
+    }
+
+    #[test]
+    fn test_shrink_to() {
+        let mut table = HashTable::with_capacity(1000);
+        for key in 0..1000u64 {
+            table.insert(key, key * 3);
+        }
+        let num_buckets = table.num_buckets();
+        for key in 100..1000u64 {
+            let (_, index) = table.insert(key, key * 3);
+            unsafe { table.erase_index(index) };
+        }
+        assert_eq!(table.len(), 100);
+
+        // Never grows.
+        table.shrink_to(5000);
+        assert_eq!(table.num_buckets(), num_buckets);
+
+        table.shrink_to(200);
+        assert_eq!(table.num_buckets(), 256);
+        assert_eq!(table.tombstone_count(), 0);
+        assert_eq!(table.len(), 100);
+        for key in 0..1000u64 {
+            assert_eq!(table.get(&key), (key < 100).then_some(&(key * 3)));
+        }
+        // Room for 200 without growing.
+        for key in 100..200u64 {
+            table.insert(key, key * 3);
+        }
+        assert_eq!(table.num_buckets(), 256);
+
+        // Never below what the contents need.
+        table.shrink_to(0);
+        assert_eq!(table.num_buckets(), 256);
+        for key in 50..200u64 {
+            let (_, index) = table.insert(key, key * 3);
+            unsafe { table.erase_index(index) };
+        }
+        table.shrink_to_fit();
+        assert_eq!(table.num_buckets(), 64);
+        for key in 0..50u64 {
+            assert_eq!(table.get(&key), Some(&(key * 3)));
+        }
+    }
+}
@@ -1,13 +1,15 @@
 //! A quadratic probing hash table for u64 keys. SwissTable design following `hashbrown` crate,
 //! with a lot of features removed but the same optimizations valid.
 
-use std::{alloc::Layout, ptr::NonNull};
+use core::{alloc::Layout, ptr::NonNull};
+use core::fmt;
 
-use crate::dropper::Dropper;
+use crate::dropper::{self, Dropper};
 use crate::TRACK_PROBE_LENGTH;
 use crate::control::{Group, Tag, TagSliceExt as _};
 use crate::u64_fold_hash_fast::fold_hash_fast;
 use crate::uunwrap::UUnwrap;
+use crate::DebugEntries;
 
 pub struct HashTable<V> {
     // Mask to get an index from a hash value. The value is one less than the
@@ -28,10 +30,16 @@ pub struct HashTable<V> {
 
     total_probe_length: usize,
 
-    marker: std::marker::PhantomData<V>,
+    marker: core::marker::PhantomData<V>,
     dropper: Dropper,
 }
 
+// SAFETY: `ctrl` points into the allocation `dropper` owns, which no other table shares. Groups
+// are aligned, so there are no mirrored control bytes, and `set_ctrl`, the one write through
+// `ctrl`, only runs under `&mut self`.
+unsafe impl<V: Send> Send for HashTable<V> {}
+unsafe impl<V: Sync> Sync for HashTable<V> {}
+
 /// Probe sequence based on triangular numbers, which is guaranteed (since our
 /// table size is a power of two) to visit every group of elements exactly once.
 ///
@@ -66,19 +74,32 @@ impl ProbeSeq {
 
 impl<V> HashTable<V> {
     pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_huge_pages(capacity, false)
+    }
+
+    /// As `with_capacity`, but on huge pages where the kernel allows; see `dropper::alloc`.
+    #[cfg(target_os = "linux")]
+    pub fn with_capacity_hugepage(capacity: usize) -> Self {
+        Self::with_capacity_and_huge_pages(capacity, true)
+    }
+
+    fn with_capacity_and_huge_pages(capacity: usize, huge_pages: bool) -> Self {
         // Calculate sizes
         // TODO: integer overflow...
         let num_buckets = ((capacity * 8) / 7).next_power_of_two();
-        let bucket_size = std::mem::size_of::<(u64, V)>();
-        let align = std::mem::align_of::<(u64, V)>().max(Group::WIDTH);
+        let bucket_size = core::mem::size_of::<(u64, V)>();
+        let align = core::mem::align_of::<(u64, V)>().max(Group::WIDTH);
         let ctrl_offset = (bucket_size * num_buckets).next_multiple_of(align);
         let size = ctrl_offset + num_buckets;
         let layout = Layout::from_size_align(size, align).uunwrap();
         // Allocate
-        let alloc = unsafe { std::alloc::alloc(layout) };
+        let (alloc, layout) = unsafe { dropper::alloc(layout, huge_pages) };
+        if alloc.is_null() {
+            alloc::alloc::handle_alloc_error(layout);
+        }
         // Write control
         let ctrl = unsafe { NonNull::new_unchecked(alloc.add(ctrl_offset)) };
-        let ctrl_slice = unsafe { std::slice::from_raw_parts_mut(ctrl.as_ptr() as *mut Tag, num_buckets) };
+        let ctrl_slice = unsafe { core::slice::from_raw_parts_mut(ctrl.as_ptr() as *mut Tag, num_buckets) };
         ctrl_slice.fill_empty();
         // dbg!(num_buckets, bucket_size, align, ctrl_offset, size, layout, alloc, ctrl);
         let seed = fastrand::Rng::with_seed(123).u64(..);
@@ -91,12 +112,12 @@ impl<V> HashTable<V> {
             ctrl,
             items: 0,
             seed,
-            marker: std::marker::PhantomData,
+            marker: core::marker::PhantomData,
             total_probe_length: 0,
             dropper: Dropper { alloc, layout },
         }
     }
-
+    #[cfg(feature = "std")]
     pub fn print_stats(&self) {
         println!("  avg_probe_length: {}", self.total_probe_length as f64 / self.items as f64);
     }
@@ -106,6 +127,17 @@ impl<V> HashTable<V> {
         self.items
     }
 
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The hash of `key`: the low bits pick the group the probe starts at, the top 7 the tag.
+    #[inline(always)]
+    pub fn hash_of(&self, key: u64) -> u64 {
+        fold_hash_fast(key, self.seed)
+    }
+
     #[inline(always)]
     pub fn insert(&mut self, key: u64, value: V) -> (bool, usize) {
         let mut insert_slot = None;
@@ -183,6 +215,12 @@ impl<V> HashTable<V> {
         }
     }
 
+    /// Marks the full slot at `index` as erased, leaving an `EMPTY` or `DELETED` tag depending on
+    /// whether a probe sequence can pass through it. The value is not dropped.
+    ///
+    /// # Safety
+    ///
+    /// `index` must be a full slot of this table, e.g. as returned by `insert`.
     #[inline(always)]
     pub unsafe fn erase_index(&mut self, index: usize) {
         let index_before = index.wrapping_sub(Group::WIDTH) & self.bucket_mask;
@@ -198,6 +236,13 @@ impl<V> HashTable<V> {
 
     }
 
+    /// Inserts `key` and, if it was new, immediately erases it again by resetting its slot to
+    /// `EMPTY`. This is the unit of work of the insert+erase churn benchmark.
+    ///
+    /// # Safety
+    ///
+    /// The table must contain no `DELETED` tombstones: the slot is reset straight to `EMPTY`, which
+    /// is only correct if `insert` took an `EMPTY` slot.
     #[inline(always)]
     pub unsafe fn insert_and_erase(&mut self, key: u64, value: V) {
         let (inserted, index) = self.insert(key, value);
@@ -230,4 +275,22 @@ impl<V> HashTable<V> {
             stride: 0,
         }
     }
-}
\ No newline at end of file
+}
+
+impl<V: fmt::Debug> fmt::Debug for HashTable<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = move || {
+            (0..=self.bucket_mask)
+                .filter(move |&index| unsafe { *self.ctrl(index) }.is_full())
+                .map(move |index| {
+                    let bucket = unsafe { &*self.bucket(index) };
+                    (bucket.0, &bucket.1)
+                })
+        };
+        f.debug_struct("HashTable")
+            .field("len", &self.len())
+            .field("num_buckets", &(self.bucket_mask + 1))
+            .field("entries", &DebugEntries(entries))
+            .finish()
+    }
+}
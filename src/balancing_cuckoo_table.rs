@@ -1,13 +1,15 @@
 //! A cuckoo hash table with 2 choices of group, each with 8-16 buckets per group.
 
-use std::hint::{black_box, likely};
-use std::{alloc::Layout, ptr::NonNull};
+use core::hint::likely;
+use core::{alloc::Layout, ptr::NonNull};
+use core::fmt;
 
-use crate::dropper::Dropper;
+use crate::dropper::{self, Dropper};
 use crate::TRACK_PROBE_LENGTH;
 use crate::control::{Group, Tag, TagSliceExt as _};
-use crate::u64_fold_hash_fast::{self, fold_hash_fast};
+use crate::u64_fold_hash_fast::fold_hash_fast;
 use crate::uunwrap::UUnwrap;
+use crate::DebugEntries;
 
 pub struct HashTable<V> {
     // Mask to get an index from a hash value. The value is one less than the
@@ -26,9 +28,12 @@ pub struct HashTable<V> {
     // Seed for the hash function
     seed: u64,
 
-    marker: std::marker::PhantomData<V>,
+    marker: core::marker::PhantomData<V>,
     rng: fastrand::Rng,
 
+    // Whether the cuckoo loop picks victims without `rng`; see `with_deterministic_eviction`.
+    deterministic_eviction: bool,
+
     total_probe_length: usize,
     total_insert_probe_length: usize,
     max_insert_probe_length: usize,
@@ -36,21 +41,43 @@ pub struct HashTable<V> {
 
 }
 
+// SAFETY: `ctrl` points into the allocation `dropper` owns, which no other table shares. Inserts
+// move entries between a key's two groups through it, but only under `&mut self`.
+unsafe impl<V: Send> Send for HashTable<V> {}
+unsafe impl<V: Sync> Sync for HashTable<V> {}
+
+/// Fewest buckets a table is built with. Smaller tables would have fewer than two groups, so a
+/// key's two candidate groups would coincide.
+const MIN_NUM_BUCKETS: usize = 2 * Group::WIDTH;
+
 impl<V> HashTable<V> {
     pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_huge_pages(capacity, false)
+    }
+
+    /// As `with_capacity`, but on huge pages where the kernel allows; see `dropper::alloc`.
+    #[cfg(target_os = "linux")]
+    pub fn with_capacity_hugepage(capacity: usize) -> Self {
+        Self::with_capacity_and_huge_pages(capacity, true)
+    }
+
+    fn with_capacity_and_huge_pages(capacity: usize, huge_pages: bool) -> Self {
         // Calculate sizes
         // TODO: integer overflow...
-        let num_buckets = ((capacity * 8) / 7).next_power_of_two();
-        let bucket_size = std::mem::size_of::<(u64, V)>();
-        let align = std::mem::align_of::<(u64, V)>().max(Group::WIDTH);
+        let num_buckets = ((capacity * 8) / 7).next_power_of_two().max(MIN_NUM_BUCKETS);
+        let bucket_size = core::mem::size_of::<(u64, V)>();
+        let align = core::mem::align_of::<(u64, V)>().max(Group::WIDTH);
         let ctrl_offset = (bucket_size * num_buckets).next_multiple_of(align);
         let size = ctrl_offset + num_buckets;
         let layout = Layout::from_size_align(size, align).uunwrap();
         // Allocate
-        let alloc = unsafe { std::alloc::alloc(layout) };
+        let (alloc, layout) = unsafe { dropper::alloc(layout, huge_pages) };
+        if alloc.is_null() {
+            alloc::alloc::handle_alloc_error(layout);
+        }
         // Write control
         let ctrl = unsafe { NonNull::new_unchecked(alloc.add(ctrl_offset)) };
-        let ctrl_slice = unsafe { std::slice::from_raw_parts_mut(ctrl.as_ptr() as *mut Tag, num_buckets) };
+        let ctrl_slice = unsafe { core::slice::from_raw_parts_mut(ctrl.as_ptr() as *mut Tag, num_buckets) };
         ctrl_slice.fill_empty();
         // dbg!(num_buckets, bucket_size, align, ctrl_offset, size, layout, alloc, ctrl);
         let seed = fastrand::Rng::with_seed(123).u64(..);
@@ -63,8 +90,9 @@ impl<V> HashTable<V> {
             ctrl,
             items: 0,
             seed,
-            marker: std::marker::PhantomData,
+            marker: core::marker::PhantomData,
             rng: fastrand::Rng::with_seed(123),
+            deterministic_eviction: false,
             total_probe_length: 0,
             total_insert_probe_length: 0,
             max_insert_probe_length: 0,
@@ -72,10 +100,19 @@ impl<V> HashTable<V> {
         }
     }
 
+    /// Picks cuckoo eviction victims from the incoming key's hash and the eviction chain length
+    /// instead of from `rng`, so the layout depends only on the keys inserted. Random victims
+    /// make the layout depend on every earlier draw too, so two table variants that evict at
+    /// different times diverge from then on.
+    pub fn with_deterministic_eviction(mut self, deterministic_eviction: bool) -> Self {
+        self.deterministic_eviction = deterministic_eviction;
+        self
+    }
+
     pub fn avg_probe_length(&self) -> f64 {
         self.total_probe_length as f64 / self.items as f64
     }
-
+    #[cfg(feature = "std")]
     pub fn print_stats(&self) {
         let items = self.items as f64;
         println!("  avg_probe_length: {}", self.total_probe_length as f64 / items);
@@ -88,6 +125,17 @@ impl<V> HashTable<V> {
         self.items
     }
 
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The hash of `key`. Each 32-bit half picks one of the key's two groups.
+    #[inline(always)]
+    pub fn hash_of(&self, key: u64) -> u64 {
+        fold_hash_fast(key, self.seed)
+    }
+
     #[inline(always)]
     pub fn insert(&mut self, key: u64, value: V) -> (bool, usize) {
         let hash0 = fold_hash_fast(key, self.seed);
@@ -130,10 +178,10 @@ impl<V> HashTable<V> {
         let mut probe_length = 2;
         let mut insert_slot = None;
         if empty_pos1 > (Group::WIDTH - 3) && empty_pos1 < empty_pos0 {
-            insert_slot = Some((pos1 + empty_pos1));
+            insert_slot = Some(pos1 + empty_pos1);
             probe_length = 2;
         } else if empty_pos0 < Group::WIDTH{
-            insert_slot = Some((pos0 + empty_pos0));
+            insert_slot = Some(pos0 + empty_pos0);
             probe_length = 1;
         }
         if let Some(insert_slot) = insert_slot {
@@ -179,9 +227,15 @@ impl<V> HashTable<V> {
                     return (true, insert_slot);
                 }
             }
-            let evict_index = self.rng.usize(..) % Group::WIDTH;
-            (key, value) = std::mem::replace(unsafe { &mut *self.bucket(pos + evict_index) }, (key, value));
-            tag_hash = std::mem::replace(unsafe { &mut *self.ctrl(pos + evict_index) }, tag_hash);
+            let evict_index = if self.deterministic_eviction {
+                // The low bits don't pick the group; stepping with the chain length avoids
+                // cycling through the same victims.
+                (hash as usize + insert_probe_length) % Group::WIDTH
+            } else {
+                self.rng.usize(..) % Group::WIDTH
+            };
+            (key, value) = core::mem::replace(unsafe { &mut *self.bucket(pos + evict_index) }, (key, value));
+            tag_hash = core::mem::replace(unsafe { &mut *self.ctrl(pos + evict_index) }, tag_hash);
             hash = fold_hash_fast(key, self.seed);
             if hash as usize & self.aligned_bucket_mask == pos {
                 // We evict from its first location and move to its second location.
@@ -200,6 +254,13 @@ impl<V> HashTable<V> {
         }
     }
 
+    /// Inserts `key` and, if it was new, immediately erases it again. This is the unit of work of
+    /// the insert+erase churn benchmark.
+    ///
+    /// # Safety
+    ///
+    /// Benchmark-only: the erased value is forgotten rather than dropped, and `len` keeps counting
+    /// it.
     #[inline(always)]
     pub unsafe fn insert_and_erase(&mut self, key: u64, value: V) {
         let (inserted, index) = self.insert(key, value);
@@ -248,6 +309,31 @@ impl<V> HashTable<V> {
         }
     }
 
+    /// Returns the number of groups `get` loads for `key`, and whether it is present.
+    pub fn probe_length(&self, key: u64) -> (usize, bool) {
+        let mut hash64 = fold_hash_fast(key, self.seed);
+        let tag_hash = Tag::full(hash64);
+        for probe_count in 1..=2 {
+            let pos = hash64 as usize & self.aligned_bucket_mask;
+            let group = unsafe { Group::load(self.ctrl(pos)) };
+            for bit in group.match_tag(tag_hash) {
+                let index = (pos + bit) & self.bucket_mask;
+                if unsafe { (*self.bucket(index)).0 } == key {
+                    return (probe_count, true);
+                }
+            }
+            hash64 = hash64.rotate_left(32);
+        }
+        // No early return on empty slots, so a miss always loads both groups.
+        (2, false)
+    }
+
+    /// Marks the full slot at `index` as erased, leaving an `EMPTY` or `DELETED` tag depending on
+    /// whether a probe sequence can pass through it. The value is not dropped.
+    ///
+    /// # Safety
+    ///
+    /// `index` must be a full slot of this table, e.g. as returned by `insert`.
     #[inline(always)]
     pub unsafe fn erase_index(&mut self, index: usize) {
         let index_before = index.wrapping_sub(Group::WIDTH) & self.bucket_mask;
@@ -278,4 +364,80 @@ impl<V> HashTable<V> {
     unsafe fn set_ctrl(&self, index: usize, tag: Tag) {
         *self.ctrl(index) = tag;
     }
-}
\ No newline at end of file
+}
+
+impl<V: fmt::Debug> fmt::Debug for HashTable<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = move || {
+            (0..=self.bucket_mask)
+                .filter(move |&index| unsafe { *self.ctrl(index) }.is_full())
+                .map(move |index| {
+                    let bucket = unsafe { &*self.bucket(index) };
+                    (bucket.0, &bucket.1)
+                })
+        };
+        f.debug_struct("HashTable")
+            .field("len", &self.len())
+            .field("num_buckets", &(self.bucket_mask + 1))
+            .field("entries", &DebugEntries(entries))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_capacity_one() {
+        // Rounded up to the minimum size, so a handful of keys fit.
+        let mut table = HashTable::<u64>::with_capacity(1);
+        for key in 1..=8 {
+            table.insert(key, key * 10);
+        }
+        assert_eq!(table.len(), 8);
+        for key in 1..=8 {
+            assert_eq!(table.get(&key), Some(&(key * 10)));
+        }
+    }
+
+    #[test]
+    fn test_probe_length() {
+        let mut table = HashTable::with_capacity(1024);
+        for key in 0..896 {
+            table.insert(key, key);
+        }
+        for key in 0..896 {
+            let (probe_length, found) = table.probe_length(key);
+            assert!(found);
+            assert!((1..=2).contains(&probe_length));
+        }
+        assert_eq!(table.probe_length(1 << 40), (2, false));
+    }
+
+    #[test]
+    fn test_deterministic_eviction() {
+        let ctrl_bytes = |table: &HashTable<u64>| -> Vec<u8> {
+            (0..=table.bucket_mask).map(|i| unsafe { (*table.ctrl(i)).0 }).collect()
+        };
+        let build = |deterministic: bool, rng_seed: u64| {
+            let mut table = HashTable::with_capacity(4096).with_deterministic_eviction(deterministic);
+            table.rng = fastrand::Rng::with_seed(rng_seed);
+            let mut keys = fastrand::Rng::with_seed(2088);
+            for _ in 0..table.bucket_mask * 7 / 8 {
+                table.insert(keys.u64(..), 0);
+            }
+            table
+        };
+
+        // Same keys, different `rng` states: only the random layouts differ.
+        assert_eq!(ctrl_bytes(&build(true, 1)), ctrl_bytes(&build(true, 2)));
+        assert_ne!(ctrl_bytes(&build(false, 1)), ctrl_bytes(&build(false, 2)));
+
+        let table = build(true, 1);
+        let mut keys = fastrand::Rng::with_seed(2088);
+        for _ in 0..table.len() {
+            assert!(table.probe_length(keys.u64(..)).1);
+        }
+    }
+}
@@ -81,11 +81,24 @@ impl BitMask {
         }
     }
 
+    /// Returns a new `BitMask` keeping only the first `width` elements.
+    #[inline]
+    #[must_use]
+    pub(crate) fn truncate(self, width: usize) -> Self {
+        BitMask(self.0 & (BitMaskWord::MAX >> (BitMaskWord::BITS as usize - width * BITMASK_STRIDE)))
+    }
+
     /// Returns the number of leading zeroes in the `BitMask`.
     #[inline]
     pub(crate) fn leading_zeros(self) -> usize {
         self.0.leading_zeros() as usize / BITMASK_STRIDE
     }
+
+    /// Returns the number of elements set in the `BitMask`.
+    #[inline]
+    pub(crate) fn count_ones(self) -> usize {
+        (self.0 & BITMASK_ITER_MASK).count_ones() as usize
+    }
 }
 
 impl IntoIterator for BitMask {
@@ -1,4 +1,5 @@
 use super::super::{BitMask, Tag};
+use cfg_if::cfg_if;
 use core::{mem, ptr};
 
 // Use the native word size as the group size. Using a 64-bit group size on
@@ -41,3 +41,190 @@ pub(crate) use self::imp::Group;
 pub(super) use self::imp::{
     BitMaskWord, NonZeroBitMaskWord, BITMASK_ITER_MASK, BITMASK_MASK, BITMASK_STRIDE,
 };
+
+/// The portable `generic` backend, built alongside whichever backend `imp` selected so the two can
+/// be checked against each other. `generic` only needs a `BitMask` and `Tag` from two modules up,
+/// so it gets its own `BitMask` here sized for its word; the shared one is sized for `imp`. The
+/// NEON and LSX backends need their target's intrinsics, so they're only covered on a target that
+/// selects them.
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "."]
+mod parity {
+    pub(crate) use super::super::Tag;
+
+    #[path = "."]
+    mod group {
+        pub(super) mod generic;
+    }
+    use group::generic;
+
+    #[derive(Copy, Clone)]
+    pub(crate) struct BitMask(pub(crate) generic::BitMaskWord);
+
+    impl BitMask {
+        #[must_use]
+        pub(crate) fn invert(self) -> Self {
+            BitMask(self.0 ^ generic::BITMASK_MASK)
+        }
+    }
+
+    /// Per-tag results of one backend's operations over a 16-tag array.
+    #[derive(Debug, PartialEq)]
+    struct Results {
+        match_tag: [bool; 16],
+        match_empty: [bool; 16],
+        match_empty_or_deleted: [bool; 16],
+        match_full: [bool; 16],
+        converted: [Tag; 16],
+    }
+
+    #[repr(C, align(16))]
+    struct AlignedTags([Tag; 16]);
+
+    /// The specification every backend implements, one tag at a time.
+    fn reference(tags: &[Tag; 16], tag: Tag) -> Results {
+        Results {
+            match_tag: tags.map(|t| t == tag),
+            match_empty: tags.map(|t| t == Tag::EMPTY),
+            match_empty_or_deleted: tags.map(Tag::is_special),
+            match_full: tags.map(Tag::is_full),
+            converted: tags.map(|t| if t.is_special() { Tag::EMPTY } else { Tag::DELETED }),
+        }
+    }
+
+    /// Runs the selected backend over `tags`, `Group::WIDTH` tags at a time.
+    fn selected(tags: &[Tag; 16], tag: Tag) -> Results {
+        use super::super::BitMask as ImpBitMask;
+        use super::imp::Group;
+        fn set(out: &mut [bool; 16], base: usize, mask: ImpBitMask) {
+            for i in mask {
+                out[base + i] = true;
+            }
+        }
+        let mut r = Results {
+            match_tag: [false; 16],
+            match_empty: [false; 16],
+            match_empty_or_deleted: [false; 16],
+            match_full: [false; 16],
+            converted: [Tag(0); 16],
+        };
+        let mut converted = AlignedTags([Tag(0); 16]);
+        for base in (0..16).step_by(Group::WIDTH) {
+            // SAFETY: `base + Group::WIDTH <= 16`, and `converted` is 16-byte aligned with
+            // `base` a multiple of `Group::WIDTH`, which is at least the group's alignment.
+            let group = unsafe { Group::load(tags.as_ptr().add(base)) };
+            set(&mut r.match_tag, base, group.match_tag(tag));
+            set(&mut r.match_empty, base, group.match_empty());
+            set(&mut r.match_empty_or_deleted, base, group.match_empty_or_deleted());
+            set(&mut r.match_full, base, group.match_full());
+            unsafe {
+                group
+                    .convert_special_to_empty_and_full_to_deleted()
+                    .store_aligned(converted.0.as_mut_ptr().add(base));
+            }
+        }
+        r.converted = converted.0;
+        r
+    }
+
+    /// Runs the `generic` backend over `tags`, `generic::Group::WIDTH` tags at a time. Its masks
+    /// carry one bit per tag in the high bit of that tag's byte.
+    fn portable(tags: &[Tag; 16], tag: Tag) -> Results {
+        use generic::Group;
+        fn set(out: &mut [bool; 16], base: usize, mask: BitMask) {
+            for i in 0..Group::WIDTH {
+                out[base + i] = mask.0 >> (i * generic::BITMASK_STRIDE + 7) & 1 != 0;
+            }
+        }
+        let mut r = Results {
+            match_tag: [false; 16],
+            match_empty: [false; 16],
+            match_empty_or_deleted: [false; 16],
+            match_full: [false; 16],
+            converted: [Tag(0); 16],
+        };
+        let mut converted = AlignedTags([Tag(0); 16]);
+        for base in (0..16).step_by(Group::WIDTH) {
+            // SAFETY: as in `selected`.
+            let group = unsafe { Group::load(tags.as_ptr().add(base)) };
+            set(&mut r.match_tag, base, group.match_tag(tag));
+            set(&mut r.match_empty, base, group.match_empty());
+            set(&mut r.match_empty_or_deleted, base, group.match_empty_or_deleted());
+            set(&mut r.match_full, base, group.match_full());
+            unsafe {
+                group
+                    .convert_special_to_empty_and_full_to_deleted()
+                    .store_aligned(converted.0.as_mut_ptr().add(base));
+            }
+        }
+        r.converted = converted.0;
+        r
+    }
+
+    /// Checks both backends against `reference` for one tag array and searched tag. `generic`'s
+    /// `match_tag` may additionally report a full tag one lowest bit away from `tag`, but only in
+    /// a group that also holds a true match (see its doc comment); everything else must agree
+    /// exactly.
+    fn check(tags: &[Tag; 16], tag: Tag) {
+        let want = reference(tags, tag);
+        assert_eq!(selected(tags, tag), want, "selected backend, tags {tags:?}, tag {tag:?}");
+
+        let mut got = portable(tags, tag);
+        for (base, chunk) in got.match_tag.chunks_mut(generic::Group::WIDTH).enumerate() {
+            let base = base * generic::Group::WIDTH;
+            let group_has_match = want.match_tag[base..base + chunk.len()].iter().any(|&m| m);
+            for (i, m) in chunk.iter_mut().enumerate() {
+                let t = tags[base + i];
+                if *m && !want.match_tag[base + i] {
+                    assert!(
+                        group_has_match && t.is_full() && t.0 ^ tag.0 == 1,
+                        "generic match_tag false positive at {}, tags {tags:?}, tag {tag:?}",
+                        base + i,
+                    );
+                    *m = false;
+                }
+            }
+        }
+        assert_eq!(got, want, "generic backend, tags {tags:?}, tag {tag:?}");
+    }
+
+    /// Full tags straddling the lowest bit (`0x00`/`0x01`, `0x7e`/`0x7f`), the specials, and
+    /// an all-same array, each searched for every tag it holds plus one it doesn't.
+    #[test]
+    fn test_crafted_groups() {
+        let f = Tag;
+        let arrays: [[Tag; 16]; 5] = [
+            [Tag::EMPTY; 16],
+            [Tag::DELETED; 16],
+            [f(0x00), f(0x01), Tag::EMPTY, Tag::DELETED, f(0x7e), f(0x7f), f(0x01), f(0x00),
+             f(0x2a), f(0x2b), Tag::DELETED, Tag::EMPTY, f(0x00), f(0x7f), f(0x01), f(0x7e)],
+            [f(0x01), f(0x00), f(0x01), f(0x00), f(0x01), f(0x00), f(0x01), f(0x00),
+             f(0x00), f(0x01), f(0x00), f(0x01), f(0x00), f(0x01), f(0x00), f(0x01)],
+            [Tag::EMPTY, f(0x10), Tag::DELETED, f(0x11), Tag::EMPTY, f(0x10), Tag::DELETED, f(0x11),
+             f(0x11), Tag::EMPTY, f(0x10), Tag::DELETED, f(0x11), Tag::EMPTY, f(0x10), Tag::DELETED],
+        ];
+        for tags in &arrays {
+            for &tag in tags.iter().filter(|t| t.is_full()) {
+                check(tags, tag);
+            }
+            check(tags, f(0x55));
+        }
+    }
+
+    /// Random mixes of specials and full tags drawn from a narrow range, so lowest-bit neighbours
+    /// and repeated tags are common.
+    #[test]
+    fn test_random_groups() {
+        let mut rng = fastrand::Rng::with_seed(123);
+        for _ in 0..10_000 {
+            let tags: [Tag; 16] = core::array::from_fn(|_| match rng.u8(0..8) {
+                0 => Tag::EMPTY,
+                1 => Tag::DELETED,
+                _ => Tag::full(u64::from(rng.u8(0..8)) << 57),
+            });
+            let tag = Tag::full(u64::from(rng.u8(0..8)) << 57);
+            check(&tags, tag);
+        }
+    }
+}
@@ -2,9 +2,8 @@ mod bitmask;
 mod group;
 mod tag;
 
-use self::bitmask::BitMask;
 pub(crate) use self::{
-    bitmask::BitMaskIter,
+    bitmask::{BitMask, BitMaskIter},
     group::Group,
     tag::{Tag, TagSliceExt},
 };
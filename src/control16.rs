@@ -0,0 +1,79 @@
+//! 16-bit control words: a full slot stores the top 15 bits of the hash (top bit clear), an empty
+//! slot stores `EMPTY`. Compared to the 7-bit `Tag`, a tag match is 256x less likely to be a
+//! false positive.
+use cfg_if::cfg_if;
+
+pub const GROUP_WIDTH: usize = 16;
+
+pub const EMPTY: u16 = 0x8000;
+
+/// Top 15 bits of the hash.
+#[inline(always)]
+pub fn tag16(hash: u64) -> u16 {
+    (hash >> 49) as u16
+}
+
+/// Returns a mask of the slots in `group` equal to `tag`, and the number of mask bits per slot.
+///
+/// # Safety
+///
+/// `group` must be valid for reading `GROUP_WIDTH` control words and aligned to 32 bytes.
+#[inline(always)]
+#[allow(clippy::needless_return)]
+pub unsafe fn match_tag16(tag: u16, group: *const u16) -> (u32, usize) {
+    cfg_if! {
+        if #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))] {
+            return {
+                use core::arch::x86_64::*;
+                let tag_vec = _mm256_set1_epi16(tag as i16);
+                let group_vec = _mm256_load_si256(group as *const __m256i);
+                let eq_mask = _mm256_cmpeq_epi16(group_vec, tag_vec);
+                // Two movemask bits per slot; keep the low one so each match is a single bit.
+                let movemask = _mm256_movemask_epi8(eq_mask) as u32;
+                (movemask & 0x5555_5555, 2)
+            };
+        } else {
+            return {
+                let mut mask = 0;
+                for i in 0..GROUP_WIDTH {
+                    mask |= ((*group.add(i) == tag) as u32) << i;
+                }
+                (mask, 1)
+            };
+        }
+    }
+}
+
+/// Returns a mask of the empty slots in `group`, as `match_tag16`.
+///
+/// # Safety
+///
+/// As `match_tag16`.
+#[inline(always)]
+pub unsafe fn match_empty16(group: *const u16) -> (u32, usize) {
+    match_tag16(EMPTY, group)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(align(32))]
+    struct AlignedGroup([u16; GROUP_WIDTH]);
+
+    #[test]
+    fn test_match_tag16_matches_scalar() {
+        let mut rng = fastrand::Rng::with_seed(123);
+        for _ in 0..1000 {
+            // Draw from a few values so groups contain repeats.
+            let group = AlignedGroup(core::array::from_fn(|_| [1, 2, 0x7fff, EMPTY][rng.usize(..4)]));
+            for tag in [1, 2, 3, 0x7fff, EMPTY] {
+                let (mask, stride) = unsafe { match_tag16(tag, group.0.as_ptr()) };
+                for (i, &slot) in group.0.iter().enumerate() {
+                    assert_eq!(mask & (1 << (i * stride)) != 0, slot == tag);
+                }
+                assert_eq!(mask.count_ones() as usize, group.0.iter().filter(|&&s| s == tag).count());
+            }
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use cfg_if::cfg_if;
 
 #[inline(always)]
+#[allow(clippy::needless_return)]
 pub fn search_mask(key: u64, bucket: [u64; 4]) -> (u64, usize) {
     cfg_if! {
         if #[cfg(all(target_arch = "aarch64", target_feature = "neon"))] {
@@ -15,7 +16,7 @@ pub fn search_mask(key: u64, bucket: [u64; 4]) -> (u64, usize) {
                     let eq1: uint64x2_t = vceqq_u64(bucket1, key);
                     let eq0: uint8x16_t = vreinterpretq_u8_u64(eq0);
                     let eq1: uint8x16_t = vreinterpretq_u8_u64(eq1);
-                    let mask: uint8x16_t = std::mem::transmute([0u8, 8, 16, 24, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0]);
+                    let mask: uint8x16_t = core::mem::transmute([0u8, 8, 16, 24, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0]);
                     let eq_by_byte: uint8x16_t = vqtbl2q_u8(uint8x16x2_t(eq0, eq1), mask);
                     let eq_by_byte: u64 = vgetq_lane_u64(vreinterpretq_u64_u8(eq_by_byte), 0);
                     (eq_by_byte, 8)
@@ -33,11 +34,113 @@ pub fn search_mask(key: u64, bucket: [u64; 4]) -> (u64, usize) {
                 }
             };
         } else {
-            unimplemented!()
+            return search_mask_scalar(key, bucket);
         }
     }
 }
 
+/// Portable `search_mask`, with one mask bit per slot.
+#[inline(always)]
+fn search_mask_scalar(key: u64, bucket: [u64; 4]) -> (u64, usize) {
+    let mut mask = 0;
+    for (i, &slot) in bucket.iter().enumerate() {
+        mask |= ((slot == key) as u64) << i;
+    }
+    (mask, 1)
+}
+
+/// As `search_mask`, for buckets of two `u128` keys: a slot matches only if both of its 64-bit
+/// halves do.
+#[inline(always)]
+#[allow(clippy::needless_return)]
+pub fn search128(key: u128, bucket: [u128; 2]) -> (u64, usize) {
+    cfg_if! {
+        if #[cfg(all(target_arch = "aarch64", target_feature = "neon"))] {
+            return {
+                use core::arch::aarch64::*;
+                unsafe {
+                    let bucket_ptr = bucket.as_ptr() as *const u64;
+                    let key: uint64x2_t = vld1q_u64([key as u64, (key >> 64) as u64].as_ptr());
+                    let eq0: uint64x2_t = vceqq_u64(vld1q_u64(bucket_ptr), key);
+                    let eq1: uint64x2_t = vceqq_u64(vld1q_u64(bucket_ptr.add(2)), key);
+                    // All-ones only if both halves matched.
+                    let full0 = vminvq_u32(vreinterpretq_u32_u64(eq0)) != 0;
+                    let full1 = vminvq_u32(vreinterpretq_u32_u64(eq1)) != 0;
+                    (full0 as u64 | (full1 as u64) << 1, 1)
+                }
+            };
+        } else if #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))] {
+            return {
+                use core::arch::x86_64::*;
+                unsafe {
+                    let (lo, hi) = (key as u64 as i64, (key >> 64) as u64 as i64);
+                    let key_vec = _mm256_set_epi64x(hi, lo, hi, lo);
+                    let bucket_vec = _mm256_loadu_si256(bucket.as_ptr() as *const __m256i);
+                    let eq_mask = _mm256_cmpeq_epi64(bucket_vec, key_vec);
+                    let movemask = _mm256_movemask_pd(_mm256_castsi256_pd(eq_mask)) as u64;
+                    // One bit per 64-bit lane; keep the low bit of each pair only if both are set.
+                    (movemask & (movemask >> 1) & 0b0101, 2)
+                }
+            };
+        } else {
+            return search128_scalar(key, bucket);
+        }
+    }
+}
+
+/// Portable `search128`, with one mask bit per slot.
+#[inline(always)]
+fn search128_scalar(key: u128, bucket: [u128; 2]) -> (u64, usize) {
+    ((bucket[0] == key) as u64 | ((bucket[1] == key) as u64) << 1, 1)
+}
+
+/// As `search_mask`, for buckets of eight `u32`s, as in a layout packing `u32` values (or keys)
+/// eight to a 32-byte half cache line.
+#[inline(always)]
+#[allow(clippy::needless_return)]
+pub fn search_u32(key: u32, bucket: [u32; 8]) -> (u64, usize) {
+    cfg_if! {
+        if #[cfg(all(target_arch = "aarch64", target_feature = "neon"))] {
+            return {
+                use core::arch::aarch64::*;
+                unsafe {
+                    let bucket_ptr = bucket.as_ptr();
+                    let key: uint32x4_t = vdupq_n_u32(key);
+                    let eq0: uint32x4_t = vceqq_u32(vld1q_u32(bucket_ptr), key);
+                    let eq1: uint32x4_t = vceqq_u32(vld1q_u32(bucket_ptr.add(4)), key);
+                    // Narrow each all-ones lane to one 0xFF byte.
+                    let eq: uint16x8_t = vcombine_u16(vmovn_u32(eq0), vmovn_u32(eq1));
+                    let eq_by_byte: uint8x8_t = vmovn_u16(eq);
+                    (vget_lane_u64(vreinterpret_u64_u8(eq_by_byte), 0), 8)
+                }
+            };
+        } else if #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))] {
+            return {
+                use core::arch::x86_64::*;
+                unsafe {
+                    let key_vec = _mm256_set1_epi32(key as i32);
+                    let bucket_vec = _mm256_loadu_si256(bucket.as_ptr() as *const __m256i);
+                    let eq_mask = _mm256_cmpeq_epi32(bucket_vec, key_vec);
+                    let movemask = _mm256_movemask_ps(_mm256_castsi256_ps(eq_mask));
+                    (movemask as u32 as u64, 1)
+                }
+            };
+        } else {
+            return search_u32_scalar(key, bucket);
+        }
+    }
+}
+
+/// Portable `search_u32`, with one mask bit per slot.
+#[inline(always)]
+fn search_u32_scalar(key: u32, bucket: [u32; 8]) -> (u64, usize) {
+    let mut mask = 0;
+    for (i, &slot) in bucket.iter().enumerate() {
+        mask |= ((slot == key) as u64) << i;
+    }
+    (mask, 1)
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use super::*;
@@ -73,3 +176,70 @@ pub fn search_mask(key: u64, bucket: [u64; 4]) -> (u64, usize) {
 //         assert_eq!(search(5, bucket), Some(0));
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search128_slots(key: u128, bucket: [u128; 2]) -> [bool; 2] {
+        let slots = |(mask, stride): (u64, usize)| {
+            assert_eq!(mask & !(1 | 1 << stride), 0);
+            [mask & 1 != 0, mask & (1 << stride) != 0]
+        };
+        let slots_simd = slots(search128(key, bucket));
+        assert_eq!(slots(search128_scalar(key, bucket)), slots_simd);
+        slots_simd
+    }
+
+    fn search_u32_slots(key: u32, bucket: [u32; 8]) -> [bool; 8] {
+        let slots = |(mask, stride): (u64, usize)| {
+            let mut slots = [false; 8];
+            for (i, slot) in slots.iter_mut().enumerate() {
+                *slot = mask & (1 << (i * stride)) != 0;
+            }
+            // No bits besides one per matching slot.
+            assert_eq!(mask.count_ones() as usize, slots.iter().filter(|&&s| s).count());
+            slots
+        };
+        let slots_simd = slots(search_u32(key, bucket));
+        assert_eq!(slots(search_u32_scalar(key, bucket)), slots_simd);
+        slots_simd
+    }
+
+    #[test]
+    fn test_search_u32() {
+        let bucket = [10, 11, 12, 13, 14, 15, 16, 17];
+        for i in 0..8 {
+            let mut expected = [false; 8];
+            expected[i] = true;
+            assert_eq!(search_u32_slots(10 + i as u32, bucket), expected);
+        }
+        assert_eq!(search_u32_slots(18, bucket), [false; 8]);
+        assert_eq!(search_u32_slots(0, bucket), [false; 8]);
+        // Keys differing only in the high bits, or by sign, don't match.
+        assert_eq!(search_u32_slots(10 | 1 << 31, bucket), [false; 8]);
+        assert_eq!(search_u32_slots(u32::MAX, [u32::MAX, 0, 0, 0, 0, 0, 0, u32::MAX]), [
+            true, false, false, false, false, false, false, true
+        ]);
+        assert_eq!(search_u32_slots(5, [5; 8]), [true; 8]);
+        assert_eq!(search_u32_slots(5, [5, 6, 5, 6, 5, 6, 5, 6]), [true, false, true, false, true, false, true, false]);
+    }
+
+    #[test]
+    fn test_search128() {
+        let key = 0x0123_4567_89ab_cdef_fedc_ba98_7654_3210u128;
+        let low_only = key ^ (1 << 100);
+        let high_only = key ^ 1;
+        let swapped = key.rotate_left(64);
+
+        assert_eq!(search128_slots(key, [key, 0]), [true, false]);
+        assert_eq!(search128_slots(key, [0, key]), [false, true]);
+        assert_eq!(search128_slots(key, [key, key]), [true, true]);
+        // Agreeing in one 64-bit half is not a match, even with the other half in the other slot.
+        assert_eq!(search128_slots(key, [low_only, high_only]), [false, false]);
+        assert_eq!(search128_slots(key, [high_only, low_only]), [false, false]);
+        assert_eq!(search128_slots(key, [swapped, low_only]), [false, false]);
+        assert_eq!(search128_slots(key, [swapped, key]), [false, true]);
+        assert_eq!(search128_slots(0, [0, key]), [true, false]);
+    }
+}
@@ -0,0 +1,119 @@
+//! A global allocator wrapping `System` that counts allocations, so the benchmark can attribute
+//! time to allocation. Install it with `#[global_allocator]`, then bracket a phase with `reset`
+//! and `read`.
+//!
+//! Counts are per thread, so that concurrent tests (and any threads the harness doesn't measure)
+//! don't pollute each other's counts.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+pub struct CountingAllocator;
+
+/// Allocations since the last `reset` on this thread. A `realloc` counts as one allocation of
+/// the new size.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocCounts {
+    pub allocations: usize,
+    pub bytes: usize,
+}
+
+thread_local! {
+    // Const-initialized without a destructor, so touching them never allocates.
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static BYTES: Cell<usize> = const { Cell::new(0) };
+}
+
+#[inline(always)]
+fn count(size: usize) {
+    ALLOCATIONS.set(ALLOCATIONS.get() + 1);
+    BYTES.set(BYTES.get() + size);
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Zeroes this thread's counts.
+pub fn reset() {
+    ALLOCATIONS.set(0);
+    BYTES.set(0);
+}
+
+/// This thread's counts since the last `reset`.
+pub fn read() -> AllocCounts {
+    AllocCounts {
+        allocations: ALLOCATIONS.get(),
+        bytes: BYTES.get(),
+    }
+}
+
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aligned_cuckoo_table::HashTable;
+
+    #[test]
+    fn test_aligned_cuckoo_with_capacity_allocates_once() {
+        reset();
+        let table = HashTable::<u64>::with_capacity(1000);
+        let counts = read();
+        assert_eq!(counts.allocations, 1);
+        assert!(counts.bytes >= table.num_buckets() * (size_of::<(u64, u64)>() + 1));
+    }
+
+    #[test]
+    fn test_aligned_cuckoo_from_exact_iter_allocates_once() {
+        reset();
+        let table = HashTable::from_exact_iter((0..1_000_000u32).map(|k| (k as u64, k as u64)));
+        assert_eq!(read().allocations, 1);
+        assert_eq!(table.len(), 1_000_000);
+
+        // Against the `size_hint`-based path, which grows when the hint is unhelpful.
+        reset();
+        let table: HashTable<u64> = (0..1_000_000u64).filter(|_| true).map(|k| (k, k)).collect();
+        assert!(read().allocations > 1);
+        assert_eq!(table.len(), 1_000_000);
+    }
+
+    #[test]
+    fn test_aligned_cuckoo_clone_from_same_capacity_does_not_allocate() {
+        let mut source = HashTable::<u64>::with_capacity(1000);
+        let mut scratch = HashTable::<u64>::with_capacity(1000);
+        for round in 0..10 {
+            let _ = source.insert(round, round);
+            reset();
+            scratch.clone_from(&source);
+            assert_eq!(read().allocations, 0);
+            assert_eq!(scratch.len(), source.len());
+            assert_eq!(scratch.get(&round), Some(&round));
+        }
+
+        // A different capacity needs a fresh allocation.
+        let mut small = HashTable::<u64>::with_capacity(16);
+        reset();
+        small.clone_from(&source);
+        assert_eq!(read().allocations, 1);
+    }
+}
@@ -0,0 +1,119 @@
+//! The cuckoo BFS of the aligned cuckoo tables (`aligned_cuckoo_table` and its tag16 and SoA
+//! variants), written once over the slot access each table's layout provides.
+
+use core::mem::MaybeUninit;
+
+use crate::InsertError;
+
+/// Slot access for `make_room`. Slots come in aligned groups of `WIDTH`, and every full slot has
+/// exactly one other candidate group, computable from the slot alone.
+pub(crate) trait CuckooSlots {
+    /// Slots per group, which is also the fan-out of the search.
+    const WIDTH: usize;
+
+    /// Given a full slot at `index` in the group at `pos`, returns the slot's other group.
+    fn other_pos(&self, pos: usize, index: usize) -> usize;
+
+    /// Returns the index of the first empty slot in the group at `pos`, if any.
+    fn first_empty(&self, pos: usize) -> Option<usize>;
+
+    /// Copies the entry at `from`, control included, into `to`, leaving `from` as it was.
+    ///
+    /// # Safety
+    ///
+    /// `from` must be full, and `to` must hold no live entry: it is either empty or has just
+    /// been copied out by the previous move.
+    unsafe fn move_slot(&mut self, from: usize, to: usize);
+}
+
+/// Cuckoo BFS from two full groups at `pos0` and `pos1`, over two complete `WIDTH`-ary trees
+/// down to `depth`. Moves entries along the shortest path to an empty slot and returns the slot
+/// it freed in `pos0` or `pos1`, plus the insertion probe length.
+///
+/// Fails with `NeedsRehash` if no path exists within `depth`, and with `BudgetExceeded` after
+/// visiting `max_nodes` groups. Nothing moves until a path is found, so either failure leaves the
+/// table unchanged.
+#[inline(always)]
+pub(crate) fn make_room<T: CuckooSlots>(
+    table: &mut T,
+    pos0: usize,
+    pos1: usize,
+    depth: usize,
+    max_nodes: usize,
+) -> Result<(usize, usize), InsertError> {
+    // 2 groups at the first level, then 2*N, 2*N^2, ..., 2*N^depth.
+    let n = T::WIDTH;
+    let bfs_max_len = 2 * (0..=depth).map(|level| n.pow(level as u32)).sum::<usize>();
+    // Sized for 16-wide groups at depth 3, the default of every caller. Deeper searches put the
+    // queue on the heap, since 2*16^4 slots would be over 1MB of stack.
+    const BFS_CAPACITY: usize = 2 * (1 + 16 + 16 * 16 + 16 * 16 * 16);
+    if bfs_max_len <= BFS_CAPACITY {
+        let mut bfs_queue = [MaybeUninit::<usize>::uninit(); BFS_CAPACITY];
+        bfs(table, pos0, pos1, max_nodes, &mut bfs_queue[..bfs_max_len])
+    } else {
+        let mut bfs_queue = alloc::vec::Vec::<usize>::with_capacity(bfs_max_len);
+        bfs(table, pos0, pos1, max_nodes, &mut bfs_queue.spare_capacity_mut()[..bfs_max_len])
+    }
+}
+
+/// Body of `make_room`, with a queue of `2 * (1 + N + ... + N^depth)` slots.
+#[inline(always)]
+fn bfs<T: CuckooSlots>(
+    table: &mut T,
+    pos0: usize,
+    pos1: usize,
+    max_nodes: usize,
+    bfs_queue: &mut [MaybeUninit<usize>],
+) -> Result<(usize, usize), InsertError> {
+    // BFS queue maintains group indexes to visit.
+    //
+    // The parent of node at index `i` is at index `(i-2)/N`. Inversely, the first child of
+    // node `j` is at index `j*N+2`.
+    let n = T::WIDTH;
+    let bfs_max_len = bfs_queue.len();
+    bfs_queue[0].write(pos0);
+    bfs_queue[1].write(pos1);
+    let mut bfs_read_pos = 0;
+    let (mut path_index, mut bucket_index, insertion_probe_length) = 'bfs: loop {
+        let pos = unsafe { bfs_queue[bfs_read_pos].assume_init() };
+
+        let bfs_write_pos = bfs_read_pos * n + 2;
+        if bfs_write_pos >= bfs_max_len {
+            return Err(InsertError::NeedsRehash);
+        }
+
+        for i in 0..n {
+            let bfs_write_pos_i = bfs_write_pos + i;
+            // The queue holds the two roots, then one entry per group visited.
+            if bfs_write_pos_i - 2 >= max_nodes {
+                return Err(InsertError::BudgetExceeded);
+            }
+            let other_pos = table.other_pos(pos, pos + i);
+            if let Some(empty_index) = table.first_empty(other_pos) {
+                // Calculate insertion probe length based on BFS level
+                let insertion_probe_length = 2 + (bfs_write_pos_i - 2) / n;
+                break 'bfs (bfs_write_pos_i, empty_index, insertion_probe_length);
+            }
+
+            // `bfs_max_len - 2` is a multiple of N, so `bfs_write_pos < bfs_max_len` keeps all
+            // N children of this node in bounds.
+            unsafe { bfs_queue.get_unchecked_mut(bfs_write_pos_i).write(other_pos) };
+        }
+
+        bfs_read_pos += 1;
+    }; // 'bfs
+    while path_index >= 2 {
+        let parent_path_index = (path_index - 2) / n;
+        let parent_bucket_offset = (path_index - 2) % n;
+        let parent_bucket_index =
+            unsafe { bfs_queue.get_unchecked(parent_path_index).assume_init() }
+                + parent_bucket_offset;
+
+        // Move from parent to child. The child is the empty slot found above, or the slot
+        // the previous iteration just copied out of.
+        unsafe { table.move_slot(parent_bucket_index, bucket_index) };
+        bucket_index = parent_bucket_index;
+        path_index = parent_path_index;
+    }
+    Ok((bucket_index, insertion_probe_length))
+}
@@ -1,9 +1,13 @@
 //! "Direct SIMD" layout which does SIMD probing on `[u64; 4]` rather than `[u8; 8]`.
 
-use std::mem::MaybeUninit;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::mem::MaybeUninit;
+use core::fmt;
 
 use crate::u64_fold_hash_fast::fold_hash_fast;
-use crate::{TRACK_PROBE_LENGTH, control64};
+use crate::{FillToFailure, InsertError, control64};
+use crate::DebugEntries;
 
 pub struct HashTable<V> {
     table: Box<[Bucket<V>]>,
@@ -13,10 +17,16 @@ pub struct HashTable<V> {
     seed: u64,
     total_probe_length: usize,
     rng: fastrand::Rng,
+    // Whether `get` probes both buckets without branching; see `with_branchless`.
+    branchless: bool,
 }
 
 const BUCKET_SIZE: usize = 4;
 
+/// Load factor `try_insert` reliably fills a table to before failing. Filling random keys into
+/// 2^10 to 2^20 slots failed between 92% and 98%, lower for larger tables.
+pub const PEAK_LOAD_FACTOR: f64 = 0.92;
+
 #[repr(align(64))] // Cache line alignment
 struct Bucket<V> {
     keys: [u64; BUCKET_SIZE],
@@ -24,6 +34,7 @@ struct Bucket<V> {
 }
 
 impl<V> HashTable<V> {
+    #[cfg(feature = "std")]
     pub fn print_stats(&self) {}
 
     #[inline(always)]
@@ -32,15 +43,18 @@ impl<V> HashTable<V> {
         let num_buckets = ((capacity * 8) / 7)
             .next_power_of_two()
             .div_ceil(BUCKET_SIZE);
+        Self::with_num_buckets(num_buckets, fastrand::Rng::with_seed(123).u64(..))
+    }
+
+    fn with_num_buckets(num_buckets: usize, seed: u64) -> Self {
         let table = {
             let mut v = Vec::new();
             v.resize_with(num_buckets, || Bucket {
                 keys: [0; BUCKET_SIZE],
-                values: std::array::from_fn(|_| MaybeUninit::uninit()),
+                values: core::array::from_fn(|_| MaybeUninit::uninit()),
             });
             v.into_boxed_slice()
         };
-        let seed = fastrand::Rng::with_seed(123).u64(..);
         Self {
             table,
             bucket_mask: num_buckets - 1,
@@ -49,23 +63,107 @@ impl<V> HashTable<V> {
             seed,
             total_probe_length: 0,
             rng: fastrand::Rng::with_seed(123),
+            branchless: false,
         }
     }
 
+    /// Makes `get` search both buckets and pick the match with `select_unpredictable`, rather
+    /// than returning as soon as the first bucket matches.
+    ///
+    /// Off by default: it's the faster choice for in-cache tables, where the second bucket is
+    /// cheap to search and a mispredicted branch isn't, but the slower one out of cache, where it
+    /// always waits on the second bucket's cache miss.
+    pub fn with_branchless(mut self, branchless: bool) -> Self {
+        self.branchless = branchless;
+        self
+    }
+
     #[inline(always)]
     pub fn len(&self) -> usize {
         self.len
     }
 
     #[inline(always)]
-    pub fn insert(&mut self, mut key: u64, mut value: V) -> (bool, (usize, usize), usize) {
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The hash of `key`. `hash` picks the first bucket and `hash ^ hash.rotate_left(32)` the
+    /// second.
+    #[inline(always)]
+    pub fn hash_of(&self, key: u64) -> u64 {
+        fold_hash_fast(key, self.seed)
+    }
+
+    /// Returns the number of buckets in the table.
+    #[inline(always)]
+    pub fn num_buckets(&self) -> usize {
+        self.bucket_mask + 1
+    }
+
+    /// Fraction of slots that are full, counting `BUCKET_SIZE` slots per bucket.
+    pub fn load_factor(&self) -> f64 {
+        self.len() as f64 / (self.num_buckets() * BUCKET_SIZE) as f64
+    }
+
+    /// Inserts `key`, doubling the table (see `grow`) whenever the BFS finds no free slot.
+    #[inline(always)]
+    pub fn insert(&mut self, key: u64, mut value: V) -> (bool, (usize, usize), usize) {
+        loop {
+            match self.insert_or_return(key, value) {
+                Ok(result) => return result,
+                Err(returned) => {
+                    value = returned;
+                    self.grow();
+                }
+            }
+        }
+    }
+
+    /// As `insert`, but returns `InsertError::NeedsRehash` instead of growing when the BFS finds
+    /// no free slot. The table is unchanged on failure.
+    #[inline(always)]
+    pub fn try_insert(&mut self, key: u64, value: V) -> Result<(bool, (usize, usize), usize), InsertError> {
+        self.insert_or_return(key, value).map_err(|_| InsertError::NeedsRehash)
+    }
+
+    /// Doubles the number of buckets and reinserts every entry under a fresh seed.
+    #[cold]
+    #[inline(never)]
+    fn grow(&mut self) {
+        let seed = fastrand::Rng::with_seed(self.seed).u64(..);
+        let mut new_table = Self::with_num_buckets(self.num_buckets() * 2, seed);
+        for bucket in &self.table {
+            for (&key, value) in bucket.keys.iter().zip(&bucket.values) {
+                if key != 0 {
+                    new_table.insert(key, unsafe { value.assume_init_read() });
+                }
+            }
+        }
+        // Out of band, so unaffected by the new bucket layout.
+        if let Some(value) = self.zero_value.take() {
+            new_table.insert(0, value);
+        }
+        new_table.total_probe_length = self.total_probe_length;
+        new_table.branchless = self.branchless;
+        *self = new_table;
+    }
+
+    /// Body of `try_insert`, handing `value` back when the BFS fails so that `insert` can grow
+    /// and retry.
+    #[inline(always)]
+    fn insert_or_return(
+        &mut self,
+        key: u64,
+        value: V,
+    ) -> Result<(bool, (usize, usize), usize), V> {
         let mut insertion_probe_length = 1;
 
         if key == 0 {
             let inserted = self.zero_value.is_none();
             self.len += inserted as usize;
             self.zero_value = Some(value);
-            return (inserted, (usize::MAX, usize::MAX), insertion_probe_length);
+            return Ok((inserted, (usize::MAX, usize::MAX), insertion_probe_length));
         }
         let bucket_mask = self.bucket_mask;
         let hash64 = fold_hash_fast(key, self.seed);
@@ -96,7 +194,7 @@ impl<V> HashTable<V> {
                         bucket.keys[index] = key;
                         bucket.values[index].write(value);
                     }
-                    return (true, (pos0, index), 1);
+                    return Ok((true, (pos0, index), 1));
                 }
             }
 
@@ -118,7 +216,6 @@ impl<V> HashTable<V> {
             //
             // The parent of node at index `i` is at index `(i-2)/N`. Inversely, the first child of
             // node `j` is at index `j*N+2`.
-            self.len += 1;
             const N: usize = BUCKET_SIZE;
             const BFS_MAX_LEN: usize = 2 * (1 + N + N * N + N * N * N);
 
@@ -132,7 +229,7 @@ impl<V> HashTable<V> {
             bfs_queue[0].write(pos0);
             bfs_queue[1].write(pos1);
             let mut bfs_read_pos = 0;
-            let (mut path_index, mut bucket_index, mut bucket_mask, stride) = 'bfs: loop {
+            let (mut path_index, mut bucket_index, bucket_mask, stride) = 'bfs: loop {
                 let (mask, stride) = control64::search_mask(0, keys0);
                 if mask != 0 {
                     break 'bfs (bfs_read_pos + 0, pos0, mask, stride);
@@ -151,10 +248,10 @@ impl<V> HashTable<V> {
                         let other_pos0 = other_pos(pos0, keys0[i]);
                         let other_pos1 = other_pos(pos1, keys1[i]);
                         unsafe {
-                            *bfs_queue
+                            bfs_queue
                                 .get_unchecked_mut(bfs_write_pos + i)
                                 .write(other_pos0);
-                            *bfs_queue
+                            bfs_queue
                                 .get_unchecked_mut(bfs_write_pos + i + N)
                                 .write(other_pos1);
                         }
@@ -164,7 +261,7 @@ impl<V> HashTable<V> {
                 bfs_read_pos += 2;
 
                 if bfs_read_pos + 2 > BFS_MAX_LEN {
-                    panic!("Failed to insert into cuckoo table; need to rehash");
+                    return Err(value);
                 }
                 pos0 = unsafe { bfs_queue[bfs_read_pos + 0].assume_init() };
                 pos1 = unsafe { bfs_queue[bfs_read_pos + 1].assume_init() };
@@ -196,15 +293,17 @@ impl<V> HashTable<V> {
                 bucket.keys[bucket_offset] = key;
                 bucket.values[bucket_offset].write(value);
             }
+            // Only counted once placed, so a failed BFS leaves `len` as it was.
+            self.len += 1;
             // Calculate insertion probe length: base probes + BFS depth
             insertion_probe_length = 2 + (path_index + 1) / 2;
-            return (true, (bucket_index, bucket_offset), insertion_probe_length);
+            return Ok((true, (bucket_index, bucket_offset), insertion_probe_length));
         };
         let existing_index = existing_mask.trailing_zeros() as usize / stride;
         unsafe {
             *self.table.get_unchecked_mut(existing_bucket).values.get_unchecked_mut(existing_index).assume_init_mut() = value;
         }
-        (false, (existing_bucket, existing_index), insertion_probe_length)
+        Ok((false, (existing_bucket, existing_index), insertion_probe_length))
     }
 
     #[inline(always)]
@@ -216,13 +315,12 @@ impl<V> HashTable<V> {
         let mut hash64 = fold_hash_fast(key, self.seed);
         let bucket_mask = self.bucket_mask;
         let mut result = None;
-        for i in 0..2 {
+        for _ in 0..2 {
             let bucket = unsafe { self.table.get_unchecked(hash64 as usize & bucket_mask) };
             let keys = bucket.keys;
             let (mask, stride) = control64::search_mask(key, keys);
-            const BRANCHLESS: bool = false;  // true for in-cache; false for out-of-cache.
-            if BRANCHLESS {
-                result = std::hint::select_unpredictable(mask != 0, Some((mask, bucket, stride)), result);
+            if self.branchless {
+                result = core::hint::select_unpredictable(mask != 0, Some((mask, bucket, stride)), result);
             } else {
                 if mask != 0 {
                     let index = mask.trailing_zeros() as usize / stride;
@@ -234,6 +332,63 @@ impl<V> HashTable<V> {
         result.map(|(mask, bucket, stride)| unsafe { bucket.values.get_unchecked(mask.trailing_zeros() as usize / stride).assume_init_ref() })
     }
 
+    /// Inserts `key`, returning the previous value if the key was already present (like
+    /// `std::collections::HashMap::insert`).
+    #[inline(always)]
+    pub fn replace(&mut self, key: u64, value: V) -> Option<V> {
+        if key == 0 {
+            let old = self.zero_value.replace(value);
+            self.len += old.is_none() as usize;
+            return old;
+        }
+        if let Some((bucket_index, bucket_offset)) = self.find_slot(key) {
+            let slot = unsafe {
+                self.table.get_unchecked_mut(bucket_index).values.get_unchecked_mut(bucket_offset).assume_init_mut()
+            };
+            return Some(core::mem::replace(slot, value));
+        }
+        self.insert(key, value);
+        None
+    }
+
+    /// Iterates over all entries, bucket by bucket. Key 0 lives out of band, so it comes first.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &V)> {
+        let zero = self.zero_value.as_ref().map(|value| (0, value));
+        let slots = self.table.iter().flat_map(|bucket| {
+            bucket
+                .keys
+                .iter()
+                .zip(&bucket.values)
+                .filter(|&(&key, _)| key != 0)
+                .map(|(&key, value)| (key, unsafe { value.assume_init_ref() }))
+        });
+        zero.into_iter().chain(slots)
+    }
+
+    /// The keys of each bucket, in bucket order, for scanning with your own SIMD. A zero key is an
+    /// empty slot; key 0 itself lives out of band (see `get`). Each array is the 64-byte-aligned
+    /// start of its bucket's cache line. The values sit in the same cache line right after the
+    /// keys, which is why this yields one array per bucket rather than a single `&[[u64; 4]]`.
+    pub fn key_buckets(&self) -> impl ExactSizeIterator<Item = &[u64; BUCKET_SIZE]> {
+        self.table.iter().map(|bucket| &bucket.keys)
+    }
+
+    /// Returns `(bucket_index, bucket_offset)` of a nonzero `key`, if present.
+    #[inline(always)]
+    fn find_slot(&self, key: u64) -> Option<(usize, usize)> {
+        let mut hash64 = fold_hash_fast(key, self.seed);
+        for _ in 0..2 {
+            let bucket_index = hash64 as usize & self.bucket_mask;
+            let keys = unsafe { self.table.get_unchecked(bucket_index) }.keys;
+            let (mask, stride) = control64::search_mask(key, keys);
+            if mask != 0 {
+                return Some((bucket_index, mask.trailing_zeros() as usize / stride));
+            }
+            hash64 ^= hash64.rotate_left(32);
+        }
+        None
+    }
+
     pub fn probe_length(&self, key: u64) -> (usize, bool) {
         if key == 0 {
             return (1, self.zero_value.is_some()); // Zero key is always in first probe
@@ -245,7 +400,7 @@ impl<V> HashTable<V> {
         for i in 0..2 {
             let bucket = unsafe { self.table.get_unchecked(hash64 as usize & bucket_mask) };
             let keys = bucket.keys;
-            let (mask, stride) = control64::search_mask(key, keys);
+            let (mask, _) = control64::search_mask(key, keys);
 
             if mask != 0 {
                 return (i + 1, true); // Key found at probe i+1
@@ -281,3 +436,171 @@ impl<V> HashTable<V> {
     }
 }
 
+
+impl<V: fmt::Debug> fmt::Debug for HashTable<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = move || self.iter();
+        f.debug_struct("HashTable")
+            .field("len", &self.len())
+            .field("num_buckets", &self.num_buckets())
+            .field("entries", &DebugEntries(entries))
+            .finish()
+    }
+}
+
+/// The table only grows when the cuckoo BFS finds no room, which is where `try_insert` fails.
+impl<V: Default> FillToFailure for HashTable<V> {
+    fn with_capacity(capacity: usize) -> Self {
+        HashTable::with_capacity(capacity)
+    }
+
+    fn try_insert_key(&mut self, key: u64) -> bool {
+        self.try_insert(key, V::default()).is_ok()
+    }
+
+    fn load_factor(&self) -> f64 {
+        HashTable::load_factor(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peak_load_factor() {
+        crate::assert_reaches_peak_load_factor::<HashTable<u64>>(PEAK_LOAD_FACTOR);
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut table = HashTable::with_capacity(64);
+        for i in 0..20 {
+            assert_eq!(table.replace(i, i * 10), None);
+        }
+        assert_eq!(table.len(), 20);
+
+        // Updating returns the old value, including for the out-of-band zero key.
+        assert_eq!(table.replace(0, 1), Some(0));
+        assert_eq!(table.replace(7, 700), Some(70));
+        assert_eq!(table.len(), 20);
+        assert_eq!(table.get(&0), Some(&1));
+        assert_eq!(table.get(&7), Some(&700));
+        assert_eq!(table.get(&8), Some(&80));
+    }
+
+    #[test]
+    fn test_branchless() {
+        let mut rng = fastrand::Rng::with_seed(5);
+        let keys: Vec<u64> = (0..3000).map(|_| rng.u64(..)).collect();
+        let mut branchy = HashTable::with_capacity(64);
+        // Small, so the setting has to survive growth.
+        let mut branchless = HashTable::with_capacity(64).with_branchless(true);
+        for &key in &keys {
+            branchy.insert(key, !key);
+            branchless.insert(key, !key);
+        }
+        assert!(branchless.branchless);
+        for key in keys.iter().copied().chain((0..1000).map(|_| rng.u64(..))).chain([0]) {
+            assert_eq!(branchy.get(&key), branchless.get(&key));
+        }
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut table = HashTable::with_capacity(64);
+        assert_eq!(table.iter().count(), 0);
+        let keys = [0, 1, 2, 3, 1000, u64::MAX];
+        for key in keys {
+            table.insert(key, !key);
+        }
+        let mut entries: Vec<_> = table.iter().map(|(key, &value)| (key, value)).collect();
+        entries.sort();
+        assert_eq!(entries, keys.map(|key| (key, !key)));
+    }
+
+    #[test]
+    fn test_key_buckets() {
+        let mut table = HashTable::with_capacity(1024);
+        let mut rng = fastrand::Rng::with_seed(123);
+        for _ in 0..800 {
+            let key = rng.u64(..1 << 20);
+            table.insert(key, !key);
+        }
+        assert_eq!(table.key_buckets().len(), table.num_buckets());
+        let present: std::collections::HashSet<u64> =
+            table.key_buckets().flatten().copied().filter(|&key| key != 0).collect();
+        assert_eq!(present.len(), table.len());
+        for key in 1..1 << 20 {
+            assert_eq!(table.get(&key).is_some(), present.contains(&key), "key {key}");
+        }
+    }
+
+    #[test]
+    fn test_try_insert_failure_leaves_table_unchanged() {
+        // No growth, so filling with random keys must eventually exhaust the BFS.
+        let mut table = HashTable::with_capacity(64);
+        let mut rng = fastrand::Rng::with_seed(123);
+        let mut keys = Vec::new();
+        let err = loop {
+            let key = rng.u64(1..);
+            match table.try_insert(key, !key) {
+                Ok((inserted, _, _)) => {
+                    assert!(inserted);
+                    keys.push(key);
+                }
+                Err(err) => break err,
+            }
+        };
+        assert_eq!(err, InsertError::NeedsRehash);
+        assert_eq!(table.len(), keys.len());
+        for key in &keys {
+            assert_eq!(table.get(key), Some(&!key));
+        }
+    }
+
+    #[test]
+    fn test_update_keeps_slot_and_len() {
+        let mut table = HashTable::with_capacity(64);
+        let mut rng = fastrand::Rng::with_seed(123);
+        let mut placed = 0;
+        while let Ok((inserted, _, _)) = table.try_insert(rng.u64(1..), 0) {
+            assert!(inserted);
+            placed += 1;
+        }
+        // The failed insert above must not have counted.
+        let len = table.len();
+        assert_eq!(len, placed);
+        let keys: Vec<u64> = table.iter().map(|(key, _)| key).collect();
+        for &key in &keys {
+            // Later BFS moves may have displaced it since it was placed, so compare against
+            // wherever it is now.
+            let slot = table.find_slot(key).unwrap();
+            let (inserted, updated_slot, _) = table.try_insert(key, key).unwrap();
+            assert!(!inserted);
+            assert_eq!(updated_slot, slot);
+            assert_eq!(table.find_slot(key), Some(slot));
+            assert_eq!(table.len(), len);
+        }
+        for key in keys {
+            assert_eq!(table.get(&key), Some(&key));
+        }
+    }
+
+    #[test]
+    fn test_grow_past_initial_capacity() {
+        let mut table = HashTable::with_capacity(16);
+        let num_buckets = table.num_buckets();
+        let n = 10_000u64;
+        for key in 0..n {
+            assert!(table.insert(key, !key).0);
+        }
+        assert!(table.num_buckets() > num_buckets);
+        assert_eq!(table.len(), n as usize);
+        // Including the out-of-band key.
+        for key in 0..n {
+            assert_eq!(table.get(&key), Some(&!key));
+        }
+        assert_eq!(table.get(&n), None);
+    }
+}
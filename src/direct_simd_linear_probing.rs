@@ -3,10 +3,14 @@
 //! the probe sequence steps by exactly 1 bucket each probe (`pos = (pos + 1) & mask`), with no
 //! running stride.
 
-use std::mem::MaybeUninit;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::mem::MaybeUninit;
+use core::fmt;
 
 use crate::u64_fold_hash_fast::fold_hash_fast;
 use crate::{TRACK_PROBE_LENGTH, control64};
+use crate::DebugEntries;
 
 pub struct HashTable<V> {
     table: Box<[Bucket<V>]>,
@@ -39,6 +43,7 @@ impl ProbeSeq {
 }
 
 impl<V> HashTable<V> {
+    #[cfg(feature = "std")]
     pub fn print_stats(&self) {
         if TRACK_PROBE_LENGTH && self.len > 0 {
             println!(
@@ -62,7 +67,7 @@ impl<V> HashTable<V> {
             let mut v = Vec::new();
             v.resize_with(num_buckets, || Bucket {
                 keys: [0; BUCKET_SIZE],
-                values: std::array::from_fn(|_| MaybeUninit::uninit()),
+                values: core::array::from_fn(|_| MaybeUninit::uninit()),
             });
             v.into_boxed_slice()
         };
@@ -82,6 +87,17 @@ impl<V> HashTable<V> {
         self.len
     }
 
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The hash of `key`, whose low bits pick the bucket the linear probe starts at.
+    #[inline(always)]
+    pub fn hash_of(&self, key: u64) -> u64 {
+        fold_hash_fast(key, self.seed)
+    }
+
     fn probe_seq(&self, hash64: u64) -> ProbeSeq {
         ProbeSeq {
             pos: (hash64 as usize) & self.bucket_mask,
@@ -188,7 +204,7 @@ impl<V> HashTable<V> {
             let bucket = unsafe { self.table.get_unchecked(probe_seq.pos) };
             let keys = bucket.keys;
 
-            let (mask, stride) = control64::search_mask(key, keys);
+            let (mask, _) = control64::search_mask(key, keys);
             if mask != 0 {
                 return (probe_count, true);
             }
@@ -223,6 +239,34 @@ impl<V> HashTable<V> {
     }
 }
 
+impl<V> Default for HashTable<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for HashTable<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = move || {
+            let zero = self.zero_value.as_ref().map(|value| (0, value));
+            let slots = self.table.iter().flat_map(|bucket| {
+                bucket
+                    .keys
+                    .iter()
+                    .zip(&bucket.values)
+                    .filter(|&(&key, _)| key != 0)
+                    .map(|(&key, value)| (key, unsafe { value.assume_init_ref() }))
+            });
+            zero.into_iter().chain(slots)
+        };
+        f.debug_struct("HashTable")
+            .field("len", &self.len())
+            .field("num_buckets", &self.table.len())
+            .field("entries", &DebugEntries(entries))
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
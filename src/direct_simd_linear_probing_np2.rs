@@ -11,10 +11,14 @@
 //! latency-1 `& mask`. This table measures that cost head-to-head against `direct_simd_linear_probing`
 //! by sizing to the *same* bucket count but using the general indexing path.
 
-use std::mem::MaybeUninit;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::mem::MaybeUninit;
+use core::fmt;
 
 use crate::u64_fold_hash_fast::fold_hash_fast;
 use crate::{TRACK_PROBE_LENGTH, control64};
+use crate::DebugEntries;
 
 pub struct HashTable<V> {
     table: Box<[Bucket<V>]>,
@@ -39,6 +43,7 @@ fn mul_high(x: u64, y: u64) -> u64 {
 }
 
 impl<V> HashTable<V> {
+    #[cfg(feature = "std")]
     pub fn print_stats(&self) {}
 
     #[inline(always)]
@@ -64,7 +69,7 @@ impl<V> HashTable<V> {
             let mut v = Vec::new();
             v.resize_with(num_buckets, || Bucket {
                 keys: [0; BUCKET_SIZE],
-                values: std::array::from_fn(|_| MaybeUninit::uninit()),
+                values: core::array::from_fn(|_| MaybeUninit::uninit()),
             });
             v.into_boxed_slice()
         };
@@ -84,6 +89,17 @@ impl<V> HashTable<V> {
         self.len
     }
 
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The hash of `key`; the probe starts at bucket `mul_high(hash, num_buckets)`.
+    #[inline(always)]
+    pub fn hash_of(&self, key: u64) -> u64 {
+        fold_hash_fast(key, self.seed)
+    }
+
     #[cfg(test)]
     pub fn num_buckets(&self) -> usize {
         self.num_buckets
@@ -208,7 +224,7 @@ impl<V> HashTable<V> {
             probe_count += 1;
             let bucket = unsafe { self.table.get_unchecked(pos) };
             let keys = bucket.keys;
-            let (mask, stride) = control64::search_mask(key, keys);
+            let (mask, _) = control64::search_mask(key, keys);
             if mask != 0 {
                 return (probe_count, true);
             }
@@ -241,6 +257,34 @@ impl<V> HashTable<V> {
     }
 }
 
+impl<V> Default for HashTable<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for HashTable<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = move || {
+            let zero = self.zero_value.as_ref().map(|value| (0, value));
+            let slots = self.table.iter().flat_map(|bucket| {
+                bucket
+                    .keys
+                    .iter()
+                    .zip(&bucket.values)
+                    .filter(|&(&key, _)| key != 0)
+                    .map(|(&key, value)| (key, unsafe { value.assume_init_ref() }))
+            });
+            zero.into_iter().chain(slots)
+        };
+        f.debug_struct("HashTable")
+            .field("len", &self.len())
+            .field("num_buckets", &self.num_buckets)
+            .field("entries", &DebugEntries(entries))
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,22 +1,39 @@
 //! "Direct SIMD + quadratic probing" layout which combines SIMD probing on `[u64; 4]` buckets
 //! with quadratic probing for collision resolution instead of cuckoo hashing.
 
-use std::mem::MaybeUninit;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::mem::MaybeUninit;
+use core::fmt;
 
 use crate::u64_fold_hash_fast::fold_hash_fast;
 use crate::{TRACK_PROBE_LENGTH, control64};
+use crate::DebugEntries;
 
 pub struct HashTable<V> {
     table: Box<[Bucket<V>]>,
     bucket_mask: usize,
     len: usize,
+    // Number of empty (zero) slots that may still be filled before the table must grow. Taking a
+    // `TOMBSTONE` slot doesn't consume growth, so every probe sequence ends at an empty slot.
+    growth_left: usize,
+    // Number of `TOMBSTONE` slots. Once this passes an eighth of the slots the next insert
+    // compacts the table, before tombstones make misses walk most of the probe sequence.
+    tombstones: usize,
     zero_value: Option<V>,
+    // Value for key `TOMBSTONE`, which, like key 0, can't be stored in a bucket.
+    tombstone_value: Option<V>,
     seed: u64,
     total_probe_length: usize,
 }
 
 const BUCKET_SIZE: usize = 4;
 
+/// Key marking a removed slot. Probing stops at the first bucket with an empty (zero) slot, so a
+/// removed slot in an otherwise-full bucket can't go back to zero without hiding keys further
+/// along the probe sequence.
+const TOMBSTONE: u64 = u64::MAX;
+
 #[repr(align(64))] // Cache line alignment
 struct Bucket<V> {
     keys: [u64; BUCKET_SIZE],
@@ -51,6 +68,7 @@ impl ProbeSeq {
 }
 
 impl<V> HashTable<V> {
+    #[cfg(feature = "std")]
     pub fn print_stats(&self) {
         if TRACK_PROBE_LENGTH && self.len > 0 {
             println!("  avg_probe_length: {}", self.total_probe_length as f64 / self.len as f64);
@@ -63,20 +81,26 @@ impl<V> HashTable<V> {
         let num_buckets = ((capacity * 8) / 7)
             .next_power_of_two()
             .div_ceil(BUCKET_SIZE);
+        Self::with_num_buckets(num_buckets, fastrand::Rng::with_seed(123).u64(..))
+    }
+
+    fn with_num_buckets(num_buckets: usize, seed: u64) -> Self {
         let table = {
             let mut v = Vec::new();
             v.resize_with(num_buckets, || Bucket {
                 keys: [0; BUCKET_SIZE],
-                values: std::array::from_fn(|_| MaybeUninit::uninit()),
+                values: core::array::from_fn(|_| MaybeUninit::uninit()),
             });
             v.into_boxed_slice()
         };
-        let seed = fastrand::Rng::with_seed(123).u64(..);
         Self {
             table,
             bucket_mask: num_buckets - 1,
             len: 0,
+            growth_left: num_buckets * BUCKET_SIZE * 7 / 8,
+            tombstones: 0,
             zero_value: None,
+            tombstone_value: None,
             seed,
             total_probe_length: 0,
         }
@@ -87,6 +111,56 @@ impl<V> HashTable<V> {
         self.len
     }
 
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The hash of `key`, whose low bits pick the bucket the quadratic probe starts at.
+    #[inline(always)]
+    pub fn hash_of(&self, key: u64) -> u64 {
+        fold_hash_fast(key, self.seed)
+    }
+
+    /// Returns the number of buckets in the table.
+    #[inline(always)]
+    pub fn num_buckets(&self) -> usize {
+        self.bucket_mask + 1
+    }
+
+    /// Doubles the number of buckets; see `rehash`.
+    #[cold]
+    #[inline(never)]
+    fn grow(&mut self) {
+        self.rehash(self.num_buckets() * 2);
+    }
+
+    /// Reinserts every entry at the same size, clearing all tombstones; see `rehash`.
+    #[cold]
+    #[inline(never)]
+    pub fn compact(&mut self) {
+        self.rehash(self.num_buckets());
+    }
+
+    /// Reinserts every entry into `num_buckets` buckets under a fresh seed, dropping tombstones.
+    fn rehash(&mut self, num_buckets: usize) {
+        let seed = fastrand::Rng::with_seed(self.seed).u64(..);
+        let mut new_table = Self::with_num_buckets(num_buckets, seed);
+        for bucket in &self.table {
+            for (&key, value) in bucket.keys.iter().zip(&bucket.values) {
+                if !is_special_key(key) {
+                    new_table.insert(key, unsafe { value.assume_init_read() });
+                }
+            }
+        }
+        // Out of band, so unaffected by the new bucket layout.
+        new_table.zero_value = self.zero_value.take();
+        new_table.tombstone_value = self.tombstone_value.take();
+        new_table.len = self.len;
+        new_table.total_probe_length = self.total_probe_length;
+        *self = new_table;
+    }
+
     fn probe_seq(&self, hash64: u64) -> ProbeSeq {
         ProbeSeq {
             pos: (hash64 as usize) & self.bucket_mask,
@@ -96,17 +170,34 @@ impl<V> HashTable<V> {
 
     #[inline(always)]
     pub fn insert(&mut self, key: u64, value: V) -> (bool, (usize, usize), usize) {
+        let (inserted, slot, insertion_probe_length, _) = self.insert_inner(key, value);
+        (inserted, slot, insertion_probe_length)
+    }
+
+    /// Like `insert`, but also returns the sentinel (0 or `TOMBSTONE`) that a newly inserted key
+    /// replaced, so `insert_and_erase` can put it back.
+    #[inline(always)]
+    fn insert_inner(&mut self, key: u64, value: V) -> (bool, (usize, usize), usize, u64) {
         let mut insertion_probe_length = 1;
-        if key == 0 {
-            let inserted = self.zero_value.is_none();
+        if is_special_key(key) {
+            let slot = self.special_value_mut(key);
+            let inserted = slot.is_none();
+            *slot = Some(value);
             self.len += inserted as usize;
-            self.zero_value = Some(value);
-            return (inserted, (usize::MAX, usize::MAX), insertion_probe_length);
+            return (inserted, (usize::MAX, usize::MAX), insertion_probe_length, 0);
+        }
+
+        if core::hint::unlikely(self.growth_left == 0) {
+            self.grow();
+        } else if core::hint::unlikely(self.tombstones > self.num_buckets() * BUCKET_SIZE / 8) {
+            self.compact();
         }
 
         let hash64 = fold_hash_fast(key, self.seed);
         let mut probe_seq = self.probe_seq(hash64);
         let mut probe_count = 0;
+        // First tombstone on the probe sequence; reused once we know the key is absent.
+        let mut tombstone = None;
 
         loop {
             let bucket = unsafe { self.table.get_unchecked(probe_seq.pos) };
@@ -122,42 +213,52 @@ impl<V> HashTable<V> {
                         .values.get_unchecked_mut(index)
                         .assume_init_mut() = value;
                 }
-                return (false, (probe_seq.pos, index), insertion_probe_length);
+                return (false, (probe_seq.pos, index), insertion_probe_length, 0);
             }
 
             // Look for empty slot (key == 0) in this bucket using SIMD
             let (empty_mask, stride) = control64::search_mask(0, keys);
+            // `growth_left > 0` leaves over an eighth of the slots empty, and the triangular
+            // sequence visits every bucket, so some bucket on it has one.
             if empty_mask != 0 {
-                // Found empty slot, insert here
-                let index = empty_mask.trailing_zeros() as usize / stride;
+                // Key is absent: insert into the first tombstone if we passed one, else here.
+                let (pos, index, replaced) = match tombstone {
+                    Some((pos, index)) => (pos, index, TOMBSTONE),
+                    None => (probe_seq.pos, empty_mask.trailing_zeros() as usize / stride, 0),
+                };
                 unsafe {
-                    let bucket = self.table.get_unchecked_mut(probe_seq.pos);
+                    let bucket = self.table.get_unchecked_mut(pos);
                     bucket.keys[index] = key;
                     bucket.values[index].write(value);
                 }
                 self.len += 1;
+                self.growth_left -= (replaced == 0) as usize;
+                self.tombstones -= (replaced == TOMBSTONE) as usize;
                 if TRACK_PROBE_LENGTH {
                     self.total_probe_length += probe_count + 1;
                 }
                 insertion_probe_length = probe_count + 1;
-                return (true, (probe_seq.pos, index), insertion_probe_length);
+                return (true, (pos, index), insertion_probe_length, replaced);
+            }
+
+            if tombstone.is_none() {
+                let (tombstone_mask, stride) = control64::search_mask(TOMBSTONE, keys);
+                if tombstone_mask != 0 {
+                    tombstone = Some((probe_seq.pos, tombstone_mask.trailing_zeros() as usize / stride));
+                }
             }
 
             // No match and no empty slot, move to next bucket via quadratic probing
             probe_seq.move_next(self.bucket_mask);
             probe_count += 1;
-
-            if probe_count > self.bucket_mask {
-                panic!("Failed to insert into hash table; table is full");
-            }
         }
     }
 
     #[inline(always)]
     pub fn get(&mut self, key: &u64) -> Option<&V> {
         let key = *key;
-        if key == 0 {
-            return self.zero_value.as_ref();
+        if is_special_key(key) {
+            return self.special_value_mut(key).as_ref();
         }
 
         let hash64 = fold_hash_fast(key, self.seed);
@@ -186,8 +287,10 @@ impl<V> HashTable<V> {
     }
 
     pub fn probe_length(&self, key: u64) -> (usize, bool) {
-        if key == 0 {
-            return (1, self.zero_value.is_some()); // Zero key is always in first probe
+        if is_special_key(key) {
+            // Stored out of band, so always found in the first probe
+            let value = if key == 0 { &self.zero_value } else { &self.tombstone_value };
+            return (1, value.is_some());
         }
 
         let hash64 = fold_hash_fast(key, self.seed);
@@ -200,7 +303,7 @@ impl<V> HashTable<V> {
             let keys = bucket.keys;
 
             // Check if key exists in this bucket using SIMD
-            let (mask, stride) = control64::search_mask(key, keys);
+            let (mask, _) = control64::search_mask(key, keys);
             if mask != 0 {
                 return (probe_count, true); // Key found
             }
@@ -218,25 +321,109 @@ impl<V> HashTable<V> {
 
     #[inline(always)]
     pub fn insert_and_erase(&mut self, key: u64, value: V) {
-        let (inserted, (bucket_index, bucket_offset), _) = self.insert(key, value);
+        let (inserted, (bucket_index, bucket_offset), _, replaced) = self.insert_inner(key, value);
         if inserted {
-            if key == 0 {
-                self.zero_value = None;
+            if is_special_key(key) {
+                *self.special_value_mut(key) = None;
             } else {
                 unsafe {
                     let bucket = self.table.get_unchecked_mut(bucket_index);
-                    *bucket.keys.get_unchecked_mut(bucket_offset) = 0;
+                    *bucket.keys.get_unchecked_mut(bucket_offset) = replaced;
                     bucket.values.get_unchecked_mut(bucket_offset).assume_init_drop();
                 }
+                self.growth_left += (replaced == 0) as usize;
+                self.tombstones += (replaced == TOMBSTONE) as usize;
             }
             self.len -= 1; // Decrement length after erase
         }
     }
+
+    /// Removes `key`, returning its value.
+    ///
+    /// The slot goes back to empty if its bucket still has another empty slot (probes already stop
+    /// there); otherwise it becomes a `TOMBSTONE` so that keys later in the probe sequence stay
+    /// reachable. Tombstones are reused by `insert`, which also compacts the table once they pass
+    /// an eighth of the slots.
+    pub fn remove(&mut self, key: &u64) -> Option<V> {
+        let key = *key;
+        if is_special_key(key) {
+            let value = self.special_value_mut(key).take();
+            self.len -= value.is_some() as usize;
+            return value;
+        }
+
+        let hash64 = fold_hash_fast(key, self.seed);
+        let mut probe_seq = self.probe_seq(hash64);
+        let mut probe_count = 0;
+        loop {
+            let bucket = unsafe { self.table.get_unchecked_mut(probe_seq.pos) };
+            let keys = bucket.keys;
+            let (mask, stride) = control64::search_mask(key, keys);
+            let (empty_mask, _) = control64::search_mask(0, keys);
+            if mask != 0 {
+                let index = mask.trailing_zeros() as usize / stride;
+                let value = unsafe { bucket.values.get_unchecked(index).assume_init_read() };
+                if empty_mask != 0 {
+                    bucket.keys[index] = 0;
+                    self.growth_left += 1;
+                } else {
+                    bucket.keys[index] = TOMBSTONE;
+                    self.tombstones += 1;
+                }
+                self.len -= 1;
+                return Some(value);
+            }
+            if empty_mask != 0 || probe_count == self.bucket_mask {
+                return None;
+            }
+            probe_seq.move_next(self.bucket_mask);
+            probe_count += 1;
+        }
+    }
+
+    /// Out-of-band storage for the keys that double as bucket sentinels.
+    #[cold]
+    fn special_value_mut(&mut self, key: u64) -> &mut Option<V> {
+        if key == 0 { &mut self.zero_value } else { &mut self.tombstone_value }
+    }
+
+}
+
+/// Whether `key` is 0 or `TOMBSTONE`, in a single comparison.
+#[inline(always)]
+fn is_special_key(key: u64) -> bool {
+    key.wrapping_add(1) <= 1
+}
+
+impl<V: fmt::Debug> fmt::Debug for HashTable<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = move || {
+            let zero = self.zero_value.as_ref().map(|value| (0, value));
+            let slots = self.table.iter().flat_map(|bucket| {
+                bucket
+                    .keys
+                    .iter()
+                    .zip(&bucket.values)
+                    .filter(|&(&key, _)| !is_special_key(key))
+                    .map(|(&key, value)| (key, unsafe { value.assume_init_ref() }))
+            });
+            zero
+                .into_iter()
+                .chain(self.tombstone_value.as_ref().map(|value| (TOMBSTONE, value)))
+                .chain(slots)
+        };
+        f.debug_struct("HashTable")
+            .field("len", &self.len())
+            .field("num_buckets", &self.num_buckets())
+            .field("entries", &DebugEntries(entries))
+            .finish()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
 
     #[test]
     fn test_basic_insert_and_get() {
@@ -331,6 +518,112 @@ mod tests {
         assert_eq!(table.len(), 0);
     }
 
+    #[test]
+    fn test_remove_mid_chain() {
+        let mut table = HashTable::with_capacity(64);
+        let keys: Vec<u64> = (1..=100).collect();
+        for &key in &keys {
+            table.insert(key, key * 10);
+        }
+
+        // Find a key that probed past a full home bucket, and a key that lives in that bucket.
+        let home = |table: &HashTable<u64>, key: u64| (fold_hash_fast(key, table.seed) as usize) & table.bucket_mask;
+        let (later, earlier) = keys
+            .iter()
+            .filter(|&&k| table.probe_length(k).0 >= 2)
+            .find_map(|&later| {
+                let earlier = keys.iter().copied().find(|&k| {
+                    table.probe_length(k).0 == 1 && home(&table, k) == home(&table, later)
+                })?;
+                Some((later, earlier))
+            })
+            .expect("no collision chain; grow the key set");
+
+        // Removing from the full home bucket leaves a tombstone, so the later key is still found.
+        assert_eq!(table.remove(&earlier), Some(earlier * 10));
+        assert!(table.table[home(&table, later)].keys.contains(&TOMBSTONE));
+        assert_eq!(table.get(&earlier), None);
+        assert_eq!(table.remove(&earlier), None);
+        assert_eq!(table.get(&later), Some(&(later * 10)));
+        assert_eq!(table.len(), 99);
+        for &key in keys.iter().filter(|&&k| k != earlier) {
+            assert_eq!(table.get(&key), Some(&(key * 10)));
+        }
+
+        // Reinserting reuses the tombstone.
+        assert!(table.insert(earlier, 1).0);
+        assert!(!table.table.iter().any(|b| b.keys.contains(&TOMBSTONE)));
+        assert_eq!(table.get(&earlier), Some(&1));
+    }
+
+    #[test]
+    fn test_grows_at_load_limit() {
+        let mut table = HashTable::with_capacity(64);
+        let num_slots = table.num_buckets() * BUCKET_SIZE;
+        let max_len = (num_slots * 7 / 8) as u64;
+        for key in 1..=max_len {
+            table.insert(key, key);
+        }
+        assert_eq!(table.num_buckets() * BUCKET_SIZE, num_slots);
+        assert_eq!(table.growth_left, 0);
+        // One more grows the table rather than filling its last eighth.
+        table.insert(max_len + 1, max_len + 1);
+        assert_eq!(table.num_buckets() * BUCKET_SIZE, num_slots * 2);
+        for key in 1..=max_len + 1 {
+            assert_eq!(table.get(&key), Some(&key));
+        }
+    }
+
+    #[test]
+    fn test_churn_compacts_tombstones() {
+        // Inserts and removes at a steady `len()`: the tombstones removes leave behind are
+        // cleared in place, without growing the table or running it out of empty slots.
+        let mut table = HashTable::with_capacity(256);
+        let num_buckets = table.num_buckets();
+        const LEN: u64 = 200;
+        for key in 1..=LEN {
+            table.insert(key, key);
+        }
+        for key in LEN + 1..100_000 {
+            assert_eq!(table.remove(&(key - LEN)), Some(key - LEN));
+            table.insert(key, key);
+            assert!(table.tombstones <= num_buckets * BUCKET_SIZE / 8);
+        }
+        assert_eq!(table.num_buckets(), num_buckets);
+        assert_eq!(table.len(), LEN as usize);
+        for key in 100_000 - LEN..100_000 {
+            assert_eq!(table.get(&key), Some(&key));
+        }
+        // Misses still hit an empty slot and stop.
+        assert_eq!(table.get(&1), None);
+    }
+
+    #[test]
+    fn test_remove_randomized() {
+        let mut rng = fastrand::Rng::with_seed(2053);
+        let mut table = HashTable::with_capacity(256);
+        let mut std_map = HashMap::new();
+
+        // Small key space (including the sentinel keys) so removes and reinserts hit the same chains.
+        for _ in 0..5000 {
+            let key = match rng.u32(0..20) {
+                0 => 0,
+                1 => u64::MAX,
+                _ => rng.u64(1..300),
+            };
+            if rng.bool() {
+                let value = rng.u64(..);
+                assert_eq!(table.insert(key, value).0, std_map.insert(key, value).is_none());
+            } else {
+                assert_eq!(table.remove(&key), std_map.remove(&key));
+            }
+            assert_eq!(table.len(), std_map.len());
+        }
+        for key in (0..300).chain([u64::MAX]) {
+            assert_eq!(table.get(&key), std_map.get(&key));
+        }
+    }
+
     #[test]
     fn test_bucket_simd_search() {
         let mut table = HashTable::with_capacity(64);
@@ -346,4 +639,21 @@ mod tests {
             assert_eq!(table.get(&i), Some(&(i * 2)));
         }
     }
+
+    #[test]
+    fn test_grow_past_initial_capacity() {
+        let mut table = HashTable::with_capacity(16);
+        let num_buckets = table.num_buckets();
+        let n = 10_000u64;
+        for key in (0..n).chain([u64::MAX]) {
+            assert!(table.insert(key, !key).0);
+        }
+        assert!(table.num_buckets() > num_buckets);
+        assert_eq!(table.len(), n as usize + 1);
+        // Including the out-of-band keys.
+        for key in (0..n).chain([u64::MAX]) {
+            assert_eq!(table.get(&key), Some(&!key));
+        }
+        assert_eq!(table.get(&n), None);
+    }
 }
\ No newline at end of file
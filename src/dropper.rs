@@ -1,14 +1,56 @@
-use std::alloc::Layout;
+use core::alloc::Layout;
+
+/// Alignment of huge-page allocations: one 2MB huge page, as on x86-64 and aarch64 Linux.
+pub const HUGE_PAGE_SIZE: usize = 2 << 20;
 
 pub struct Dropper {
     pub alloc: *mut u8,
     pub layout: Layout,
 }
 
+impl Dropper {
+    /// Whether the allocation came from `alloc` with `huge_pages`, so that a table rebuilt from
+    /// this one can allocate the same way.
+    pub fn huge_pages(&self) -> bool {
+        self.layout.align() == HUGE_PAGE_SIZE
+    }
+}
+
 impl Drop for Dropper {
     fn drop(&mut self) {
         unsafe {
-            std::alloc::dealloc(self.alloc, self.layout);
+            alloc::alloc::dealloc(self.alloc, self.layout);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(target_os = "linux")]
+unsafe extern "C" {
+    fn madvise(addr: *mut core::ffi::c_void, len: usize, advice: core::ffi::c_int) -> core::ffi::c_int;
+}
+
+/// Allocates `layout`, or with `huge_pages`, the same size aligned to `HUGE_PAGE_SIZE` (unless that
+/// overflows) and, on Linux, advised (`MADV_HUGEPAGE`) to be backed by transparent huge pages,
+/// cutting TLB misses on large tables. The advice is best effort: it does nothing if the kernel
+/// has them disabled.
+///
+/// Returns the allocation, null if the allocator failed, and the layout it was made with, which
+/// is what the `Dropper` must free it with.
+///
+/// # Safety
+///
+/// `layout` must have nonzero size, as for `alloc::alloc::alloc`. Every table's layout includes
+/// its control bytes or slots, so callers meet this by construction.
+pub unsafe fn alloc(layout: Layout, huge_pages: bool) -> (*mut u8, Layout) {
+    if !huge_pages {
+        return (unsafe { alloc::alloc::alloc(layout) }, layout);
+    }
+    let layout = layout.align_to(HUGE_PAGE_SIZE).unwrap_or(layout);
+    let alloc = unsafe { alloc::alloc::alloc(layout) };
+    #[cfg(target_os = "linux")]
+    if !alloc.is_null() {
+        const MADV_HUGEPAGE: core::ffi::c_int = 14;
+        unsafe { madvise(alloc.cast(), layout.size(), MADV_HUGEPAGE) };
+    }
+    (alloc, layout)
+}
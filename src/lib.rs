@@ -0,0 +1,259 @@
+//! SwissTable-style hash tables specialized to `u64` keys, for benchmarking probing strategies
+//! (linear, quadratic, double hashing, cuckoo) against each other across memory layouts.
+//!
+//! Each table lives in its own module and exposes a `HashTable` (or `U64HashSet`) with the same
+//! core methods: `with_capacity`, `insert`, `get`, `len` and `probe_length`. The `u64_map`
+//! module puts a few of them behind a common trait, and `build_table` picks one for a workload.
+//! `reference_table` implements that trait over std's `HashMap`, as an oracle for tests.
+//!
+//! ```
+//! use cuckoo_hashing_benchmark::aligned_cuckoo_table::HashTable;
+//!
+//! let mut table = HashTable::with_capacity(16);
+//! assert!(table.insert(42, "hello").is_new());
+//! assert_eq!(table.get(&42), Some(&"hello"));
+//! assert_eq!(table.get(&7), None);
+//! assert_eq!(table.len(), 1);
+//! ```
+//!
+//! The crate is `no_std` + `alloc` unless the default `std` feature is enabled; `std` adds
+//! `print_stats` and is required by the benchmark binary.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+#![allow(dead_code)]
+#![allow(unsafe_op_in_unsafe_fn)]
+#![feature(likely_unlikely)]
+#![cfg_attr(test, feature(hashmap_internals, alloc_error_hook), allow(internal_features))]
+
+extern crate alloc;
+
+pub mod aligned_cuckoo_soa_table;
+pub mod aligned_cuckoo_table;
+pub mod aligned_cuckoo_tag16_table;
+pub mod aligned_double_hashing_table;
+pub mod aligned_quadratic_probing_table;
+pub mod balancing_cuckoo_table;
+#[cfg(feature = "count-alloc")]
+pub mod count_alloc;
+mod control;
+mod cuckoo_bfs;
+pub mod quadratic_probing_table;
+#[cfg(feature = "std")]
+pub mod reference_table;
+pub mod scalar_cache_line_aligned_table;
+pub mod scalar_cuckoo_table;
+pub mod scalar_unaligned_table;
+pub mod u64_fold_hash_fast;
+pub mod u64_map;
+pub mod unaligned_cuckoo_table;
+mod uunwrap;
+mod dropper;
+pub mod direct_simd_cuckoo_table;
+mod control64;
+mod control16;
+pub mod localized_simd_cuckoo_table;
+pub mod direct_simd_quadratic_probing;
+pub mod linear_probing_table;
+pub mod p2_quantile;
+pub mod direct_simd_linear_probing;
+pub mod direct_simd_linear_probing_np2;
+
+pub use u64_fold_hash_fast::{BuildHasherHash64, FoldHashFast, Hash64, SipHash13, WyHash64};
+
+/// Whether tables accumulate probe-length statistics for `print_stats`. Off for timing runs.
+pub const TRACK_PROBE_LENGTH: bool = false;
+
+/// Error returned by `try_insert` when a key can't be placed. The table is left unchanged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InsertError {
+    /// The cuckoo search found no path to an empty slot within its depth limit.
+    NeedsRehash,
+    /// The table is at its growth threshold; `try_insert` never allocates.
+    NeedsGrowth,
+    /// The cuckoo search gave up after visiting its node budget; see
+    /// `aligned_cuckoo_table::HashTable::insert_with_budget`.
+    BudgetExceeded,
+}
+
+impl core::fmt::Display for InsertError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NeedsRehash => f.write_str("Failed to insert into cuckoo table; need to rehash"),
+            Self::NeedsGrowth => f.write_str("Failed to insert into cuckoo table; need to grow"),
+            Self::BudgetExceeded => f.write_str("Failed to insert into cuckoo table; search budget exceeded"),
+        }
+    }
+}
+
+/// Error returned by `try_reserve` when the table can't grow. The table is left usable, at its
+/// old size or some size in between.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TryReserveError {
+    /// The requested capacity doesn't fit in a `usize` or a `Layout`.
+    CapacityOverflow,
+    /// The allocator returned null for `layout`.
+    AllocError { layout: core::alloc::Layout },
+}
+
+impl core::fmt::Display for TryReserveError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::CapacityOverflow => f.write_str("Hash table capacity overflow"),
+            Self::AllocError { layout } => write!(f, "Failed to allocate {} bytes for hash table", layout.size()),
+        }
+    }
+}
+
+/// Unwraps the result of a fallible allocation step, for the infallible methods built on it.
+/// Allocation failure goes to `handle_alloc_error`, as in std's collections.
+#[inline(always)]
+pub(crate) fn infallible<T>(result: Result<T, TryReserveError>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(TryReserveError::AllocError { layout }) => alloc::alloc::handle_alloc_error(layout),
+        Err(err) => panic!("{err}"),
+    }
+}
+
+/// Formats the entries `0()` yields as a `{key: value, ...}` map, for the tables' `Debug` impls.
+/// Takes a closure rather than an iterator so that formatting doesn't consume it.
+pub(crate) struct DebugEntries<F>(pub(crate) F);
+
+impl<F, I, V> core::fmt::Debug for DebugEntries<F>
+where
+    F: Fn() -> I,
+    I: Iterator<Item = (u64, V)>,
+    V: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_map().entries((self.0)()).finish()
+    }
+}
+
+/// A table `fill_to_failure` can fill: one whose `try_insert` fails only once the table itself
+/// has no room for a key, never at a growth threshold.
+pub trait FillToFailure {
+    /// An empty table sized as `with_capacity(capacity)`.
+    fn with_capacity(capacity: usize) -> Self;
+    /// Inserts `key` without growing, returning whether there was room for it.
+    fn try_insert_key(&mut self, key: u64) -> bool;
+    /// Fraction of slots that are full.
+    fn load_factor(&self) -> f64;
+}
+
+/// Inserts random keys from `seed` into a table of about `num_slots` slots until one doesn't fit,
+/// and returns the load factor reached. This is what each table's `PEAK_LOAD_FACTOR` is chosen
+/// from.
+pub fn fill_to_failure<T: FillToFailure>(num_slots: usize, seed: u64) -> f64 {
+    let mut table = T::with_capacity(num_slots * 7 / 8);
+    let mut rng = fastrand::Rng::with_seed(seed);
+    while table.try_insert_key(rng.u64(..)) && table.load_factor() < 1.0 {}
+    table.load_factor()
+}
+
+/// Asserts that nearly every fill of a 4096-slot `T` gets to `peak_load_factor`, for each
+/// table's `test_peak_load_factor`.
+#[cfg(test)]
+pub(crate) fn assert_reaches_peak_load_factor<T: FillToFailure>(peak_load_factor: f64) {
+    let reached = (0..20).filter(|&seed| fill_to_failure::<T>(4096, seed) >= peak_load_factor).count();
+    assert!(reached >= 18, "{reached}/20 seeds reached {peak_load_factor}");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::catch_unwind;
+
+    /// Small enough for a valid `Layout`, far too big for the address space.
+    const HUGE: usize = 1 << 50;
+
+    #[test]
+    fn test_failed_allocation_calls_handle_alloc_error() {
+        // `handle_alloc_error` aborts by default; a panicking hook lets us observe it instead of
+        // the tables carrying on with a null pointer.
+        std::alloc::set_alloc_error_hook(|layout| panic!("handle_alloc_error({} bytes)", layout.size()));
+        let constructors: [(&str, fn()); 8] = [
+            ("aligned_cuckoo_table", || drop(crate::aligned_cuckoo_table::HashTable::<u64>::with_capacity(HUGE))),
+            ("aligned_cuckoo_tag16_table", || drop(crate::aligned_cuckoo_tag16_table::HashTable::<u64>::with_capacity(HUGE))),
+            ("aligned_double_hashing_table", || drop(crate::aligned_double_hashing_table::HashTable::<u64>::with_capacity(HUGE))),
+            ("aligned_quadratic_probing_table", || drop(crate::aligned_quadratic_probing_table::HashTable::<u64>::with_capacity(HUGE))),
+            ("balancing_cuckoo_table", || drop(crate::balancing_cuckoo_table::HashTable::<u64>::with_capacity(HUGE))),
+            ("linear_probing_table", || drop(crate::linear_probing_table::HashTable::<u64>::with_capacity(HUGE))),
+            ("quadratic_probing_table", || drop(crate::quadratic_probing_table::HashTable::<u64>::with_capacity(HUGE))),
+            ("unaligned_cuckoo_table", || drop(crate::unaligned_cuckoo_table::HashTable::<u64>::with_capacity(HUGE))),
+        ];
+        for (name, constructor) in constructors {
+            let payload = catch_unwind(constructor).expect_err(name);
+            let message = payload.downcast_ref::<String>().map(String::as_str).unwrap_or_default();
+            assert!(message.starts_with("handle_alloc_error"), "{name}: {message}");
+        }
+    }
+
+    #[test]
+    fn test_tables_are_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<crate::aligned_cuckoo_table::HashTable<u64>>();
+        assert_send_sync::<crate::aligned_cuckoo_tag16_table::HashTable<u64>>();
+        assert_send_sync::<crate::aligned_double_hashing_table::HashTable<u64>>();
+        assert_send_sync::<crate::aligned_quadratic_probing_table::HashTable<u64>>();
+        assert_send_sync::<crate::balancing_cuckoo_table::HashTable<u64>>();
+        assert_send_sync::<crate::linear_probing_table::HashTable<u64>>();
+        assert_send_sync::<crate::quadratic_probing_table::HashTable<u64>>();
+        assert_send_sync::<crate::unaligned_cuckoo_table::HashTable<u64>>();
+
+        // Built on one thread, read on another, then shared between two.
+        let mut table = crate::aligned_double_hashing_table::HashTable::with_capacity(1000);
+        for key in 0..1000 {
+            table.insert(key, key * 2);
+        }
+        let table = std::thread::spawn(move || {
+            for key in 0..1000 {
+                assert_eq!(table.get(&key), Some(&(key * 2)));
+            }
+            table
+        })
+        .join()
+        .unwrap();
+        std::thread::scope(|scope| {
+            for _ in 0..2 {
+                scope.spawn(|| assert_eq!(table.keys().count(), 1000));
+            }
+        });
+    }
+
+    #[test]
+    fn test_debug_prints_every_entry() {
+        // Including the keys some tables store out of band: 0, and `u64::MAX` (a tombstone in
+        // `direct_simd_quadratic_probing`).
+        const KEYS: [u64; 6] = [0, 1, 42, 1000, 123_456_789, u64::MAX];
+        macro_rules! check {
+            ($table:ty) => {{
+                let mut table = <$table>::with_capacity(64);
+                for key in KEYS {
+                    let _ = table.insert(key, key.wrapping_add(7));
+                }
+                let debug = format!("{table:?}");
+                assert!(debug.contains("len: 6"), "{debug}");
+                for key in KEYS {
+                    assert!(debug.contains(&format!("{key}: {}", key.wrapping_add(7))), "{debug}");
+                }
+                assert_eq!(debug.matches(": ").count(), 3 + KEYS.len(), "{debug}");
+            }};
+        }
+        check!(crate::aligned_cuckoo_table::HashTable<u64>);
+        check!(crate::aligned_cuckoo_tag16_table::HashTable<u64>);
+        check!(crate::aligned_double_hashing_table::HashTable<u64>);
+        check!(crate::aligned_quadratic_probing_table::HashTable<u64>);
+        check!(crate::balancing_cuckoo_table::HashTable<u64>);
+        check!(crate::direct_simd_cuckoo_table::HashTable<u64>);
+        check!(crate::direct_simd_linear_probing::HashTable<u64>);
+        check!(crate::direct_simd_linear_probing_np2::HashTable<u64>);
+        check!(crate::direct_simd_quadratic_probing::HashTable<u64>);
+        check!(crate::linear_probing_table::HashTable<u64>);
+        check!(crate::localized_simd_cuckoo_table::HashTable<u64>);
+        check!(crate::quadratic_probing_table::HashTable<u64>);
+        check!(crate::scalar_cache_line_aligned_table::U64HashSet<u64>);
+        check!(crate::scalar_cuckoo_table::U64HashSet<u64>);
+        check!(crate::scalar_unaligned_table::U64HashSet<u64>);
+        check!(crate::unaligned_cuckoo_table::HashTable<u64>);
+    }
+}
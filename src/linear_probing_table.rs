@@ -12,25 +12,33 @@
 //! `Group::WIDTH` tail control bytes make the wrap-around group load valid, exactly as in the
 //! quadratic table.
 
-use std::hint::likely;
-use std::{alloc::Layout, ptr::NonNull};
+use core::hint::likely;
+use core::{alloc::Layout, ptr::NonNull};
+use core::fmt;
 
 use crate::TRACK_PROBE_LENGTH;
 use crate::control::{Group, Tag, TagSliceExt as _};
 use crate::u64_fold_hash_fast::fold_hash_fast;
 use crate::uunwrap::UUnwrap;
-use crate::dropper::Dropper;
+use crate::dropper::{self, Dropper};
+use crate::DebugEntries;
 
 pub struct HashTable<V> {
     bucket_mask: usize,
     ctrl: NonNull<u8>,
     items: usize,
     seed: u64,
-    marker: std::marker::PhantomData<V>,
+    marker: core::marker::PhantomData<V>,
     total_probe_length: usize,
     dropper: Dropper,
 }
 
+// SAFETY: `ctrl` points into the allocation `dropper` owns, which no other table shares.
+// `set_ctrl` writes a tag and its mirror in the trailing `Group::WIDTH` bytes through `&self`, but
+// is only called under `&mut self`.
+unsafe impl<V: Send> Send for HashTable<V> {}
+unsafe impl<V: Sync> Sync for HashTable<V> {}
+
 /// Linear probe sequence: step by one group (`Group::WIDTH`) each probe.
 #[derive(Clone)]
 struct ProbeSeq {
@@ -50,16 +58,29 @@ impl<V> HashTable<V> {
     }
 
     pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_huge_pages(capacity, false)
+    }
+
+    /// As `with_capacity`, but on huge pages where the kernel allows; see `dropper::alloc`.
+    #[cfg(target_os = "linux")]
+    pub fn with_capacity_hugepage(capacity: usize) -> Self {
+        Self::with_capacity_and_huge_pages(capacity, true)
+    }
+
+    fn with_capacity_and_huge_pages(capacity: usize, huge_pages: bool) -> Self {
         let num_buckets = ((capacity * 8) / 7).next_power_of_two();
-        let bucket_size = std::mem::size_of::<(u64, V)>();
-        let align = std::mem::align_of::<(u64, V)>().max(Group::WIDTH);
+        let bucket_size = core::mem::size_of::<(u64, V)>();
+        let align = core::mem::align_of::<(u64, V)>().max(Group::WIDTH);
         let ctrl_offset = (bucket_size * num_buckets).next_multiple_of(align);
         let size = ctrl_offset + num_buckets + Group::WIDTH;
         let layout = Layout::from_size_align(size, align).uunwrap();
-        let alloc = unsafe { std::alloc::alloc(layout) };
+        let (alloc, layout) = unsafe { dropper::alloc(layout, huge_pages) };
+        if alloc.is_null() {
+            alloc::alloc::handle_alloc_error(layout);
+        }
         let ctrl = unsafe { NonNull::new_unchecked(alloc.add(ctrl_offset)) };
         let ctrl_slice = unsafe {
-            std::slice::from_raw_parts_mut(ctrl.as_ptr() as *mut Tag, num_buckets + Group::WIDTH)
+            core::slice::from_raw_parts_mut(ctrl.as_ptr() as *mut Tag, num_buckets + Group::WIDTH)
         };
         ctrl_slice.fill_empty();
         let seed = fastrand::Rng::with_seed(123).u64(..);
@@ -69,7 +90,7 @@ impl<V> HashTable<V> {
             ctrl,
             items: 0,
             seed,
-            marker: std::marker::PhantomData,
+            marker: core::marker::PhantomData,
             total_probe_length: 0,
             dropper: Dropper { alloc, layout },
         }
@@ -80,6 +101,17 @@ impl<V> HashTable<V> {
         self.items
     }
 
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The hash of `key`: the low bits pick the slot the probe starts at, the top 7 the tag.
+    #[inline(always)]
+    pub fn hash_of(&self, key: u64) -> u64 {
+        fold_hash_fast(key, self.seed)
+    }
+    #[cfg(feature = "std")]
     pub fn print_stats(&self) {
         println!(
             "  avg_probe_length: {}",
@@ -187,6 +219,12 @@ impl<V> HashTable<V> {
         }
     }
 
+    /// Marks the full slot at `index` as erased, leaving an `EMPTY` or `DELETED` tag depending on
+    /// whether a probe sequence can pass through it. The value is not dropped.
+    ///
+    /// # Safety
+    ///
+    /// `index` must be a full slot of this table, e.g. as returned by `insert`.
     #[inline(always)]
     pub unsafe fn erase_index(&mut self, index: usize) {
         let index_before = index.wrapping_sub(Group::WIDTH) & self.bucket_mask;
@@ -210,6 +248,10 @@ impl<V> HashTable<V> {
     /// Correctness rests on this table being logically slot-level linear probing: an element with
     /// home `h` stored at slot `s` has all of `[h, s)` full, so `get` (which stops at the first
     /// empty slot in any window) finds it. Shifting preserves that invariant.
+    ///
+    /// # Safety
+    ///
+    /// `index` must be a full slot of this table. The removed value is not dropped.
     #[inline]
     pub unsafe fn remove_at(&mut self, index: usize) {
         let mask = self.bucket_mask;
@@ -261,6 +303,12 @@ impl<V> HashTable<V> {
         }
     }
 
+    /// Inserts `key` and, if it was new, immediately removes it again with backward-shift
+    /// deletion. This is the unit of work of the insert+erase churn benchmark.
+    ///
+    /// # Safety
+    ///
+    /// `V` must not need dropping: the removed value is forgotten, not dropped.
     #[inline(always)]
     pub unsafe fn insert_and_erase(&mut self, key: u64, value: V) {
         let (inserted, index, _) = self.insert(key, value);
@@ -363,6 +411,30 @@ impl<V> HashTable<V> {
     }
 }
 
+impl<V> Default for HashTable<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for HashTable<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = move || {
+            (0..=self.bucket_mask)
+                .filter(move |&index| unsafe { *self.ctrl(index) }.is_full())
+                .map(move |index| {
+                    let bucket = unsafe { &*self.bucket(index) };
+                    (bucket.0, &bucket.1)
+                })
+        };
+        f.debug_struct("HashTable")
+            .field("len", &self.len())
+            .field("num_buckets", &(self.bucket_mask + 1))
+            .field("entries", &DebugEntries(entries))
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,11 +1,14 @@
 //! "Direct SIMD" layout which does SIMD probing on `[u64; 4]` rather than `[u8; 8]`.
 
-use std::hint::likely;
-use std::mem::MaybeUninit;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::hint::likely;
+use core::mem::MaybeUninit;
+use core::fmt;
 
 use crate::control::{Group, Tag};
 use crate::u64_fold_hash_fast::fold_hash_fast;
-use crate::{TRACK_PROBE_LENGTH, control64};
+use crate::{DebugEntries, FillToFailure};
 
 pub struct HashTable<V> {
     table: Box<[Bucket<V>]>,
@@ -18,40 +21,49 @@ pub struct HashTable<V> {
 
 const BUCKET_SIZE: usize = 7;
 
+/// Load factor `try_insert` reliably fills a table to before failing. Filling random keys into
+/// 2^10 to 2^20 slots reached at least 98.3% on every seed, lower for larger tables.
+pub const PEAK_LOAD_FACTOR: f64 = 0.98;
+
+/// Index in `fprints` of the bucket's overflow flag. It starts as `NO_OVERFLOW` and becomes
+/// `OVERFLOW` once a key whose first bucket is this one is stored in its second bucket, so `get`
+/// can stop after the first bucket while it's clear. The flag is sticky: erasing the key that
+/// overflowed doesn't clear it.
+const OVERFLOW_INDEX: usize = BUCKET_SIZE;
+// Neither matches `EMPTY` or any full tag, so the slot is never picked for a key.
+const NO_OVERFLOW: Tag = Tag::DELETED;
+const OVERFLOW: Tag = Tag(0x81);
+
 #[repr(C)]
 #[repr(align(128))] // Cache line alignment
 struct Bucket<V> {
     keys: [u64; BUCKET_SIZE],
-    // TODO: 1 byte "overflow" flag?
-    fprints: [Tag; BUCKET_SIZE + 1],
+    /// One tag per slot, then the overflow flag, then `Tag::DELETED` padding up to a whole
+    /// `Group`. With 16-wide groups this still fills the bucket to exactly 128 bytes.
+    fprints: [Tag; Group::WIDTH],
     values: [MaybeUninit<V>; BUCKET_SIZE],
 }
 
+const _: () = assert!(Group::WIDTH > BUCKET_SIZE);
+
+/// Fewest buckets a table is built with, so that a key's two buckets are usually distinct and a
+/// small table holds a handful of keys without overflowing the BFS.
+const MIN_NUM_BUCKETS: usize = 4;
+
 impl<V> HashTable<V> {
+    #[cfg(feature = "std")]
     pub fn print_stats(&self) {}
 
     #[inline(always)]
     pub fn with_capacity(capacity: usize) -> Self {
         // TODO: integer overflow...
         let num_buckets = ((capacity * 8) / 7).div_ceil(BUCKET_SIZE + 1)
-            .next_power_of_two();
-        let table = {
-            let mut v = Vec::new();
-            v.resize_with(num_buckets, || Bucket {
-                fprints: {
-                    let mut fprints = [Tag::EMPTY; BUCKET_SIZE + 1];
-                    fprints[BUCKET_SIZE] = Tag::DELETED;
-                    fprints
-                },
-                keys: [0; BUCKET_SIZE],
-                values: std::array::from_fn(|_| MaybeUninit::uninit()),
-            });
-            v.into_boxed_slice()
-        };
+            .next_power_of_two()
+            .max(MIN_NUM_BUCKETS);
         let seed = fastrand::Rng::with_seed(123).u64(..);
         Self {
-            table,
-            bucket_mask: (num_buckets - 1) * std::mem::size_of::<Bucket<V>>(),
+            table: Self::empty_buckets(num_buckets),
+            bucket_mask: Self::byte_mask(num_buckets),
             len: 0,
             seed,
             total_probe_length: 0,
@@ -59,13 +71,113 @@ impl<V> HashTable<V> {
         }
     }
 
+    fn empty_buckets(num_buckets: usize) -> Box<[Bucket<V>]> {
+        let mut v = Vec::new();
+        v.resize_with(num_buckets, || Bucket {
+            fprints: {
+                let mut fprints = [Tag::DELETED; Group::WIDTH];
+                fprints[..BUCKET_SIZE].fill(Tag::EMPTY);
+                fprints[OVERFLOW_INDEX] = NO_OVERFLOW;
+                fprints
+            },
+            keys: [0; BUCKET_SIZE],
+            values: core::array::from_fn(|_| MaybeUninit::uninit()),
+        });
+        v.into_boxed_slice()
+    }
+
+    /// `bucket_mask` for a table of `num_buckets`: the bucket index mask scaled by the bucket
+    /// size, so that a masked hash is directly the byte offset of a bucket (see `bucket`). Buckets
+    /// are a power of two bytes, so masking never lands inside one.
+    fn byte_mask(num_buckets: usize) -> usize {
+        const { assert!(core::mem::size_of::<Bucket<V>>().is_power_of_two()) };
+        (num_buckets - 1) * core::mem::size_of::<Bucket<V>>()
+    }
+
+    /// Returns the number of buckets in the table.
+    #[inline(always)]
+    pub fn num_buckets(&self) -> usize {
+        self.table.len()
+    }
+
+    /// Fraction of slots that are full, counting `BUCKET_SIZE` slots per bucket.
+    pub fn load_factor(&self) -> f64 {
+        self.len as f64 / (self.num_buckets() * BUCKET_SIZE) as f64
+    }
+
+    /// Rebuilds the table with `new_num_buckets` buckets, a power of two, reinserting every entry
+    /// under the new `bucket_mask`. Overflow flags start clear and are set again by the
+    /// reinsertions, so this also drops flags left sticky by erased keys.
+    ///
+    /// Returns false, leaving the table unchanged, if the entries don't all fit.
+    pub fn resize(&mut self, new_num_buckets: usize) -> bool {
+        assert!(new_num_buckets.is_power_of_two(), "bucket count must be a power of two");
+        let new_num_buckets = new_num_buckets.max(MIN_NUM_BUCKETS);
+        let mut new_table = Self {
+            table: Self::empty_buckets(new_num_buckets),
+            bucket_mask: Self::byte_mask(new_num_buckets),
+            len: 0,
+            seed: self.seed,
+            total_probe_length: self.total_probe_length,
+            rng: self.rng.clone(),
+        };
+        // The new table gets bitwise copies of the values. Neither table drops values (`Bucket`
+        // has no drop glue), so exactly one of them ends up owning each value: the new table
+        // if every entry fits, this one otherwise.
+        for bucket in &self.table {
+            for i in (0..BUCKET_SIZE).filter(|&i| bucket.fprints[i].is_full()) {
+                let value = unsafe { bucket.values[i].assume_init_read() };
+                if let Err(value) = new_table.try_insert(bucket.keys[i], value) {
+                    core::mem::forget(value);
+                    return false;
+                }
+            }
+        }
+        *self = new_table;
+        true
+    }
+
+    /// Shrinks the table to the fewest buckets that hold `len()` entries at 7/8 load, or to the
+    /// next size up if the entries don't fit there. Does nothing if no smaller size fits.
+    pub fn shrink_to_fit(&mut self) {
+        let mut num_buckets = (self.len * 8 / 7)
+            .div_ceil(BUCKET_SIZE)
+            .next_power_of_two()
+            .max(MIN_NUM_BUCKETS);
+        while num_buckets < self.num_buckets() && !self.resize(num_buckets) {
+            num_buckets *= 2;
+        }
+    }
+
     #[inline(always)]
     pub fn len(&self) -> usize {
         self.len
     }
 
     #[inline(always)]
-    pub fn insert(&mut self, mut key: u64, mut value: V) -> (bool, (usize, usize)) {
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The hash of `key`. Its low bits pick the first bucket and its top 7 bits the tag; the
+    /// second bucket is the hash XOR the scrambled tag.
+    #[inline(always)]
+    pub fn hash_of(&self, key: u64) -> u64 {
+        fold_hash_fast(key, self.seed)
+    }
+
+    #[inline(always)]
+    pub fn insert(&mut self, key: u64, value: V) -> (bool, (usize, usize)) {
+        match self.try_insert(key, value) {
+            Ok(result) => result,
+            Err(_) => panic!("Failed to insert into cuckoo table; need to rehash"),
+        }
+    }
+
+    /// `insert`, except that when the BFS finds no free slot it gives `value` back and leaves the
+    /// table unchanged.
+    #[inline(always)]
+    fn try_insert(&mut self, key: u64, value: V) -> Result<(bool, (usize, usize)), V> {
         let bucket_mask = self.bucket_mask;
         let hash64 = fold_hash_fast(key, self.seed);
         let tag_hash = Tag::full(hash64);
@@ -74,10 +186,10 @@ impl<V> HashTable<V> {
             // Probe first group for a match.
             let pos0 = hash64 as usize & bucket_mask;
             let bucket0 = unsafe { self.bucket(pos0) };
-            assert!(Group::WIDTH == BUCKET_SIZE + 1);
             let group0 = unsafe { Group::load(bucket0.fprints.as_ptr().cast()) };
 
             for bit in group0.match_tag(tag_hash) {
+                // SAFETY: `bit < BUCKET_SIZE`, as in `get`.
                 if likely(unsafe { *bucket0.keys.get_unchecked(bit) } == key) {
                     break 'existing (pos0, bit);
                 }
@@ -134,10 +246,10 @@ impl<V> HashTable<V> {
                         let other_pos0 = other_pos(pos0);
                         let other_pos1 = other_pos(pos1);
                         unsafe {
-                            *bfs_queue
+                            bfs_queue
                                 .get_unchecked_mut(bfs_write_pos + i)
                                 .write(other_pos0);
-                            *bfs_queue
+                            bfs_queue
                                 .get_unchecked_mut(bfs_write_pos + i + N)
                                 .write(other_pos1);
                         }
@@ -147,7 +259,9 @@ impl<V> HashTable<V> {
                 bfs_read_pos += 2;
 
                 if bfs_read_pos + 2 > BFS_MAX_LEN {
-                    panic!("Failed to insert into cuckoo table; need to rehash");
+                    // Nothing has moved yet.
+                    self.len -= 1;
+                    return Err(value);
                 }
                 pos0 = unsafe { bfs_queue[bfs_read_pos + 0].assume_init() };
                 pos1 = unsafe { bfs_queue[bfs_read_pos + 1].assume_init() };
@@ -171,6 +285,11 @@ impl<V> HashTable<V> {
                     child_bucket.fprints[bucket_offset] = parent_tag;
                     child_bucket.keys[bucket_offset] = parent_key;
                     child_bucket.values[bucket_offset].write(parent_value);
+
+                    // Moving out of its first bucket overflows that bucket.
+                    if fold_hash_fast(parent_key, seed) as usize & bucket_mask == parent_bucket_index {
+                        self.bucket_mut(parent_bucket_index).fprints[OVERFLOW_INDEX] = OVERFLOW;
+                    }
                 }
                 bucket_index = parent_bucket_index;
                 bucket_offset = parent_bucket_offset;
@@ -181,13 +300,17 @@ impl<V> HashTable<V> {
                 bucket.fprints[bucket_offset] = tag_hash;
                 bucket.keys[bucket_offset] = key;
                 bucket.values[bucket_offset].write(value);
+                let first_pos = hash64 as usize & bucket_mask;
+                if bucket_index != first_pos {
+                    self.bucket_mut(first_pos).fprints[OVERFLOW_INDEX] = OVERFLOW;
+                }
             }
-            return (true, (bucket_index, bucket_offset));
+            return Ok((true, (bucket_index, bucket_offset)));
         };
         unsafe {
             *self.bucket_mut(existing_bucket).values.get_unchecked_mut(existing_index).assume_init_mut() = value;
         }
-        (false, (existing_bucket, existing_index))
+        Ok((false, (existing_bucket, existing_index)))
     }
 
     #[inline(always)]
@@ -198,26 +321,24 @@ impl<V> HashTable<V> {
         let bucket_mask = self.bucket_mask;
         for i in 0..2 {
             let bucket = unsafe { self.bucket(hash64 as usize & bucket_mask) };
-            assert!(Group::WIDTH == BUCKET_SIZE + 1);
             let group = unsafe { Group::load(bucket.fprints.as_ptr().cast()) };
 
             let matches = group.match_tag(tag_hash);
             if matches.any_bit_set() {
                 for bit in group.match_tag(tag_hash) {
+                    // SAFETY: only the first `BUCKET_SIZE` fprints can hold a full tag. The
+                    // overflow flag and padding after them never match, so `bit` is in bounds
+                    // for `keys` and `values`.
                     if likely(unsafe { *bucket.keys.get_unchecked(bit) } == key) {
                         return Some(unsafe { bucket.values.get_unchecked(bit).assume_init_ref() });
                     }
                 }
             }
 
-            // if i == 1 || group.match_empty().any_bit_set() {
-            //     return None;
-            // }
-
-            // // Only return None if this is the second location AND there are empty slots
-            // if i == 1 {
-            //     return None;
-            // }
+            // No key that starts here has been stored in its second bucket.
+            if i == 0 && bucket.fprints[OVERFLOW_INDEX] == NO_OVERFLOW {
+                return None;
+            }
 
             hash64 ^= scramble_tag(tag_hash);
         }
@@ -238,6 +359,12 @@ impl<V> HashTable<V> {
         }
     }
 
+    /// The bucket at byte offset `masked_position` into `table`.
+    ///
+    /// # Safety
+    ///
+    /// `masked_position` must be a hash masked by `bucket_mask`. That mask is byte-scaled (see
+    /// `byte_mask`), so the result is a multiple of the bucket size and below the table's size.
     #[inline(always)]
     unsafe fn bucket(&self, masked_position: usize) -> &Bucket<V> {
         unsafe {
@@ -245,6 +372,7 @@ impl<V> HashTable<V> {
         }
     }
 
+    /// As `bucket`, with the same requirement on `masked_position`.
     #[inline(always)]
     unsafe fn bucket_mut(&mut self, masked_position: usize) -> &mut Bucket<V> {
         unsafe {
@@ -259,10 +387,60 @@ fn scramble_tag(tag: Tag) -> u64 {
 
 const MUL: u64 = 0x2d35_8dcc_aa6c_78a5;
 
+impl<V: fmt::Debug> fmt::Debug for HashTable<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = move || {
+            self.table.iter().flat_map(|bucket| {
+                (0..BUCKET_SIZE)
+                    .filter(|&i| bucket.fprints[i].is_full())
+                    .map(|i| (bucket.keys[i], unsafe { bucket.values[i].assume_init_ref() }))
+            })
+        };
+        f.debug_struct("HashTable")
+            .field("len", &self.len())
+            .field("num_buckets", &self.table.len())
+            .field("entries", &DebugEntries(entries))
+            .finish()
+    }
+}
+
+/// The table has no growth threshold, so `try_insert` fills it until the cuckoo BFS finds no room.
+impl<V: Default> FillToFailure for HashTable<V> {
+    fn with_capacity(capacity: usize) -> Self {
+        HashTable::with_capacity(capacity)
+    }
+
+    fn try_insert_key(&mut self, key: u64) -> bool {
+        self.try_insert(key, V::default()).is_ok()
+    }
+
+    fn load_factor(&self) -> f64 {
+        HashTable::load_factor(self)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_peak_load_factor() {
+        crate::assert_reaches_peak_load_factor::<HashTable<u64>>(PEAK_LOAD_FACTOR);
+    }
+
+    #[test]
+    fn test_with_capacity_one() {
+        // Rounded up to the minimum size, so a handful of keys fit.
+        let mut table = HashTable::<u64>::with_capacity(1);
+        for key in 1..=8 {
+            table.insert(key, key * 10);
+        }
+        assert_eq!(table.len(), 8);
+        for key in 1..=8 {
+            assert_eq!(table.get(&key), Some(&(key * 10)));
+        }
+    }
+
     #[test]
     fn test_basic_insert_and_get() {
         let mut table = HashTable::<u64>::with_capacity(16);
@@ -281,4 +459,121 @@ mod tests {
             assert_eq!(*found.unwrap(), key + 1000, "Value should match for key {:#x}", key);
         }
     }
+
+    #[test]
+    fn test_resize() {
+        let mut table = HashTable::<u64>::with_capacity(1 << 14);
+        let num_buckets = table.num_buckets();
+        let mut rng = fastrand::Rng::with_seed(2149);
+        let keys: Vec<u64> = (0..500).map(|_| rng.u64(..)).collect();
+        for &key in &keys {
+            table.insert(key, !key);
+        }
+        let bucket_size = core::mem::size_of::<Bucket<u64>>();
+        let mut check = |table: &mut HashTable<u64>| {
+            assert_eq!(table.bucket_mask, (table.num_buckets() - 1) * bucket_size);
+            assert_eq!(table.len(), keys.len());
+            for &key in &keys {
+                // Both of the key's byte offsets are the start of a bucket of the table.
+                let hash64 = fold_hash_fast(key, table.seed);
+                let pos0 = hash64 as usize & table.bucket_mask;
+                let pos1 = (hash64 ^ scramble_tag(Tag::full(hash64))) as usize & table.bucket_mask;
+                for pos in [pos0, pos1] {
+                    assert!(pos.is_multiple_of(bucket_size) && pos / bucket_size < table.num_buckets());
+                }
+                assert_eq!(table.get(&key), Some(&!key));
+            }
+            for _ in 0..1000 {
+                assert_eq!(table.get(&rng.u64(..)), None);
+            }
+        };
+
+        table.shrink_to_fit();
+        assert!(table.num_buckets() < num_buckets);
+        check(&mut table);
+        // Already as small as it goes.
+        let shrunk = table.num_buckets();
+        table.shrink_to_fit();
+        assert_eq!(table.num_buckets(), shrunk);
+
+        assert!(table.resize(shrunk * 8));
+        assert_eq!(table.num_buckets(), shrunk * 8);
+        check(&mut table);
+    }
+
+    #[test]
+    fn test_shrink_to_fit_full_buckets() {
+        // 7 * 2^6 entries would exactly fill 64 buckets; shrinking must leave headroom instead.
+        let mut table = HashTable::<u64>::with_capacity(1 << 14);
+        let n = (BUCKET_SIZE << 6) as u64;
+        for key in 0..n {
+            table.insert(key, !key);
+        }
+        table.shrink_to_fit();
+        assert!(table.num_buckets() * BUCKET_SIZE * 7 >= table.len() * 8);
+        assert_eq!(table.len(), n as usize);
+        for key in 0..n {
+            assert_eq!(table.get(&key), Some(&!key));
+        }
+
+        // A size the entries can't fit in is refused without losing any of them.
+        assert!(!table.resize(n as usize / BUCKET_SIZE / 2));
+        assert_eq!(table.len(), n as usize);
+        for key in 0..n {
+            assert_eq!(table.get(&key), Some(&!key));
+        }
+    }
+
+    #[test]
+    fn test_overflow_flag() {
+        let mut table = HashTable::<u64>::with_capacity(1 << 12);
+        // Fill to high load so plenty of keys end up in their second bucket.
+        let n = 3500;
+        for key in 0..n {
+            table.insert(key, !key);
+        }
+        let mut overflowed = 0;
+        for key in 0..n {
+            assert_eq!(table.get(&key), Some(&!key));
+            let first_pos = fold_hash_fast(key, table.seed) as usize & table.bucket_mask;
+            let bucket = unsafe { table.bucket(first_pos) };
+            if !bucket.fprints[..BUCKET_SIZE].contains(&Tag::full(fold_hash_fast(key, table.seed))) {
+                assert_eq!(bucket.fprints[OVERFLOW_INDEX], OVERFLOW);
+                overflowed += 1;
+            }
+        }
+        assert!(overflowed > 0);
+        for key in n..2 * n {
+            assert_eq!(table.get(&key), None);
+        }
+    }
+
+    #[test]
+    fn test_get_skips_second_bucket_without_overflow() {
+        let mut table = HashTable::<u64>::with_capacity(1 << 10);
+        let key = 12345;
+        let hash64 = fold_hash_fast(key, table.seed);
+        let tag = Tag::full(hash64);
+        let pos0 = hash64 as usize & table.bucket_mask;
+        let pos1 = (hash64 ^ scramble_tag(tag)) as usize & table.bucket_mask;
+        assert_ne!(pos0, pos1);
+
+        // Plant the key in its second bucket behind `insert`'s back: with the first bucket's
+        // flag clear, `get` never looks there.
+        unsafe {
+            let bucket1 = table.bucket_mut(pos1);
+            bucket1.fprints[0] = tag;
+            bucket1.keys[0] = key;
+            bucket1.values[0].write(1);
+        }
+        assert_eq!(table.get(&key), None);
+        unsafe { table.bucket_mut(pos0).fprints[OVERFLOW_INDEX] = OVERFLOW };
+        assert_eq!(table.get(&key), Some(&1));
+
+        // A key in its first bucket is found either way.
+        let mut table = HashTable::<u64>::with_capacity(1 << 10);
+        table.insert(key, 2);
+        assert_eq!(unsafe { table.bucket(pos0) }.fprints[OVERFLOW_INDEX], NO_OVERFLOW);
+        assert_eq!(table.get(&key), Some(&2));
+    }
 }
\ No newline at end of file
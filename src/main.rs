@@ -1,32 +1,23 @@
 #![allow(unused)]
-#![allow(unsafe_op_in_unsafe_fn)]
-#![feature(likely_unlikely)]
-#![feature(rust_cold_cc)]
-use std::{hint::black_box, io::Write, time::Instant};
-
-mod aligned_cuckoo_table;
-mod aligned_double_hashing_table;
-mod aligned_quadratic_probing_table;
-mod balancing_cuckoo_table;
-mod control;
-mod quadratic_probing_table;
-mod scalar_cache_line_aligned_table;
-mod scalar_cuckoo_table;
-mod scalar_unaligned_table;
-mod u64_fold_hash_fast;
-mod unaligned_cuckoo_table;
-mod uunwrap;
-mod dropper;
-mod direct_simd_cuckoo_table;
-mod control64;
-mod localized_simd_cuckoo_table;
-mod direct_simd_quadratic_probing;
-mod linear_probing_table;
-mod direct_simd_linear_probing;
-mod direct_simd_linear_probing_np2;
+use std::{hint::black_box, io::Write, time::{Duration, Instant}};
+
+use cuckoo_hashing_benchmark::{
+    TRACK_PROBE_LENGTH, aligned_cuckoo_table, aligned_cuckoo_tag16_table, aligned_double_hashing_table,
+    aligned_quadratic_probing_table, balancing_cuckoo_table, direct_simd_cuckoo_table,
+    direct_simd_linear_probing, direct_simd_linear_probing_np2, direct_simd_quadratic_probing,
+    linear_probing_table, localized_simd_cuckoo_table, p2_quantile::P2Quantile, quadratic_probing_table,
+    scalar_cache_line_aligned_table, scalar_cuckoo_table, scalar_unaligned_table,
+    u64_fold_hash_fast, unaligned_cuckoo_table,
+};
+
+#[cfg(feature = "count-alloc")]
+use cuckoo_hashing_benchmark::count_alloc;
+
+#[cfg(feature = "count-alloc")]
+#[global_allocator]
+static ALLOCATOR: count_alloc::CountingAllocator = count_alloc::CountingAllocator;
 
 const ITERS: usize = 40_000_000;
-const TRACK_PROBE_LENGTH: bool = false;
 // Toggle which workloads run. The four lookup/churn ops are the main sweep; BENCH_BUILD adds an
 // amortized build-from-empty measurement (insert n distinct keys into a pre-sized table).
 const BENCH_OPS: bool = true;
@@ -34,6 +25,56 @@ const BENCH_BUILD: bool = true;
 // Focus switch: when false, the find workloads are skipped (used to re-measure churn/build in
 // isolation). Set true for the full sweep.
 const RUN_FINDS: bool = true;
+// Hasher sweep: prints present/absent probe-length histograms for the aligned cuckoo table under
+// each `Hash64` implementation, to see how hash quality moves probe lengths. Also compares the
+// XOR-tag and double-hashing second-group derivations; deep insertion probes (BFS levels) are the
+// precursor to insertion failures.
+const BENCH_HASHERS: bool = false;
+// Group-width sweep: load factor the aligned cuckoo table reaches before its first failed insert,
+// for 4-, 8- and 16-slot groups.
+const BENCH_GROUP_WIDTHS: bool = false;
+// BFS-depth sweep: the same peak load for 2- and 4-slot groups (16-slot groups reach the 7/8
+// growth threshold at any depth) with the cuckoo BFS searching 2 to 5 levels deep.
+const BENCH_BFS_DEPTH: bool = false;
+// Early-return sweep: find_miss/find_hit for the aligned cuckoo table with lookups stopping at a
+// first group that has an empty slot, vs always checking both groups.
+const BENCH_EARLY_RETURN: bool = false;
+// Failure-rate sweep: fraction of random fills that fail (`try_insert` errors) before reaching
+// each load factor from 50% to 98%. Small tables only; each point is `FAILURE_RATE_RUNS` fills.
+const BENCH_FAILURE_RATE: bool = false;
+const FAILURE_RATE_RUNS: u64 = 100;
+// Fill-to-failure sweep: load factor each table with `try_insert` reaches before its first
+// failure, past any growth threshold, averaged over `FILL_TO_FAILURE_SEEDS` fills, next to its
+// declared `PEAK_LOAD_FACTOR`.
+const BENCH_FILL_TO_FAILURE: bool = false;
+const FILL_TO_FAILURE_SEEDS: u64 = 20;
+// Less-loaded insertion sweep: present/absent and insertion probe-length histograms for the aligned
+// cuckoo table filling the emptier of a key's two groups, vs the first whenever it has room.
+const BENCH_LESS_LOADED: bool = false;
+// Both-groups sweep (out of cache only): find_miss/find_hit for the aligned cuckoo table with
+// `get` loading both groups up front vs resolving the first group before loading the second.
+const BENCH_BOTH_GROUPS: bool = false;
+// Scalar cuckoo window sweep: find_miss/find_hit for 2-slot windows, 4-slot windows scanned by
+// the scalar loop, and 4-slot windows compared with one `control64` SIMD search.
+const BENCH_SCALAR_CUCKOO_SIMD: bool = false;
+// Direct-SIMD cuckoo lookup sweep: find_miss/find_hit with `get` branching after the first bucket
+// vs probing both buckets branchlessly.
+const BENCH_BRANCHLESS: bool = false;
+// Growth sweep: time to double a full aligned cuckoo table with `grow_double` (split each group
+// in place) vs reinserting every entry into a table of twice the size.
+const BENCH_GROW_DOUBLE: bool = false;
+// Building aligned_cuckoo_table with `extend_prefetched` vs plain `Extend`, per table size.
+const BENCH_EXTEND_PREFETCHED: bool = false;
+// Bulk-load sweep: build_reserved for aligned_cuckoo_table with `insert` vs `insert_unique`,
+// which places a known-distinct key in its first group without scanning for a match.
+const BENCH_INSERT_UNIQUE: bool = false;
+// Prime-size sweep: present/absent probe-length histograms for aligned_double_hashing_table with
+// a power-of-two vs a prime number of groups, to see whether prime sizes cluster less.
+const BENCH_PRIME_BUCKETS: bool = false;
+// Counting sweep: frequency histogram of Zipf-distributed keys, i.e. increment-or-insert per key,
+// where most ops update a few hot keys and the rest insert or update a long tail.
+const BENCH_COUNT: bool = false;
+const ZIPF_EXPONENT: f64 = 1.0;
 
 trait PrintStats {
     fn print_stats(&self) {}
@@ -51,28 +92,112 @@ impl InsertAndErase for hashbrown::HashMap<u64, u64> {
     }
 }
 
+trait IncrementCount {
+    fn increment(&mut self, key: u64);
+}
+
+impl IncrementCount for hashbrown::HashMap<u64, u64> {
+    fn increment(&mut self, key: u64) {
+        *self.entry(key).or_insert(0) += 1;
+    }
+}
+
+impl IncrementCount for aligned_cuckoo_table::HashTable<u64> {
+    fn increment(&mut self, key: u64) {
+        match self.find_entry(key) {
+            Some(mut entry) => *entry.get_mut() += 1,
+            None => {
+                self.insert(key, 1);
+            }
+        }
+    }
+}
+
+// Tables without an entry API probe twice on a hit: once to read the count, once to write it.
+macro_rules! impl_increment_count_by_get_insert {
+    ($($table:ty),*) => {$(
+        impl IncrementCount for $table {
+            fn increment(&mut self, key: u64) {
+                let count = self.get(&key).copied().unwrap_or(0);
+                self.insert(key, count + 1);
+            }
+        }
+    )*};
+}
+
+impl_increment_count_by_get_insert!(
+    quadratic_probing_table::HashTable<u64>,
+    linear_probing_table::HashTable<u64>,
+    unaligned_cuckoo_table::HashTable<u64>,
+    direct_simd_cuckoo_table::HashTable<u64>
+);
+
 trait ProbeLength {
     fn probe_length(&self, key: u64) -> (usize, bool) {
         (1, false) // Default dummy implementation
     }
+
+    /// Slots whose tag matched and so needed a key comparison, for tables that report it.
+    fn key_comparisons(&self, key: u64) -> Option<usize> {
+        None
+    }
 }
 
 impl ProbeLength for hashbrown::HashMap<u64, u64> {}
-impl ProbeLength for aligned_double_hashing_table::HashTable<u64> {}
 impl ProbeLength for aligned_quadratic_probing_table::HashTable<u64> {}
-impl ProbeLength for balancing_cuckoo_table::HashTable<u64> {}
-impl ProbeLength for scalar_cache_line_aligned_table::U64HashSet<u64> {}
-impl ProbeLength for scalar_unaligned_table::U64HashSet<u64> {}
-impl ProbeLength for scalar_cuckoo_table::U64HashSet<u64> {}
 impl ProbeLength for localized_simd_cuckoo_table::HashTable<u64> {}
+impl ProbeLength for aligned_cuckoo_tag16_table::HashTable<u64> {}
 
 // Real implementations for tables that have proper probe_length methods
-impl ProbeLength for aligned_cuckoo_table::HashTable<u64> {
+impl<H: u64_fold_hash_fast::Hash64, const W: usize, const D: usize> ProbeLength
+    for aligned_cuckoo_table::HashTable<u64, H, W, D>
+{
+    fn probe_length(&self, key: u64) -> (usize, bool) {
+        let (groups, _, found) = self.probe_length(key);
+        (groups, found)
+    }
+
+    fn key_comparisons(&self, key: u64) -> Option<usize> {
+        Some(self.probe_length(key).1)
+    }
+}
+
+impl ProbeLength for aligned_double_hashing_table::HashTable<u64> {
+    fn probe_length(&self, key: u64) -> (usize, bool) {
+        self.probe_length(key)
+    }
+}
+
+impl ProbeLength for balancing_cuckoo_table::HashTable<u64> {
+    fn probe_length(&self, key: u64) -> (usize, bool) {
+        self.probe_length(key)
+    }
+}
+
+impl ProbeLength for scalar_cache_line_aligned_table::U64HashSet<u64> {
+    fn probe_length(&self, key: u64) -> (usize, bool) {
+        self.probe_length(key)
+    }
+}
+
+impl ProbeLength for scalar_unaligned_table::U64HashSet<u64> {
+    fn probe_length(&self, key: u64) -> (usize, bool) {
+        self.probe_length(key)
+    }
+}
+
+impl<const WINDOW_SIZE: usize> ProbeLength for scalar_cuckoo_table::U64HashSet<u64, WINDOW_SIZE> {
     fn probe_length(&self, key: u64) -> (usize, bool) {
         self.probe_length(key)
     }
 }
 
+impl ProbeLength for ScalarCuckooSimd {
+    fn probe_length(&self, key: u64) -> (usize, bool) {
+        self.0.probe_length(key)
+    }
+}
+
 impl ProbeLength for unaligned_cuckoo_table::HashTable<u64> {
     fn probe_length(&self, key: u64) -> (usize, bool) {
         self.probe_length(key)
@@ -115,6 +240,45 @@ impl ProbeLength for direct_simd_linear_probing_np2::HashTable<u64> {
     }
 }
 
+/// `scalar_cuckoo_table` with 4-slot windows, going through `insert_simd`/`get_simd` so the
+/// benchmark macros can time them.
+struct ScalarCuckooSimd(scalar_cuckoo_table::U64HashSet<u64, 4>);
+
+impl ScalarCuckooSimd {
+    fn with_capacity(capacity: usize) -> Self {
+        Self(scalar_cuckoo_table::U64HashSet::with_capacity(capacity))
+    }
+
+    fn insert(&mut self, key: u64, value: u64) -> (bool, usize) {
+        self.0.insert_simd(key, value)
+    }
+
+    fn get(&mut self, key: &u64) -> Option<&u64> {
+        self.0.get_simd(key)
+    }
+
+    fn print_stats(&self) {
+        self.0.print_stats();
+    }
+}
+
+/// `aligned_cuckoo_table` built with `insert_unique`, so `benchmark_build_reserved!` can time it.
+struct AlignedCuckooUnique(aligned_cuckoo_table::HashTable<u64>);
+
+impl AlignedCuckooUnique {
+    fn with_capacity(capacity: usize) -> Self {
+        Self(aligned_cuckoo_table::HashTable::with_capacity(capacity))
+    }
+
+    fn insert(&mut self, key: u64, value: u64) -> usize {
+        self.0.insert_unique(key, value)
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
 fn drop_spaces(s: &str) -> String {
     s.split_whitespace().collect()
 }
@@ -137,6 +301,84 @@ fn print_histogram(name: &str, histogram: &std::collections::HashMap<usize, usiz
     }
 }
 
+/// Prints the median and 99th percentile of `probe_lengths`.
+fn print_probe_length_percentiles(probe_lengths: impl Iterator<Item = usize>) {
+    // Streaming estimates, so that long runs don't have to keep every probe length.
+    let (mut p50, mut p99) = (P2Quantile::new(0.5), P2Quantile::new(0.99));
+    for probe_length in probe_lengths {
+        p50.observe(probe_length as f64);
+        p99.observe(probe_length as f64);
+    }
+    if let (Some(p50), Some(p99)) = (p50.estimate(), p99.estimate()) {
+        println!("  probe_length p50: {p50:.2}, p99: {p99:.2}");
+    }
+}
+
+/// Prints allocations and allocated bytes per op since the last `count_alloc::reset`.
+#[cfg(feature = "count-alloc")]
+fn print_alloc_counts(ops: usize) {
+    let counts = count_alloc::read();
+    println!(
+        "  allocs_per_op: {:.4}, alloc_bytes_per_op: {:.1}",
+        counts.allocations as f64 / ops as f64,
+        counts.bytes as f64 / ops as f64
+    );
+}
+
+/// Timed runs per benchmark. Each run does `1 / TIMED_RUNS` of the work, so a benchmark takes
+/// about as long as a single run of the whole thing would.
+const TIMED_RUNS: usize = 5;
+
+/// Runs `f` once untimed, so page faults and cold caches don't land in a measurement, then
+/// `n_runs` times timed. Returns the median and minimum run times.
+fn time_median(n_runs: usize, mut f: impl FnMut()) -> (Duration, Duration) {
+    f();
+    let mut times = (0..n_runs)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .collect::<Vec<_>>();
+    times.sort_unstable();
+    (times[n_runs / 2], times[0])
+}
+
+/// Prints `time_median`'s times divided by `ops`, the ops per run.
+fn print_ns_per_op((median, min): (Duration, Duration), ops: usize) {
+    println!(
+        "{:.2} ns/op (min {:.2})",
+        median.as_nanos() as f64 / ops as f64,
+        min.as_nanos() as f64 / ops as f64
+    );
+}
+
+/// Samples ranks `0..n` with probability proportional to `1 / (rank + 1)^exponent`.
+struct Zipf {
+    cdf: Vec<f64>,
+}
+
+impl Zipf {
+    fn new(n: usize, exponent: f64) -> Self {
+        let mut total = 0.0;
+        let mut cdf = (1..=n)
+            .map(|rank| {
+                total += (rank as f64).powf(-exponent);
+                total
+            })
+            .collect::<Vec<_>>();
+        for p in &mut cdf {
+            *p /= total;
+        }
+        Self { cdf }
+    }
+
+    fn sample(&self, rng: &mut fastrand::Rng) -> usize {
+        let u = rng.f64();
+        self.cdf.partition_point(|&p| p <= u).min(self.cdf.len() - 1)
+    }
+}
+
 #[inline(always)]
 fn mul_high_u64(x: u64, y: u64) -> u64 {
     let r = (x as u128) * (y as u128);
@@ -145,26 +387,31 @@ fn mul_high_u64(x: u64, y: u64) -> u64 {
 
 macro_rules! benchmark_find_miss {
     ($table:ty, $v:ty) => {
+        benchmark_find_miss!($table, $v, |capacity| <$table>::with_capacity(capacity))
+    };
+    ($table:ty, $v:ty, $new:expr) => {
         (|n: usize, capacity: usize| {
             print!("find_miss  {}/{n}: ", drop_spaces(stringify!($table)));
             std::io::stdout().flush().unwrap();
-            let mut table = <$table>::with_capacity(capacity);
+            let mut table: $table = ($new)(capacity);
             let mut rng = fastrand::Rng::with_seed(123);
             for _ in 0..n {
                 let key = rng.u64(..);
                 table.insert(key, <$v>::default());
             }
-            let start = Instant::now();
-            let mut found = 0;
-            for _ in 0..ITERS {
-                let key = rng.u64(..);
-                found += table.get(&key).is_some() as usize;
-            }
-            black_box(found);
-            let duration = start.elapsed();
-            println!("{:.2} ns/op", duration.as_nanos() as f64 / ITERS as f64);
+            let iters = ITERS / TIMED_RUNS;
+            let times = time_median(TIMED_RUNS, || {
+                let mut found = 0;
+                for _ in 0..iters {
+                    let key = rng.u64(..);
+                    found += table.get(&key).is_some() as usize;
+                }
+                black_box(found);
+            });
+            print_ns_per_op(times, iters);
             if TRACK_PROBE_LENGTH {
                 table.print_stats();
+                print_probe_length_percentiles((0..iters).map(|_| ProbeLength::probe_length(&table, rng.u64(..)).0));
             }
         })
     };
@@ -172,10 +419,13 @@ macro_rules! benchmark_find_miss {
 
 macro_rules! benchmark_find_hit {
     ($table:ty, $v:ty) => {
+        benchmark_find_hit!($table, $v, |capacity| <$table>::with_capacity(capacity))
+    };
+    ($table:ty, $v:ty, $new:expr) => {
         (|n: usize, capacity: usize| {
             print!("find_hit  {}/{n}: ", drop_spaces(stringify!($table)));
             std::io::stdout().flush().unwrap();
-            let mut table = <$table>::with_capacity(capacity);
+            let mut table: $table = ($new)(capacity);
             let mut rng = fastrand::Rng::with_seed(123);
             let mut keys = (0..n).map(|i| i as u64).collect::<Vec<_>>();
             rng.shuffle(&mut keys);
@@ -183,18 +433,20 @@ macro_rules! benchmark_find_hit {
                 table.insert(key, <$v>::default());
             }
             let n_ish_mask = ((n.next_power_of_two() / 2) - 1) as u64;
-            let start = Instant::now();
-            let mut found = 0;
-            for _ in 0..ITERS {
-                let key = rng.u64(..) & n_ish_mask;
-                found += table.get(&key).is_some() as usize;
+            let iters = ITERS / TIMED_RUNS;
+            let times = time_median(TIMED_RUNS, || {
+                let mut found = 0;
+                for _ in 0..iters {
+                    let key = rng.u64(..) & n_ish_mask;
+                    found += table.get(&key).is_some() as usize;
+                }
+                black_box(found);
+            });
+            print_ns_per_op(times, iters);
+            if TRACK_PROBE_LENGTH {
+                let probe_lengths = (0..iters).map(|_| ProbeLength::probe_length(&table, rng.u64(..) & n_ish_mask).0);
+                print_probe_length_percentiles(probe_lengths);
             }
-            black_box(found);
-            let duration = start.elapsed();
-            println!(
-                "{:.2} ns/op",
-                duration.as_nanos() as f64 / ITERS as f64
-            );
         })
     };
 }
@@ -213,27 +465,23 @@ macro_rules! benchmark_find_latency {
                 let key = rng.u64(..);
                 table.insert(key, <$v>::default());
             }
-            let outer_iters = (ITERS / 3).div_ceil(n);
+            let outer_iters = (ITERS / 3 / TIMED_RUNS).div_ceil(n);
             let true_iters = outer_iters * n;
-            let start = Instant::now();
-            let mut found = 0;
-            for _ in 0..outer_iters {
-                let mut rng = fastrand::Rng::with_seed(123);
-                let mut prev_value = 0;
-                for _ in 0..n {
-                    let key = rng.u64(..) ^ prev_value;
-                    let Some(value) = table.get(&key) else {
-                        panic!("key {key:x} not found");
-                    };
-                    prev_value = *value;
+            let times = time_median(TIMED_RUNS, || {
+                for _ in 0..outer_iters {
+                    let mut rng = fastrand::Rng::with_seed(123);
+                    let mut prev_value = 0;
+                    for _ in 0..n {
+                        let key = rng.u64(..) ^ prev_value;
+                        let Some(value) = table.get(&key) else {
+                            panic!("key {key:x} not found");
+                        };
+                        prev_value = *value;
+                    }
+                    black_box(prev_value);
                 }
-                black_box(prev_value);
-            }
-            let duration = start.elapsed();
-            println!(
-                "{:.2} ns/op",
-                duration.as_nanos() as f64 / true_iters as f64
-            );
+            });
+            print_ns_per_op(times, true_iters);
         })
     };
 }
@@ -249,22 +497,19 @@ macro_rules! benchmark_insert_and_erase {
                 let key = rng.u64(..);
                 table.insert(key, <$v>::default());
             }
-            let outer_iters = ITERS.div_ceil(n);
+            let outer_iters = (ITERS / TIMED_RUNS).div_ceil(n);
             let true_iters = outer_iters * n;
-            let start = Instant::now();
-            for _ in 0..outer_iters {
-                let mut rng = fastrand::Rng::with_seed(456);
-                for _ in 0..n {
-                    let key = rng.u64(..);
-                    unsafe { table.insert_and_erase(key, <$v>::default()) };
+            let times = time_median(TIMED_RUNS, || {
+                for _ in 0..outer_iters {
+                    let mut rng = fastrand::Rng::with_seed(456);
+                    for _ in 0..n {
+                        let key = rng.u64(..);
+                        unsafe { table.insert_and_erase(key, <$v>::default()) };
+                    }
                 }
-            }
-            black_box(table.len());
-            let duration = start.elapsed();
-            println!(
-                "{:.2} ns/op",
-                duration.as_nanos() as f64 / true_iters as f64
-            );
+                black_box(table.len());
+            });
+            print_ns_per_op(times, true_iters);
         })
     };
 }
@@ -275,23 +520,25 @@ macro_rules! benchmark_build_unreserved {
             _ = capacity;
             print!("build_unreserved  {}/{n}: ", drop_spaces(stringify!($table)));
             std::io::stdout().flush().unwrap();
-            let outer_iters = (ITERS / 8).div_ceil(n);
+            let outer_iters = (ITERS / 8 / TIMED_RUNS).div_ceil(n);
             let true_iters = outer_iters * n;
-            let start = Instant::now();
-            for _ in 0..outer_iters {
-                let mut table = black_box(<$table>::new());
-                let mut rng = fastrand::Rng::with_seed(124);
-                for _ in 0..n {
-                    let key = rng.u64(..);
-                    table.insert(key, <$v>::default());
+            #[cfg(feature = "count-alloc")]
+            count_alloc::reset();
+            let times = time_median(TIMED_RUNS, || {
+                for _ in 0..outer_iters {
+                    let mut table = black_box(<$table>::new());
+                    let mut rng = fastrand::Rng::with_seed(124);
+                    for _ in 0..n {
+                        let key = rng.u64(..);
+                        table.insert(key, <$v>::default());
+                    }
+                    black_box(table.len());
                 }
-                black_box(table.len());
-            }
-            let duration = start.elapsed();
-            println!(
-                "{:.2} ns/op",
-                duration.as_nanos() as f64 / true_iters as f64
-            );
+            });
+            print_ns_per_op(times, true_iters);
+            // Counted over the warmup run too.
+            #[cfg(feature = "count-alloc")]
+            print_alloc_counts((TIMED_RUNS + 1) * true_iters);
         })
     };
 }
@@ -302,32 +549,61 @@ macro_rules! benchmark_build_reserved {
             _ = capacity;
             print!("build_reserved  {}/{n}: ", drop_spaces(stringify!($table)));
             std::io::stdout().flush().unwrap();
-            let outer_iters = (ITERS / 8).div_ceil(n);
+            let outer_iters = (ITERS / 8 / TIMED_RUNS).div_ceil(n);
             let true_iters = outer_iters * n;
-            let start = Instant::now();
-            for _ in 0..outer_iters {
+            #[cfg(feature = "count-alloc")]
+            count_alloc::reset();
+            let times = time_median(TIMED_RUNS, || {
+                for _ in 0..outer_iters {
+                    let mut table = black_box(<$table>::with_capacity(capacity));
+                    let mut rng = fastrand::Rng::with_seed(124);
+                    for _ in 0..n {
+                        let key = rng.u64(..);
+                        table.insert(key, <$v>::default());
+                    }
+                    black_box(table.len());
+                }
+            });
+            print_ns_per_op(times, true_iters);
+            // Counted over the warmup run too.
+            #[cfg(feature = "count-alloc")]
+            print_alloc_counts((TIMED_RUNS + 1) * true_iters);
+        })
+    };
+}
+
+macro_rules! benchmark_count {
+    ($table:ty) => {
+        (|n: usize, capacity: usize| {
+            print!("count  {}/{n}: ", drop_spaces(stringify!($table)));
+            std::io::stdout().flush().unwrap();
+            // `n` distinct keys, so the table never exceeds the load factor being measured. Keys
+            // are drawn up front so that sampling (a binary search) isn't timed.
+            let mut rng = fastrand::Rng::with_seed(125);
+            let distinct_keys = (0..n).map(|_| rng.u64(..)).collect::<Vec<_>>();
+            let zipf = Zipf::new(n, ZIPF_EXPONENT);
+            let ops = ITERS / 8 / TIMED_RUNS;
+            let keys = (0..ops).map(|_| distinct_keys[zipf.sample(&mut rng)]).collect::<Vec<_>>();
+            let times = time_median(TIMED_RUNS, || {
                 let mut table = black_box(<$table>::with_capacity(capacity));
-                let mut rng = fastrand::Rng::with_seed(124);
-                for _ in 0..n {
-                    let key = rng.u64(..);
-                    table.insert(key, <$v>::default());
+                for &key in &keys {
+                    IncrementCount::increment(&mut table, key);
                 }
                 black_box(table.len());
-            }
-            let duration = start.elapsed();
-            println!(
-                "{:.2} ns/op",
-                duration.as_nanos() as f64 / true_iters as f64
-            );
+            });
+            print_ns_per_op(times, ops);
         })
     };
 }
 
 macro_rules! benchmark_probe_histogram {
     ($table:ty, $v:ty) => {
+        benchmark_probe_histogram!($table, $v, |capacity| <$table>::with_capacity(capacity))
+    };
+    ($table:ty, $v:ty, $new:expr) => {
         (|n: usize, capacity: usize| {
             println!("probe_histogram  {}/{n}:", drop_spaces(stringify!($table)));
-            let mut table = <$table>::with_capacity(capacity);
+            let mut table: $table = ($new)(capacity);
             let mut rng = fastrand::Rng::with_seed(123);
 
             // Insert keys same way as find_hit to get consistent results
@@ -340,38 +616,53 @@ macro_rules! benchmark_probe_histogram {
             // Build histograms
             let mut present_histogram = std::collections::HashMap::new();
             let mut absent_histogram = std::collections::HashMap::new();
+            let mut present_comparisons_histogram = std::collections::HashMap::new();
+            let mut absent_comparisons_histogram = std::collections::HashMap::new();
 
             let n_ish_mask = ((n.next_power_of_two() / 2) - 1) as u64;
 
             // Sample present keys
             for key in 0..n as u64 {
-                let (probe_length, found) = table.probe_length(key);
+                let (probe_length, found) = ProbeLength::probe_length(&table, key);
                 assert!(found);
                 *present_histogram.entry(probe_length).or_insert(0) += 1;
+                if let Some(key_comparisons) = ProbeLength::key_comparisons(&table, key) {
+                    *present_comparisons_histogram.entry(key_comparisons).or_insert(0) += 1;
+                }
             }
 
             // Sample absent keys
             let mut rng_absent = fastrand::Rng::with_seed(456);
             for _ in 0..n {
                 let key = rng_absent.u64(..);
-                let (probe_length, found) = table.probe_length(key);
+                let (probe_length, found) = ProbeLength::probe_length(&table, key);
                 if !found {
                     *absent_histogram.entry(probe_length).or_insert(0) += 1;
+                    if let Some(key_comparisons) = ProbeLength::key_comparisons(&table, key) {
+                        *absent_comparisons_histogram.entry(key_comparisons).or_insert(0) += 1;
+                    }
                 }
             }
 
             // Print histograms using shared function
             print_histogram("Present key probe lengths", &present_histogram);
             print_histogram("Absent key probe lengths", &absent_histogram);
+            if !present_comparisons_histogram.is_empty() {
+                print_histogram("Present key comparisons", &present_comparisons_histogram);
+                print_histogram("Absent key comparisons", &absent_comparisons_histogram);
+            }
         })
     };
 }
 
 macro_rules! benchmark_insertion_probe_histogram {
     ($table:ty, $v:ty) => {
+        benchmark_insertion_probe_histogram!($table, $v, |capacity| <$table>::with_capacity(capacity))
+    };
+    ($table:ty, $v:ty, $new:expr) => {
         (|n: usize, capacity: usize| {
             println!("insertion_probe_histogram  {}/{n}:", drop_spaces(stringify!($table)));
-            let mut table = <$table>::with_capacity(capacity);
+            let mut table: $table = ($new)(capacity);
             let mut rng = fastrand::Rng::with_seed(123);
             let mut insertion_histogram = std::collections::HashMap::new();
 
@@ -379,7 +670,8 @@ macro_rules! benchmark_insertion_probe_histogram {
             let mut keys = (0..n).map(|i| i as u64).collect::<Vec<_>>();
             rng.shuffle(&mut keys);
             for key in keys {
-                let (_, _, insertion_probe_length) = table.insert(key, <$v>::default());
+                // `try_insert` still reports the probe length; `n` is below the growth threshold.
+                let (_, _, insertion_probe_length) = table.try_insert(key, <$v>::default()).unwrap();
                 *insertion_histogram.entry(insertion_probe_length).or_insert(0) += 1;
             }
 
@@ -389,13 +681,150 @@ macro_rules! benchmark_insertion_probe_histogram {
     };
 }
 
+/// Inserts random keys into an aligned cuckoo table with `W`-slot groups and BFS depth `D` until
+/// an insert fails (the BFS finds no free slot) or the table reaches its 7/8 growth threshold, and
+/// prints the load factor reached.
+fn benchmark_peak_load<const W: usize, const D: usize>(capacity: usize) {
+    use u64_fold_hash_fast::FoldHashFast;
+    print!("peak_load  aligned_cuckoo_table::HashTable<u64,FoldHashFast,{W},{D}>/{capacity}: ");
+    std::io::stdout().flush().unwrap();
+    let mut table = aligned_cuckoo_table::HashTable::<u64, FoldHashFast, W, D>::with_capacity_and_hasher(
+        capacity,
+        FoldHashFast,
+    );
+    let item_capacity = table.num_buckets() * 7 / 8;
+    let mut rng = fastrand::Rng::with_seed(123);
+    // A failed insert panics before touching the table, so we can keep using it afterwards.
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    while table.len() < item_capacity {
+        let key = rng.u64(..);
+        let insert = std::panic::AssertUnwindSafe(|| table.insert(key, 0));
+        if std::panic::catch_unwind(insert).is_err() {
+            break;
+        }
+    }
+    std::panic::set_hook(hook);
+    println!("{:.1}%", table.len() as f64 / table.num_buckets() as f64 * 100.0);
+}
+
+fn benchmark_grow_double(capacity: usize) {
+    let build = || {
+        let mut table = aligned_cuckoo_table::HashTable::<u64>::with_capacity(capacity);
+        let mut rng = fastrand::Rng::with_seed(123);
+        for _ in 0..capacity {
+            table.insert(rng.u64(..), 0);
+        }
+        table
+    };
+    let outer_iters = (ITERS / 8).div_ceil(capacity);
+    let (mut split, mut reinsert) = (Duration::ZERO, Duration::ZERO);
+    for _ in 0..outer_iters {
+        let mut table = build();
+        let start = Instant::now();
+        table.grow_double();
+        split += start.elapsed();
+        black_box(&table);
+
+        let table = build();
+        let start = Instant::now();
+        let mut grown = aligned_cuckoo_table::HashTable::<u64>::with_capacity(table.num_buckets() * 7 / 4);
+        for (key, &value) in table.iter() {
+            grown.insert(key, value);
+        }
+        reinsert += start.elapsed();
+        black_box(&grown);
+    }
+    let true_iters = (outer_iters * capacity) as f64;
+    println!("grow_double  aligned_cuckoo_table/{capacity}: {:.2} ns/op", split.as_nanos() as f64 / true_iters);
+    println!("grow_reinsert  aligned_cuckoo_table/{capacity}: {:.2} ns/op", reinsert.as_nanos() as f64 / true_iters);
+}
+
+fn benchmark_extend_prefetched(capacity: usize) {
+    let mut rng = fastrand::Rng::with_seed(123);
+    let pairs = (0..capacity).map(|_| (rng.u64(..), 0u64)).collect::<Vec<_>>();
+    let outer_iters = (ITERS / 8 / TIMED_RUNS).div_ceil(capacity);
+    let true_iters = outer_iters * capacity;
+    for prefetched in [false, true] {
+        let name = if prefetched { "extend_prefetched" } else { "extend" };
+        print!("{name}  aligned_cuckoo_table/{capacity}: ");
+        std::io::stdout().flush().unwrap();
+        let times = time_median(TIMED_RUNS, || {
+            for _ in 0..outer_iters {
+                let mut table = aligned_cuckoo_table::HashTable::<u64>::new();
+                if prefetched {
+                    table.extend_prefetched(pairs.iter().copied());
+                } else {
+                    table.extend(pairs.iter().copied());
+                }
+                black_box(&table);
+            }
+        });
+        print_ns_per_op(times, true_iters);
+    }
+}
+
+/// For each load factor, fills `FAILURE_RATE_RUNS` fresh tables of `num_slots` slots with random
+/// keys (one RNG seed per run) via `try_insert`, and prints the fraction of runs in which an insert
+/// failed before the table reached that load. For tables that grow, hitting the growth threshold
+/// counts as a failure, since `try_insert` doesn't grow.
+macro_rules! benchmark_failure_rate {
+    ($table:ty, $v:ty) => {
+        (|num_slots: usize| {
+            for load_percent in [50usize, 60, 70, 75, 80, 85, 87, 90, 92, 94, 96, 98] {
+                let target = num_slots * load_percent / 100;
+                let mut failures = 0;
+                for seed in 0..FAILURE_RATE_RUNS {
+                    let mut table = <$table>::with_capacity(num_slots * 7 / 8);
+                    let mut rng = fastrand::Rng::with_seed(seed);
+                    while table.len() < target {
+                        if table.try_insert(rng.u64(..), <$v>::default()).is_err() {
+                            failures += 1;
+                            break;
+                        }
+                    }
+                }
+                println!(
+                    "failure_rate  {}/{num_slots}/{load_percent}%: {:.2}",
+                    drop_spaces(stringify!($table)),
+                    failures as f64 / FAILURE_RATE_RUNS as f64
+                );
+            }
+        })
+    };
+}
+
+/// Fills tables of `num_slots` slots with random keys until `try_insert` fails (see
+/// `cuckoo_hashing_benchmark::fill_to_failure`) and prints the load factor reached. Takes a table
+/// module, whose `HashTable` implements `FillToFailure` and which declares `PEAK_LOAD_FACTOR`.
+macro_rules! fill_to_failure {
+    ($module:ident) => {
+        (|num_slots: usize| {
+            let (mut total, mut min) = (0.0, f64::INFINITY);
+            for seed in 0..FILL_TO_FAILURE_SEEDS {
+                let load_factor = cuckoo_hashing_benchmark::fill_to_failure::<$module::HashTable<u64>>(num_slots, seed);
+                total += load_factor;
+                min = min.min(load_factor);
+            }
+            println!(
+                "fill_to_failure  {}/{num_slots}: mean {:.2}%, min {:.2}% (declared peak {:.1}%)",
+                stringify!($module),
+                total / FILL_TO_FAILURE_SEEDS as f64 * 100.0,
+                min * 100.0,
+                $module::PEAK_LOAD_FACTOR * 100.0
+            );
+        })
+    };
+}
+
 fn main() {
     // Head-to-head: LINEAR vs QUADRATIC vs CUCKOO probing, on two layouts (Indirect SIMD =
     // 1-byte tags + W=8 group; Direct SIMD = aligned [u64;4] cache-line buckets), across cache
     // residency (2^10 in-cache .. 2^25 far out-of-cache) and load factor (25% .. 87.5%).
     //
-    // Output is line-per-benchmark "op  table/n: X.YZ ns/op", grouped under "mi:" and
-    // "load factor:" headers, so it can be parsed mechanically.
+    // Output is line-per-benchmark "op  table/n: X.YZ ns/op (min A.BC)", the median and fastest of
+    // `TIMED_RUNS` runs, grouped under "mi:" and "load factor:" headers, so it can be parsed
+    // mechanically.
     for lg_mi in [10usize, 15, 20, 25] {
         println!("mi: 2^{lg_mi}");
         let mi = 1usize << lg_mi;
@@ -420,6 +849,7 @@ fn main() {
             benchmark_find_miss!(linear_probing_table::HashTable::<u64>, u64)(n, capacity);
             benchmark_find_miss!(direct_simd_linear_probing::HashTable::<u64>, u64)(n, capacity);
             benchmark_find_miss!(aligned_cuckoo_table::HashTable::<u64>, u64)(n, capacity);
+            benchmark_find_miss!(aligned_cuckoo_tag16_table::HashTable::<u64>, u64)(n, capacity);
             benchmark_find_miss!(unaligned_cuckoo_table::HashTable::<u64>, u64)(n, capacity);
             benchmark_find_miss!(direct_simd_cuckoo_table::HashTable::<u64>, u64)(n, capacity);
             benchmark_find_miss!(hashbrown::HashMap::<u64, u64>, u64)(n, capacity);
@@ -482,6 +912,141 @@ fn main() {
             benchmark_build_reserved!(direct_simd_linear_probing::HashTable::<u64>, u64)(n, capacity);
             benchmark_build_reserved!(hashbrown::HashMap::<u64, u64>, u64)(n, capacity);
           } // BENCH_BUILD
+
+          if BENCH_INSERT_UNIQUE {
+            benchmark_build_reserved!(aligned_cuckoo_table::HashTable::<u64>, u64)(n, capacity);
+            benchmark_build_reserved!(AlignedCuckooUnique, u64)(n, capacity);
+          } // BENCH_INSERT_UNIQUE
+
+          if BENCH_PRIME_BUCKETS {
+            for prime_buckets in [false, true] {
+                println!("prime_buckets: {prime_buckets}");
+                benchmark_probe_histogram!(aligned_double_hashing_table::HashTable::<u64>, u64, |capacity| {
+                    aligned_double_hashing_table::HashTable::with_capacity(capacity).with_prime_buckets(prime_buckets)
+                })(n, capacity);
+            }
+          } // BENCH_PRIME_BUCKETS
+
+          if BENCH_COUNT {
+            benchmark_count!(quadratic_probing_table::HashTable::<u64>)(n, capacity);
+            benchmark_count!(linear_probing_table::HashTable::<u64>)(n, capacity);
+            benchmark_count!(aligned_cuckoo_table::HashTable::<u64>)(n, capacity);
+            benchmark_count!(unaligned_cuckoo_table::HashTable::<u64>)(n, capacity);
+            benchmark_count!(direct_simd_cuckoo_table::HashTable::<u64>)(n, capacity);
+            benchmark_count!(hashbrown::HashMap::<u64, u64>)(n, capacity);
+          } // BENCH_COUNT
+
+          if BENCH_HASHERS {
+            use u64_fold_hash_fast::{FoldHashFast, SipHash13, WyHash64};
+            benchmark_probe_histogram!(aligned_cuckoo_table::HashTable::<u64, FoldHashFast>, u64, |capacity| {
+                aligned_cuckoo_table::HashTable::with_capacity_and_hasher(capacity, FoldHashFast)
+            })(n, capacity);
+            benchmark_probe_histogram!(aligned_cuckoo_table::HashTable::<u64, WyHash64>, u64, |capacity| {
+                aligned_cuckoo_table::HashTable::with_capacity_and_hasher(capacity, WyHash64)
+            })(n, capacity);
+            benchmark_probe_histogram!(aligned_cuckoo_table::HashTable::<u64, SipHash13>, u64, |capacity| {
+                aligned_cuckoo_table::HashTable::with_capacity_and_hasher(capacity, SipHash13::random())
+            })(n, capacity);
+            for double_hashing in [false, true] {
+                println!("double_hashing: {double_hashing}");
+                benchmark_probe_histogram!(aligned_cuckoo_table::HashTable::<u64>, u64, |capacity| {
+                    aligned_cuckoo_table::HashTable::with_capacity(capacity).with_double_hashing(double_hashing)
+                })(n, capacity);
+                benchmark_insertion_probe_histogram!(aligned_cuckoo_table::HashTable::<u64>, u64, |capacity| {
+                    aligned_cuckoo_table::HashTable::with_capacity(capacity).with_double_hashing(double_hashing)
+                })(n, capacity);
+            }
+          } // BENCH_HASHERS
+
+          if BENCH_EARLY_RETURN {
+            for early_return in [false, true] {
+                println!("early_return: {early_return}");
+                benchmark_find_miss!(aligned_cuckoo_table::HashTable::<u64>, u64, |capacity| {
+                    aligned_cuckoo_table::HashTable::with_capacity(capacity).with_early_return(early_return)
+                })(n, capacity);
+                benchmark_find_hit!(aligned_cuckoo_table::HashTable::<u64>, u64, |capacity| {
+                    aligned_cuckoo_table::HashTable::with_capacity(capacity).with_early_return(early_return)
+                })(n, capacity);
+            }
+          } // BENCH_EARLY_RETURN
+
+          if BENCH_LESS_LOADED {
+            for less_loaded in [false, true] {
+                println!("less_loaded: {less_loaded}");
+                benchmark_probe_histogram!(aligned_cuckoo_table::HashTable::<u64>, u64, |capacity| {
+                    aligned_cuckoo_table::HashTable::with_capacity(capacity).with_less_loaded_insertion(less_loaded)
+                })(n, capacity);
+                benchmark_insertion_probe_histogram!(aligned_cuckoo_table::HashTable::<u64>, u64, |capacity| {
+                    aligned_cuckoo_table::HashTable::with_capacity(capacity).with_less_loaded_insertion(less_loaded)
+                })(n, capacity);
+            }
+          } // BENCH_LESS_LOADED
+
+          if BENCH_BOTH_GROUPS && !in_cache {
+            for load_both_groups in [false, true] {
+                println!("load_both_groups: {load_both_groups}");
+                benchmark_find_miss!(aligned_cuckoo_table::HashTable::<u64>, u64, |capacity| {
+                    aligned_cuckoo_table::HashTable::with_capacity(capacity).with_both_groups_loaded(load_both_groups)
+                })(n, capacity);
+                benchmark_find_hit!(aligned_cuckoo_table::HashTable::<u64>, u64, |capacity| {
+                    aligned_cuckoo_table::HashTable::with_capacity(capacity).with_both_groups_loaded(load_both_groups)
+                })(n, capacity);
+            }
+          } // BENCH_BOTH_GROUPS
+
+          if BENCH_SCALAR_CUCKOO_SIMD {
+            benchmark_find_miss!(scalar_cuckoo_table::U64HashSet::<u64>, u64)(n, capacity);
+            benchmark_find_miss!(scalar_cuckoo_table::U64HashSet::<u64, 4>, u64)(n, capacity);
+            benchmark_find_miss!(ScalarCuckooSimd, u64)(n, capacity);
+            benchmark_find_hit!(scalar_cuckoo_table::U64HashSet::<u64>, u64)(n, capacity);
+            benchmark_find_hit!(scalar_cuckoo_table::U64HashSet::<u64, 4>, u64)(n, capacity);
+            benchmark_find_hit!(ScalarCuckooSimd, u64)(n, capacity);
+          } // BENCH_SCALAR_CUCKOO_SIMD
+
+          if BENCH_BRANCHLESS {
+            for branchless in [false, true] {
+                println!("branchless: {branchless}");
+                benchmark_find_miss!(direct_simd_cuckoo_table::HashTable::<u64>, u64, |capacity| {
+                    direct_simd_cuckoo_table::HashTable::with_capacity(capacity).with_branchless(branchless)
+                })(n, capacity);
+                benchmark_find_hit!(direct_simd_cuckoo_table::HashTable::<u64>, u64, |capacity| {
+                    direct_simd_cuckoo_table::HashTable::with_capacity(capacity).with_branchless(branchless)
+                })(n, capacity);
+            }
+          } // BENCH_BRANCHLESS
+        }
+        if BENCH_GROUP_WIDTHS {
+            benchmark_peak_load::<4, { aligned_cuckoo_table::BFS_DEPTH }>(mi);
+            benchmark_peak_load::<8, { aligned_cuckoo_table::BFS_DEPTH }>(mi);
+            #[cfg(target_feature = "sse2")]
+            benchmark_peak_load::<16, { aligned_cuckoo_table::BFS_DEPTH }>(mi);
+        }
+        if BENCH_BFS_DEPTH && lg_mi <= 20 {
+            benchmark_peak_load::<2, 2>(mi);
+            benchmark_peak_load::<2, 3>(mi);
+            benchmark_peak_load::<2, 4>(mi);
+            benchmark_peak_load::<2, 5>(mi);
+            benchmark_peak_load::<4, 2>(mi);
+            benchmark_peak_load::<4, 3>(mi);
+            benchmark_peak_load::<4, 4>(mi);
+            benchmark_peak_load::<4, 5>(mi);
+        }
+        if BENCH_GROW_DOUBLE {
+            benchmark_grow_double(mi * 7 / 8);
+        }
+        if BENCH_EXTEND_PREFETCHED {
+            benchmark_extend_prefetched(mi * 3 / 4);
+        }
+        if BENCH_FILL_TO_FAILURE && lg_mi <= 20 {
+            fill_to_failure!(aligned_cuckoo_table)(mi);
+            fill_to_failure!(unaligned_cuckoo_table)(mi);
+            fill_to_failure!(direct_simd_cuckoo_table)(mi);
+            fill_to_failure!(localized_simd_cuckoo_table)(mi);
+        }
+        if BENCH_FAILURE_RATE && lg_mi <= 15 {
+            benchmark_failure_rate!(aligned_cuckoo_table::HashTable::<u64>, u64)(mi);
+            benchmark_failure_rate!(unaligned_cuckoo_table::HashTable::<u64>, u64)(mi);
+            benchmark_failure_rate!(direct_simd_cuckoo_table::HashTable::<u64>, u64)(mi);
         }
     }
 }
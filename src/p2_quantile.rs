@@ -0,0 +1,160 @@
+//! Streaming quantile estimation with the P² algorithm (Jain & Chlamtac, 1985), so the benchmark
+//! can report a percentile of billions of probe lengths without storing them. Keeps five markers
+//! whose heights track the minimum, the `p/2`, `p` and `(1+p)/2` quantiles and the maximum,
+//! nudging each towards its desired position with a piecewise-parabolic fit as samples arrive.
+
+/// Estimates the `p` quantile of a stream in O(1) memory.
+#[derive(Clone, Debug)]
+pub struct P2Quantile {
+    p: f64,
+    count: usize,
+    // Marker heights; the first `count` samples until there are five.
+    heights: [f64; 5],
+    // Actual and desired marker positions, 1-based as in the paper.
+    positions: [f64; 5],
+    desired: [f64; 5],
+    increments: [f64; 5],
+}
+
+impl P2Quantile {
+    pub fn new(p: f64) -> Self {
+        assert!(p > 0.0 && p < 1.0, "p must be in (0, 1)");
+        Self {
+            p,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    /// Samples seen so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn observe(&mut self, x: f64) {
+        if self.count < 5 {
+            self.heights[self.count] = x;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_unstable_by(f64::total_cmp);
+            }
+            return;
+        }
+        self.count += 1;
+
+        // The cell `x` falls in, widening the extremes if it's outside them.
+        let h = &mut self.heights;
+        let k = if x < h[0] {
+            h[0] = x;
+            0
+        } else if x >= h[4] {
+            h[4] = x;
+            3
+        } else {
+            (0..4).rfind(|&i| h[i] <= x).unwrap_or(0)
+        };
+        for position in &mut self.positions[k + 1..] {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(self.increments) {
+            *desired += increment;
+        }
+
+        // Move the middle markers by at most one position towards where they should be.
+        for i in 1..4 {
+            let d = self.desired[i] - self.positions[i];
+            let n = &self.positions;
+            if (d >= 1.0 && n[i + 1] - n[i] > 1.0) || (d <= -1.0 && n[i - 1] - n[i] < -1.0) {
+                let d = if d >= 1.0 { 1.0 } else { -1.0 };
+                let parabolic = self.parabolic(i, d);
+                self.heights[i] = if self.heights[i - 1] < parabolic && parabolic < self.heights[i + 1] {
+                    parabolic
+                } else {
+                    self.linear(i, d)
+                };
+                self.positions[i] += d;
+            }
+        }
+    }
+
+    /// The current estimate, or `None` before any samples. Exact (nearest rank) for fewer than
+    /// five samples.
+    pub fn estimate(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            1..5 => {
+                let mut samples = self.heights;
+                let samples = &mut samples[..self.count];
+                samples.sort_unstable_by(f64::total_cmp);
+                let rank = (self.p * self.count as f64) as usize;
+                Some(samples[rank.min(self.count - 1)])
+            }
+            _ => Some(self.heights[2]),
+        }
+    }
+
+    /// Piecewise-parabolic prediction of marker `i`'s height after moving it by `d`.
+    fn parabolic(&self, i: usize, d: f64) -> f64 {
+        let (q, n) = (&self.heights, &self.positions);
+        q[i] + d / (n[i + 1] - n[i - 1])
+            * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]))
+    }
+
+    /// Linear prediction of marker `i`'s height after moving it by `d`, towards its neighbour.
+    fn linear(&self, i: usize, d: f64) -> f64 {
+        let (q, n) = (&self.heights, &self.positions);
+        let j = if d > 0.0 { i + 1 } else { i - 1 };
+        q[i] + d * (q[j] - q[i]) / (n[j] - n[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The exact `p` quantile of `samples` (nearest rank).
+    fn exact(samples: &[f64], p: f64) -> f64 {
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable_by(f64::total_cmp);
+        sorted[((p * sorted.len() as f64) as usize).min(sorted.len() - 1)]
+    }
+
+    #[test]
+    fn test_against_exact() {
+        let mut rng = fastrand::Rng::with_seed(2122);
+        let uniform: Vec<f64> = (0..100_000).map(|_| rng.f64()).collect();
+        // Exponential with mean 1: long-tailed, like probe lengths at high load.
+        let exponential: Vec<f64> = (0..100_000).map(|_| -(1.0 - rng.f64()).ln()).collect();
+        for samples in [&uniform, &exponential] {
+            for p in [0.5, 0.99] {
+                let mut estimator = P2Quantile::new(p);
+                for &x in samples.iter() {
+                    estimator.observe(x);
+                }
+                assert_eq!(estimator.count(), samples.len());
+                let (estimate, exact) = (estimator.estimate().unwrap(), exact(samples, p));
+                assert!((estimate - exact).abs() < 0.02 * exact.max(1.0), "p{p}: {estimate} vs {exact}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_few_samples() {
+        let mut estimator = P2Quantile::new(0.5);
+        assert_eq!(estimator.estimate(), None);
+        for x in [3.0, 1.0, 2.0] {
+            estimator.observe(x);
+        }
+        assert_eq!(estimator.estimate(), Some(2.0));
+        // A constant stream estimates that constant.
+        let mut estimator = P2Quantile::new(0.99);
+        for _ in 0..1000 {
+            estimator.observe(1.0);
+        }
+        assert_eq!(estimator.estimate(), Some(1.0));
+    }
+}
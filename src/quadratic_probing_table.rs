@@ -1,14 +1,16 @@
 //! A quadratic probing hash table for u64 keys. SwissTable design following `hashbrown` crate,
 //! with a lot of features removed but the same optimizations valid.
 
-use std::hint::likely;
-use std::{alloc::Layout, ptr::NonNull};
+use core::hint::likely;
+use core::{alloc::Layout, ptr::NonNull};
+use core::fmt;
 
 use crate::TRACK_PROBE_LENGTH;
 use crate::control::{Group, Tag, TagSliceExt as _};
 use crate::u64_fold_hash_fast::fold_hash_fast;
 use crate::uunwrap::UUnwrap;
-use crate::dropper::Dropper;
+use crate::dropper::{self, Dropper};
+use crate::DebugEntries;
 
 pub struct HashTable<V> {
     // Mask to get an index from a hash value. The value is one less than the
@@ -25,11 +27,17 @@ pub struct HashTable<V> {
     // Seed for the hash function
     seed: u64,
 
-    marker: std::marker::PhantomData<V>,
+    marker: core::marker::PhantomData<V>,
     total_probe_length: usize,
     dropper: Dropper,
 }
 
+// SAFETY: `ctrl` points into the allocation `dropper` owns, which no other table shares.
+// `set_ctrl` writes a tag and its mirror in the trailing `Group::WIDTH` bytes through `&self`, but
+// is only called under `&mut self`.
+unsafe impl<V: Send> Send for HashTable<V> {}
+unsafe impl<V: Sync> Sync for HashTable<V> {}
+
 /// Probe sequence based on triangular numbers, which is guaranteed (since our
 /// table size is a power of two) to visit every group of elements exactly once.
 ///
@@ -64,19 +72,32 @@ impl ProbeSeq {
 
 impl<V> HashTable<V> {
     pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_huge_pages(capacity, false)
+    }
+
+    /// As `with_capacity`, but on huge pages where the kernel allows; see `dropper::alloc`.
+    #[cfg(target_os = "linux")]
+    pub fn with_capacity_hugepage(capacity: usize) -> Self {
+        Self::with_capacity_and_huge_pages(capacity, true)
+    }
+
+    fn with_capacity_and_huge_pages(capacity: usize, huge_pages: bool) -> Self {
         // Calculate sizes
         // TODO: integer overflow...
         let num_buckets = ((capacity * 8) / 7).next_power_of_two();
-        let bucket_size = std::mem::size_of::<(u64, V)>();
-        let align = std::mem::align_of::<(u64, V)>().max(Group::WIDTH);
+        let bucket_size = core::mem::size_of::<(u64, V)>();
+        let align = core::mem::align_of::<(u64, V)>().max(Group::WIDTH);
         let ctrl_offset = (bucket_size * num_buckets).next_multiple_of(align);
         let size = ctrl_offset + num_buckets + Group::WIDTH;
         let layout = Layout::from_size_align(size, align).uunwrap();
         // Allocate
-        let alloc = unsafe { std::alloc::alloc(layout) };
+        let (alloc, layout) = unsafe { dropper::alloc(layout, huge_pages) };
+        if alloc.is_null() {
+            alloc::alloc::handle_alloc_error(layout);
+        }
         // Write control
         let ctrl = unsafe { NonNull::new_unchecked(alloc.add(ctrl_offset)) };
-        let ctrl_slice = unsafe { std::slice::from_raw_parts_mut(ctrl.as_ptr() as *mut Tag, num_buckets + Group::WIDTH) };
+        let ctrl_slice = unsafe { core::slice::from_raw_parts_mut(ctrl.as_ptr() as *mut Tag, num_buckets + Group::WIDTH) };
         ctrl_slice.fill_empty();
         // dbg!(num_buckets, bucket_size, align, ctrl_offset, size, layout, alloc, ctrl);
         let seed = fastrand::Rng::with_seed(123).u64(..);
@@ -86,7 +107,7 @@ impl<V> HashTable<V> {
             ctrl,
             items: 0,
             seed,
-            marker: std::marker::PhantomData,
+            marker: core::marker::PhantomData,
             total_probe_length: 0,
             dropper: Dropper { alloc, layout },
         }
@@ -97,6 +118,17 @@ impl<V> HashTable<V> {
         self.items
     }
 
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The hash of `key`: the low bits pick the slot the probe starts at, the top 7 the tag.
+    #[inline(always)]
+    pub fn hash_of(&self, key: u64) -> u64 {
+        fold_hash_fast(key, self.seed)
+    }
+    #[cfg(feature = "std")]
     pub fn print_stats(&self) {
         println!("  avg_probe_length: {}", self.total_probe_length as f64 / self.items as f64);
     }
@@ -206,6 +238,42 @@ impl<V> HashTable<V> {
         }
     }
 
+    /// Removes `key`, returning its value. Leaves a `DELETED` tombstone rather than `EMPTY` where
+    /// a probe sequence may pass through the slot (see `erase_index`), so keys further along it
+    /// stay findable.
+    pub fn remove(&mut self, key: &u64) -> Option<V> {
+        let index = self.find_index(*key)?;
+        let (_, value) = unsafe { self.bucket(index).read() };
+        unsafe { self.erase_index(index) };
+        Some(value)
+    }
+
+    /// Returns the slot index holding `key`, if present. Probes like `get`.
+    fn find_index(&self, key: u64) -> Option<usize> {
+        let hash64 = fold_hash_fast(key, self.seed);
+        let tag_hash = Tag::full(hash64);
+        let mut probe_seq = self.probe_seq(hash64);
+        loop {
+            let group = unsafe { Group::load(self.ctrl(probe_seq.pos)) };
+            for bit in group.match_tag(tag_hash) {
+                let index = (probe_seq.pos + bit) & self.bucket_mask;
+                if unsafe { (*self.bucket(index)).0 } == key {
+                    return Some(index);
+                }
+            }
+            if group.match_empty().any_bit_set() {
+                return None;
+            }
+            probe_seq.move_next(self.bucket_mask);
+        }
+    }
+
+    /// Marks the full slot at `index` as erased, leaving an `EMPTY` or `DELETED` tag depending on
+    /// whether a probe sequence can pass through it. The value is not dropped.
+    ///
+    /// # Safety
+    ///
+    /// `index` must be a full slot of this table, e.g. as returned by `insert`.
     #[inline(always)]
     pub unsafe fn erase_index(&mut self, index: usize) {
         let index_before = index.wrapping_sub(Group::WIDTH) & self.bucket_mask;
@@ -221,6 +289,13 @@ impl<V> HashTable<V> {
 
     }
 
+    /// Inserts `key` and, if it was new, immediately erases it again by resetting its slot to
+    /// `EMPTY`. This is the unit of work of the insert+erase churn benchmark.
+    ///
+    /// # Safety
+    ///
+    /// The table must contain no `DELETED` tombstones: the slot is reset straight to `EMPTY`, which
+    /// is only correct if `insert` took an `EMPTY` slot.
     #[inline(always)]
     pub unsafe fn insert_and_erase(&mut self, key: u64, value: V) {
         let (inserted, index, _) = self.insert(key, value);
@@ -255,4 +330,90 @@ impl<V> HashTable<V> {
             stride: 0,
         }
     }
-}
\ No newline at end of file
+}
+
+impl<V: fmt::Debug> fmt::Debug for HashTable<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = move || {
+            (0..=self.bucket_mask)
+                .filter(move |&index| unsafe { *self.ctrl(index) }.is_full())
+                .map(move |index| {
+                    let bucket = unsafe { &*self.bucket(index) };
+                    (bucket.0, &bucket.1)
+                })
+        };
+        f.debug_struct("HashTable")
+            .field("len", &self.len())
+            .field("num_buckets", &(self.bucket_mask + 1))
+            .field("entries", &DebugEntries(entries))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_with_capacity_hugepage() {
+        let mut table = HashTable::with_capacity_hugepage(100_000);
+        assert_eq!(table.dropper.alloc as usize % dropper::HUGE_PAGE_SIZE, 0);
+        assert!(table.dropper.huge_pages());
+        for key in 0..100_000u64 {
+            table.insert(key, !key);
+        }
+        assert_eq!(table.len(), 100_000);
+        for key in 0..100_000u64 {
+            assert_eq!(table.get(&key), Some(&!key));
+        }
+        assert_eq!(table.get(&100_000), None);
+        assert!(!HashTable::<u64>::with_capacity(100_000).dropper.huge_pages());
+    }
+
+    #[test]
+    fn test_remove_mid_chain() {
+        let mut table = HashTable::with_capacity(100);
+        // Keys sharing a home slot, so they form one probe chain spanning several groups.
+        let home = |key| fold_hash_fast(key, table.seed) as usize & table.bucket_mask;
+        let home0 = home(0);
+        let keys: Vec<u64> = (0..).filter(|&key| home(key) == home0).take(3 * Group::WIDTH).collect();
+        for &key in &keys {
+            assert!(table.insert(key, !key).0);
+        }
+        // Remove keys from the first group of the chain and from the middle.
+        let removed = |i: usize| i < Group::WIDTH / 2 || i == Group::WIDTH + 3;
+        for (_, &key) in keys.iter().enumerate().filter(|&(i, _)| removed(i)) {
+            assert_eq!(table.remove(&key), Some(!key));
+        }
+        for (i, &key) in keys.iter().enumerate() {
+            let expected = (!removed(i)).then_some(!key);
+            assert_eq!(table.get(&key).copied(), expected, "key {i}");
+            assert_eq!(table.remove(&key), expected);
+        }
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn test_remove_vs_std_hashmap() {
+        let mut table = HashTable::with_capacity(1 << 12);
+        let mut std_map = HashMap::new();
+        let mut rng = fastrand::Rng::with_seed(2125);
+        // A small key range at high load, so removes and reinserts land in each other's chains.
+        for _ in 0..100_000 {
+            let key = rng.u64(..4000);
+            if rng.bool() {
+                let value = rng.u64(..);
+                let (inserted, _, _) = table.insert(key, value);
+                assert_eq!(inserted, std_map.insert(key, value).is_none());
+            } else {
+                assert_eq!(table.remove(&key), std_map.remove(&key));
+            }
+            assert_eq!(table.len(), std_map.len());
+        }
+        for key in 0..4000 {
+            assert_eq!(table.get(&key), std_map.get(&key));
+        }
+    }
+}
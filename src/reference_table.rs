@@ -0,0 +1,85 @@
+//! A `U64Map` over std's `HashMap`: slow, but obviously correct, so differential tests can use it
+//! as the oracle for the other tables.
+
+use std::collections::HashMap;
+
+use crate::u64_map::U64Map;
+
+#[derive(Clone, Debug, Default)]
+pub struct HashTable<V> {
+    map: HashMap<u64, V>,
+}
+
+impl<V> HashTable<V> {
+    pub fn new() -> Self {
+        Self { map: HashMap::new() }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self { map: HashMap::with_capacity(capacity) }
+    }
+}
+
+impl<V> U64Map<V> for HashTable<V> {
+    fn insert(&mut self, key: u64, value: V) -> bool {
+        self.map.insert(key, value).is_none()
+    }
+
+    fn get(&mut self, key: &u64) -> Option<&V> {
+        self.map.get(key)
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::u64_map::{Profile, build_table};
+    use crate::{aligned_cuckoo_table, aligned_double_hashing_table, scalar_cache_line_aligned_table};
+
+    /// Runs a random sequence of inserts and lookups, mostly on a small key range so that keys
+    /// repeat, against `table` and the reference, and checks every result matches.
+    fn check_against_reference(name: &str, table: &mut dyn U64Map<u64>, seed: u64) {
+        let mut reference = HashTable::new();
+        let mut rng = fastrand::Rng::with_seed(seed);
+        for op in 0..20_000 {
+            let key = if rng.u8(..10) == 0 { rng.u64(..) } else { rng.u64(..2000) };
+            if rng.bool() {
+                let value = rng.u64(..);
+                assert_eq!(table.insert(key, value), reference.insert(key, value), "{name}: op {op}");
+            } else {
+                assert_eq!(table.get(&key), reference.get(&key), "{name}: op {op}");
+            }
+            assert_eq!(table.len(), reference.len(), "{name}: op {op}");
+        }
+    }
+
+    #[test]
+    fn test_tables_match_reference() {
+        for seed in 0..4 {
+            check_against_reference("aligned_cuckoo_table", &mut aligned_cuckoo_table::HashTable::new(), seed);
+            check_against_reference(
+                "aligned_double_hashing_table",
+                &mut aligned_double_hashing_table::HashTable::with_capacity(16),
+                seed,
+            );
+            // Doesn't grow, so sized for every key the sequence could insert.
+            check_against_reference(
+                "scalar_cache_line_aligned_table",
+                &mut scalar_cache_line_aligned_table::U64HashSet::with_capacity(20_000),
+                seed,
+            );
+            for bits in 0..8 {
+                let profile = Profile {
+                    read_heavy: bits & 1 != 0,
+                    delete_heavy: bits & 2 != 0,
+                    peak_load: bits & 4 != 0,
+                };
+                check_against_reference(&format!("{profile:?}"), &mut *build_table(profile, 20_000), seed);
+            }
+        }
+    }
+}
@@ -5,10 +5,14 @@
 //! we may need to do longer probe sequences (each probe is 8 bytes, not 1 byte), but on the other hand we only take
 //! 1 cache miss per access, not 2.
 
-use std::mem::MaybeUninit;
+use alloc::boxed::Box;
+use alloc::vec;
+use core::mem::MaybeUninit;
+use core::fmt;
 
 use crate::TRACK_PROBE_LENGTH;
 use crate::u64_fold_hash_fast::fold_hash_fast;
+use crate::DebugEntries;
 
 pub struct U64HashSet<V: Copy> {
     table: Box<[Bucket<V>]>,
@@ -26,6 +30,7 @@ const BUCKET_SIZE: usize = 8;
 struct Bucket<V: Copy>([(u64, MaybeUninit<V>); BUCKET_SIZE]);
 
 impl<V: Copy> U64HashSet<V> {
+    #[cfg(feature = "std")]
     pub fn print_stats(&self) {
         println!("  avg_probe_length: {}", self.total_probe_length as f64 / self.len as f64);
     }
@@ -51,6 +56,24 @@ impl<V: Copy> U64HashSet<V> {
         self.len
     }
 
+    /// Counts the entries from scratch, scanning for nonzero keys plus key 0 out of band, to
+    /// audit the running `len()`.
+    pub fn recount(&self) -> usize {
+        self.zero_value.is_some() as usize + self.table.iter().flat_map(|bucket| &bucket.0).filter(|&&(key, _)| key != 0).count()
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The hash of `key`. The low bits pick the cache-line bucket and the top 3 bits the slot
+    /// in it where the search starts.
+    #[inline(always)]
+    pub fn hash_of(&self, key: u64) -> u64 {
+        fold_hash_fast(key, self.seed)
+    }
+
     #[inline(always)]
     pub fn insert(&mut self, key: u64, value: V) -> (bool, (usize, usize)) {
         if key == 0 {
@@ -119,10 +142,42 @@ impl<V: Copy> U64HashSet<V> {
         }
     }
 
+    /// Returns the number of buckets (cache lines) `get` scans for `key`, and whether it is
+    /// present.
+    pub fn probe_length(&self, key: u64) -> (usize, bool) {
+        if key == 0 {
+            return (1, self.zero_value.is_some()); // Stored out of band
+        }
+        let hash64 = fold_hash_fast(key, self.seed);
+        let element_offset_in_bucket = (hash64 >> 61) as usize;
+        let mut bucket_i = hash64 as usize;
+        let mut probe_count = 1;
+        loop {
+            let bucket = unsafe { self.table.get_unchecked(bucket_i & self.bucket_mask) };
+            for element_i in 0..BUCKET_SIZE {
+                let element = &bucket.0[(element_i + element_offset_in_bucket) % BUCKET_SIZE];
+                if element.0 == key {
+                    return (probe_count, true);
+                } else if element.0 == 0 {
+                    return (probe_count, false);
+                }
+            }
+            bucket_i += 1;
+            probe_count += 1;
+        }
+    }
+
+    /// Inserts `key` and, if it was new, immediately erases it again by zeroing its slot. This is
+    /// the unit of work of the insert+erase churn benchmark.
+    ///
+    /// # Safety
+    ///
+    /// `V` must not need dropping: the erased value is forgotten, not dropped.
     #[inline(always)]
     pub unsafe fn insert_and_erase(&mut self, key: u64, value: V) {
         let (inserted, (bucket_pos, element_pos)) = self.insert(key, value);
         if inserted {
+            self.len -= 1;
             if key == 0 {
                 self.zero_value = None;
             } else {
@@ -132,4 +187,60 @@ impl<V: Copy> U64HashSet<V> {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+impl<V: Copy + fmt::Debug> fmt::Debug for U64HashSet<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = move || {
+            let zero = self.zero_value.as_ref().map(|value| (0, value));
+            let slots = self
+                .table
+                .iter()
+                .flat_map(|bucket| &bucket.0)
+                .filter(|&&(key, _)| key != 0)
+                .map(|(key, value)| (*key, unsafe { value.assume_init_ref() }));
+            zero.into_iter().chain(slots)
+        };
+        f.debug_struct("U64HashSet")
+            .field("len", &self.len())
+            .field("num_buckets", &self.table.len())
+            .field("entries", &DebugEntries(entries))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recount_after_insert_and_erase() {
+        let mut table = U64HashSet::<u64>::with_capacity(1024);
+        let mut rng = fastrand::Rng::with_seed(123);
+        for _ in 0..5000 {
+            // Small keys, so that some are already present and key 0 comes up.
+            let key = rng.u64(..2000);
+            if rng.bool() {
+                table.insert(key, key);
+            } else {
+                unsafe { table.insert_and_erase(key, key) };
+            }
+            assert_eq!(table.recount(), table.len());
+        }
+        assert!(table.len() > 0);
+    }
+
+    #[test]
+    fn test_probe_length() {
+        let mut table = U64HashSet::with_capacity(1024);
+        for key in 0..896 {
+            table.insert(key, key);
+        }
+        for key in 0..896 {
+            let (probe_length, found) = table.probe_length(key);
+            assert!(found);
+            assert!((1..=table.table.len()).contains(&probe_length));
+        }
+        assert!(!table.probe_length(1 << 40).1);
+    }
+}
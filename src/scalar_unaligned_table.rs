@@ -5,10 +5,14 @@
 //! we may need to do longer probe sequences (each probe is 8 bytes, not 1 byte), but on the other hand we only take
 //! 1 cache miss per access, not 2.
 
-use std::mem::MaybeUninit;
+use alloc::boxed::Box;
+use alloc::vec;
+use core::mem::MaybeUninit;
+use core::fmt;
 
 use crate::TRACK_PROBE_LENGTH;
 use crate::u64_fold_hash_fast::fold_hash_fast;
+use crate::DebugEntries;
 
 pub struct U64HashSet<V: Copy> {
     table: Box<[(u64, MaybeUninit<V>)]>,
@@ -20,6 +24,7 @@ pub struct U64HashSet<V: Copy> {
 }
 
 impl<V: Copy> U64HashSet<V> {
+    #[cfg(feature = "std")]
     pub fn print_stats(&self) {
         println!(
             "  avg_probe_length: {}",
@@ -48,6 +53,23 @@ impl<V: Copy> U64HashSet<V> {
         self.len
     }
 
+    /// Counts the entries from scratch, scanning for nonzero keys plus key 0 out of band, to
+    /// audit the running `len()`.
+    pub fn recount(&self) -> usize {
+        self.zero_value.is_some() as usize + self.table.iter().filter(|&&(key, _)| key != 0).count()
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The hash of `key`, whose low bits pick the slot the linear probe starts at.
+    #[inline(always)]
+    pub fn hash_of(&self, key: u64) -> u64 {
+        fold_hash_fast(key, self.seed)
+    }
+
     #[inline(always)]
     pub fn insert(&mut self, key: u64, value: V) -> (bool, usize) {
         if key == 0 {
@@ -83,10 +105,17 @@ impl<V: Copy> U64HashSet<V> {
         }
     }
 
+    /// Inserts `key` and, if it was new, immediately erases it again by zeroing its slot. This is
+    /// the unit of work of the insert+erase churn benchmark.
+    ///
+    /// # Safety
+    ///
+    /// `V` must not need dropping: the erased value is forgotten, not dropped.
     #[inline(always)]
     pub unsafe fn insert_and_erase(&mut self, key: u64, value: V) {
         let (inserted, bucket_pos) = self.insert(key, value);
         if inserted {
+            self.len -= 1;
             if key == 0 {
                 self.zero_value = None;
             } else {
@@ -118,4 +147,77 @@ impl<V: Copy> U64HashSet<V> {
             bucket_i += 1;
         }
     }
+
+    /// Returns the number of slots `get` scans for `key`, and whether it is present.
+    pub fn probe_length(&self, key: u64) -> (usize, bool) {
+        if key == 0 {
+            return (1, self.zero_value.is_some()); // Stored out of band
+        }
+        let hash64 = fold_hash_fast(key, self.seed);
+        let mut bucket_i = hash64 as usize;
+        let mut probe_count = 1;
+        loop {
+            let element = unsafe { self.table.get_unchecked(bucket_i & self.bucket_mask) };
+            if element.0 == key {
+                return (probe_count, true);
+            } else if element.0 == 0 {
+                return (probe_count, false);
+            }
+            bucket_i += 1;
+            probe_count += 1;
+        }
+    }
+}
+
+impl<V: Copy + fmt::Debug> fmt::Debug for U64HashSet<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = move || {
+            let zero = self.zero_value.as_ref().map(|value| (0, value));
+            let slots = self.table.iter()
+                .filter(|&&(key, _)| key != 0)
+                .map(|(key, value)| (*key, unsafe { value.assume_init_ref() }));
+            zero.into_iter().chain(slots)
+        };
+        f.debug_struct("U64HashSet")
+            .field("len", &self.len())
+            .field("num_buckets", &self.table.len())
+            .field("entries", &DebugEntries(entries))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recount_after_insert_and_erase() {
+        let mut table = U64HashSet::<u64>::with_capacity(1024);
+        let mut rng = fastrand::Rng::with_seed(123);
+        for _ in 0..5000 {
+            // Small keys, so that some are already present and key 0 comes up.
+            let key = rng.u64(..2000);
+            if rng.bool() {
+                table.insert(key, key);
+            } else {
+                unsafe { table.insert_and_erase(key, key) };
+            }
+            assert_eq!(table.recount(), table.len());
+        }
+        assert!(table.len() > 0);
+    }
+
+    #[test]
+    fn test_probe_length() {
+        let mut table = U64HashSet::with_capacity(1024);
+        for key in 0..896 {
+            table.insert(key, key);
+        }
+        for key in 0..896 {
+            let (probe_length, found) = table.probe_length(key);
+            assert!(found);
+            assert!((1..=table.table.len()).contains(&probe_length));
+        }
+        assert!(!table.probe_length(1 << 40).1);
+    }
 }
@@ -1,7 +1,184 @@
+use core::hash::{BuildHasher, Hasher};
+
 #[inline(always)]
 pub fn fold_hash_fast(mut key: u64, seed: u64) -> u64 {
     const FOLD: u64 = 0x2d35_8dcc_aa6c_78a5;
     key ^= seed;
     let r = (key as u128) * FOLD as u128;
     ((r >> 64) as u64) ^ (r as u64)
-}
\ No newline at end of file
+}
+
+/// A seeded hash from a `u64` key to a `u64`, from which tables derive both the group position
+/// (low bits) and the tag (`Tag::full`, top 7 bits).
+pub trait Hash64 {
+    fn hash64(&self, key: u64, seed: u64) -> u64;
+}
+
+/// The default hash: a single multiply-fold. Very cheap, but the avalanche into the high bits
+/// (which `Tag::full` uses) is weaker than a full finalizer.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct FoldHashFast;
+
+impl Hash64 for FoldHashFast {
+    #[inline(always)]
+    fn hash64(&self, key: u64, seed: u64) -> u64 {
+        fold_hash_fast(key, seed)
+    }
+}
+
+/// A wyhash-style finalizer: two multiply-folds plus a final xorshift. Costs one extra
+/// multiply over `FoldHashFast`, in exchange for much better mixing into the high bits.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct WyHash64;
+
+impl Hash64 for WyHash64 {
+    #[inline(always)]
+    fn hash64(&self, key: u64, seed: u64) -> u64 {
+        wy_hash64(key, seed)
+    }
+}
+
+#[inline(always)]
+pub fn wy_hash64(key: u64, seed: u64) -> u64 {
+    const P0: u64 = 0xa076_1d64_78bd_642f;
+    const P1: u64 = 0xe703_7ed1_a0b4_28db;
+    let r = mum(key ^ P1, seed ^ P0);
+    let r = mum(r ^ P0, P1 ^ 8);
+    r ^ (r >> 32)
+}
+
+#[inline(always)]
+fn mum(a: u64, b: u64) -> u64 {
+    let r = (a as u128) * (b as u128);
+    ((r >> 64) as u64) ^ (r as u64)
+}
+
+/// SipHash-1-3 of the key's 8 little-endian bytes, under a secret 128-bit key. Much slower than
+/// the multiply-folds, but without the key an attacker can't pick keys that all land in the same
+/// pair of groups and make cuckoo inserts fail.
+#[derive(Clone, Copy, Debug)]
+pub struct SipHash13 {
+    k0: u64,
+    k1: u64,
+}
+
+impl SipHash13 {
+    pub fn new(k0: u64, k1: u64) -> Self {
+        Self { k0, k1 }
+    }
+
+    /// A hasher with a randomly drawn key.
+    #[cfg(feature = "std")]
+    pub fn random() -> Self {
+        Self::new(fastrand::u64(..), fastrand::u64(..))
+    }
+}
+
+impl Hash64 for SipHash13 {
+    #[inline(always)]
+    fn hash64(&self, key: u64, seed: u64) -> u64 {
+        sip_hash13(self.k0 ^ seed, self.k1, key)
+    }
+}
+
+/// Adapts any `BuildHasher`, such as std's `RandomState`, to `Hash64`, so the tables can hash
+/// with whatever a `HashMap` was built with. Feeds the seed and then the key to a fresh hasher
+/// per call, so it costs a full `Hasher` round trip.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BuildHasherHash64<S>(pub S);
+
+impl<S: BuildHasher> Hash64 for BuildHasherHash64<S> {
+    #[inline(always)]
+    fn hash64(&self, key: u64, seed: u64) -> u64 {
+        let mut hasher = self.0.build_hasher();
+        hasher.write_u64(seed);
+        hasher.write_u64(key);
+        hasher.finish()
+    }
+}
+
+#[inline(always)]
+pub fn sip_hash13(k0: u64, k1: u64, key: u64) -> u64 {
+    let mut v = [
+        k0 ^ 0x736f_6d65_7073_6575,
+        k1 ^ 0x646f_7261_6e64_6f6d,
+        k0 ^ 0x6c79_6765_6e65_7261,
+        k1 ^ 0x7465_6462_7974_6573,
+    ];
+    // One compression round per 8-byte block: the key, then the length-only final block.
+    for m in [key, 8 << 56] {
+        v[3] ^= m;
+        sip_round(&mut v);
+        v[0] ^= m;
+    }
+    v[2] ^= 0xff;
+    for _ in 0..3 {
+        sip_round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+#[inline(always)]
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13) ^ v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16) ^ v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21) ^ v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17) ^ v[2];
+    v[2] = v[2].rotate_left(32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::Tag;
+
+    /// Chi-squared statistic of the `Tag::full` distribution of `hash(0..n)` over the 128 tag values.
+    fn tag_chi_squared(hash: impl Hash64, n: u64) -> f64 {
+        let seed = fastrand::Rng::with_seed(123).u64(..);
+        let mut counts = [0u64; 128];
+        for key in 0..n {
+            counts[Tag::full(hash.hash64(key, seed)).0 as usize] += 1;
+        }
+        let expected = n as f64 / 128.0;
+        counts
+            .iter()
+            .map(|&c| (c as f64 - expected) * (c as f64 - expected) / expected)
+            .sum()
+    }
+
+    #[test]
+    fn test_wy_hash64_tag_uniformity() {
+        // 127 degrees of freedom; the 99.9th percentile of chi-squared(127) is ~182.
+        const CHI_SQUARED_999: f64 = 182.0;
+        let chi2 = tag_chi_squared(WyHash64, 1_000_000);
+        assert!(chi2 < CHI_SQUARED_999, "WyHash64 tag chi-squared too high: {chi2}");
+    }
+
+    #[test]
+    fn test_hashers_depend_on_seed() {
+        for key in [0, 1, 42, u64::MAX] {
+            assert_ne!(FoldHashFast.hash64(key, 1), FoldHashFast.hash64(key, 2));
+            assert_ne!(WyHash64.hash64(key, 1), WyHash64.hash64(key, 2));
+            assert_ne!(SipHash13::new(0, 0).hash64(key, 1), SipHash13::new(0, 0).hash64(key, 2));
+            let std = BuildHasherHash64(std::hash::RandomState::new());
+            assert_ne!(std.hash64(key, 1), std.hash64(key, 2));
+        }
+    }
+
+    #[test]
+    fn test_sip_hash13_matches_std() {
+        use core::hash::{Hasher, SipHasher13};
+        let mut rng = fastrand::Rng::with_seed(123);
+        for _ in 0..1000 {
+            let (k0, k1, key) = (rng.u64(..), rng.u64(..), rng.u64(..));
+            let mut hasher = SipHasher13::new_with_keys(k0, k1);
+            hasher.write_u64(key);
+            assert_eq!(sip_hash13(k0, k1, key), hasher.finish());
+        }
+    }
+}
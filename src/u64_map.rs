@@ -0,0 +1,129 @@
+//! A common interface over the tables, and `build_table` to pick one for a workload without
+//! knowing the tradeoffs between them.
+
+use alloc::boxed::Box;
+
+use crate::{aligned_cuckoo_table, aligned_double_hashing_table, scalar_cache_line_aligned_table};
+
+/// The operations every table in this crate supports.
+pub trait U64Map<V> {
+    /// Inserts `key`, or updates its value if present. Returns whether `key` was new.
+    fn insert(&mut self, key: u64, value: V) -> bool;
+
+    fn get(&mut self, key: &u64) -> Option<&V>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<V: Copy> U64Map<V> for aligned_cuckoo_table::HashTable<V> {
+    fn insert(&mut self, key: u64, value: V) -> bool {
+        // Not `insert`: its early return can duplicate a key that sits in its second group.
+        self.replace(key, value).is_none()
+    }
+
+    fn get(&mut self, key: &u64) -> Option<&V> {
+        self.get(key)
+    }
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<V> U64Map<V> for aligned_double_hashing_table::HashTable<V> {
+    fn insert(&mut self, key: u64, value: V) -> bool {
+        self.insert(key, value).0
+    }
+
+    fn get(&mut self, key: &u64) -> Option<&V> {
+        self.get(key)
+    }
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<V: Copy> U64Map<V> for scalar_cache_line_aligned_table::U64HashSet<V> {
+    fn insert(&mut self, key: u64, value: V) -> bool {
+        self.insert(key, value).0
+    }
+
+    fn get(&mut self, key: &u64) -> Option<&V> {
+        self.get(key)
+    }
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+}
+
+/// What a table will be used for; see `build_table`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Lookups dominate inserts.
+    pub read_heavy: bool,
+    /// Keys are erased and reinserted often.
+    pub delete_heavy: bool,
+    /// The table will be filled close to `capacity`.
+    pub peak_load: bool,
+}
+
+/// Tables up to this size are assumed to stay in cache (about L2).
+const IN_CACHE_BYTES: usize = 1 << 20;
+
+/// Returns a table for `capacity` keys suited to `profile`:
+/// * delete-heavy: `aligned_double_hashing_table`, which reuses and compacts tombstones;
+/// * near peak load, or read-heavy and in cache: `aligned_cuckoo_table`, whose lookups probe at
+///   most two groups at any load;
+/// * otherwise (out of cache): `scalar_cache_line_aligned_table`, which takes one cache miss per
+///   lookup rather than one for the tags and one for the key. It doesn't grow, so it must not be
+///   filled past `capacity`.
+pub fn build_table<V: Copy + 'static>(profile: Profile, capacity: usize) -> Box<dyn U64Map<V>> {
+    let in_cache = capacity * core::mem::size_of::<(u64, V)>() <= IN_CACHE_BYTES;
+    if profile.delete_heavy {
+        Box::new(aligned_double_hashing_table::HashTable::with_capacity(capacity))
+    } else if profile.peak_load || (profile.read_heavy && in_cache) {
+        Box::new(aligned_cuckoo_table::HashTable::with_capacity(capacity))
+    } else {
+        Box::new(scalar_cache_line_aligned_table::U64HashSet::with_capacity(capacity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_table_round_trip() {
+        for capacity in [1000, 1 << 18] {
+            for bits in 0..8 {
+                let profile = Profile {
+                    read_heavy: bits & 1 != 0,
+                    delete_heavy: bits & 2 != 0,
+                    peak_load: bits & 4 != 0,
+                };
+                let mut table = build_table::<u64>(profile, capacity);
+                assert!(table.is_empty());
+                let n = capacity as u64 * 3 / 4;
+                for key in 0..n {
+                    assert!(table.insert(key, key));
+                }
+                // Updates, including of the zero key.
+                for key in (0..n).step_by(7) {
+                    assert!(!table.insert(key, !key));
+                }
+                assert_eq!(table.len(), n as usize, "{profile:?}");
+                for key in 0..n {
+                    let expected = if key % 7 == 0 { !key } else { key };
+                    assert_eq!(table.get(&key), Some(&expected), "{profile:?}");
+                }
+                assert_eq!(table.get(&n), None);
+            }
+        }
+    }
+}
@@ -15,13 +15,15 @@
 //! 
 //! https://www.cs.princeton.edu/~mfreed/docs/cuckoo-eurosys14.pdf <-- follow-up on libcuckoo/MemC3. They explain why they use BFS rather than DFS. Some is irrelevant (critical section length) but some is relevant: BFS offers better memory level parallelism via prefetching.
 
-use std::{alloc::Layout, ptr::NonNull};
+use core::{alloc::Layout, ptr::NonNull};
+use core::fmt;
 
-use crate::TRACK_PROBE_LENGTH;
+use crate::{FillToFailure, InsertError, TRACK_PROBE_LENGTH};
 use crate::control::{Group, Tag, TagSliceExt as _};
-use crate::u64_fold_hash_fast::{self, fold_hash_fast};
+use crate::u64_fold_hash_fast::fold_hash_fast;
 use crate::uunwrap::UUnwrap;
-use crate::dropper::Dropper;
+use crate::dropper::{self, Dropper};
+use crate::DebugEntries;
 
 pub struct HashTable<V> {
     // Mask to get an index from a hash value. The value is one less than the
@@ -38,7 +40,7 @@ pub struct HashTable<V> {
     // Seed for the hash function
     seed: u64,
 
-    marker: std::marker::PhantomData<V>,
+    marker: core::marker::PhantomData<V>,
     rng: fastrand::Rng,
     total_probe_length: usize,
     total_insert_probe_length: usize,
@@ -46,21 +48,49 @@ pub struct HashTable<V> {
     dropper: Dropper,
 }
 
+// SAFETY: `ctrl` points into the allocation `dropper` owns, which no other table shares. Groups
+// start at any slot and wrap through the mirrored trailing `Group::WIDTH` bytes, which `set_ctrl`
+// keeps in sync; it takes `&self` but only runs under `&mut self`.
+unsafe impl<V: Send> Send for HashTable<V> {}
+unsafe impl<V: Sync> Sync for HashTable<V> {}
+
+/// Load factor `try_insert` reliably fills a table to before failing. Overlapping 16-slot
+/// windows leave the BFS so many paths that filling random keys into 2^10 to 2^20 slots
+/// reached at least 99.95% on every seed.
+pub const PEAK_LOAD_FACTOR: f64 = 0.99;
+
+/// Fewest buckets a table is built with. With only one group's worth of buckets, a key's two
+/// candidate groups overlap almost entirely and a tiny table fails after a few inserts.
+const MIN_NUM_BUCKETS: usize = 2 * Group::WIDTH;
+
 impl<V> HashTable<V> {
     pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_huge_pages(capacity, false)
+    }
+
+    /// As `with_capacity`, but on huge pages where the kernel allows; see `dropper::alloc`.
+    #[cfg(target_os = "linux")]
+    pub fn with_capacity_hugepage(capacity: usize) -> Self {
+        Self::with_capacity_and_huge_pages(capacity, true)
+    }
+
+    fn with_capacity_and_huge_pages(capacity: usize, huge_pages: bool) -> Self {
         // Calculate sizes
         // TODO: integer overflow...
-        let num_buckets = ((capacity * 8) / 7).next_power_of_two();
-        let bucket_size = std::mem::size_of::<(u64, V)>();
-        let align = std::mem::align_of::<(u64, V)>().max(Group::WIDTH);
+        let num_buckets = ((capacity * 8) / 7).next_power_of_two().max(MIN_NUM_BUCKETS);
+        let bucket_size = core::mem::size_of::<(u64, V)>();
+        let align = core::mem::align_of::<(u64, V)>().max(Group::WIDTH);
         let ctrl_offset = (bucket_size * num_buckets).next_multiple_of(align);
         let size = ctrl_offset + num_buckets + Group::WIDTH;
         let layout = Layout::from_size_align(size, align).uunwrap();
         // Allocate
-        let alloc = unsafe { std::alloc::alloc(layout) };
+        let (alloc, layout) = unsafe { dropper::alloc(layout, huge_pages) };
+        if alloc.is_null() {
+            alloc::alloc::handle_alloc_error(layout);
+        }
         // Write control
         let ctrl = unsafe { NonNull::new_unchecked(alloc.add(ctrl_offset)) };
-        let ctrl_slice = unsafe { std::slice::from_raw_parts_mut(ctrl.as_ptr() as *mut Tag, num_buckets + Group::WIDTH) };
+        let ctrl_slice = unsafe { core::slice::from_raw_parts_mut(ctrl.as_ptr() as *mut Tag, num_buckets + Group::WIDTH) };
         ctrl_slice.fill_empty();
         // dbg!(num_buckets, bucket_size, align, ctrl_offset, size, layout, alloc, ctrl);
         let seed = fastrand::Rng::with_seed(123).u64(..);
@@ -71,7 +101,7 @@ impl<V> HashTable<V> {
             ctrl,
             items: 0,
             seed,
-            marker: std::marker::PhantomData,
+            marker: core::marker::PhantomData,
             rng: fastrand::Rng::with_seed(123),
             total_probe_length: 0,
             total_insert_probe_length: 0,
@@ -79,7 +109,7 @@ impl<V> HashTable<V> {
             dropper: Dropper { alloc, layout },
         }
     }
-
+    #[cfg(feature = "std")]
     pub fn print_stats(&self) {
         let items = self.items as f64;
         println!("  avg_probe_length: {}", self.total_probe_length as f64 / items);
@@ -92,8 +122,34 @@ impl<V> HashTable<V> {
         self.items
     }
 
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The hash of `key`. Each 32-bit half picks the start of one of the key's two groups.
+    #[inline(always)]
+    pub fn hash_of(&self, key: u64) -> u64 {
+        fold_hash_fast(key, self.seed)
+    }
+
+    /// Fraction of slots that are full.
+    pub fn load_factor(&self) -> f64 {
+        self.len() as f64 / (self.bucket_mask + 1) as f64
+    }
+
     #[inline(always)]
     pub fn insert(&mut self, key: u64, value: V) -> (bool, usize, usize) {
+        match self.try_insert(key, value) {
+            Ok(result) => result,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// As `insert`, but returns `InsertError::NeedsRehash` instead of panicking when the BFS finds
+    /// no free slot. The table is unchanged on failure.
+    #[inline(always)]
+    pub fn try_insert(&mut self, key: u64, value: V) -> Result<(bool, usize, usize), InsertError> {
         let hash0 = fold_hash_fast(key, self.seed);
         let hash1 = hash0.rotate_left(32);
         let tag_hash = Tag::full(hash0);
@@ -110,7 +166,7 @@ impl<V> HashTable<V> {
 
             if unsafe { (*bucket).0 } == key {
                 unsafe { (*bucket).1 = value };
-                return (false, index, insertion_probe_length);
+                return Ok((false, index, insertion_probe_length));
             }
         }
         // No match. Now check first group for an empty slot.
@@ -124,7 +180,7 @@ impl<V> HashTable<V> {
                 if TRACK_PROBE_LENGTH {
                     self.total_probe_length += 1;
                 }
-                return (true, insert_slot, insertion_probe_length);
+                return Ok((true, insert_slot, insertion_probe_length));
             }
         }
 
@@ -141,7 +197,7 @@ impl<V> HashTable<V> {
 
             if unsafe { (*bucket).0 } == key {
                 unsafe { (*bucket).1 = value };
-                return (false, index, insertion_probe_length);
+                return Ok((false, index, insertion_probe_length));
             }
         }
 
@@ -156,7 +212,7 @@ impl<V> HashTable<V> {
                 if TRACK_PROBE_LENGTH {
                     self.total_probe_length += 2;
                 }
-                return (true, insert_slot, insertion_probe_length);
+                return Ok((true, insert_slot, insertion_probe_length));
             }
         }
 
@@ -168,7 +224,7 @@ impl<V> HashTable<V> {
         // BFS Cuckoo loop adapted for unaligned buckets.
         // Each key can be in two different windows, so we explore both alternatives.
         // This is similar to aligned_cuckoo_table.rs but adapted for two alternatives per key.
-        use std::mem::MaybeUninit;
+        use core::mem::MaybeUninit;
 
         const N: usize = Group::WIDTH;
         const BFS_MAX_LEN: usize = 2 * (1 + 2*N + 2*N*N + 2*N*N*N);
@@ -206,8 +262,8 @@ impl<V> HashTable<V> {
                     let alt_pos1 = rehash.rotate_left(32) as usize & self.bucket_mask;
 
                     unsafe {
-                        *bfs_queue.get_unchecked_mut(bfs_write_pos + i * 2).write(alt_pos0);
-                        *bfs_queue.get_unchecked_mut(bfs_write_pos + i * 2 + 1).write(alt_pos1);
+                        bfs_queue.get_unchecked_mut(bfs_write_pos + i * 2).write(alt_pos0);
+                        bfs_queue.get_unchecked_mut(bfs_write_pos + i * 2 + 1).write(alt_pos1);
                     }
                 }
                 for i in 0..N {
@@ -219,8 +275,8 @@ impl<V> HashTable<V> {
                     let alt_pos1 = rehash.rotate_left(32) as usize & self.bucket_mask;
 
                     unsafe {
-                        *bfs_queue.get_unchecked_mut(bfs_write_pos + 2 * N + i * 2).write(alt_pos0);
-                        *bfs_queue.get_unchecked_mut(bfs_write_pos + 2 * N + i * 2 + 1).write(alt_pos1);
+                        bfs_queue.get_unchecked_mut(bfs_write_pos + 2 * N + i * 2).write(alt_pos0);
+                        bfs_queue.get_unchecked_mut(bfs_write_pos + 2 * N + i * 2 + 1).write(alt_pos1);
                     }
                 }
             }
@@ -228,7 +284,7 @@ impl<V> HashTable<V> {
             bfs_read_pos += 2;
 
             if bfs_read_pos + 2 > BFS_MAX_LEN {
-                panic!("Failed to insert into cuckoo table; need to rehash");
+                return Err(InsertError::NeedsRehash);
             }
             pos0 = unsafe { bfs_queue[bfs_read_pos + 0].assume_init() };
             pos1 = unsafe { bfs_queue[bfs_read_pos + 1].assume_init() };
@@ -246,7 +302,6 @@ impl<V> HashTable<V> {
             //         window1_bucket0_pos0, window1_bucket0_pos1, window1_bucket1_pos0, window1_bucket1_pos1, ...
             let parent_window_index = parent_bucket_offset / (2 * N);  // 0 or 1
             let parent_bucket_in_window = (parent_bucket_offset % (2 * N)) / 2;
-            let parent_alt_index = parent_bucket_offset % 2;
 
             let parent_pos = unsafe { bfs_queue.get_unchecked(parent_path_index + parent_window_index).assume_init() };
             let parent_bucket_index = (parent_pos + parent_bucket_in_window) & self.bucket_mask;
@@ -267,10 +322,17 @@ impl<V> HashTable<V> {
             self.bucket(bucket_index).write((key, value));
             self.set_ctrl(bucket_index, tag_hash);
             self.items += 1;
-            return (true, bucket_index, insertion_probe_length);
+            return Ok((true, bucket_index, insertion_probe_length));
         }
     }
 
+    /// Inserts `key` and, if it was new, immediately erases it again. This is the unit of work of
+    /// the insert+erase churn benchmark.
+    ///
+    /// # Safety
+    ///
+    /// Benchmark-only: the erased value is forgotten rather than dropped, and `len` keeps counting
+    /// it.
     #[inline(always)]
     pub unsafe fn insert_and_erase(&mut self, key: u64, value: V) {
         let (inserted, index, _) = self.insert(key, value);
@@ -356,6 +418,44 @@ impl<V> HashTable<V> {
         }
     }
 
+    /// Inserts `key`, returning the previous value if the key was already present (like
+    /// `std::collections::HashMap::insert`).
+    #[inline(always)]
+    pub fn replace(&mut self, key: u64, value: V) -> Option<V> {
+        if let Some(index) = self.find_index(key) {
+            let slot = unsafe { &mut (*self.bucket(index)).1 };
+            return Some(core::mem::replace(slot, value));
+        }
+        self.insert(key, value);
+        None
+    }
+
+    /// Returns the slot index holding `key`, if present. Same probe as `get`.
+    #[inline(always)]
+    fn find_index(&self, key: u64) -> Option<usize> {
+        let hash0 = fold_hash_fast(key, self.seed);
+        let tag_hash = Tag::full(hash0);
+        for hash in [hash0, hash0.rotate_left(32)] {
+            let pos = hash as usize & self.bucket_mask;
+            let group = unsafe { Group::load(self.ctrl(pos)) };
+            for bit in group.match_tag(tag_hash) {
+                let index = (pos + bit) & self.bucket_mask;
+                if unsafe { (*self.bucket(index)).0 } == key {
+                    return Some(index);
+                }
+            }
+            if group.match_empty().any_bit_set() {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Marks the full slot at `index` as empty. The value is not dropped.
+    ///
+    /// # Safety
+    ///
+    /// `index` must be a full slot of this table, e.g. as returned by `insert`.
     #[inline(always)]
     pub unsafe fn erase_index(&mut self, index: usize) {
         self.set_ctrl(index, Tag::EMPTY);
@@ -382,11 +482,62 @@ impl<V> HashTable<V> {
     }
 }
 
+impl<V: fmt::Debug> fmt::Debug for HashTable<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = move || {
+            (0..=self.bucket_mask)
+                .filter(move |&index| unsafe { *self.ctrl(index) }.is_full())
+                .map(move |index| {
+                    let bucket = unsafe { &*self.bucket(index) };
+                    (bucket.0, &bucket.1)
+                })
+        };
+        f.debug_struct("HashTable")
+            .field("len", &self.len())
+            .field("num_buckets", &(self.bucket_mask + 1))
+            .field("entries", &DebugEntries(entries))
+            .finish()
+    }
+}
+
+/// The table has no growth threshold, so `try_insert` fills it until the cuckoo BFS finds no room.
+impl<V: Default> FillToFailure for HashTable<V> {
+    fn with_capacity(capacity: usize) -> Self {
+        HashTable::with_capacity(capacity)
+    }
+
+    fn try_insert_key(&mut self, key: u64) -> bool {
+        self.try_insert(key, V::default()).is_ok()
+    }
+
+    fn load_factor(&self) -> f64 {
+        HashTable::load_factor(self)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::collections::HashMap;
 
+    #[test]
+    fn test_peak_load_factor() {
+        crate::assert_reaches_peak_load_factor::<HashTable<u64>>(PEAK_LOAD_FACTOR);
+    }
+
+    #[test]
+    fn test_with_capacity_one() {
+        // Rounded up to the minimum size, so a handful of keys fit.
+        let mut table = HashTable::<u64>::with_capacity(1);
+        for key in 1..=8 {
+            table.insert(key, key * 10);
+        }
+        assert_eq!(table.len(), 8);
+        for key in 1..=8 {
+            assert_eq!(table.get(&key), Some(&(key * 10)));
+        }
+    }
+
     #[test]
     fn test_basic_insert_and_get() {
         let mut table = HashTable::with_capacity(16);
@@ -437,6 +588,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_replace() {
+        let mut table = HashTable::with_capacity(64);
+        assert_eq!(table.replace(42, String::from("a")), None);
+        assert_eq!(table.replace(43, String::from("b")), None);
+        assert_eq!(table.len(), 2);
+
+        // Updating hands back the old (non-Copy) value.
+        assert_eq!(table.replace(42, String::from("c")), Some(String::from("a")));
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(&42).map(String::as_str), Some("c"));
+        assert_eq!(table.get(&43).map(String::as_str), Some("b"));
+    }
+
     #[test]
     fn test_cross_check_with_std_hashmap_small() {
         let mut cuckoo_table = HashTable::with_capacity(32);
@@ -693,4 +858,26 @@ mod tests {
             println!("Inserted key {}: {}", i, inserted);
         }
     }
+
+    #[test]
+    fn test_try_insert_failure_leaves_table_unchanged() {
+        let mut table = HashTable::with_capacity(64);
+        let mut rng = fastrand::Rng::with_seed(123);
+        let mut keys = Vec::new();
+        let err = loop {
+            let key = rng.u64(..);
+            match table.try_insert(key, !key) {
+                Ok((inserted, _, _)) => {
+                    assert!(inserted);
+                    keys.push(key);
+                }
+                Err(err) => break err,
+            }
+        };
+        assert_eq!(err, InsertError::NeedsRehash);
+        assert_eq!(table.len(), keys.len());
+        for key in &keys {
+            assert_eq!(table.get(key), Some(&!key));
+        }
+    }
 }
\ No newline at end of file
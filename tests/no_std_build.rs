@@ -0,0 +1,16 @@
+//! Smoke check that the table modules still build as `no_std` + `alloc`.
+
+use std::process::Command;
+
+#[test]
+fn builds_without_default_features() {
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    // Separate target dir so we don't contend with the outer cargo's build lock.
+    let target_dir = format!("{manifest_dir}/target/no_std_check");
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "--lib", "--no-default-features", "--target-dir", &target_dir])
+        .current_dir(manifest_dir)
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "`cargo build --lib --no-default-features` failed");
+}